@@ -0,0 +1,236 @@
+//! Procedural macros for SurrealX
+//!
+//! Use these through the `surrealx` crate with the `macros` feature rather
+//! than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, FnArg, GenericArgument, ItemFn, LitStr, PathArguments, ReturnType, Type};
+
+/// Declare a SurrealX function from a plain fn
+///
+/// The fn is replaced by a unit struct of the same name that implements
+/// `FunctionHandler` and `DeclaredFunction`, so it can be added to a module
+/// with `Module::with_declared_function`. The original body stays callable
+/// as `name::run(...)`.
+///
+/// Each parameter is deserialized from the argument at the same position;
+/// missing arguments are passed as `null`, so `Option` parameters are
+/// optional. The fn may be `async` and must return a `Result` whose value
+/// serializes to JSON and whose error converts into `surrealx::Error`.
+///
+/// ```rust,ignore
+/// #[surrealx::function(name = "business::tax")]
+/// async fn calculate_tax(price: f64, rate: Option<f64>) -> surrealx::Result<f64> {
+///     Ok(price * rate.unwrap_or(0.15))
+/// }
+///
+/// let business = Module::new("business").with_declared_function(calculate_tax);
+/// ```
+///
+/// The `name` defaults to the fn name and is registered under the module's
+/// namespace like any other module function.
+///
+/// The parameter and result types also make up the function's
+/// `FunctionSignature`: booleans, integers of up to 32 bits, floats, strings
+/// and `Vec`s of those keep their type, `Option` parameters are optional, and
+/// anything else is any JSON value. Add `mutation` to the attribute, as in
+/// `#[surrealx::function(mutation)]`, for functions that change state.
+#[proc_macro_attribute]
+pub fn function(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name: Option<LitStr> = None;
+    let mut mutation = false;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("mutation") {
+            mutation = true;
+            Ok(())
+        } else {
+            Err(meta.error("unsupported attribute; expected `name = \"...\"` or `mutation`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+
+    let function = parse_macro_input!(item as ItemFn);
+    expand(name, mutation, function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(name: Option<LitStr>, mutation: bool, function: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let ItemFn { attrs, vis, sig, block } = function;
+
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(&sig.generics, "declared functions cannot be generic"));
+    }
+    if let ReturnType::Default = sig.output {
+        return Err(syn::Error::new_spanned(&sig, "declared functions must return a `Result`"));
+    }
+
+    let ident = &sig.ident;
+    let name = name.unwrap_or_else(|| LitStr::new(&ident.to_string(), Span::call_site()));
+
+    let mut params = Vec::new();
+    for input in &sig.inputs {
+        match input {
+            FnArg::Typed(param) => params.push(param),
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(receiver, "declared functions cannot take `self`"))
+            }
+        }
+    }
+
+    let arity = params.len();
+    let bindings: Vec<_> = (0..arity).map(|index| format_ident!("__arg{}", index)).collect();
+    let parses = params.iter().zip(&bindings).enumerate().map(|(index, (param, binding))| {
+        let (pat, ty) = (&param.pat, &param.ty);
+        let label = quote!(#pat).to_string();
+        quote! {
+            let #binding: #ty = ::surrealx::__private::parse_argument(
+                <Self as ::surrealx::DeclaredFunction>::NAME,
+                #index,
+                #label,
+                args.next(),
+            )?;
+        }
+    });
+    let args = (arity > 0).then(|| quote!(let mut args = args.into_iter();));
+
+    let signature_params = params.iter().map(|param| {
+        let pat = &param.pat;
+        let label = quote!(#pat).to_string();
+        match option_inner(&param.ty) {
+            Some(inner) => {
+                let ty = value_type(inner);
+                quote!(.optional_param(#label, #ty))
+            }
+            None => {
+                let ty = value_type(&param.ty);
+                quote!(.param(#label, #ty))
+            }
+        }
+    });
+    let returns = match &sig.output {
+        ReturnType::Type(_, ty) => {
+            let ty = result_inner(ty).unwrap_or(ty);
+            value_type(option_inner(ty).unwrap_or(ty))
+        }
+        ReturnType::Default => value_type_any(),
+    };
+    let mutation = mutation.then(|| quote!(.mutation()));
+    let await_output = sig.asyncness.map(|_| quote!(.await));
+
+    let mut run = sig.clone();
+    run.ident = format_ident!("run");
+    let docs = attrs.iter().filter(|attr| attr.path().is_ident("doc"));
+
+    Ok(quote! {
+        #(#docs)*
+        #[allow(non_camel_case_types)]
+        #[derive(Debug, Clone, Copy, Default)]
+        #vis struct #ident;
+
+        impl #ident {
+            #(#attrs)*
+            #vis #run #block
+        }
+
+        impl ::surrealx::DeclaredFunction for #ident {
+            const NAME: &'static str = #name;
+
+            fn signature() -> ::surrealx::FunctionSignature {
+                ::surrealx::FunctionSignature::new()
+                    #(#signature_params)*
+                    .returns(#returns)
+                    #mutation
+            }
+        }
+
+        impl ::surrealx::FunctionHandler for #ident {
+            fn call<'life0, 'async_trait>(
+                &'life0 self,
+                args: ::std::vec::Vec<::surrealx::__private::serde_json::Value>,
+            ) -> ::core::pin::Pin<::std::boxed::Box<
+                dyn ::core::future::Future<
+                    Output = ::surrealx::Result<::surrealx::__private::serde_json::Value>
+                > + ::core::marker::Send + 'async_trait
+            >>
+            where
+                'life0: 'async_trait,
+                Self: 'async_trait,
+            {
+                ::std::boxed::Box::pin(async move {
+                    ::surrealx::__private::check_arity(
+                        <Self as ::surrealx::DeclaredFunction>::NAME,
+                        #arity,
+                        args.len(),
+                    )?;
+                    #args
+                    #(#parses)*
+                    ::surrealx::__private::function_output(Self::run(#(#bindings),*) #await_output)
+                })
+            }
+        }
+    })
+}
+
+/// The single type argument of `ty` if its last path segment is `wrapper`
+fn type_argument<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => arguments.args.iter().find_map(|argument| match argument {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    type_argument(ty, "Option")
+}
+
+fn result_inner(ty: &Type) -> Option<&Type> {
+    type_argument(ty, "Result")
+}
+
+/// The `ValueType` describing values of `ty`
+fn value_type(ty: &Type) -> proc_macro2::TokenStream {
+    match ty {
+        Type::Reference(reference) => value_type(&reference.elem),
+        Type::Slice(slice) => array(value_type(&slice.elem)),
+        Type::Path(path) => {
+            if let Some(inner) = type_argument(ty, "Vec") {
+                return array(value_type(inner));
+            }
+            let Some(segment) = path.path.segments.last() else {
+                return value_type_any();
+            };
+            match segment.ident.to_string().as_str() {
+                "bool" => quote!(::surrealx::ValueType::Bool),
+                "i8" | "i16" | "i32" | "u8" | "u16" => quote!(::surrealx::ValueType::Int),
+                "f32" | "f64" => quote!(::surrealx::ValueType::Float),
+                "String" | "str" | "char" => quote!(::surrealx::ValueType::String),
+                _ => value_type_any(),
+            }
+        }
+        _ => value_type_any(),
+    }
+}
+
+fn array(item: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote!(::surrealx::ValueType::Array(::std::boxed::Box::new(#item)))
+}
+
+fn value_type_any() -> proc_macro2::TokenStream {
+    quote!(::surrealx::ValueType::Any)
+}
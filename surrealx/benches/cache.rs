@@ -0,0 +1,99 @@
+//! Compares reading a large value from `MemoryCacheProvider` with `get`,
+//! which clones it, and `get_arc`, which shares it
+//!
+//! Run with `cargo bench --bench cache`. Prints the time and the heap
+//! allocations per read.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use surrealx::{CacheProvider, MemoryCacheProvider};
+
+const READS: u32 = 2_000;
+
+/// System allocator counting allocations and allocated bytes
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// A catalog of 10,000 products, about 1 MB as JSON
+fn large_value() -> Value {
+    let products: Vec<Value> = (0..10_000)
+        .map(|id| {
+            json!({
+                "id": id,
+                "name": format!("product {}", id),
+                "tags": ["catalog", "bench"],
+                "price": { "amount": id * 100, "currency": "EUR" },
+            })
+        })
+        .collect();
+    json!({ "products": products })
+}
+
+/// Time and allocations per iteration of `read`
+async fn measure<F, Fut>(mut read: F) -> (Duration, usize, usize)
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..READS {
+        read().await;
+    }
+    let elapsed = start.elapsed() / READS;
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / READS as usize;
+    let bytes = (BYTES.load(Ordering::Relaxed) - bytes) / READS as usize;
+    (elapsed, allocations, bytes)
+}
+
+fn report(name: &str, (elapsed, allocations, bytes): (Duration, usize, usize)) {
+    println!("{:<8} {:>12?}/read {:>8} allocations/read {:>10} bytes/read", name, elapsed, allocations, bytes);
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    runtime.block_on(async {
+        let cache = MemoryCacheProvider::new();
+        cache.set("catalog", large_value(), None).await.unwrap();
+
+        let get = measure(|| async {
+            let value = cache.get("catalog").await.unwrap().unwrap();
+            std::hint::black_box(value);
+        })
+        .await;
+        let get_arc = measure(|| async {
+            let value = cache.get_arc("catalog").await.unwrap().unwrap();
+            std::hint::black_box(value);
+        })
+        .await;
+
+        report("get", get);
+        report("get_arc", get_arc);
+    });
+}
@@ -1,13 +1,13 @@
 //! Basic SurrealX example demonstrating custom functions and events
 
-use surrealx::{SurrealX, Module, ServerConfig, Result, Error};
+use surrealx::{SurrealX, Module, ServerConfig, SqlAccess, Result, Error};
 use serde_json::{json, Value};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Custom function: calculate tax
     async fn calculate_tax(args: Vec<Value>) -> Result<Value> {
-        let price = args.get(0)
+        let price = args.first()
             .and_then(|v| v.as_f64())
             .ok_or_else(|| Error::Function("Invalid price".to_string()))?;
 
@@ -20,7 +20,7 @@ async fn main() -> anyhow::Result<()> {
 
     // Custom function: format currency
     async fn format_currency(args: Vec<Value>) -> Result<Value> {
-        let amount = args.get(0)
+        let amount = args.first()
             .and_then(|v| v.as_f64())
             .ok_or_else(|| Error::Function("Invalid amount".to_string()))?;
 
@@ -43,15 +43,19 @@ async fn main() -> anyhow::Result<()> {
     // Start SurrealX with extensions
     println!("🚀 Starting SurrealX with business extensions...\n");
 
+    println!("📚 SQL Usage Examples (POST to http://127.0.0.1:8000/sql):");
+    println!("  RETURN ext::calculate_tax(100.0, 0.15);");
+    println!("  RETURN ext::format_currency(1234.56);");
+    println!("  RETURN sx::emit('orders:created', {{ order_id: 123 }});\n");
+
     SurrealX::new()
         .with_module(business)
-        .serve(ServerConfig::default())
+        // `/sql` runs queries as root; open it only on a development machine
+        .serve(ServerConfig {
+            sql_access: SqlAccess::Open,
+            ..ServerConfig::default()
+        })
         .await?;
 
-    println!("\n📚 SQL Usage Examples:");
-    println!("  SELECT ext::calculate_tax(100.0, 0.15) AS tax;");
-    println!("  SELECT ext::format_currency(1234.56) AS formatted;");
-    println!("  SELECT sx::emit('orders:created', {{ order_id: 123 }});");
-
     Ok(())
 }
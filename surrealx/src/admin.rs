@@ -0,0 +1,262 @@
+//! Admin API for inspecting and managing a running server
+//!
+//! With `ServerConfig::admin_route` set, the server answers under
+//! `/sx/admin`:
+//!
+//! - `GET /sx/admin/functions`: registered functions with their signature,
+//!   deprecation and whether they stream, and the aliases.
+//! - `GET /sx/admin/functions/stats`: call counts, error rates and latency
+//!   percentiles of module functions; see the [`stats`](crate::stats) module.
+//! - `GET /sx/admin/events`: listener patterns and event queue counters.
+//! - `GET /sx/admin/modules`: modules with their health and whether they are
+//!   enabled, and the modules that failed to initialize.
+//! - `POST /sx/admin/modules/{name}/disable` and `/enable`: stop or resume
+//!   serving a module. Calls to a disabled module's functions fail with
+//!   [`Error::ModuleDisabled`], its routes answer `503 Service Unavailable`,
+//!   and its listeners and jobs are skipped. Workflow steps still run.
+//! - `GET /sx/admin/cache`: the cache backend, its statistics and, for the
+//!   memory cache, its entries counted by remaining TTL.
+//! - `DELETE /sx/admin/cache/{namespace}`: delete every cache entry in a
+//!   namespace, such as `module:billing` for a module's cache.
+//! - `GET /sx/admin/jobs`: scheduled jobs with their last and next run.
+//! - `GET /sx/admin/dead-letters`: events listeners failed to handle.
+//! - `POST /sx/admin/dead-letters/{id}/redrive`: deliver one again, and
+//!   `POST /sx/admin/dead-letters/redrive` every one.
+//!
+//! The API requires `ServerConfig::auth`: every request needs a valid token
+//! carrying `ServerConfig::admin_role`, `Owner` by default, or is refused
+//! with `401` or `403`. With an [audit log](crate::audit), every request but
+//! `GET` is recorded, refused ones included.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use axum::extract::{Path, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use crate::audit::{self, AuditLog};
+use crate::auth;
+use crate::cache::{CacheProvider, ScopedCache};
+use crate::events::EventRegistry;
+use crate::functions::FunctionRegistry;
+use crate::health::HealthChecks;
+use crate::scheduler::Scheduler;
+use crate::server::ModuleFailure;
+use crate::error::{Error, Result};
+
+/// Names of the modules disabled through the admin API
+#[derive(Clone, Default)]
+pub(crate) struct DisabledModules {
+    names: Arc<RwLock<HashSet<String>>>,
+}
+
+impl DisabledModules {
+    pub(crate) fn contains(&self, module: &str) -> bool {
+        self.names.read().unwrap_or_else(|poisoned| poisoned.into_inner()).contains(module)
+    }
+
+    /// Disable or enable `module`; returns whether that changed anything
+    pub(crate) fn set(&self, module: &str, disabled: bool) -> bool {
+        let mut names = self.names.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if disabled {
+            names.insert(module.to_string())
+        } else {
+            names.remove(module)
+        }
+    }
+}
+
+/// Everything the admin API reads and manages
+#[derive(Clone)]
+pub(crate) struct Admin {
+    pub(crate) functions: FunctionRegistry,
+    pub(crate) events: EventRegistry,
+    pub(crate) cache: Arc<dyn CacheProvider>,
+    pub(crate) health: HealthChecks,
+    pub(crate) scheduler: Arc<Scheduler>,
+    /// Modules loaded when the server was built
+    pub(crate) modules: Arc<Vec<String>>,
+    pub(crate) failed_modules: Arc<Vec<ModuleFailure>>,
+    pub(crate) disabled: DisabledModules,
+    pub(crate) audit: Option<Arc<dyn AuditLog>>,
+}
+
+/// Router exposing the admin API to callers holding `role`
+pub(crate) fn router(admin: Admin, role: String) -> Router {
+    let audit_log = admin.audit.clone();
+    let router = Router::new()
+        .route("/sx/admin/functions", get(functions_handler))
+        .route("/sx/admin/functions/stats", get(function_stats_handler))
+        .route("/sx/admin/events", get(events_handler))
+        .route("/sx/admin/modules", get(modules_handler))
+        .route("/sx/admin/modules/:name/disable", post(disable_handler))
+        .route("/sx/admin/modules/:name/enable", post(enable_handler))
+        .route("/sx/admin/cache", get(cache_handler))
+        .route("/sx/admin/cache/:namespace", delete(flush_handler))
+        .route("/sx/admin/jobs", get(jobs_handler))
+        .route("/sx/admin/dead-letters", get(dead_letters_handler))
+        .route("/sx/admin/dead-letters/redrive", post(redrive_all_handler))
+        .route("/sx/admin/dead-letters/:id/redrive", post(redrive_handler))
+        .with_state(admin);
+    let router = auth::require_role(router, role);
+    // Outside the role check, so refused requests are recorded too
+    match audit_log {
+        Some(log) => router.layer(middleware::from_fn_with_state(log, audit::audit_admin_request)),
+        None => router,
+    }
+}
+
+/// Answer `503 Service Unavailable` for the routes of `module` while it is
+/// disabled
+pub(crate) fn guard_routes(router: Router, module: &str, disabled: DisabledModules) -> Router {
+    router.layer(middleware::from_fn_with_state((module.to_string(), disabled), check_enabled))
+}
+
+async fn check_enabled(
+    State((module, disabled)): State<(String, DisabledModules)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if disabled.contains(&module) {
+        return failure(Error::ModuleDisabled(module));
+    }
+    next.run(request).await
+}
+
+async fn functions_handler(State(admin): State<Admin>) -> Json<Value> {
+    let mut names = admin.functions.list();
+    names.sort();
+    let functions: Vec<Value> = names
+        .into_iter()
+        .map(|name| {
+            json!({
+                "signature": admin.functions.signature(&name),
+                "deprecated": admin.functions.deprecation(&name),
+                "streaming": admin.functions.get_stream(&name).is_some(),
+                "name": name,
+            })
+        })
+        .collect();
+    let aliases: HashMap<String, String> = admin.functions.aliases().into_iter().collect();
+    Json(json!({ "functions": functions, "aliases": aliases }))
+}
+
+async fn function_stats_handler(State(admin): State<Admin>) -> Json<Value> {
+    Json(json!({ "functions": admin.functions.stats() }))
+}
+
+async fn events_handler(State(admin): State<Admin>) -> Json<Value> {
+    let mut patterns = admin.events.patterns().await;
+    patterns.sort();
+    Json(json!({ "patterns": patterns, "queue": admin.events.queue_stats() }))
+}
+
+async fn modules_handler(State(admin): State<Admin>) -> Json<Value> {
+    let modules: Vec<Value> = admin
+        .health
+        .modules()
+        .await
+        .into_iter()
+        .map(|health| {
+            json!({
+                "enabled": !admin.disabled.contains(&health.module),
+                "module": health.module,
+                "healthy": health.healthy,
+                "error": health.error,
+            })
+        })
+        .collect();
+    Json(json!({ "modules": modules, "failed": admin.failed_modules.as_slice() }))
+}
+
+async fn disable_handler(State(admin): State<Admin>, Path(name): Path<String>) -> Response {
+    toggle(&admin, &name, true)
+}
+
+async fn enable_handler(State(admin): State<Admin>, Path(name): Path<String>) -> Response {
+    toggle(&admin, &name, false)
+}
+
+fn toggle(admin: &Admin, name: &str, disabled: bool) -> Response {
+    if !admin.modules.iter().any(|module| module == name) {
+        return failure(Error::NotFound(format!("module '{}'", name)));
+    }
+    if admin.disabled.set(name, disabled) {
+        tracing::info!(module = %name, disabled, "module toggled through the admin API");
+    }
+    Json(json!({ "module": name, "enabled": !disabled })).into_response()
+}
+
+async fn cache_handler(State(admin): State<Admin>) -> Json<Value> {
+    let stats = admin.cache.stats();
+    // Keyed by bucket, such as `{"no_expiry": 3, "under_minute": 1, ...}`
+    let ttl_histogram = admin.cache.ttl_histogram().await.map(|histogram| {
+        histogram
+            .into_iter()
+            .map(|(bucket, count)| (json!(bucket).as_str().unwrap_or_default().to_string(), json!(count)))
+            .collect::<serde_json::Map<_, _>>()
+    });
+    Json(json!({
+        "backend": admin.cache.backend_info(),
+        "stats": stats,
+        "hit_ratio": stats.and_then(|stats| stats.hit_ratio()),
+        "ttl_histogram": ttl_histogram,
+    }))
+}
+
+async fn flush_handler(State(admin): State<Admin>, Path(namespace): Path<String>) -> Response {
+    match ScopedCache::new(admin.cache.clone(), &namespace).clear_namespace().await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => failure(err),
+    }
+}
+
+async fn jobs_handler(State(admin): State<Admin>) -> Json<Value> {
+    Json(json!(admin.scheduler.schedules().await))
+}
+
+async fn dead_letters_handler(State(admin): State<Admin>) -> Response {
+    match admin.events.dead_letters().await {
+        Ok(letters) => Json(json!(letters)).into_response(),
+        Err(err) => failure(err),
+    }
+}
+
+async fn redrive_handler(State(admin): State<Admin>, Path(id): Path<String>) -> Response {
+    match admin.events.redrive(&id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => failure(err),
+    }
+}
+
+async fn redrive_all_handler(State(admin): State<Admin>) -> Response {
+    match redrive_all(&admin.events).await {
+        Ok(report) => Json(report).into_response(),
+        Err(err) => failure(err),
+    }
+}
+
+/// Redrive every dead letter, reporting those that failed again
+async fn redrive_all(events: &EventRegistry) -> Result<Value> {
+    let mut redriven = 0;
+    let mut failed = Vec::new();
+    for letter in events.dead_letters().await? {
+        match events.redrive(&letter.id).await {
+            Ok(()) => redriven += 1,
+            Err(err) => failed.push(json!({ "id": letter.id, "error": err.to_string() })),
+        }
+    }
+    Ok(json!({ "redriven": redriven, "failed": failed }))
+}
+
+fn failure(err: Error) -> Response {
+    let status = match err {
+        Error::NotFound(_) => StatusCode::NOT_FOUND,
+        Error::ModuleDisabled(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(json!({ "error": err.to_string() }))).into_response()
+}
@@ -0,0 +1,316 @@
+//! Audit log of who did what, for compliance
+//!
+//! With an [`AuditLog`] set through `SurrealX::with_audit_log`, or
+//! `ServerConfig::audit_log` for [`SurrealAuditLog`], the server records an
+//! [`AuditEntry`] for:
+//!
+//! - every call to a module's function, with the caller, tenant, request ID,
+//!   outcome and arguments, redacted by the server's
+//!   [`ArgumentRedactor`](crate::stats::ArgumentRedactor);
+//! - every admin API request that changes something, that is every request
+//!   but `GET`, with the caller and the response status, including those
+//!   refused for lack of the admin role;
+//! - every event matching one of `ServerConfig::audit_events`, with its
+//!   table, record, data and tenant. Events carry no caller, so their actor
+//!   is unset.
+//!
+//! Entries are recorded before the call returns or the response is sent. A
+//! log that fails to record an entry is reported at `error` but does not
+//! fail what was being audited.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use async_trait::async_trait;
+use axum::extract::{Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::auth::Identity;
+use crate::context::FunctionContext;
+use crate::db::Database;
+use crate::events::{Event, EventListener};
+use crate::functions::FunctionHandler;
+use crate::stats::ArgumentRedactor;
+use crate::error::Result;
+
+/// What an audit entry records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    /// A call to a module's function
+    FunctionCall,
+    /// A request to the admin API
+    AdminAction,
+    /// An event matching `ServerConfig::audit_events`
+    Event,
+}
+
+/// Whether an audited action succeeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// One action recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the action finished, in milliseconds since the Unix epoch
+    pub timestamp: i64,
+    pub kind: AuditKind,
+    /// Function name, `METHOD /path` of an admin request, or event pattern
+    pub action: String,
+    /// Record of the caller's token, such as `user:tobie`, or else its
+    /// `sub` claim
+    #[serde(default)]
+    pub actor: Option<String>,
+    /// Roles of the caller's token
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+    pub outcome: AuditOutcome,
+    /// Why the action failed
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Arguments of a call, status of an admin request, or the event
+    #[serde(default)]
+    pub details: Value,
+}
+
+impl AuditEntry {
+    fn new(kind: AuditKind, action: impl Into<String>, identity: Option<&Identity>) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            kind,
+            action: action.into(),
+            actor: identity.and_then(Identity::subject).map(str::to_string),
+            roles: identity.map(|identity| identity.roles.clone()).unwrap_or_default(),
+            tenant: None,
+            request_id: None,
+            outcome: AuditOutcome::Success,
+            error: None,
+            details: Value::Null,
+        }
+    }
+
+    fn failed(mut self, error: impl Into<String>) -> Self {
+        self.outcome = AuditOutcome::Failure;
+        self.error = Some(error.into());
+        self
+    }
+}
+
+/// Storage for audit entries
+#[async_trait]
+pub trait AuditLog: Send + Sync {
+    /// Record an entry
+    async fn record(&self, entry: &AuditEntry) -> Result<()>;
+
+    /// Read entries with a timestamp at or after `from_timestamp`, in
+    /// milliseconds, oldest first
+    async fn read_since(&self, from_timestamp: i64) -> Result<Vec<AuditEntry>>;
+
+    /// Write buffered entries to durable storage
+    ///
+    /// Called by `BuiltSurrealX::shutdown()`; logs that write each entry as
+    /// it is recorded need not override it.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Record `entry`, reporting rather than returning a failure
+async fn record(log: &dyn AuditLog, entry: AuditEntry) {
+    if let Err(err) = log.record(&entry).await {
+        tracing::error!(error = %err, action = %entry.action, "failed to record an audit entry");
+    }
+}
+
+/// Audit log backed by a table in the embedded database
+pub struct SurrealAuditLog {
+    db: Database,
+    table: String,
+}
+
+impl SurrealAuditLog {
+    /// Record entries in the `sx_audit_log` table
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            table: "sx_audit_log".to_string(),
+        }
+    }
+
+    /// Record entries in a different table
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+}
+
+#[async_trait]
+impl AuditLog for SurrealAuditLog {
+    async fn record(&self, entry: &AuditEntry) -> Result<()> {
+        self.db
+            .query("CREATE type::table($table) CONTENT $entry RETURN NONE")
+            .bind(("table", self.table.clone()))
+            .bind(("entry", serde_json::to_value(entry)?))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn read_since(&self, from_timestamp: i64) -> Result<Vec<AuditEntry>> {
+        let mut response = self
+            .db
+            .query("SELECT * OMIT id FROM type::table($table) WHERE timestamp >= $from ORDER BY timestamp")
+            .bind(("table", self.table.clone()))
+            .bind(("from", from_timestamp))
+            .await?;
+        let entries = response.take::<surrealdb::Value>(0)?.into_inner().into_json();
+        Ok(serde_json::from_value(entries)?)
+    }
+}
+
+/// Audit log appending JSON lines to a file
+pub struct FileAuditLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileAuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditLog for FileAuditLog {
+    async fn record(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        Ok(())
+    }
+
+    async fn read_since(&self, from_timestamp: i64) -> Result<Vec<AuditEntry>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut entries = Vec::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let entry: AuditEntry = serde_json::from_str(line)?;
+            if entry.timestamp >= from_timestamp {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        // Taking the lock waits for records in progress
+        let _guard = self.lock.lock().await;
+        match tokio::fs::File::open(&self.path).await {
+            Ok(file) => Ok(file.sync_all().await?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Function handler that records its calls in the audit log
+pub(crate) struct AuditedFunction {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) log: Arc<dyn AuditLog>,
+    pub(crate) redactor: Arc<dyn ArgumentRedactor>,
+    pub(crate) function: String,
+}
+
+impl AuditedFunction {
+    async fn record(&self, mut entry: AuditEntry, args: &[Value], result: &Result<Value>) {
+        entry.details = json!({ "args": self.redactor.redact(&self.function, args) });
+        if let Err(err) = result {
+            entry = entry.failed(err.to_string());
+        }
+        record(self.log.as_ref(), entry).await;
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for AuditedFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        let entry = AuditEntry::new(AuditKind::FunctionCall, &self.function, None);
+        let result = self.inner.call(args.clone()).await;
+        self.record(entry, &args, &result).await;
+        result
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let mut entry = AuditEntry::new(AuditKind::FunctionCall, &self.function, context.identity());
+        entry.tenant = context.tenant().map(|tenant| tenant.id().to_string());
+        entry.request_id = Some(context.request_id().to_string());
+        let result = self.inner.call_with_context(context, args.clone()).await;
+        self.record(entry, &args, &result).await;
+        result
+    }
+}
+
+/// Listener recording the events of `ServerConfig::audit_events`
+pub(crate) struct AuditListener {
+    pub(crate) log: Arc<dyn AuditLog>,
+}
+
+#[async_trait]
+impl EventListener for AuditListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditKind::Event, event.pattern(), None);
+        entry.tenant = event.tenant.clone();
+        entry.details = serde_json::to_value(&event)?;
+        record(self.log.as_ref(), entry).await;
+        Ok(())
+    }
+}
+
+/// Record the admin API requests that change something
+pub(crate) async fn audit_admin_request(State(log): State<Arc<dyn AuditLog>>, request: Request, next: Next) -> Response {
+    if request.method() == Method::GET {
+        return next.run(request).await;
+    }
+    let action = format!("{} {}", request.method(), request.uri().path());
+    let mut entry = AuditEntry::new(AuditKind::AdminAction, action, request.extensions().get::<Identity>());
+    entry.request_id = request
+        .headers()
+        .get(crate::telemetry::REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+    let status = response.status();
+    entry.details = json!({ "status": status.as_u16() });
+    if !status.is_success() {
+        entry = entry.failed(status.to_string());
+    }
+    record(log.as_ref(), entry).await;
+    response
+}
@@ -0,0 +1,335 @@
+//! Authentication of HTTP requests with SurrealDB tokens
+//!
+//! With `ServerConfig::auth` set, module routes and `/sql`, when served, read
+//! a bearer token from the `Authorization` header and verify it as SurrealDB
+//! does for access methods defined `WITH JWT`: the signature against the
+//! configured key, the expiry, and that its `NS` and `DB` claims, when
+//! present, name the server's namespace and database. With a
+//! [`TenantResolver`](crate::TenantResolver), `NS` must instead name the
+//! namespace of the tenant the request resolves to. A valid token makes the
+//! caller's [`Identity`] available to route handlers as an extractor and to
+//! functions through
+//! [`FunctionContext::identity`](crate::FunctionContext::identity).
+//!
+//! ```rust,ignore
+//! async fn profile(identity: Identity) -> String {
+//!     format!("signed in as {:?}", identity.record)
+//! }
+//! ```
+//!
+//! Requests with an invalid token are rejected with `401 Unauthorized`, as
+//! are requests without one when `AuthConfig::required` is set. WebSocket
+//! upgrades and event streams may pass the token in an `access_token` query
+//! parameter instead.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use axum::extract::{FromRequestParts, Query, Request, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use async_trait::async_trait;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::server::AuthConfig;
+use crate::error::{Error, Result};
+
+/// The authenticated caller of a request, from the claims of its token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    /// Namespace the token was issued for (`NS`)
+    pub namespace: Option<String>,
+    /// Database the token was issued for (`DB`)
+    pub database: Option<String>,
+    /// Access method the token was issued by (`AC`; `SC`, the scope, in
+    /// SurrealDB 1.x tokens)
+    pub access: Option<String>,
+    /// Record the token was issued for, such as `user:tobie` (`ID`)
+    pub record: Option<String>,
+    /// Roles granted to a system user, such as `Editor` (`RL`)
+    pub roles: Vec<String>,
+    /// Every claim of the token
+    pub claims: Value,
+}
+
+impl Identity {
+    fn from_claims(claims: Value) -> Self {
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        Self {
+            namespace: claim("NS"),
+            database: claim("DB"),
+            access: claim("AC").or_else(|| claim("SC")),
+            record: claim("ID"),
+            roles: claims
+                .get("RL")
+                .and_then(Value::as_array)
+                .map(|roles| roles.iter().filter_map(Value::as_str).map(str::to_string).collect())
+                .unwrap_or_default(),
+            claims,
+        }
+    }
+
+    /// Who the caller is: the record the token was issued for, or else the
+    /// user its `sub` claim names, such as a system user
+    pub fn subject(&self) -> Option<&str> {
+        self.record
+            .as_deref()
+            .or_else(|| self.claims.get("sub").and_then(Value::as_str))
+    }
+
+    /// Whether the caller holds `role`
+    ///
+    /// Roles come from the `RL` claim, compared case-insensitively like
+    /// SurrealDB's own roles.
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|held| held.eq_ignore_ascii_case(role))
+    }
+
+    /// Whether the token was issued by the access method `access`
+    pub fn signed_in_with(&self, access: &str) -> bool {
+        self.access.as_deref() == Some(access)
+    }
+}
+
+/// Extracts the caller's identity, rejecting unauthenticated requests
+///
+/// Use `Option<Identity>` on routes that also serve anonymous callers.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Identity {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> std::result::Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Identity>()
+            .cloned()
+            .ok_or_else(|| unauthorized("authentication required"))
+    }
+}
+
+/// Checks tokens against `AuthConfig`
+#[derive(Clone)]
+pub(crate) struct Verifier {
+    inner: Arc<VerifierInner>,
+}
+
+struct VerifierInner {
+    key: DecodingKey,
+    validation: Validation,
+    namespace: String,
+    database: String,
+    /// Whether the namespace is checked against the request's tenant
+    /// instead, once it is resolved
+    tenants: bool,
+    required: bool,
+}
+
+impl Verifier {
+    /// Accept tokens for `namespace` and `database` as `config` describes
+    ///
+    /// With `tenants`, tokens may name any namespace, which the tenant
+    /// resolution checks against the request's tenant.
+    pub(crate) fn new(config: &AuthConfig, namespace: &str, database: &str, tenants: bool) -> Result<Self> {
+        let algorithm = Algorithm::from_str(&config.algorithm)
+            .map_err(|_| Error::Config(format!("unknown auth algorithm '{}'", config.algorithm)))?;
+        let key = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(DecodingKey::from_secret(config.key.as_bytes())),
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => {
+                DecodingKey::from_rsa_pem(config.key.as_bytes())
+            }
+            Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(config.key.as_bytes()),
+            Algorithm::EdDSA => DecodingKey::from_ed_pem(config.key.as_bytes()),
+        }
+        .map_err(|err| Error::Config(format!("invalid auth key: {}", err)))?;
+
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &config.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        Ok(Self {
+            inner: Arc::new(VerifierInner {
+                key,
+                validation,
+                namespace: namespace.to_string(),
+                database: database.to_string(),
+                tenants,
+                required: config.required,
+            }),
+        })
+    }
+
+    /// Check a token and return the identity it carries
+    pub(crate) fn verify(&self, token: &str) -> Result<Identity> {
+        let inner = &self.inner;
+        let claims = jsonwebtoken::decode::<Value>(token, &inner.key, &inner.validation)
+            .map_err(|err| Error::Unauthorized(format!("invalid token: {}", err)))?
+            .claims;
+        let identity = Identity::from_claims(claims);
+
+        if !inner.tenants && identity.namespace.as_ref().is_some_and(|namespace| *namespace != inner.namespace) {
+            return Err(Error::Unauthorized("token was issued for another namespace".to_string()));
+        }
+        if identity.database.as_ref().is_some_and(|database| *database != inner.database) {
+            return Err(Error::Unauthorized("token was issued for another database".to_string()));
+        }
+        Ok(identity)
+    }
+}
+
+/// Authenticate every request `router` serves
+pub(crate) fn authenticate(router: Router, verifier: Verifier) -> Router {
+    router.layer(middleware::from_fn_with_state(verifier, check_request))
+}
+
+async fn check_request(State(verifier): State<Verifier>, mut request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| query_token(&request));
+
+    match token {
+        Some(token) => match verifier.verify(token.trim()) {
+            Ok(identity) => {
+                request.extensions_mut().insert(identity);
+            }
+            Err(err) => return unauthorized(&err.to_string()),
+        },
+        None if verifier.inner.required => return unauthorized("authentication required"),
+        None => {}
+    }
+    next.run(request).await
+}
+
+/// The `access_token` query parameter of a WebSocket upgrade or an event
+/// stream request, since browsers cannot set headers on those
+fn query_token(request: &Request) -> Option<String> {
+    let value_of = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+    let upgrade = value_of(header::UPGRADE).is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let event_stream = value_of(header::ACCEPT).is_some_and(|value| value.contains("text/event-stream"));
+    if !upgrade && !event_stream {
+        return None;
+    }
+    let Query(mut query) = Query::<HashMap<String, String>>::try_from_uri(request.uri()).ok()?;
+    query.remove("access_token")
+}
+
+/// Refuse requests to `router` from callers without `role`
+pub(crate) fn require_role(router: Router, role: String) -> Router {
+    router.layer(middleware::from_fn_with_state(role, check_role))
+}
+
+async fn check_role(State(role): State<String>, request: Request, next: Next) -> Response {
+    match request.extensions().get::<Identity>() {
+        Some(identity) if identity.has_role(&role) => next.run(request).await,
+        Some(_) => (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": format!("this endpoint requires the '{}' role", role) })),
+        )
+            .into_response(),
+        None => unauthorized("authentication required"),
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(json!({ "error": message })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    const KEY: &str = "secret";
+
+    fn token(claims: Value) -> String {
+        let mut claims = claims;
+        claims["exp"] = json!(chrono::Utc::now().timestamp() + 3600);
+        jsonwebtoken::encode(&Header::new(Algorithm::HS512), &claims, &EncodingKey::from_secret(KEY.as_bytes())).unwrap()
+    }
+
+    fn verifier() -> Verifier {
+        Verifier::new(&AuthConfig::new("HS512", KEY), "shop", "main", false).unwrap()
+    }
+
+    #[test]
+    fn accepts_tokens_for_the_server_namespace_and_database() {
+        let identity = verifier()
+            .verify(&token(json!({ "NS": "shop", "DB": "main", "AC": "users", "ID": "user:tobie" })))
+            .unwrap();
+        assert_eq!(identity.record.as_deref(), Some("user:tobie"));
+        assert!(identity.signed_in_with("users"));
+        assert!(verifier().verify(&token(json!({ "ID": "user:tobie" }))).is_ok());
+    }
+
+    #[test]
+    fn rejects_tokens_for_another_namespace_or_database() {
+        let other_namespace = verifier().verify(&token(json!({ "NS": "blog", "DB": "main" })));
+        assert!(matches!(other_namespace, Err(Error::Unauthorized(_))));
+        let other_database = verifier().verify(&token(json!({ "NS": "shop", "DB": "archive" })));
+        assert!(matches!(other_database, Err(Error::Unauthorized(_))));
+    }
+
+    #[test]
+    fn rejects_tokens_signed_with_another_key() {
+        let claims = json!({ "NS": "shop", "exp": chrono::Utc::now().timestamp() + 3600 });
+        let forged = jsonwebtoken::encode(&Header::new(Algorithm::HS512), &claims, &EncodingKey::from_secret(b"guess")).unwrap();
+        assert!(matches!(verifier().verify(&forged), Err(Error::Unauthorized(_))));
+    }
+
+    #[test]
+    fn leaves_the_namespace_to_tenant_resolution_with_tenants() {
+        let tenants = Verifier::new(&AuthConfig::new("HS512", KEY), "shop", "main", true).unwrap();
+        let identity = tenants.verify(&token(json!({ "NS": "acme", "DB": "main" }))).unwrap();
+        assert_eq!(identity.namespace.as_deref(), Some("acme"));
+        let other_database = tenants.verify(&token(json!({ "NS": "acme", "DB": "archive" })));
+        assert!(matches!(other_database, Err(Error::Unauthorized(_))));
+    }
+
+    #[test]
+    fn compares_roles_case_insensitively() {
+        let identity = Identity::from_claims(json!({ "RL": ["Editor", "viewer"] }));
+        assert!(identity.has_role("editor"));
+        assert!(identity.has_role("VIEWER"));
+        assert!(!identity.has_role("Owner"));
+        assert!(!Identity::from_claims(json!({})).has_role("editor"));
+    }
+
+    fn request(uri: &str, headers: &[(header::HeaderName, &str)]) -> Request {
+        let mut request = axum::http::Request::builder().uri(uri);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        request.body(axum::body::Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn takes_the_query_token_of_websocket_upgrades_and_event_streams() {
+        let upgrade = request("/sx/events?access_token=abc", &[(header::UPGRADE, "WebSocket")]);
+        assert_eq!(query_token(&upgrade).as_deref(), Some("abc"));
+        let stream = request("/sx/events?access_token=abc", &[(header::ACCEPT, "text/event-stream")]);
+        assert_eq!(query_token(&stream).as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn ignores_the_query_token_of_other_requests() {
+        let plain = request("/api/orders?access_token=abc", &[(header::ACCEPT, "application/json")]);
+        assert_eq!(query_token(&plain), None);
+    }
+}
@@ -0,0 +1,214 @@
+//! Batched and debounced delivery of events to a listener
+//!
+//! A [`BatchingListener`] collects the events matching its pattern and hands
+//! them to a [`BatchEventListener`] as one `Vec<Event>`, so a noisy table,
+//! such as one receiving metrics, does not run the handler once per row.
+//! Events waiting in a batch are not delivered if the server stops first.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::time::Instant;
+use tracing::Instrument;
+use crate::context::{self, Context};
+use crate::events::{Event, EventListener};
+use crate::error::Result;
+
+/// Listener receiving events in batches; see [`BatchOptions`]
+#[async_trait]
+pub trait BatchEventListener: Send + Sync {
+    /// Handle a batch of events, oldest first
+    async fn on_batch(&self, events: Vec<Event>) -> Result<()>;
+}
+
+/// Batch listener using async closures
+pub struct SimpleBatchListener<F>
+where
+    F: Fn(Vec<Event>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>> + Send + Sync,
+{
+    handler: F,
+}
+
+impl<F> SimpleBatchListener<F>
+where
+    F: Fn(Vec<Event>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>> + Send + Sync,
+{
+    pub fn new(handler: F) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl<F> BatchEventListener for SimpleBatchListener<F>
+where
+    F: Fn(Vec<Event>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + Send>> + Send + Sync,
+{
+    async fn on_batch(&self, events: Vec<Event>) -> Result<()> {
+        (self.handler)(events).await
+    }
+}
+
+/// When a batch of events is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    max: Option<usize>,
+    window: Option<Duration>,
+    debounce: Option<Duration>,
+}
+
+impl BatchOptions {
+    /// Deliver once `max` events are waiting or `window` has passed since
+    /// the first of them arrived, whichever comes first
+    pub fn batch(max: usize, window: Duration) -> Self {
+        Self {
+            max: Some(max.max(1)),
+            window: Some(window),
+            debounce: None,
+        }
+    }
+
+    /// Deliver once no event has arrived for `quiet`
+    ///
+    /// A pattern that never goes quiet for that long is never delivered;
+    /// combine with [`batch`](Self::batch) through
+    /// [`with_debounce`](Self::with_debounce) to bound the wait.
+    pub fn debounce(quiet: Duration) -> Self {
+        Self {
+            max: None,
+            window: None,
+            debounce: Some(quiet),
+        }
+    }
+
+    /// Also deliver once no event has arrived for `quiet`
+    pub fn with_debounce(mut self, quiet: Duration) -> Self {
+        self.debounce = Some(quiet);
+        self
+    }
+
+    /// When the batch started at `started`, last added to at `last`, is due
+    fn due(&self, started: Instant, last: Instant) -> Option<Instant> {
+        let window = self.window.map(|window| started + window);
+        let debounce = self.debounce.map(|quiet| last + quiet);
+        match (window, debounce) {
+            (Some(window), Some(debounce)) => Some(window.min(debounce)),
+            (due, None) | (None, due) => due,
+        }
+    }
+}
+
+/// Events waiting to be delivered together
+#[derive(Default)]
+struct Pending {
+    events: Vec<Event>,
+    started: Option<Instant>,
+    last: Option<Instant>,
+    /// Incremented whenever a batch is taken, so timers of earlier batches
+    /// stop
+    generation: u64,
+}
+
+impl Pending {
+    fn take(&mut self) -> Vec<Event> {
+        self.generation += 1;
+        self.started = None;
+        self.last = None;
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// Listener wrapper collecting events into batches for a
+/// [`BatchEventListener`]
+///
+/// A batch that reaches its maximum size is delivered by the emit that
+/// filled it, which fails if the batch listener does, so a flood of events
+/// slows down the emitter instead of piling up. A batch delivered on a
+/// timer runs on its own task, with the same module context, and its
+/// failure is logged.
+pub struct BatchingListener {
+    inner: Arc<dyn BatchEventListener>,
+    options: BatchOptions,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl BatchingListener {
+    pub fn new(inner: Arc<dyn BatchEventListener>, options: BatchOptions) -> Self {
+        Self {
+            inner,
+            options,
+            pending: Arc::new(Mutex::new(Pending::default())),
+        }
+    }
+
+    /// Deliver the batch of `generation` once it is due, unless it was
+    /// delivered otherwise first
+    fn schedule(&self, generation: u64) {
+        let inner = self.inner.clone();
+        let options = self.options;
+        let pending = self.pending.clone();
+        let current = Context::current();
+
+        tokio::spawn(
+            async move {
+                let events = loop {
+                    let due = {
+                        let mut pending = pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                        let (Some(started), Some(last)) = (pending.started, pending.last) else {
+                            return;
+                        };
+                        if pending.generation != generation {
+                            return;
+                        }
+                        match options.due(started, last) {
+                            Some(due) if due <= Instant::now() => break pending.take(),
+                            Some(due) => due,
+                            None => return,
+                        }
+                    };
+                    tokio::time::sleep_until(due).await;
+                };
+
+                let count = events.len();
+                let delivery = inner.on_batch(events);
+                let result = match current {
+                    Some(current) => context::scope(current, delivery).await,
+                    None => delivery.await,
+                };
+                if let Err(err) = result {
+                    tracing::warn!(events = count, error = %err, "batch event listener failed");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+#[async_trait]
+impl EventListener for BatchingListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let now = Instant::now();
+        let full = {
+            let mut pending = self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            pending.events.push(event);
+            pending.last = Some(now);
+            let first = pending.started.is_none();
+            if first {
+                pending.started = Some(now);
+            }
+
+            if self.options.max.is_some_and(|max| pending.events.len() >= max) {
+                Some(pending.take())
+            } else {
+                if first {
+                    self.schedule(pending.generation);
+                }
+                None
+            }
+        };
+
+        match full {
+            Some(events) => self.inner.on_batch(events).await,
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Bridges between the event registry and external messaging systems
+
+use async_trait::async_trait;
+use crate::events::EventRegistry;
+use crate::error::Result;
+
+/// Connects the event registry to an external messaging system
+///
+/// Bridges added with [`SurrealX::with_bridge`](crate::SurrealX::with_bridge)
+/// are started by [`BuiltSurrealX::start`](crate::server::BuiltSurrealX::start)
+/// once the modules have started, and stopped at shutdown once in-flight
+/// handlers have finished. A bridge typically registers listeners that
+/// forward matching events out and emits the messages it receives.
+#[async_trait]
+pub trait EventBridge: Send + Sync {
+    /// Name used in logs and errors, such as `kafka`
+    fn name(&self) -> &str;
+
+    /// Start forwarding events between `events` and the external system
+    ///
+    /// A failure stops the server from starting.
+    async fn start(&self, events: EventRegistry) -> Result<()>;
+
+    /// Stop receiving messages and send anything still buffered
+    async fn stop(&self);
+}
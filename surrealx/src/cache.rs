@@ -2,10 +2,88 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "disk-cache")]
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
 use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::future::BoxFuture;
 use tokio::sync::RwLock;
-use serde_json::Value;
-use crate::error::Result;
+use tokio::task::JoinHandle;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::tenancy::Tenant;
+use crate::error::{Error, Result};
+
+/// Description of the backend behind a cache provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheBackendInfo {
+    /// Backend kind (e.g., "memory", "redis")
+    pub kind: String,
+    /// Backend-specific configuration, with secrets redacted
+    pub details: Value,
+}
+
+/// Snapshot of a cache provider's counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// Reads that found a live entry
+    pub hits: u64,
+    /// Reads that found nothing or an expired entry
+    pub misses: u64,
+    /// Writes
+    pub sets: u64,
+    /// Entries removed by the provider because they expired or exceeded a size cap
+    pub evictions: u64,
+    /// Current number of entries, if the provider can count them cheaply
+    pub entries: Option<u64>,
+}
+
+impl CacheStats {
+    /// Fraction of reads that were hits, or `None` before the first read
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let reads = self.hits + self.misses;
+        (reads > 0).then(|| self.hits as f64 / reads as f64)
+    }
+}
+
+#[derive(Default)]
+struct StatsCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    sets: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl StatsCounters {
+    fn read(&self, found: bool) {
+        let counter = if found { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn set(&self) {
+        self.sets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn evicted(&self, count: usize) {
+        self.evictions.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, entries: Option<u64>) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            sets: self.sets.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            entries,
+        }
+    }
+}
 
 /// Cache provider trait
 #[async_trait]
@@ -24,49 +102,522 @@ pub trait CacheProvider: Send + Sync {
 
     /// Clear all cache entries
     async fn clear(&self) -> Result<()>;
+
+    /// Delete every entry whose key starts with `prefix`
+    ///
+    /// Providers that cannot enumerate keys return `Error::Cache`.
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        let _ = prefix;
+        Err(Error::Cache(format!(
+            "{} cache provider does not support clearing by prefix",
+            self.backend_info().kind
+        )))
+    }
+
+    /// Get several values, returned in the same order as `keys`
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(values)
+    }
+
+    /// Set several values with the same optional TTL (seconds)
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        for (key, value) in entries {
+            self.set(&key, value, ttl).await?;
+        }
+        Ok(())
+    }
+
+    /// Delete several values
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        for key in keys {
+            self.delete(key).await?;
+        }
+        Ok(())
+    }
+
+    /// Add `delta` to the integer stored at `key` and return the new value
+    ///
+    /// A missing key counts as 0. The default implementation reads and then
+    /// writes the value, so concurrent increments can be lost and the
+    /// entry's TTL is dropped; providers with a native counter override it.
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        let current = match self.get(key).await? {
+            Some(value) => counter_value(key, &value)?,
+            None => 0,
+        };
+        let next = add_to_counter(key, current, delta)?;
+        self.set(key, Value::from(next), None).await?;
+        Ok(next)
+    }
+
+    /// Subtract `delta` from the integer stored at `key` and return the new value
+    async fn decr(&self, key: &str, delta: i64) -> Result<i64> {
+        let delta = delta
+            .checked_neg()
+            .ok_or_else(|| Error::Cache(format!("cannot decrement '{}' by {}", key, delta)))?;
+        self.incr(key, delta).await
+    }
+
+    /// Store a value only if `key` is missing, returning whether it was stored
+    ///
+    /// The default implementation checks and then writes, so two concurrent
+    /// callers may both store; providers with a native primitive override it.
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        if self.exists(key).await? {
+            return Ok(false);
+        }
+        self.set(key, value, ttl).await?;
+        Ok(true)
+    }
+
+    /// Get a value, or run `load` and store its result if the key is missing
+    ///
+    /// The default implementation does not coordinate concurrent callers, so
+    /// each of them may run its own `load`. Providers that can deduplicate
+    /// loads, such as `MemoryCacheProvider`, run one and let the other
+    /// callers wait for its result.
+    async fn get_or_set(&self, key: &str, ttl: Option<u64>, load: BoxFuture<'_, Result<Value>>) -> Result<Value> {
+        if let Some(value) = self.get(key).await? {
+            return Ok(value);
+        }
+
+        let value = load.await?;
+        self.set(key, value.clone(), ttl).await?;
+        Ok(value)
+    }
+
+    /// Describe the backend and its salient configuration
+    fn backend_info(&self) -> CacheBackendInfo {
+        CacheBackendInfo {
+            kind: "custom".to_string(),
+            details: Value::Null,
+        }
+    }
+
+    /// Start background maintenance, such as sweeping expired entries
+    ///
+    /// Called once by `SurrealX::build()`. Providers without background work
+    /// return `None`.
+    fn start_maintenance(&self) -> Option<MaintenanceHandle> {
+        None
+    }
+
+    /// Get hit, miss, write and eviction counters
+    ///
+    /// Providers that do not track them return `None`; wrap them in a
+    /// [`StatsCache`] to count at the call site instead.
+    fn stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Count live entries by remaining TTL, in `TtlBucket::ALL` order
+    ///
+    /// Providers that cannot list their entries return `None`.
+    async fn ttl_histogram(&self) -> Option<Vec<(TtlBucket, usize)>> {
+        None
+    }
+
+    /// Write buffered changes to durable storage
+    ///
+    /// Called by `BuiltSurrealX::shutdown()`. Providers that write through
+    /// need not override it.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Background cache maintenance tasks, stopped when dropped
+pub struct MaintenanceHandle {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl MaintenanceHandle {
+    pub fn new(tasks: Vec<JoinHandle<()>>) -> Self {
+        Self { tasks }
+    }
+
+    /// Stop the maintenance tasks
+    pub fn stop(self) {}
+
+    fn merge(handles: impl IntoIterator<Item = Option<MaintenanceHandle>>) -> Option<MaintenanceHandle> {
+        let tasks: Vec<_> = handles
+            .into_iter()
+            .flatten()
+            .flat_map(|mut handle| std::mem::take(&mut handle.tasks))
+            .collect();
+        (!tasks.is_empty()).then(|| MaintenanceHandle::new(tasks))
+    }
+}
+
+impl Drop for MaintenanceHandle {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+fn counter_value(key: &str, value: &Value) -> Result<i64> {
+    value
+        .as_i64()
+        .ok_or_else(|| Error::Cache(format!("value at '{}' is not an integer", key)))
+}
+
+fn add_to_counter(key: &str, current: i64, delta: i64) -> Result<i64> {
+    current
+        .checked_add(delta)
+        .ok_or_else(|| Error::Cache(format!("counter '{}' would overflow", key)))
+}
+
+#[async_trait]
+impl<C: CacheProvider + ?Sized> CacheProvider for Arc<C> {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        (**self).get(key).await
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        (**self).set(key, value, ttl).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        (**self).delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        (**self).exists(key).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        (**self).clear().await
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        (**self).clear_prefix(prefix).await
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        (**self).get_many(keys).await
+    }
+
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        (**self).set_many(entries, ttl).await
+    }
+
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        (**self).delete_many(keys).await
+    }
+
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        (**self).incr(key, delta).await
+    }
+
+    async fn decr(&self, key: &str, delta: i64) -> Result<i64> {
+        (**self).decr(key, delta).await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        (**self).set_if_absent(key, value, ttl).await
+    }
+
+    async fn get_or_set(&self, key: &str, ttl: Option<u64>, load: BoxFuture<'_, Result<Value>>) -> Result<Value> {
+        (**self).get_or_set(key, ttl, load).await
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        (**self).backend_info()
+    }
+
+    fn start_maintenance(&self) -> Option<MaintenanceHandle> {
+        (**self).start_maintenance()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        (**self).stats()
+    }
+
+    async fn ttl_histogram(&self) -> Option<Vec<(TtlBucket, usize)>> {
+        (**self).ttl_histogram().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        (**self).flush().await
+    }
+}
+
+/// How typed cache reads handle values that fail to deserialize
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeErrorPolicy {
+    /// Return the deserialization error
+    #[default]
+    Error,
+    /// Report a miss and leave the entry in place
+    TreatAsMiss,
+    /// Report a miss and delete the entry so it can be recomputed
+    Delete,
+}
+
+/// Typed helpers available on every cache provider
+#[async_trait]
+pub trait CacheExt: CacheProvider {
+    /// Get a value and deserialize it into `T`
+    async fn get_as<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send,
+    {
+        self.get_as_with(key, DecodeErrorPolicy::Error).await
+    }
+
+    /// Get a value and deserialize it into `T`, handling decode failures
+    /// according to `on_decode_error`
+    async fn get_as_with<T>(&self, key: &str, on_decode_error: DecodeErrorPolicy) -> Result<Option<T>>
+    where
+        T: DeserializeOwned + Send,
+    {
+        let Some(value) = self.get(key).await? else {
+            return Ok(None);
+        };
+
+        match serde_json::from_value(value) {
+            Ok(decoded) => Ok(Some(decoded)),
+            Err(err) => match on_decode_error {
+                DecodeErrorPolicy::Error => Err(err.into()),
+                DecodeErrorPolicy::TreatAsMiss => {
+                    tracing::debug!(key, error = %err, "cached value failed to decode; treating as miss");
+                    Ok(None)
+                }
+                DecodeErrorPolicy::Delete => {
+                    tracing::debug!(key, error = %err, "cached value failed to decode; deleting");
+                    self.delete(key).await?;
+                    Ok(None)
+                }
+            },
+        }
+    }
+
+    /// Serialize `value` and store it with optional TTL (seconds)
+    async fn set_as<T>(&self, key: &str, value: &T, ttl: Option<u64>) -> Result<()>
+    where
+        T: Serialize + Sync,
+    {
+        self.set(key, serde_json::to_value(value)?, ttl).await
+    }
+}
+
+impl<C: CacheProvider + ?Sized> CacheExt for C {}
+
+/// Remaining-TTL bucket used by `MemoryCacheProvider::ttl_histogram`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TtlBucket {
+    /// Entries stored without a TTL
+    NoExpiry,
+    /// Expires within a minute
+    UnderMinute,
+    /// Expires within five minutes
+    UnderFiveMinutes,
+    /// Expires within an hour
+    UnderHour,
+    /// Expires within a day
+    UnderDay,
+    /// Expires in a day or more
+    DayOrMore,
+}
+
+impl TtlBucket {
+    /// All buckets, from no expiry to the longest TTLs
+    pub const ALL: [TtlBucket; 6] = [
+        TtlBucket::NoExpiry,
+        TtlBucket::UnderMinute,
+        TtlBucket::UnderFiveMinutes,
+        TtlBucket::UnderHour,
+        TtlBucket::UnderDay,
+        TtlBucket::DayOrMore,
+    ];
+
+    fn for_remaining(seconds: Option<i64>) -> Self {
+        match seconds {
+            None => TtlBucket::NoExpiry,
+            Some(s) if s < 60 => TtlBucket::UnderMinute,
+            Some(s) if s < 300 => TtlBucket::UnderFiveMinutes,
+            Some(s) if s < 3_600 => TtlBucket::UnderHour,
+            Some(s) if s < 86_400 => TtlBucket::UnderDay,
+            Some(_) => TtlBucket::DayOrMore,
+        }
+    }
 }
 
 /// In-memory cache provider using SurrealDB's memory
+///
+/// Expired entries are dropped when read and, once the provider is attached
+/// to a server, by a background sweep every 60 seconds (see
+/// `with_sweep_interval`). With `with_max_entries` the least recently used
+/// entries are evicted once the cap is exceeded, down to a tenth below it.
 #[derive(Clone)]
 pub struct MemoryCacheProvider {
     cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
+    clock: Arc<AtomicU64>,
+    counters: Arc<StatsCounters>,
+    in_flight: Arc<InFlightLoads>,
+    max_entries: Option<usize>,
+    sweep_interval: Duration,
 }
 
+pub(crate) type InFlightLoads = std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>;
+
 struct CacheEntry {
-    value: Value,
+    value: Arc<Value>,
     expires_at: Option<i64>,
+    last_used: AtomicU64,
 }
 
 impl MemoryCacheProvider {
     pub fn new() -> Self {
         Self {
             cache: Arc::new(RwLock::new(HashMap::new())),
+            clock: Arc::new(AtomicU64::new(0)),
+            counters: Arc::new(StatsCounters::default()),
+            in_flight: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            max_entries: None,
+            sweep_interval: Duration::from_secs(60),
         }
     }
 
-    async fn cleanup_expired(&self) {
-        let mut cache = self.cache.write().await;
+    /// Cap the number of entries, evicting the least recently used first
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Set how often the background task sweeps expired entries
+    pub fn with_sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval = interval;
+        self
+    }
+
+    /// Get a shared handle to a cached value without deep-cloning it
+    pub async fn get_arc(&self, key: &str) -> Result<Option<Arc<Value>>> {
+        let value = self.lookup(key).await;
+        self.counters.read(value.is_some());
+        Ok(value)
+    }
+
+    /// Count live entries by remaining TTL
+    ///
+    /// Every bucket is returned, in `TtlBucket::ALL` order, even when empty.
+    pub async fn ttl_histogram(&self) -> Vec<(TtlBucket, usize)> {
+        let cache = self.cache.read().await;
         let now = chrono::Utc::now().timestamp();
-        cache.retain(|_, entry| {
-            entry.expires_at.map_or(true, |expires| expires > now)
-        });
+        let mut histogram: Vec<(TtlBucket, usize)> = TtlBucket::ALL.iter().map(|bucket| (*bucket, 0)).collect();
+
+        for entry in cache.values() {
+            let remaining = entry.expires_at.map(|expires| expires - now);
+            if remaining.is_some_and(|seconds| seconds <= 0) {
+                continue;
+            }
+
+            histogram[TtlBucket::for_remaining(remaining) as usize].1 += 1;
+        }
+
+        histogram
+    }
+
+    /// Get a live entry, dropping the entry instead if it expired
+    async fn lookup(&self, key: &str) -> Option<Arc<Value>> {
+        let now = chrono::Utc::now().timestamp();
+        let expired = |entry: &CacheEntry| entry.expires_at.is_some_and(|expires| expires <= now);
+        {
+            let cache = self.cache.read().await;
+            let entry = cache.get(key)?;
+            if !expired(entry) {
+                entry.last_used.store(self.tick(), Ordering::Relaxed);
+                return Some(entry.value.clone());
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        if cache.get(key).is_some_and(expired) {
+            cache.remove(key);
+            self.counters.evicted(1);
+        }
+        None
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Once the cap is exceeded, evict the least recently used entries down
+    /// to a tenth below it, so inserts at the cap do not each scan the map
+    fn evict_lru(&self, cache: &mut HashMap<String, CacheEntry>) {
+        let Some(max_entries) = self.max_entries else {
+            return;
+        };
+        if cache.len() <= max_entries {
+            return;
+        }
+
+        self.counters.evicted(sweep(cache));
+        let excess = cache.len().saturating_sub(max_entries - max_entries / 10);
+        if excess == 0 {
+            return;
+        }
+
+        let mut by_use: Vec<(u64, String)> = cache
+            .iter()
+            .map(|(key, entry)| (entry.last_used.load(Ordering::Relaxed), key.clone()))
+            .collect();
+        by_use.select_nth_unstable(excess - 1);
+        for (_, key) in by_use.into_iter().take(excess) {
+            cache.remove(&key);
+        }
+        self.counters.evicted(excess);
+    }
+}
+
+/// Claim on the load of one key, released from the in-flight map when the
+/// last caller waiting on it is done
+pub(crate) struct InFlight<'a> {
+    loads: &'a InFlightLoads,
+    key: &'a str,
+    pub(crate) lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> InFlight<'a> {
+    pub(crate) fn join(loads: &'a InFlightLoads, key: &'a str) -> Self {
+        let mut map = loads.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let lock = map.entry(key.to_string()).or_default().clone();
+        Self { loads, key, lock }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        // Only the map and this claim still hold the lock
+        let mut map = self.loads.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if map.get(self.key).is_some_and(|lock| Arc::strong_count(lock) == 2) {
+            map.remove(self.key);
+        }
     }
 }
 
+/// Remove expired entries, returning how many were removed
+fn sweep(cache: &mut HashMap<String, CacheEntry>) -> usize {
+    let now = chrono::Utc::now().timestamp();
+    let before = cache.len();
+    cache.retain(|_, entry| {
+        entry.expires_at.is_none_or(|expires| expires > now)
+    });
+    before - cache.len()
+}
+
 #[async_trait]
 impl CacheProvider for MemoryCacheProvider {
     async fn get(&self, key: &str) -> Result<Option<Value>> {
-        self.cleanup_expired().await;
-        let cache = self.cache.read().await;
-        let now = chrono::Utc::now().timestamp();
-
-        Ok(cache.get(key).and_then(|entry| {
-            if entry.expires_at.map_or(true, |expires| expires > now) {
-                Some(entry.value.clone())
-            } else {
-                None
-            }
-        }))
+        Ok(self.get_arc(key).await?.map(|value| Value::clone(&value)))
     }
 
     async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
@@ -78,10 +629,13 @@ impl CacheProvider for MemoryCacheProvider {
         cache.insert(
             key.to_string(),
             CacheEntry {
-                value,
+                value: Arc::new(value),
                 expires_at,
+                last_used: AtomicU64::new(self.tick()),
             },
         );
+        self.counters.set();
+        self.evict_lru(&mut cache);
 
         Ok(())
     }
@@ -101,83 +655,1583 @@ impl CacheProvider for MemoryCacheProvider {
         cache.clear();
         Ok(())
     }
-}
 
-impl Default for MemoryCacheProvider {
-    fn default() -> Self {
-        Self::new()
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        let mut cache = self.cache.write().await;
+        cache.retain(|key, _| !key.starts_with(prefix));
+        Ok(())
     }
-}
 
-/// Redis cache provider (requires redis-cache feature)
-#[cfg(feature = "redis-cache")]
-pub struct RedisCacheProvider {
-    client: redis::Client,
-}
+    async fn get_or_set(&self, key: &str, ttl: Option<u64>, load: BoxFuture<'_, Result<Value>>) -> Result<Value> {
+        if let Some(value) = self.get_arc(key).await? {
+            return Ok(Value::clone(&value));
+        }
 
-#[cfg(feature = "redis-cache")]
-impl RedisCacheProvider {
-    pub fn new(url: impl AsRef<str>) -> Result<Self> {
-        let client = redis::Client::open(url.as_ref())?;
-        Ok(Self { client })
-    }
+        // Callers queue on a per-key lock; whoever gets it first loads and
+        // the rest find the stored value. If that load fails the next caller
+        // in line runs its own.
+        let in_flight = InFlight::join(&self.in_flight, key);
+        let _guard = in_flight.lock.lock().await;
+        if let Some(value) = self.lookup(key).await {
+            return Ok(Value::clone(&value));
+        }
 
-    pub async fn from_client(client: redis::Client) -> Result<Self> {
-        Ok(Self { client })
+        let value = load.await?;
+        self.set(key, value.clone(), ttl).await?;
+        Ok(value)
     }
-}
-
-#[cfg(feature = "redis-cache")]
-#[async_trait]
-impl CacheProvider for RedisCacheProvider {
-    async fn get(&self, key: &str) -> Result<Option<Value>> {
-        use redis::AsyncCommands;
 
-        let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let value: Option<String> = conn.get(key).await?;
-
-        match value {
-            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
-            None => Ok(None),
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            let value = self.lookup(key).await.map(|value| Value::clone(&value));
+            self.counters.read(value.is_some());
+            values.push(value);
         }
+        Ok(values)
     }
 
-    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
-        use redis::AsyncCommands;
-
-        let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let json = serde_json::to_string(&value)?;
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        let expires_at = ttl.map(|seconds| {
+            chrono::Utc::now().timestamp() + seconds as i64
+        });
 
-        if let Some(seconds) = ttl {
-            conn.set_ex(key, json, seconds).await?;
-        } else {
-            conn.set(key, json).await?;
+        let mut cache = self.cache.write().await;
+        for (key, value) in entries {
+            cache.insert(
+                key,
+                CacheEntry {
+                    value: Arc::new(value),
+                    expires_at,
+                    last_used: AtomicU64::new(self.tick()),
+                },
+            );
+            self.counters.set();
         }
+        self.evict_lru(&mut cache);
 
         Ok(())
     }
 
-    async fn delete(&self, key: &str) -> Result<()> {
-        use redis::AsyncCommands;
-
-        let mut conn = self.client.get_multiplexed_async_connection().await?;
-        conn.del(key).await?;
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        let mut cache = self.cache.write().await;
+        for key in keys {
+            cache.remove(*key);
+        }
         Ok(())
     }
 
-    async fn exists(&self, key: &str) -> Result<bool> {
-        use redis::AsyncCommands;
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        let mut cache = self.cache.write().await;
+        let now = chrono::Utc::now().timestamp();
+        let live = cache
+            .get(key)
+            .filter(|entry| entry.expires_at.is_none_or(|expires| expires > now));
 
-        let mut conn = self.client.get_multiplexed_async_connection().await?;
-        let exists: bool = conn.exists(key).await?;
-        Ok(exists)
+        let (current, expires_at) = match live {
+            Some(entry) => (counter_value(key, &entry.value)?, entry.expires_at),
+            None => (0, None),
+        };
+        let next = add_to_counter(key, current, delta)?;
+
+        cache.insert(
+            key.to_string(),
+            CacheEntry {
+                value: Arc::new(Value::from(next)),
+                expires_at,
+                last_used: AtomicU64::new(self.tick()),
+            },
+        );
+        self.counters.set();
+        self.evict_lru(&mut cache);
+        Ok(next)
     }
 
-    async fn clear(&self) -> Result<()> {
-        use redis::AsyncCommands;
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        let mut cache = self.cache.write().await;
+        let now = chrono::Utc::now().timestamp();
+        if cache
+            .get(key)
+            .is_some_and(|entry| entry.expires_at.is_none_or(|expires| expires > now))
+        {
+            return Ok(false);
+        }
 
-        let mut conn = self.client.get_multiplexed_async_connection().await?;
-        redis::cmd("FLUSHDB").query_async(&mut conn).await?;
-        Ok(())
+        cache.insert(
+            key.to_string(),
+            CacheEntry {
+                value: Arc::new(value),
+                expires_at: ttl.map(|seconds| now + seconds as i64),
+                last_used: AtomicU64::new(self.tick()),
+            },
+        );
+        self.counters.set();
+        self.evict_lru(&mut cache);
+        Ok(true)
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        CacheBackendInfo {
+            kind: "memory".to_string(),
+            details: json!({
+                "entries": self.cache.try_read().map(|cache| cache.len()).ok(),
+                "max_entries": self.max_entries,
+                "sweep_interval": self.sweep_interval.as_secs(),
+            }),
+        }
+    }
+
+    fn start_maintenance(&self) -> Option<MaintenanceHandle> {
+        // Hold a weak reference so the sweep stops once the provider is gone
+        let cache = Arc::downgrade(&self.cache);
+        let counters = self.counters.clone();
+        let interval = self.sweep_interval;
+
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(cache) = cache.upgrade() else {
+                    break;
+                };
+                let removed = sweep(&mut *cache.write().await);
+                counters.evicted(removed);
+                if removed > 0 {
+                    tracing::debug!(removed, "swept expired memory cache entries");
+                }
+            }
+        });
+
+        Some(MaintenanceHandle::new(vec![task]))
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        let entries = self.cache.try_read().map(|cache| cache.len() as u64).ok();
+        Some(self.counters.snapshot(entries))
+    }
+
+    async fn ttl_histogram(&self) -> Option<Vec<(TtlBucket, usize)>> {
+        Some(MemoryCacheProvider::ttl_histogram(self).await)
+    }
+}
+
+impl Default for MemoryCacheProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Validator = dyn Fn(&str, &Value) -> Result<()> + Send + Sync;
+
+/// Cache wrapper that validates every value before it is stored
+///
+/// Writes rejected by the validator fail with `Error::Validation` and never
+/// reach the inner provider.
+pub struct ValidatingCache<C> {
+    inner: C,
+    validator: Arc<Validator>,
+}
+
+impl<C: CacheProvider> ValidatingCache<C> {
+    pub fn new<F>(inner: C, validator: F) -> Self
+    where
+        F: Fn(&str, &Value) -> Result<()> + Send + Sync + 'static,
+    {
+        Self {
+            inner,
+            validator: Arc::new(validator),
+        }
+    }
+
+    /// Get the wrapped provider
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn validate(&self, key: &str, value: &Value) -> Result<()> {
+        (self.validator)(key, value).map_err(|err| match err {
+            Error::Validation(_) => err,
+            other => Error::Validation(other.to_string()),
+        })
+    }
+}
+
+#[async_trait]
+impl<C: CacheProvider> CacheProvider for ValidatingCache<C> {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        self.inner.get(key).await
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        self.inner.get_many(keys).await
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        self.validate(key, &value)?;
+        self.inner.set(key, value, ttl).await
+    }
+
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        for (key, value) in &entries {
+            self.validate(key, value)?;
+        }
+        self.inner.set_many(entries, ttl).await
+    }
+
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        self.inner.delete_many(keys).await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        self.validate(key, &value)?;
+        self.inner.set_if_absent(key, value, ttl).await
+    }
+
+    // Counters are only ever integers, so they are not validated
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        self.inner.incr(key, delta).await
+    }
+
+    async fn get_or_set(&self, key: &str, ttl: Option<u64>, load: BoxFuture<'_, Result<Value>>) -> Result<Value> {
+        let load = Box::pin(async move {
+            let value = load.await?;
+            self.validate(key, &value)?;
+            Ok(value)
+        });
+        self.inner.get_or_set(key, ttl, load).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        self.inner.clear_prefix(prefix).await
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        self.inner.backend_info()
+    }
+
+    fn start_maintenance(&self) -> Option<MaintenanceHandle> {
+        self.inner.start_maintenance()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.inner.stats()
+    }
+
+    async fn ttl_histogram(&self) -> Option<Vec<(TtlBucket, usize)>> {
+        self.inner.ttl_histogram().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.inner.flush().await
+    }
+}
+
+/// Cache wrapper counting hits, misses and writes for any provider
+///
+/// Evictions and the entry count are taken from the inner provider when it
+/// tracks them and are otherwise reported as zero and unknown.
+pub struct StatsCache<C> {
+    inner: C,
+    counters: StatsCounters,
+}
+
+impl<C: CacheProvider> StatsCache<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            counters: StatsCounters::default(),
+        }
+    }
+
+    /// Get the wrapped provider
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<C: CacheProvider> CacheProvider for StatsCache<C> {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        let value = self.inner.get(key).await?;
+        self.counters.read(value.is_some());
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        self.inner.set(key, value, ttl).await?;
+        self.counters.set();
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        self.inner.clear_prefix(prefix).await
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        let values = self.inner.get_many(keys).await?;
+        for value in &values {
+            self.counters.read(value.is_some());
+        }
+        Ok(values)
+    }
+
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        let count = entries.len();
+        self.inner.set_many(entries, ttl).await?;
+        self.counters.sets.fetch_add(count as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        self.inner.delete_many(keys).await
+    }
+
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        let value = self.inner.incr(key, delta).await?;
+        self.counters.set();
+        Ok(value)
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        let stored = self.inner.set_if_absent(key, value, ttl).await?;
+        if stored {
+            self.counters.set();
+        }
+        Ok(stored)
+    }
+
+    async fn get_or_set(&self, key: &str, ttl: Option<u64>, load: BoxFuture<'_, Result<Value>>) -> Result<Value> {
+        let loaded = AtomicBool::new(false);
+        let load = Box::pin(async {
+            let value = load.await?;
+            loaded.store(true, Ordering::Relaxed);
+            Ok(value)
+        });
+        let value = self.inner.get_or_set(key, ttl, load).await?;
+        // Callers that waited for another one's load count as hits
+        let loaded = loaded.load(Ordering::Relaxed);
+        self.counters.read(!loaded);
+        if loaded {
+            self.counters.set();
+        }
+        Ok(value)
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        self.inner.backend_info()
+    }
+
+    fn start_maintenance(&self) -> Option<MaintenanceHandle> {
+        self.inner.start_maintenance()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        let inner = self.inner.stats().unwrap_or_default();
+        Some(CacheStats {
+            evictions: inner.evictions,
+            entries: inner.entries,
+            ..self.counters.snapshot(None)
+        })
+    }
+
+    async fn ttl_histogram(&self) -> Option<Vec<(TtlBucket, usize)>> {
+        self.inner.ttl_histogram().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.inner.flush().await
+    }
+}
+
+/// Router exposing `GET /sx/cache/stats`
+pub(crate) fn stats_router(cache: Arc<dyn CacheProvider>) -> Router {
+    Router::new().route("/sx/cache/stats", get(stats_handler)).with_state(cache)
+}
+
+async fn stats_handler(State(cache): State<Arc<dyn CacheProvider>>) -> (StatusCode, Json<Value>) {
+    match cache.stats() {
+        Some(stats) => {
+            let mut body = json!(stats);
+            body["hit_ratio"] = json!(stats.hit_ratio());
+            (StatusCode::OK, Json(body))
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("{} cache provider does not track statistics", cache.backend_info().kind) })),
+        ),
+    }
+}
+
+/// Cache view that prefixes every key with a namespace
+///
+/// Lets modules share one provider without clobbering each other's keys.
+/// `clear()` on a scoped cache only removes its own namespace.
+#[derive(Clone)]
+pub struct ScopedCache {
+    inner: Arc<dyn CacheProvider>,
+    prefix: String,
+}
+
+impl ScopedCache {
+    /// Scope `inner` to keys starting with `namespace:`
+    pub fn new(inner: Arc<dyn CacheProvider>, namespace: impl AsRef<str>) -> Self {
+        Self {
+            inner,
+            prefix: format!("{}:", namespace.as_ref()),
+        }
+    }
+
+    /// Get the namespace, without the trailing separator
+    pub fn namespace(&self) -> &str {
+        self.prefix.trim_end_matches(':')
+    }
+
+    /// Delete every entry in this namespace
+    pub async fn clear_namespace(&self) -> Result<()> {
+        self.inner.clear_prefix(&self.prefix).await
+    }
+
+    /// The provider the namespace is carved out of
+    pub(crate) fn provider(&self) -> &Arc<dyn CacheProvider> {
+        &self.inner
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl CacheProvider for ScopedCache {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        self.inner.get(&self.key(key)).await
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        self.inner.set(&self.key(key), value, ttl).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(&self.key(key)).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(&self.key(key)).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.clear_namespace().await
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        self.inner.clear_prefix(&self.key(prefix)).await
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        let keys: Vec<String> = keys.iter().map(|key| self.key(key)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.inner.get_many(&keys).await
+    }
+
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        let entries = entries.into_iter().map(|(key, value)| (self.key(&key), value)).collect();
+        self.inner.set_many(entries, ttl).await
+    }
+
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        let keys: Vec<String> = keys.iter().map(|key| self.key(key)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        self.inner.delete_many(&keys).await
+    }
+
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        self.inner.incr(&self.key(key), delta).await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        self.inner.set_if_absent(&self.key(key), value, ttl).await
+    }
+
+    async fn get_or_set(&self, key: &str, ttl: Option<u64>, load: BoxFuture<'_, Result<Value>>) -> Result<Value> {
+        self.inner.get_or_set(&self.key(key), ttl, load).await
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        let mut info = self.inner.backend_info();
+        info.details = json!({ "namespace": self.namespace(), "inner": info.details });
+        info
+    }
+}
+
+/// Most a tenant may keep in the cache; unlimited by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TenantQuota {
+    /// Most entries
+    pub max_entries: Option<u64>,
+    /// Most bytes of JSON-encoded values
+    pub max_bytes: Option<u64>,
+}
+
+impl TenantQuota {
+    fn is_unlimited(&self) -> bool {
+        self.max_entries.is_none() && self.max_bytes.is_none()
+    }
+
+    fn admits(&self, usage: &TenantCacheUsage) -> bool {
+        self.max_entries.is_none_or(|max| usage.entries <= max) && self.max_bytes.is_none_or(|max| usage.bytes <= max)
+    }
+}
+
+/// What a tenant keeps in the cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantCacheUsage {
+    pub entries: u64,
+    /// Bytes of JSON-encoded values
+    pub bytes: u64,
+}
+
+/// Size of every entry a tenant wrote, by key
+type TenantEntries = Arc<std::sync::Mutex<HashMap<String, u64>>>;
+
+/// Cache wrapper confining a tenant to keys under `tenant:<id>:` and to its
+/// [`TenantQuota`]
+///
+/// `clear()` only removes the tenant's entries. A write that would take the
+/// tenant over its quota fails with `Error::Cache`, after forgetting entries
+/// that expired meanwhile, so one tenant cannot crowd the others out of a
+/// bounded cache. Usage is counted from the writes made through the
+/// wrappers of this process; entries written by other instances sharing the
+/// backend are not counted.
+#[derive(Clone)]
+pub struct TenantCache {
+    inner: ScopedCache,
+    tenant: String,
+    quota: TenantQuota,
+    entries: TenantEntries,
+}
+
+impl TenantCache {
+    /// Confine `tenant` to its keys of `inner` and to `quota`
+    pub fn new(inner: Arc<dyn CacheProvider>, tenant: &Tenant, quota: TenantQuota) -> Self {
+        Self::with_entries(inner, tenant, quota, TenantEntries::default())
+    }
+
+    fn with_entries(inner: Arc<dyn CacheProvider>, tenant: &Tenant, quota: TenantQuota, entries: TenantEntries) -> Self {
+        Self {
+            inner: ScopedCache::new(inner, format!("tenant:{}", tenant.id())),
+            tenant: tenant.id().to_string(),
+            quota,
+            entries,
+        }
+    }
+
+    /// What the tenant keeps in the cache, as far as this process knows
+    pub fn usage(&self) -> TenantCacheUsage {
+        usage_of(&self.lock())
+    }
+
+    /// The tenant's quota
+    pub fn quota(&self) -> TenantQuota {
+        self.quota
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, u64>> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Record that `key` is about to hold `bytes`, if the quota allows it
+    fn try_admit(&self, key: &str, bytes: u64) -> bool {
+        let mut entries = self.lock();
+        let mut usage = usage_of(&entries);
+        match entries.get(key) {
+            Some(&previous) => usage.bytes = usage.bytes - previous + bytes,
+            None => {
+                usage.entries += 1;
+                usage.bytes += bytes;
+            }
+        }
+        if !self.quota.admits(&usage) {
+            return false;
+        }
+        entries.insert(key.to_string(), bytes);
+        true
+    }
+
+    async fn admit(&self, key: &str, value: &Value) -> Result<()> {
+        if self.quota.is_unlimited() {
+            return Ok(());
+        }
+        let bytes = serde_json::to_vec(value)?.len() as u64;
+        if self.try_admit(key, bytes) {
+            return Ok(());
+        }
+        self.forget_expired().await?;
+        if self.try_admit(key, bytes) {
+            return Ok(());
+        }
+        Err(Error::Cache(format!(
+            "tenant '{}' would exceed its cache quota writing '{}'",
+            self.tenant, key
+        )))
+    }
+
+    /// Stop counting entries that are gone from the backend
+    async fn forget_expired(&self) -> Result<()> {
+        let keys: Vec<String> = self.lock().keys().cloned().collect();
+        for key in keys {
+            if !self.inner.exists(&key).await? {
+                self.lock().remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    fn forget(&self, key: &str) {
+        if !self.quota.is_unlimited() {
+            self.lock().remove(key);
+        }
+    }
+}
+
+fn usage_of(entries: &HashMap<String, u64>) -> TenantCacheUsage {
+    TenantCacheUsage {
+        entries: entries.len() as u64,
+        bytes: entries.values().sum(),
+    }
+}
+
+#[async_trait]
+impl CacheProvider for TenantCache {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        self.inner.get(key).await
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        self.inner.get_many(keys).await
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        self.admit(key, &value).await?;
+        self.inner.set(key, value, ttl).await.inspect_err(|_| self.forget(key))
+    }
+
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        for (key, value) in &entries {
+            self.admit(key, value).await?;
+        }
+        self.inner.set_many(entries, ttl).await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        self.admit(key, &value).await?;
+        self.inner.set_if_absent(key, value, ttl).await
+    }
+
+    async fn get_or_set(&self, key: &str, ttl: Option<u64>, load: BoxFuture<'_, Result<Value>>) -> Result<Value> {
+        let load = Box::pin(async move {
+            let value = load.await?;
+            self.admit(key, &value).await?;
+            Ok(value)
+        });
+        self.inner.get_or_set(key, ttl, load).await
+    }
+
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        self.admit(key, &Value::from(i64::MIN)).await?;
+        self.inner.incr(key, delta).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key).await?;
+        self.forget(key);
+        Ok(())
+    }
+
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        self.inner.delete_many(keys).await?;
+        for key in keys {
+            self.forget(key);
+        }
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.inner.clear().await?;
+        self.lock().clear();
+        Ok(())
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        self.inner.clear_prefix(prefix).await?;
+        self.lock().retain(|key, _| !key.starts_with(prefix));
+        Ok(())
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        let mut info = self.inner.backend_info();
+        info.details = json!({ "tenant": self.tenant, "quota": self.quota, "inner": info.details });
+        info
+    }
+}
+
+/// Quotas of every tenant and what each keeps in the cache, shared by the
+/// contexts handling their calls
+#[derive(Clone, Default)]
+pub(crate) struct TenantCaches {
+    quota: TenantQuota,
+    quotas: Arc<HashMap<String, TenantQuota>>,
+    entries: Arc<std::sync::Mutex<HashMap<String, TenantEntries>>>,
+}
+
+impl TenantCaches {
+    pub(crate) fn new(quota: TenantQuota, quotas: HashMap<String, TenantQuota>) -> Self {
+        Self {
+            quota,
+            quotas: Arc::new(quotas),
+            entries: Arc::default(),
+        }
+    }
+
+    /// `tenant`'s part of `inner`
+    pub(crate) fn scope(&self, inner: Arc<dyn CacheProvider>, tenant: &Tenant) -> TenantCache {
+        let quota = self.quotas.get(tenant.id()).copied().unwrap_or(self.quota);
+        let entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(tenant.id().to_string())
+            .or_default()
+            .clone();
+        TenantCache::with_entries(inner, tenant, quota, entries)
+    }
+}
+
+/// Two-level cache reading from a fast local layer before a shared one
+///
+/// Reads try `l1` first and fall back to `l2`, copying hits back into `l1`.
+/// Writes and deletes go through to both layers. Entries are kept in `l1`
+/// for at most the L1 TTL (60 seconds by default), which bounds how stale a
+/// local copy can get when another instance updates `l2`.
+pub struct TieredCacheProvider<L1, L2> {
+    l1: L1,
+    l2: L2,
+    l1_ttl: u64,
+}
+
+impl<L1: CacheProvider, L2: CacheProvider> TieredCacheProvider<L1, L2> {
+    pub fn new(l1: L1, l2: L2) -> Self {
+        Self { l1, l2, l1_ttl: 60 }
+    }
+
+    /// Set the longest time (seconds) an entry stays in the local layer
+    pub fn with_l1_ttl(mut self, seconds: u64) -> Self {
+        self.l1_ttl = seconds;
+        self
+    }
+
+    /// Get the local layer
+    pub fn l1(&self) -> &L1 {
+        &self.l1
+    }
+
+    /// Get the shared layer
+    pub fn l2(&self) -> &L2 {
+        &self.l2
+    }
+
+    fn l1_ttl_for(&self, ttl: Option<u64>) -> u64 {
+        ttl.map_or(self.l1_ttl, |ttl| ttl.min(self.l1_ttl))
+    }
+}
+
+#[async_trait]
+impl<L1: CacheProvider, L2: CacheProvider> CacheProvider for TieredCacheProvider<L1, L2> {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        if let Some(value) = self.l1.get(key).await? {
+            return Ok(Some(value));
+        }
+
+        let value = self.l2.get(key).await?;
+        if let Some(value) = &value {
+            self.l1.set(key, value.clone(), Some(self.l1_ttl)).await?;
+        }
+        Ok(value)
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        self.l2.set(key, value.clone(), ttl).await?;
+        self.l1.set(key, value, Some(self.l1_ttl_for(ttl))).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.l2.delete(key).await?;
+        self.l1.delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(self.l1.exists(key).await? || self.l2.exists(key).await?)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.l2.clear().await?;
+        self.l1.clear().await
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        self.l2.clear_prefix(prefix).await?;
+        self.l1.clear_prefix(prefix).await
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        let mut values = self.l1.get_many(keys).await?;
+        let missing: Vec<usize> = (0..keys.len()).filter(|&index| values[index].is_none()).collect();
+        if missing.is_empty() {
+            return Ok(values);
+        }
+
+        let missing_keys: Vec<&str> = missing.iter().map(|&index| keys[index]).collect();
+        let found = self.l2.get_many(&missing_keys).await?;
+        let mut backfill = Vec::new();
+        for (index, value) in missing.into_iter().zip(found) {
+            if let Some(value) = &value {
+                backfill.push((keys[index].to_string(), value.clone()));
+            }
+            values[index] = value;
+        }
+        if !backfill.is_empty() {
+            self.l1.set_many(backfill, Some(self.l1_ttl)).await?;
+        }
+
+        Ok(values)
+    }
+
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        self.l2.set_many(entries.clone(), ttl).await?;
+        self.l1.set_many(entries, Some(self.l1_ttl_for(ttl))).await
+    }
+
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        self.l2.delete_many(keys).await?;
+        self.l1.delete_many(keys).await
+    }
+
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        // Counters live in the shared layer; drop any stale local copy
+        let value = self.l2.incr(key, delta).await?;
+        self.l1.delete(key).await?;
+        Ok(value)
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        let stored = self.l2.set_if_absent(key, value.clone(), ttl).await?;
+        if stored {
+            self.l1.set(key, value, Some(self.l1_ttl_for(ttl))).await?;
+        }
+        Ok(stored)
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        CacheBackendInfo {
+            kind: "tiered".to_string(),
+            details: json!({
+                "l1": self.l1.backend_info(),
+                "l2": self.l2.backend_info(),
+                "l1_ttl": self.l1_ttl,
+            }),
+        }
+    }
+
+    fn start_maintenance(&self) -> Option<MaintenanceHandle> {
+        MaintenanceHandle::merge([self.l1.start_maintenance(), self.l2.start_maintenance()])
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.l1.flush().await?;
+        self.l2.flush().await
+    }
+}
+
+/// Redis cache provider (requires redis-cache feature)
+#[cfg(feature = "redis-cache")]
+pub struct RedisCacheProvider {
+    client: redis::Client,
+}
+
+#[cfg(feature = "redis-cache")]
+impl RedisCacheProvider {
+    pub fn new(url: impl AsRef<str>) -> Result<Self> {
+        let client = redis::Client::open(url.as_ref())?;
+        Ok(Self { client })
+    }
+
+    pub async fn from_client(client: redis::Client) -> Result<Self> {
+        Ok(Self { client })
+    }
+}
+
+#[cfg(feature = "redis-cache")]
+#[async_trait]
+impl CacheProvider for RedisCacheProvider {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        use redis::AsyncCommands;
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let value: Option<String> = conn.get(key).await?;
+
+        match value {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        use redis::AsyncCommands;
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let json = serde_json::to_string(&value)?;
+
+        if let Some(seconds) = ttl {
+            conn.set_ex::<_, _, ()>(key, json, seconds).await?;
+        } else {
+            conn.set::<_, _, ()>(key, json).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        use redis::AsyncCommands;
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        conn.del::<_, ()>(key).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        use redis::AsyncCommands;
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let exists: bool = conn.exists(key).await?;
+        Ok(exists)
+    }
+
+    async fn clear(&self) -> Result<()> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        redis::cmd("FLUSHDB").query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let pattern = format!("{}*", redis_glob_escape(prefix));
+        let mut cursor: u64 = 0;
+
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(500)
+                .query_async(&mut conn)
+                .await?;
+            if !keys.is_empty() {
+                redis::cmd("DEL").arg(keys).query_async::<_, ()>(&mut conn).await?;
+            }
+            if next == 0 {
+                return Ok(());
+            }
+            cursor = next;
+        }
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let values: Vec<Option<String>> = redis::cmd("MGET").arg(keys).query_async(&mut conn).await?;
+        values
+            .into_iter()
+            .map(|value| value.map(|json| serde_json::from_str(&json)).transpose().map_err(Error::from))
+            .collect()
+    }
+
+    async fn set_many(&self, entries: Vec<(String, Value)>, ttl: Option<u64>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, value) in entries {
+            let json = serde_json::to_string(&value)?;
+            match ttl {
+                Some(seconds) => pipe.set_ex(key, json, seconds).ignore(),
+                None => pipe.set(key, json).ignore(),
+            };
+        }
+
+        pipe.query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn delete_many(&self, keys: &[&str]) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        redis::cmd("DEL").arg(keys).query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        use redis::AsyncCommands;
+
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        Ok(conn.incr(key, delta).await?)
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(key).arg(serde_json::to_string(&value)?).arg("NX");
+        if let Some(seconds) = ttl {
+            cmd.arg("EX").arg(seconds);
+        }
+
+        let reply: Option<String> = cmd.query_async(&mut conn).await?;
+        Ok(reply.is_some())
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        // Built from the parsed connection info so the password never leaks
+        let info = self.client.get_connection_info();
+        CacheBackendInfo {
+            kind: "redis".to_string(),
+            details: json!({
+                "addr": info.addr.to_string(),
+                "tls": matches!(info.addr, redis::ConnectionAddr::TcpTls { .. }),
+                "db": info.redis.db,
+                "username": info.redis.username,
+                "password": info.redis.password.as_ref().map(|_| "<redacted>"),
+            }),
+        }
+    }
+}
+
+// SCAN MATCH treats these characters as glob syntax
+#[cfg(feature = "redis-cache")]
+fn redis_glob_escape(prefix: &str) -> String {
+    let mut escaped = String::with_capacity(prefix.len());
+    for c in prefix.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Memcached cache provider (requires memcached-cache feature)
+///
+/// Values are stored as JSON strings. The memcached client is blocking, so
+/// every operation runs on tokio's blocking thread pool. Memcached cannot
+/// enumerate keys, so `clear_prefix` is unsupported and `clear` flushes
+/// every server.
+#[cfg(feature = "memcached-cache")]
+pub struct MemcachedCacheProvider {
+    client: memcache::Client,
+    servers: Vec<String>,
+}
+
+#[cfg(feature = "memcached-cache")]
+impl MemcachedCacheProvider {
+    /// Connect to a server, e.g. `memcache://127.0.0.1:11211`
+    pub fn new(url: impl AsRef<str>) -> Result<Self> {
+        Self::with_servers(vec![url.as_ref().to_string()])
+    }
+
+    /// Connect to several servers, distributing keys between them
+    pub fn with_servers(urls: Vec<String>) -> Result<Self> {
+        let client = memcache::Client::connect(urls.clone())?;
+        Ok(Self {
+            client,
+            servers: urls.iter().map(|url| redact_userinfo(url)).collect(),
+        })
+    }
+
+    pub fn from_client(client: memcache::Client) -> Self {
+        Self {
+            client,
+            servers: Vec::new(),
+        }
+    }
+
+    async fn run<T, F>(&self, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&memcache::Client) -> std::result::Result<T, memcache::MemcacheError> + Send + 'static,
+    {
+        let client = self.client.clone();
+        tokio::task::spawn_blocking(move || op(&client))
+            .await
+            .map_err(|err| Error::Cache(format!("memcached operation panicked: {}", err)))?
+            .map_err(Error::from)
+    }
+}
+
+// Memcached reads expirations over 30 days as absolute Unix timestamps
+#[cfg(feature = "memcached-cache")]
+fn memcached_expiration(ttl: Option<u64>) -> u32 {
+    const MAX_RELATIVE: u64 = 60 * 60 * 24 * 30;
+
+    match ttl {
+        None | Some(0) => 0,
+        Some(seconds) if seconds <= MAX_RELATIVE => seconds as u32,
+        Some(seconds) => {
+            let expires_at = chrono::Utc::now().timestamp() as u64 + seconds;
+            expires_at.min(u32::MAX as u64) as u32
+        }
+    }
+}
+
+// Keeps SASL credentials embedded in server URLs out of backend_info
+#[cfg(feature = "memcached-cache")]
+fn redact_userinfo(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    match rest.split_once('@') {
+        Some((_, host)) if scheme.is_empty() => format!("<redacted>@{}", host),
+        Some((_, host)) => format!("{}://<redacted>@{}", scheme, host),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(feature = "memcached-cache")]
+#[async_trait]
+impl CacheProvider for MemcachedCacheProvider {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        let key = key.to_string();
+        let value: Option<String> = self.run(move |client| client.get(&key)).await?;
+
+        match value {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<Value>>> {
+        let owned: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let found: HashMap<String, String> = self
+            .run(move |client| {
+                let keys: Vec<&str> = owned.iter().map(String::as_str).collect();
+                client.gets(&keys)
+            })
+            .await?;
+
+        keys.iter()
+            .map(|key| match found.get(*key) {
+                Some(json) => Ok(Some(serde_json::from_str(json)?)),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        let key = key.to_string();
+        let json = serde_json::to_string(&value)?;
+        let expiration = memcached_expiration(ttl);
+        self.run(move |client| client.set(&key, json.as_str(), expiration)).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.run(move |client| client.delete(&key)).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        let key = key.to_string();
+        let value: Option<String> = self.run(move |client| client.get(&key)).await?;
+        Ok(value.is_some())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.run(|client| client.flush()).await
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        CacheBackendInfo {
+            kind: "memcached".to_string(),
+            details: json!({
+                "servers": self.servers,
+            }),
+        }
+    }
+}
+
+/// Disk-backed cache provider (requires disk-cache feature)
+///
+/// Entries are stored in a sled database together with their expiry, so a
+/// cache reopened after a restart is still warm. sled blocks on disk I/O, so
+/// every operation runs on tokio's blocking thread pool. Expired entries are
+/// removed when read, when the cache is opened and by `compact()`. With a
+/// size cap, a write that takes the cache over the cap first compacts it and
+/// then evicts the oldest writes until it is back under 90% of the cap.
+#[cfg(feature = "disk-cache")]
+pub struct DiskCacheProvider {
+    store: Arc<DiskStore>,
+    path: std::path::PathBuf,
+    max_entries: Option<usize>,
+}
+
+/// The sled database of a `DiskCacheProvider`, shared with the blocking
+/// tasks running its operations
+#[cfg(feature = "disk-cache")]
+struct DiskStore {
+    db: sled::Db,
+    entries: AtomicUsize,
+    counters: StatsCounters,
+}
+
+#[cfg(feature = "disk-cache")]
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    value: Value,
+    expires_at: Option<i64>,
+    written_at: i64,
+}
+
+#[cfg(feature = "disk-cache")]
+impl DiskEntry {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires| expires <= now)
+    }
+}
+
+#[cfg(feature = "disk-cache")]
+impl DiskCacheProvider {
+    /// Open or create a cache in the directory at `path`
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let db = sled::open(&path)?;
+        let provider = Self {
+            store: Arc::new(DiskStore {
+                entries: AtomicUsize::new(db.len()),
+                db,
+                counters: StatsCounters::default(),
+            }),
+            path,
+            max_entries: None,
+        };
+
+        let removed = provider.compact()?;
+        if removed > 0 {
+            tracing::debug!(path = %provider.path.display(), removed, "removed expired disk cache entries");
+        }
+        Ok(provider)
+    }
+
+    /// Cap the number of entries kept on disk
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Remove every expired entry and return how many were removed
+    ///
+    /// Blocks on disk I/O; from async code, prefer running it with
+    /// `tokio::task::spawn_blocking`.
+    pub fn compact(&self) -> Result<usize> {
+        self.store.compact()
+    }
+
+    /// Write pending changes to disk
+    ///
+    /// sled flushes periodically on its own; call this before shutting down
+    /// to make sure recent writes survive.
+    pub async fn flush(&self) -> Result<()> {
+        self.store.db.flush_async().await?;
+        Ok(())
+    }
+
+    async fn run<T, F>(&self, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&DiskStore) -> Result<T> + Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || op(&store))
+            .await
+            .map_err(|err| Error::Cache(format!("disk cache operation panicked: {}", err)))?
+    }
+}
+
+#[cfg(feature = "disk-cache")]
+impl DiskStore {
+    fn compact(&self) -> Result<usize> {
+        let now = chrono::Utc::now().timestamp();
+        let mut removed = 0;
+
+        for item in self.db.iter() {
+            let (key, bytes) = item?;
+            let expired = serde_json::from_slice::<DiskEntry>(&bytes).map_or(true, |entry| entry.is_expired(now));
+            if expired && self.remove(&key)? {
+                removed += 1;
+            }
+        }
+
+        self.counters.evicted(removed);
+        Ok(removed)
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Value>> {
+        let Some(bytes) = self.db.get(key)? else {
+            self.counters.read(false);
+            return Ok(None);
+        };
+
+        let entry: DiskEntry = serde_json::from_slice(&bytes)?;
+        if entry.is_expired(chrono::Utc::now().timestamp()) {
+            if self.remove(key.as_bytes())? {
+                self.counters.evicted(1);
+            }
+            self.counters.read(false);
+            return Ok(None);
+        }
+        self.counters.read(true);
+        Ok(Some(entry.value))
+    }
+
+    fn set(&self, key: &str, value: Value, ttl: Option<u64>, max_entries: Option<usize>) -> Result<()> {
+        let now = chrono::Utc::now();
+        let entry = DiskEntry {
+            value,
+            expires_at: ttl.map(|seconds| now.timestamp() + seconds as i64),
+            written_at: now.timestamp_millis(),
+        };
+
+        if self.db.insert(key, serde_json::to_vec(&entry)?)?.is_none() {
+            self.entries.fetch_add(1, Ordering::Relaxed);
+        }
+        self.counters.set();
+        self.enforce_cap(max_entries)
+    }
+
+    fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        for item in self.db.scan_prefix(prefix) {
+            let (key, _) = item?;
+            self.remove(&key)?;
+        }
+        Ok(())
+    }
+
+    fn incr(&self, key: &str, delta: i64, max_entries: Option<usize>) -> Result<i64> {
+        loop {
+            let now = chrono::Utc::now();
+            let current = self.db.get(key)?;
+            let live = match &current {
+                Some(bytes) => Some(serde_json::from_slice::<DiskEntry>(bytes)?)
+                    .filter(|entry| !entry.is_expired(now.timestamp())),
+                None => None,
+            };
+
+            let (value, expires_at) = match &live {
+                Some(entry) => (counter_value(key, &entry.value)?, entry.expires_at),
+                None => (0, None),
+            };
+            let next = add_to_counter(key, value, delta)?;
+            let entry = DiskEntry {
+                value: Value::from(next),
+                expires_at,
+                written_at: now.timestamp_millis(),
+            };
+
+            if self.swap(key, current, &entry)? {
+                self.counters.set();
+                self.enforce_cap(max_entries)?;
+                return Ok(next);
+            }
+        }
+    }
+
+    fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>, max_entries: Option<usize>) -> Result<bool> {
+        let now = chrono::Utc::now();
+        let entry = DiskEntry {
+            value,
+            expires_at: ttl.map(|seconds| now.timestamp() + seconds as i64),
+            written_at: now.timestamp_millis(),
+        };
+
+        loop {
+            let current = self.db.get(key)?;
+            if let Some(bytes) = &current {
+                let existing: DiskEntry = serde_json::from_slice(bytes)?;
+                if !existing.is_expired(now.timestamp()) {
+                    return Ok(false);
+                }
+            }
+
+            if self.swap(key, current, &entry)? {
+                self.counters.set();
+                self.enforce_cap(max_entries)?;
+                return Ok(true);
+            }
+        }
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<bool> {
+        let removed = self.db.remove(key)?.is_some();
+        if removed {
+            self.entries.fetch_sub(1, Ordering::Relaxed);
+        }
+        Ok(removed)
+    }
+
+    /// Replace `current` with `entry`, returning false if another writer got there first
+    fn swap(&self, key: &str, current: Option<sled::IVec>, entry: &DiskEntry) -> Result<bool> {
+        let created = current.is_none();
+        let swapped = self
+            .db
+            .compare_and_swap(key, current, Some(serde_json::to_vec(entry)?))?
+            .is_ok();
+        if swapped && created {
+            self.entries.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(swapped)
+    }
+
+    fn enforce_cap(&self, max_entries: Option<usize>) -> Result<()> {
+        let Some(max_entries) = max_entries else {
+            return Ok(());
+        };
+        if self.entries.load(Ordering::Relaxed) <= max_entries {
+            return Ok(());
+        }
+
+        self.compact()?;
+        let len = self.entries.load(Ordering::Relaxed);
+        if len <= max_entries {
+            return Ok(());
+        }
+
+        // Evict below the cap so the next writes don't each trigger a scan
+        let target = max_entries - max_entries / 10;
+        let mut by_age = Vec::with_capacity(len);
+        for item in self.db.iter() {
+            let (key, bytes) = item?;
+            let written_at = serde_json::from_slice::<DiskEntry>(&bytes).map_or(i64::MIN, |entry| entry.written_at);
+            by_age.push((written_at, key));
+        }
+        by_age.sort_unstable_by_key(|(written_at, _)| *written_at);
+
+        for (_, key) in by_age.into_iter().take(len.saturating_sub(target)) {
+            if self.remove(&key)? {
+                self.counters.evicted(1);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "disk-cache")]
+#[async_trait]
+impl CacheProvider for DiskCacheProvider {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        let key = key.to_string();
+        self.run(move |store| store.get(&key)).await
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        let key = key.to_string();
+        let max_entries = self.max_entries;
+        self.run(move |store| store.set(&key, value, ttl, max_entries)).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.run(move |store| store.remove(key.as_bytes()).map(drop)).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(self.get(key).await?.is_some())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.run(|store| {
+            store.db.clear()?;
+            store.entries.store(0, Ordering::Relaxed);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        let prefix = prefix.to_string();
+        self.run(move |store| store.clear_prefix(&prefix)).await
+    }
+
+    async fn incr(&self, key: &str, delta: i64) -> Result<i64> {
+        let key = key.to_string();
+        let max_entries = self.max_entries;
+        self.run(move |store| store.incr(&key, delta, max_entries)).await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<bool> {
+        let key = key.to_string();
+        let max_entries = self.max_entries;
+        self.run(move |store| store.set_if_absent(&key, value, ttl, max_entries)).await
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        CacheBackendInfo {
+            kind: "disk".to_string(),
+            details: json!({
+                "path": self.path.display().to_string(),
+                "entries": self.store.entries.load(Ordering::Relaxed),
+                "max_entries": self.max_entries,
+            }),
+        }
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        Some(self.store.counters.snapshot(Some(self.store.entries.load(Ordering::Relaxed) as u64)))
+    }
+
+    async fn flush(&self) -> Result<()> {
+        DiskCacheProvider::flush(self).await
     }
 }
@@ -0,0 +1,110 @@
+//! Bridge from SurrealDB live queries to the event registry
+
+use futures::StreamExt;
+use serde_json::Value;
+use surrealdb::opt::Resource;
+use surrealdb::Action;
+use tokio::task::JoinHandle;
+use crate::db::Database;
+use crate::events::{Event, EventRegistry, EventType};
+use crate::error::Result;
+
+/// Turns writes to database tables into registry events
+///
+/// Each configured table gets a `LIVE SELECT`; every created, updated or
+/// deleted record is emitted as an [`Event`] with the record's table and id,
+/// so a listener on `orders:*` fires on real writes to `orders`. Delete
+/// events carry the record as it was before deletion.
+pub struct ChangeFeedBridge {
+    db: Database,
+    events: EventRegistry,
+    tables: Vec<String>,
+}
+
+impl ChangeFeedBridge {
+    pub fn new(db: Database, events: EventRegistry) -> Self {
+        Self {
+            db,
+            events,
+            tables: Vec::new(),
+        }
+    }
+
+    /// Watch a table for changes
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.tables.push(table.into());
+        self
+    }
+
+    /// Subscribe to every table and start dispatching events
+    ///
+    /// Fails if any subscription cannot be created. Dispatch stops when the
+    /// returned handle is dropped.
+    pub async fn start(self) -> Result<ChangeFeedHandle> {
+        let mut tasks = Vec::with_capacity(self.tables.len());
+
+        for table in self.tables {
+            let mut stream = self.db.select(Resource::from(table.as_str())).live().await?;
+            let events = self.events.clone();
+
+            tasks.push(tokio::spawn(async move {
+                while let Some(notification) = stream.next().await {
+                    let data = notification.data.into_inner().into_json();
+                    let Some(event) = to_event(&table, notification.action, data) else {
+                        continue;
+                    };
+                    match events.emit(event).await {
+                        Ok(report) => {
+                            for (listener, err) in report.failed {
+                                tracing::warn!(table = %table, %listener, error = %err, "change feed listener failed");
+                            }
+                        }
+                        Err(err) => tracing::warn!(table = %table, error = %err, "failed to dispatch change feed event"),
+                    }
+                }
+                tracing::debug!(table = %table, "change feed closed");
+            }));
+        }
+
+        Ok(ChangeFeedHandle { tasks })
+    }
+}
+
+/// Running change feed subscriptions, stopped when dropped
+pub struct ChangeFeedHandle {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ChangeFeedHandle {
+    /// Stop dispatching events
+    pub fn stop(self) {}
+}
+
+impl Drop for ChangeFeedHandle {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+fn to_event(table: &str, action: Action, data: Value) -> Option<Event> {
+    let event_type = match action {
+        Action::Create => EventType::Create,
+        Action::Update => EventType::Update,
+        Action::Delete => EventType::Delete,
+        _ => return None,
+    };
+
+    // Record ids render as "table:id"; events carry the table separately
+    let record_id = data
+        .get("id")
+        .and_then(Value::as_str)
+        .map(|id| id.strip_prefix(table).and_then(|id| id.strip_prefix(':')).unwrap_or(id).to_string());
+
+    let event = Event::new(event_type, table, data);
+    Some(match record_id {
+        Some(id) => event.with_record_id(id),
+        None => event,
+    })
+}
@@ -0,0 +1,491 @@
+//! Circuit breakers for functions calling external services
+//!
+//! A [`CircuitBreaker`] added to a function with
+//! `Module::with_function_middleware_for` counts its consecutive failures.
+//! Once they reach the failure threshold the circuit opens: calls fail at
+//! once with [`Error::CircuitOpen`] instead of waiting on a service that is
+//! down. After the open duration the circuit is half-open and lets a few
+//! probe calls through; if they all succeed it closes again, and if one
+//! fails it opens for another open duration.
+//!
+//! The state of each circuit is kept in the module's cache under keys
+//! starting with `circuit:{function}`, so with a shared cache provider, such
+//! as Redis, every instance of the server sees the same circuit. Failures
+//! and probes are counted with the cache's atomic increment, and each change
+//! of status writes a new generation of the state with
+//! [`set_if_absent`](crate::cache::CacheProvider::set_if_absent), so
+//! concurrent calls cannot both open or close the circuit or overwrite each
+//! other's counts. With tenancy each tenant has its own circuit, as it has
+//! its own cache.
+//!
+//! Opening and closing emit the events `circuit:opened` and
+//! `circuit:closed`, with the function and its consecutive failures, for
+//! alerting listeners.
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::cache::{CacheProvider, ScopedCache};
+use crate::context::Context;
+use crate::events::{Event, EventRegistry};
+use crate::functions::{CallNext, FunctionCall, FunctionMiddleware};
+use crate::tenancy::Tenant;
+use crate::error::{Error, Result};
+
+/// Whether a circuit lets calls through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitStatus {
+    /// Calls go through
+    #[default]
+    Closed,
+    /// Calls fail without running
+    Open,
+    /// Probe calls go through to find out whether the service is back
+    HalfOpen,
+}
+
+/// State of one generation of a circuit as kept in the cache
+///
+/// A generation is never changed once written: a transition writes the next
+/// one, so only one of several concurrent calls can make it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CircuitState {
+    /// `Closed` or `Open`; an open circuit is half-open once its open
+    /// duration has passed
+    status: CircuitStatus,
+    /// Consecutive failures that opened the circuit
+    failures: u32,
+    /// When the generation started, in milliseconds since the Unix epoch
+    since: i64,
+}
+
+/// How a call was let through
+#[derive(Debug, Clone, Copy)]
+struct Admission {
+    generation: u64,
+    /// Round of probe calls, for calls let through while half-open
+    probe: Option<i64>,
+    failures: u32,
+}
+
+/// Middleware refusing calls to a function while the service it calls is
+/// failing
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    half_open_probes: u32,
+    is_failure: Arc<dyn Fn(&Error) -> bool + Send + Sync>,
+}
+
+impl CircuitBreaker {
+    /// Open after `failure_threshold` consecutive failures, for
+    /// `open_duration`, then close after one successful probe call
+    ///
+    /// Errors blaming the caller, such as invalid arguments, a missing role
+    /// or a rate limit, do not count as failures.
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            half_open_probes: 1,
+            is_failure: Arc::new(|err| !err.blames_caller()),
+        }
+    }
+
+    /// Let `probes` calls through while half-open, and close only once they
+    /// all succeeded
+    pub fn with_half_open_probes(mut self, probes: u32) -> Self {
+        self.half_open_probes = probes.max(1);
+        self
+    }
+
+    /// Count only the errors `is_failure` accepts as failures
+    pub fn with_failure_predicate<F>(mut self, is_failure: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.is_failure = Arc::new(is_failure);
+        self
+    }
+
+    fn open_millis(&self) -> i64 {
+        i64::try_from(self.open_duration.as_millis()).unwrap_or(i64::MAX)
+    }
+
+    fn refused(&self, function: &str, remaining_ms: i64) -> Error {
+        Error::CircuitOpen {
+            function: function.to_string(),
+            retry_after: Duration::from_millis(u64::try_from(remaining_ms).unwrap_or_default()),
+        }
+    }
+
+    /// Let a call through or refuse it, counting it as a probe when
+    /// half-open
+    async fn admit(&self, cache: &ScopedCache, key: &str, function: &str) -> Result<Admission> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let (generation, state) = load(cache, key).await?;
+        let mut admission = Admission {
+            generation,
+            probe: None,
+            failures: state.failures,
+        };
+        if state.status != CircuitStatus::Open {
+            return Ok(admission);
+        }
+        let open = self.open_millis().max(1);
+        let elapsed = now - state.since;
+        if elapsed < open {
+            return Err(self.refused(function, open - elapsed));
+        }
+        // Probes that never reported back, such as from an instance that
+        // stopped, give way to new ones after another open duration
+        let round = elapsed / open;
+        let probes = format!("{}:{}:probes:{}", key, generation, round);
+        cache.set_if_absent(&probes, Value::from(0), Some(self.round_ttl())).await?;
+        if cache.incr(&probes, 1).await? > i64::from(self.half_open_probes) {
+            return Err(self.refused(function, open - elapsed % open));
+        }
+        admission.probe = Some(round);
+        Ok(admission)
+    }
+
+    /// Count the outcome of a call; returns the status the circuit changed
+    /// to, if it changed
+    async fn record(
+        &self,
+        cache: &ScopedCache,
+        key: &str,
+        admission: Admission,
+        failed: bool,
+    ) -> Result<Option<(CircuitStatus, u32)>> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let generation = admission.generation;
+        let failures = format!("{}:{}:failures", key, generation);
+        match (admission.probe, failed) {
+            (None, false) => {
+                // Start a new generation to reset the failures
+                let counted = cache.get(&failures).await?.and_then(|value| value.as_i64());
+                if counted.unwrap_or_default() > 0 {
+                    advance(cache, key, generation, &closed(now)).await?;
+                }
+                Ok(None)
+            }
+            (None, true) => {
+                cache.set_if_absent(&failures, Value::from(0), None).await?;
+                let counted = cache.incr(&failures, 1).await?;
+                // Only the call reaching the threshold opens the circuit
+                if counted != i64::from(self.failure_threshold) {
+                    return Ok(None);
+                }
+                let state = opened(now, self.failure_threshold);
+                let changed = advance(cache, key, generation, &state).await?;
+                Ok(changed.then_some((CircuitStatus::Open, self.failure_threshold)))
+            }
+            (Some(round), false) => {
+                let successes = format!("{}:{}:successes:{}", key, generation, round);
+                cache.set_if_absent(&successes, Value::from(0), Some(self.round_ttl())).await?;
+                if cache.incr(&successes, 1).await? != i64::from(self.half_open_probes) {
+                    return Ok(None);
+                }
+                let changed = advance(cache, key, generation, &closed(now)).await?;
+                Ok(changed.then_some((CircuitStatus::Closed, 0)))
+            }
+            (Some(_), true) => {
+                let state = opened(now, admission.failures);
+                let changed = advance(cache, key, generation, &state).await?;
+                Ok(changed.then_some((CircuitStatus::Open, admission.failures)))
+            }
+        }
+    }
+
+    /// Seconds the counters of a round of probes are kept: two open
+    /// durations, rounded up to whole seconds
+    fn round_ttl(&self) -> u64 {
+        let millis = u64::try_from(self.open_millis()).unwrap_or_default();
+        millis.saturating_mul(2).div_ceil(1000).max(1)
+    }
+}
+
+impl std::fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("failure_threshold", &self.failure_threshold)
+            .field("open_duration", &self.open_duration)
+            .field("half_open_probes", &self.half_open_probes)
+            .finish_non_exhaustive()
+    }
+}
+
+fn opened(now: i64, failures: u32) -> CircuitState {
+    CircuitState {
+        status: CircuitStatus::Open,
+        failures,
+        since: now,
+    }
+}
+
+fn closed(now: i64) -> CircuitState {
+    CircuitState {
+        status: CircuitStatus::Closed,
+        failures: 0,
+        since: now,
+    }
+}
+
+/// Find the current generation of a circuit and its state
+///
+/// `{key}:generation` counts the generations, but the counter may lag behind
+/// one written by a call that failed right after, so newer ones are looked
+/// for too.
+async fn load(cache: &ScopedCache, key: &str) -> Result<(u64, CircuitState)> {
+    let counted = cache.get(&format!("{}:generation", key)).await?;
+    let mut generation = counted.and_then(|value| value.as_u64()).unwrap_or_default();
+    let mut state = None;
+    while let Some(next) = cache.get(&format!("{}:{}", key, generation + 1)).await? {
+        generation += 1;
+        state = Some(next);
+    }
+    if state.is_none() && generation > 0 {
+        state = cache.get(&format!("{}:{}", key, generation)).await?;
+    }
+    match state {
+        Some(state) => Ok((generation, serde_json::from_value(state)?)),
+        None => Ok((generation, CircuitState::default())),
+    }
+}
+
+/// Write the generation after `generation`, unless another call already
+/// did; returns whether this call did
+async fn advance(cache: &ScopedCache, key: &str, generation: u64, state: &CircuitState) -> Result<bool> {
+    let next = format!("{}:{}", key, generation + 1);
+    if !cache.set_if_absent(&next, serde_json::to_value(state)?, None).await? {
+        return Ok(false);
+    }
+    cache.incr(&format!("{}:generation", key), 1).await?;
+    // Readers still on the old generation find the next one first
+    let previous = format!("{}:{}", key, generation);
+    let failures = format!("{}:{}:failures", key, generation);
+    cache.delete_many(&[&previous, &failures]).await?;
+    Ok(true)
+}
+
+/// Emit `circuit:opened` or `circuit:closed` without delaying the call
+fn announce(events: EventRegistry, tenant: Option<Tenant>, function: &str, status: CircuitStatus, failures: u32) {
+    let pattern = match status {
+        CircuitStatus::Open => "circuit:opened",
+        _ => "circuit:closed",
+    };
+    let data = json!({ "function": function, "failures": failures });
+    let Ok(mut event) = Event::custom(pattern, data) else {
+        return;
+    };
+    if let Some(tenant) = &tenant {
+        event = event.with_tenant(tenant);
+    }
+    tokio::spawn(async move {
+        if let Err(err) = events.emit_quiet(event).await {
+            tracing::warn!(error = %err, "failed to emit circuit event");
+        }
+    });
+}
+
+#[async_trait]
+impl FunctionMiddleware for CircuitBreaker {
+    async fn on_call(&self, call: FunctionCall, next: CallNext<'_>) -> Result<Value> {
+        let Some(current) = Context::current() else {
+            return next.run(call).await;
+        };
+        let cache = current.cache().clone();
+        let key = format!("circuit:{}", call.function);
+        let function = call.function.clone();
+        let events = call.context.as_ref().map(|context| context.events().clone());
+
+        // A cache that cannot be read leaves the circuit closed rather than
+        // refusing every call
+        let admission = match self.admit(&cache, &key, &function).await {
+            Err(err @ Error::CircuitOpen { .. }) => return Err(err),
+            Err(err) => {
+                tracing::warn!(function = %function, error = %err, "failed to read circuit state");
+                None
+            }
+            Ok(admission) => Some(admission),
+        };
+
+        let result = next.run(call).await;
+        let Some(admission) = admission else {
+            return result;
+        };
+        let failed = result.as_ref().err().is_some_and(|err| (self.is_failure)(err));
+        match self.record(&cache, &key, admission, failed).await {
+            Ok(Some((status, failures))) => {
+                match status {
+                    CircuitStatus::Open => tracing::warn!(function = %function, failures, "circuit opened"),
+                    _ => tracing::info!(function = %function, "circuit closed"),
+                }
+                if let Some(events) = events {
+                    announce(events, current.tenant().cloned(), &function, status, failures);
+                }
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!(function = %function, error = %err, "failed to record circuit state"),
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::DisabledModules;
+    use crate::cache::{MemoryCacheProvider, TenantCaches};
+    use crate::context::{self, FunctionContext};
+    use crate::functions::{FunctionHandler, LayeredFunctionHandler};
+
+    /// Succeeds, fails like a service that is down, or blames the caller,
+    /// depending on its first argument
+    struct Service;
+
+    #[async_trait]
+    impl FunctionHandler for Service {
+        async fn call(&self, args: Vec<Value>) -> Result<Value> {
+            match args.first().and_then(Value::as_str) {
+                Some("down") => Err(Error::Function("unavailable".to_string())),
+                Some("invalid") => Err(Error::Validation("bad amount".to_string())),
+                _ => Ok(Value::Null),
+            }
+        }
+    }
+
+    fn guarded(breaker: CircuitBreaker) -> LayeredFunctionHandler {
+        LayeredFunctionHandler {
+            inner: Arc::new(Service),
+            function: "billing::charge".to_string(),
+            middleware: vec![Arc::new(breaker)],
+        }
+    }
+
+    fn module_context() -> Context {
+        let cache = ScopedCache::new(Arc::new(MemoryCacheProvider::new()), "module:billing");
+        Context::new("billing", cache, None, None, DisabledModules::default(), TenantCaches::default(), None)
+    }
+
+    async fn call(handler: &LayeredFunctionHandler, events: &EventRegistry, outcome: &str) -> Result<Value> {
+        let context = FunctionContext::new("test", "test", Arc::new(MemoryCacheProvider::new()), events.clone());
+        handler.call_with_context(context, vec![json!(outcome)]).await
+    }
+
+    fn is_open(result: &Result<Value>) -> bool {
+        matches!(result, Err(Error::CircuitOpen { .. }))
+    }
+
+    #[tokio::test]
+    async fn opens_at_the_failure_threshold() {
+        let handler = guarded(CircuitBreaker::new(3, Duration::from_secs(60)));
+        let events = EventRegistry::new();
+        context::scope(module_context(), async {
+            for _ in 0..3 {
+                assert!(matches!(call(&handler, &events, "down").await, Err(Error::Function(_))));
+            }
+            assert!(is_open(&call(&handler, &events, "up").await));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn a_success_resets_the_failures() {
+        let handler = guarded(CircuitBreaker::new(2, Duration::from_secs(60)));
+        let events = EventRegistry::new();
+        context::scope(module_context(), async {
+            call(&handler, &events, "down").await.unwrap_err();
+            call(&handler, &events, "up").await.unwrap();
+            call(&handler, &events, "down").await.unwrap_err();
+            assert!(call(&handler, &events, "up").await.is_ok());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn does_not_count_errors_blaming_the_caller() {
+        let handler = guarded(CircuitBreaker::new(1, Duration::from_secs(60)));
+        let events = EventRegistry::new();
+        context::scope(module_context(), async {
+            assert!(matches!(call(&handler, &events, "invalid").await, Err(Error::Validation(_))));
+            assert!(call(&handler, &events, "up").await.is_ok());
+            call(&handler, &events, "down").await.unwrap_err();
+            assert!(is_open(&call(&handler, &events, "up").await));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn lets_a_limited_number_of_probes_through_once_half_open() -> Result<()> {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50)).with_half_open_probes(2);
+        let cache = ScopedCache::new(Arc::new(MemoryCacheProvider::new()), "module:billing");
+        let key = "circuit:billing::charge";
+        let admission = breaker.admit(&cache, key, "billing::charge").await?;
+        breaker.record(&cache, key, admission, true).await?;
+        assert!(matches!(
+            breaker.admit(&cache, key, "billing::charge").await,
+            Err(Error::CircuitOpen { .. })
+        ));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        for _ in 0..2 {
+            assert!(breaker.admit(&cache, key, "billing::charge").await?.probe.is_some());
+        }
+        assert!(matches!(
+            breaker.admit(&cache, key, "billing::charge").await,
+            Err(Error::CircuitOpen { .. })
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn a_failed_probe_reopens_the_circuit() {
+        let handler = guarded(CircuitBreaker::new(1, Duration::from_millis(50)));
+        let events = EventRegistry::new();
+        context::scope(module_context(), async {
+            call(&handler, &events, "down").await.unwrap_err();
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            assert!(matches!(call(&handler, &events, "down").await, Err(Error::Function(_))));
+            assert!(is_open(&call(&handler, &events, "up").await));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn successful_probes_close_the_circuit() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50)).with_half_open_probes(2);
+        let handler = guarded(breaker);
+        let events = EventRegistry::new();
+        let mut tap = events.tap();
+        context::scope(module_context(), async {
+            call(&handler, &events, "down").await.unwrap_err();
+            tokio::time::sleep(Duration::from_millis(60)).await;
+            call(&handler, &events, "up").await.unwrap();
+            call(&handler, &events, "up").await.unwrap();
+            assert!(call(&handler, &events, "up").await.is_ok());
+        })
+        .await;
+
+        let mut announced = Vec::new();
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(1), tap.recv()).await.unwrap().unwrap();
+            announced.push(event.pattern());
+        }
+        announced.sort();
+        assert_eq!(announced, ["circuit:closed", "circuit:opened"]);
+    }
+
+    #[test]
+    fn rounds_probe_counter_ttls_up_to_whole_seconds() {
+        let ttl = |millis| CircuitBreaker::new(1, Duration::from_millis(millis)).round_ttl();
+        assert_eq!(ttl(100), 1);
+        assert_eq!(ttl(1500), 3);
+        assert_eq!(ttl(60_000), 120);
+    }
+}
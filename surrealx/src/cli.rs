@@ -0,0 +1,155 @@
+//! Command line for server binaries
+//!
+//! With the `cli` feature, a binary hands its modules to [`run`] and gets
+//! the usual subcommands for free:
+//!
+//! ```rust,ignore
+//! #[tokio::main]
+//! async fn main() -> surrealx::Result<()> {
+//!     surrealx::cli::run(vec![billing::module()]).await
+//! }
+//! ```
+//!
+//! - `serve`, the default: serve HTTP until interrupted, like
+//!   [`SurrealX::serve`].
+//! - `list-functions`: print the fully qualified name of every function the
+//!   modules register.
+//! - `validate-config`: check the configuration and modules as
+//!   [`SurrealX::check`] does and print the build report; fails if a module
+//!   would be skipped.
+//! - `call <function> [args]...`: build the server, call a function and
+//!   print its result. Each argument is parsed as JSON, or else taken as a
+//!   string.
+//!
+//! The configuration is read from the file given with `--config` (or
+//! `SURREALX_CONFIG`), then overridden by the `SURREALX_*` variables of
+//! [`ServerConfig::with_env`], then by the `--bind`, `--data-path`,
+//! `--namespace`, `--database` and `--log-level` flags.
+
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use serde_json::Value;
+use crate::module::Module;
+use crate::server::{ServerConfig, SurrealX};
+use crate::error::{Error, Result};
+
+#[derive(Debug, Parser)]
+#[command(about = "SurrealDB with SurrealX extensions")]
+struct Cli {
+    /// Configuration file, `.toml` or `.json`
+    #[arg(long, short, global = true, env = "SURREALX_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on, such as `0.0.0.0:8000`
+    #[arg(long, global = true)]
+    bind: Option<String>,
+    /// Directory for on-disk storage
+    #[arg(long, global = true)]
+    data_path: Option<String>,
+    /// SurrealDB namespace
+    #[arg(long, global = true)]
+    namespace: Option<String>,
+    /// SurrealDB database
+    #[arg(long, global = true)]
+    database: Option<String>,
+    /// Log filter, such as `info` or `surrealx=debug,warn`
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Serve HTTP until interrupted
+    Serve,
+    /// List the functions the modules register
+    ListFunctions,
+    /// Check the configuration and modules without serving
+    ValidateConfig,
+    /// Call a function and print its result
+    Call {
+        /// Fully qualified name, such as `ext::business::calculate_tax`
+        function: String,
+        /// Arguments, each parsed as JSON or else taken as a string
+        args: Vec<String>,
+    },
+}
+
+impl Cli {
+    fn server_config(&self) -> Result<ServerConfig> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::from_file(path)?,
+            None => ServerConfig::default(),
+        }
+        .with_env()?;
+
+        if let Some(bind) = &self.bind {
+            config.bind_addr = bind.clone();
+        }
+        if let Some(data_path) = &self.data_path {
+            config.data_path = Some(data_path.clone());
+        }
+        if let Some(namespace) = &self.namespace {
+            config.namespace = namespace.clone();
+        }
+        if let Some(database) = &self.database {
+            config.database = database.clone();
+        }
+        if let Some(log_level) = &self.log_level {
+            config.log_level = Some(log_level.clone());
+        }
+        Ok(config)
+    }
+}
+
+/// Run the subcommand given on the command line with `modules`
+///
+/// Exits the process with a usage message if the command line is invalid.
+pub async fn run(modules: Vec<Module>) -> Result<()> {
+    run_with(modules.into_iter().fold(SurrealX::new(), SurrealX::with_module)).await
+}
+
+/// Run the subcommand given on the command line with a server set up in
+/// code, such as one with middleware or a custom cache
+///
+/// The configuration from the command line replaces any set with
+/// `SurrealX::with_config`.
+pub async fn run_with(surrealx: SurrealX) -> Result<()> {
+    let cli = Cli::parse();
+    let config = cli.server_config()?;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => surrealx.serve(config).await,
+        Command::ListFunctions => {
+            let mut functions = surrealx.with_config(config).check().await?.functions;
+            functions.sort();
+            for function in functions {
+                println!("{}", function);
+            }
+            Ok(())
+        }
+        Command::ValidateConfig => {
+            let report = surrealx.with_config(config).check().await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if report.is_degraded() {
+                return Err(Error::Config(format!(
+                    "{} module(s) failed to initialize",
+                    report.failed_modules.len()
+                )));
+            }
+            Ok(())
+        }
+        Command::Call { function, args } => {
+            let args = args
+                .into_iter()
+                .map(|arg| serde_json::from_str(&arg).unwrap_or(Value::String(arg)))
+                .collect();
+            let built = surrealx.with_config(config).build().await?;
+            built.start().await?;
+            let result = built.call_function(&function, args).await;
+            built.shutdown().await;
+            println!("{}", serde_json::to_string_pretty(&result?)?);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Configuration sources for module settings
+//!
+//! Sources are loaded when the server is built and merged into a single
+//! document keyed by module name, later sources overriding earlier ones:
+//!
+//! ```toml
+//! [billing]
+//! api_key = "sk_live_..."
+//! max_retries = 3
+//! ```
+//!
+//! A module declares the type of its section with
+//! [`Module::with_config`](crate::Module::with_config) and its handlers read
+//! it back through [`Context::config`](crate::Context::config).
+
+use std::any::Any;
+use std::path::PathBuf;
+use std::sync::Arc;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use crate::error::{Error, Result};
+
+/// Where module configuration is read from
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    /// A JSON document
+    Json(String),
+    /// A TOML document
+    Toml(String),
+    /// A `.toml` or `.json` file, read when the server is built
+    File(PathBuf),
+    /// Environment variables starting with a prefix
+    ///
+    /// `PREFIX_BILLING__API_KEY=abc` sets `api_key` in the `billing` section;
+    /// `__` separates nesting levels and names are lowercased. Values that
+    /// parse as JSON (numbers, booleans, arrays) are used as such, anything
+    /// else as a string.
+    Env(String),
+    /// An already parsed document
+    Value(Value),
+}
+
+impl ConfigSource {
+    pub fn json(document: impl Into<String>) -> Self {
+        ConfigSource::Json(document.into())
+    }
+
+    pub fn toml(document: impl Into<String>) -> Self {
+        ConfigSource::Toml(document.into())
+    }
+
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        ConfigSource::File(path.into())
+    }
+
+    pub fn env(prefix: impl Into<String>) -> Self {
+        ConfigSource::Env(prefix.into())
+    }
+
+    /// Read the source into a JSON document
+    pub fn load(&self) -> Result<Value> {
+        match self {
+            ConfigSource::Json(document) => parse_json(document),
+            ConfigSource::Toml(document) => parse_toml(document),
+            ConfigSource::File(path) => {
+                let document = std::fs::read_to_string(path)
+                    .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+                let is_toml = path.extension().is_some_and(|extension| extension == "toml");
+                if is_toml {
+                    parse_toml(&document)
+                } else {
+                    parse_json(&document)
+                }
+                .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))
+            }
+            ConfigSource::Env(prefix) => Ok(from_env(prefix, std::env::vars())),
+            ConfigSource::Value(value) => Ok(value.clone()),
+        }
+    }
+}
+
+fn parse_json(document: &str) -> Result<Value> {
+    serde_json::from_str(document).map_err(|err| Error::Config(format!("invalid JSON configuration: {}", err)))
+}
+
+fn parse_toml(document: &str) -> Result<Value> {
+    toml::from_str(document).map_err(|err| Error::Config(format!("invalid TOML configuration: {}", err)))
+}
+
+fn from_env(prefix: &str, vars: impl Iterator<Item = (String, String)>) -> Value {
+    let mut document = Value::Object(Map::new());
+
+    for (name, raw) in vars {
+        let Some(path) = name.strip_prefix(prefix) else {
+            continue;
+        };
+        let segments: Vec<String> = path.split("__").map(str::to_lowercase).collect();
+        if segments.iter().any(String::is_empty) {
+            continue;
+        }
+
+        let value = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+        let nested = segments
+            .into_iter()
+            .rev()
+            .fold(value, |value, segment| Value::Object(Map::from_iter([(segment, value)])));
+        merge(&mut document, nested);
+    }
+
+    document
+}
+
+/// Deep-merge `overlay` into `base`; objects merge key by key, anything else
+/// is replaced
+pub(crate) fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Load and merge every source, later ones taking precedence
+pub(crate) fn load_all(sources: &[ConfigSource]) -> Result<Value> {
+    let mut document = Value::Object(Map::new());
+    for source in sources {
+        merge(&mut document, source.load()?);
+    }
+    Ok(document)
+}
+
+pub(crate) type ConfigParser = dyn Fn(Value) -> Result<Arc<dyn Any + Send + Sync>> + Send + Sync;
+
+/// Build a parser that deserializes a module's section into `T`
+pub(crate) fn parser<T>() -> Arc<ConfigParser>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    Arc::new(|section| {
+        let config: T = serde_json::from_value(section)
+            .map_err(|err| Error::Config(format!("invalid {}: {}", std::any::type_name::<T>(), err)))?;
+        Ok(Arc::new(config) as Arc<dyn Any + Send + Sync>)
+    })
+}
@@ -0,0 +1,363 @@
+//! Context available to module handlers while they run
+
+use std::any::Any;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use serde_json::Value;
+use crate::admin::DisabledModules;
+use crate::auth::Identity;
+use crate::cache::{CacheProvider, ScopedCache, TenantCaches};
+use crate::db::{Database, ScopedDatabase};
+use crate::pool::DatabasePool;
+use crate::secrets::Secrets;
+use crate::events::{Event, EventRegistry};
+use crate::tenancy::Tenant;
+use crate::error::{Error, Result};
+
+tokio::task_local! {
+    static CURRENT: Context;
+}
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+/// Module-specific services for the function or listener being run
+///
+/// Set for every handler registered through a module; tasks spawned by the
+/// handler do not inherit it.
+#[derive(Clone)]
+pub struct Context {
+    module: String,
+    cache: ScopedCache,
+    config: Option<Arc<dyn Any + Send + Sync>>,
+    db: Option<ScopedDatabase>,
+    disabled: DisabledModules,
+    tenant_caches: TenantCaches,
+    tenant: Option<Tenant>,
+    secrets: Option<Secrets>,
+}
+
+impl Context {
+    pub(crate) fn new(
+        module: impl Into<String>,
+        cache: ScopedCache,
+        config: Option<Arc<dyn Any + Send + Sync>>,
+        db: Option<ScopedDatabase>,
+        disabled: DisabledModules,
+        tenant_caches: TenantCaches,
+        secrets: Option<Secrets>,
+    ) -> Self {
+        Self {
+            module: module.into(),
+            cache,
+            config,
+            db,
+            disabled,
+            tenant_caches,
+            tenant: None,
+            secrets,
+        }
+    }
+
+    /// The context of the same module for handling `tenant`'s calls and
+    /// events, with its cache and database partitioned by the tenant
+    pub(crate) fn for_tenant(&self, tenant: Option<&Tenant>) -> Self {
+        let Some(tenant) = tenant else {
+            return self.clone();
+        };
+        Self {
+            cache: ScopedCache::new(
+                Arc::new(self.tenant_caches.scope(self.cache.provider().clone(), tenant)),
+                self.cache.namespace(),
+            ),
+            db: self.db.clone().map(|db| db.for_tenant(tenant)),
+            tenant: Some(tenant.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// Get the context of the handler running on this task, if any
+    pub fn current() -> Option<Context> {
+        CURRENT.try_with(Context::clone).ok()
+    }
+
+    /// Name of the module the handler belongs to
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// Tenant the handler is running for, if any
+    pub fn tenant(&self) -> Option<&Tenant> {
+        self.tenant.as_ref()
+    }
+
+    /// Cache scoped to the module, so its keys cannot clash with other
+    /// modules', and to the tenant and its quota if there is one
+    pub fn cache(&self) -> &ScopedCache {
+        &self.cache
+    }
+
+    /// Module configuration declared with [`Module::with_config`](crate::Module::with_config)
+    ///
+    /// Returns `None` if the module declared no configuration or declared a
+    /// different type.
+    pub fn config<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.config.clone()?.downcast().ok()
+    }
+
+    /// The embedded database, for listeners and other handlers without a caller
+    ///
+    /// Fails until the server is built.
+    pub fn db(&self) -> Result<ScopedDatabase> {
+        self.db
+            .clone()
+            .ok_or_else(|| Error::Server("the database is not running".to_string()))
+    }
+
+    /// Value of the secret `name`, such as `stripe_api_key`; see the
+    /// [`secrets`](crate::secrets) module
+    ///
+    /// Fails if no secret provider is configured or it has no such secret.
+    pub async fn secret(&self, name: &str) -> Result<String> {
+        secrets(self.secrets.as_ref())?.get(name).await
+    }
+
+    /// Run `callback` with the new value whenever the secret `name` changes,
+    /// such as from a module's `on_init` hook
+    pub fn on_secret_rotation<F>(&self, name: impl Into<String>, callback: F) -> Result<()>
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        secrets(self.secrets.as_ref())?.on_rotate(name, callback);
+        Ok(())
+    }
+
+    /// Fail if the module was disabled through the admin API
+    pub(crate) fn check_enabled(&self) -> Result<()> {
+        if self.disabled.contains(&self.module) {
+            return Err(Error::ModuleDisabled(self.module.clone()));
+        }
+        Ok(())
+    }
+}
+
+fn secrets(secrets: Option<&Secrets>) -> Result<&Secrets> {
+    secrets.ok_or_else(|| Error::Config("no secret provider is configured".to_string()))
+}
+
+/// Run a future with `context` as the current context
+pub(crate) async fn scope<F: Future>(context: Context, future: F) -> F::Output {
+    CURRENT.scope(context, future).await
+}
+
+/// Run a closure, such as one polling a stream, with `context` as the
+/// current context
+pub(crate) fn sync_scope<R>(context: Context, f: impl FnOnce() -> R) -> R {
+    CURRENT.sync_scope(context, f)
+}
+
+/// Information about a function call and handles to the rest of the framework
+///
+/// Passed to [`FunctionHandler::call_with_context`](crate::FunctionHandler::call_with_context)
+/// for every call the framework makes, whether from SurrealQL, an event
+/// listener or `BuiltSurrealX::call_function`.
+#[derive(Clone)]
+pub struct FunctionContext {
+    request_id: String,
+    namespace: String,
+    database: String,
+    session: Option<Value>,
+    identity: Option<Identity>,
+    tenant: Option<Tenant>,
+    cache: Arc<dyn CacheProvider>,
+    tenant_caches: TenantCaches,
+    events: EventRegistry,
+    db: Option<Database>,
+    pool: Option<DatabasePool>,
+    secrets: Option<Secrets>,
+    idempotency_key: Option<String>,
+}
+
+impl FunctionContext {
+    /// Create a context for a call against `namespace` and `database`, with a
+    /// freshly generated request ID
+    pub fn new(
+        namespace: impl Into<String>,
+        database: impl Into<String>,
+        cache: Arc<dyn CacheProvider>,
+        events: EventRegistry,
+    ) -> Self {
+        Self {
+            request_id: next_request_id(),
+            namespace: namespace.into(),
+            database: database.into(),
+            session: None,
+            identity: None,
+            tenant: None,
+            cache,
+            tenant_caches: TenantCaches::default(),
+            events,
+            db: None,
+            pool: None,
+            secrets: None,
+            idempotency_key: None,
+        }
+    }
+
+    /// Set the request ID, e.g. from an `X-Request-Id` header
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = request_id.into();
+        self
+    }
+
+    /// Attach the caller's session data, such as authentication claims
+    pub fn with_session(mut self, session: Value) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Attach the caller's authenticated identity
+    pub fn with_identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Run the call for `tenant`, partitioning its cache and database by
+    /// the tenant; see the [`tenancy`](crate::tenancy) module
+    pub fn with_tenant(mut self, tenant: Tenant) -> Self {
+        self.cache = Arc::new(self.tenant_caches.scope(self.cache.clone(), &tenant));
+        self.tenant = Some(tenant);
+        self
+    }
+
+    /// Hold tenants to the cache quotas of `tenant_caches`
+    pub(crate) fn with_tenant_caches(mut self, tenant_caches: TenantCaches) -> Self {
+        self.tenant_caches = tenant_caches;
+        self
+    }
+
+    /// Attach the caller's idempotency key, e.g. from an `Idempotency-Key`
+    /// header; see [`FunctionOpts::idempotent`](crate::FunctionOpts::idempotent)
+    pub fn with_idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Attach the database calls run against
+    pub fn with_database(mut self, db: Database) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Run the call's queries over the connections of `pool`, if any
+    pub(crate) fn with_pool(mut self, pool: Option<DatabasePool>) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Read secrets for the call from `secrets`, if any
+    pub(crate) fn with_secrets(mut self, secrets: Option<Secrets>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Copy this context for a new call, with a new request ID unless one is given
+    pub(crate) fn for_request(&self, request_id: Option<String>) -> Self {
+        self.clone().with_request_id(request_id.unwrap_or_else(next_request_id))
+    }
+
+    /// Identifier of the request that led to this call
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// SurrealDB namespace the call runs against
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// SurrealDB database the call runs against
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// Session data of the caller, if any
+    pub fn session(&self) -> Option<&Value> {
+        self.session.as_ref()
+    }
+
+    /// Authenticated identity of the caller, if the request carried a valid token
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// Tenant the call is made for, if any
+    pub fn tenant(&self) -> Option<&Tenant> {
+        self.tenant.as_ref()
+    }
+
+    /// Idempotency key of the caller, if any
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    /// The server's cache provider, shared by every module, scoped to the
+    /// tenant and its quota if there is one
+    pub fn cache(&self) -> &Arc<dyn CacheProvider> {
+        &self.cache
+    }
+
+    /// The server's event registry, for emitting events
+    pub fn events(&self) -> &EventRegistry {
+        &self.events
+    }
+
+    /// The embedded database, bound to the namespace and database of the
+    /// call, or of its tenant, and to its caller
+    ///
+    /// See [`ScopedDatabase`] for how the caller is applied to queries.
+    /// Fails if no database is attached, as in contexts built outside a server.
+    pub fn db(&self) -> Result<ScopedDatabase> {
+        let db = self
+            .db
+            .clone()
+            .ok_or_else(|| Error::Server("no database is attached to this context".to_string()))?;
+        let db = ScopedDatabase::new(db, &self.namespace, &self.database).with_pool(self.pool.clone());
+        let db = match &self.tenant {
+            Some(tenant) => db.for_tenant(tenant),
+            None => db,
+        };
+        Ok(db.for_caller(self.identity.clone()))
+    }
+
+    /// Value of the secret `name`, such as `stripe_api_key`; see the
+    /// [`secrets`](crate::secrets) module
+    ///
+    /// Fails if no secret provider is configured or it has no such secret.
+    pub async fn secret(&self, name: &str) -> Result<String> {
+        secrets(self.secrets.as_ref())?.get(name).await
+    }
+
+    /// Emit `event` only once the data changes of the call are committed
+    ///
+    /// When the function is called from SurrealQL, the event is written to
+    /// the outbox table in the same transaction as the query and emitted by
+    /// the outbox relay after it commits; nothing is emitted if the query
+    /// fails or the function returns an error. Fails outside a call made
+    /// through the [`Outbox`](crate::Outbox), such as from a spawned task.
+    /// An event without a tenant gets the call's.
+    pub fn emit_after_commit(&self, mut event: Event) -> Result<()> {
+        if let (None, Some(tenant)) = (&event.tenant, &self.tenant) {
+            event = event.with_tenant(tenant);
+        }
+        crate::outbox::record_after_commit(event)
+    }
+}
+
+pub(crate) fn next_request_id() -> String {
+    format!(
+        "{}-{}",
+        chrono::Utc::now().timestamp_millis(),
+        NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+    )
+}
@@ -0,0 +1,235 @@
+//! Embedded SurrealDB instance, or the external server SurrealX runs in
+//! front of
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use surrealdb::engine::any::{self, Any};
+use surrealdb::opt::auth::Root;
+use surrealdb::Surreal;
+use crate::auth::Identity;
+use crate::pool::DatabasePool;
+use crate::tenancy::Tenant;
+use crate::server::ServerConfig;
+use crate::error::{Error, Result};
+
+/// Handle to the embedded database, or to the upstream server
+pub type Database = Surreal<Any>;
+
+/// Open the database engine selected by the configuration
+///
+/// Data is kept in memory unless `data_path` is set, in which case it is
+/// stored on disk with RocksDB (requires the `kv-rocksdb` feature). With
+/// `upstream` set, connects to that server instead, signing in as its root
+/// user when credentials are given.
+pub(crate) async fn connect(config: &ServerConfig) -> Result<Database> {
+    let db = any::connect(endpoint(config)?).await?;
+    if let Some(upstream) = &config.upstream {
+        if let (Some(username), Some(password)) = (&upstream.username, &upstream.password) {
+            db.signin(Root { username, password }).await?;
+        }
+    }
+    db.use_ns(&config.namespace).use_db(&config.database).await?;
+    Ok(db)
+}
+
+fn endpoint(config: &ServerConfig) -> Result<String> {
+    match (&config.upstream, &config.data_path) {
+        (Some(_), Some(path)) => Err(Error::Config(format!(
+            "data_path '{}' cannot be used with an upstream server",
+            path
+        ))),
+        (Some(upstream), None) if cfg!(feature = "upstream") => Ok(upstream.url.clone()),
+        (Some(upstream), None) => Err(Error::Config(format!(
+            "upstream '{}' requires the upstream feature",
+            upstream.url
+        ))),
+        (None, None) => Ok("mem://".to_string()),
+        (None, Some(path)) if cfg!(feature = "kv-rocksdb") => Ok(format!("rocksdb://{}", path)),
+        (None, Some(path)) => Err(Error::Config(format!(
+            "data_path '{}' requires the kv-rocksdb feature",
+            path
+        ))),
+    }
+}
+
+/// Run SurrealQL and return the result of each statement as JSON
+///
+/// Fails only if the query cannot be parsed or sent; statement errors are
+/// reported per statement, like SurrealDB's own `/sql` endpoint.
+pub(crate) async fn query(db: &Database, sql: String) -> Result<Vec<Value>> {
+    let mut response = db.query(sql).await?;
+    let mut results = Vec::with_capacity(response.num_statements());
+
+    for index in 0..response.num_statements() {
+        let result = match response.take::<surrealdb::Value>(index) {
+            Ok(value) => json!({ "status": "OK", "result": value.into_inner().into_json() }),
+            Err(err) => json!({ "status": "ERR", "result": err.to_string() }),
+        };
+        results.push(result);
+    }
+
+    Ok(results)
+}
+
+/// Statement switching the rest of a query to `namespace` and `database`
+///
+/// The embedded engine's session stays on the server's namespace; a `USE`
+/// statement only applies to the query it starts. Both names are tenant
+/// identifiers or come from the configuration. Queries it starts must pass
+/// [`sql::check_bound`](crate::sql::check_bound), so they cannot switch
+/// again.
+pub(crate) fn use_statement(namespace: &str, database: &str) -> String {
+    format!("USE NS `{}` DB `{}`;\n", namespace.replace('`', ""), database.replace('`', ""))
+}
+
+/// Database access for handlers, bound to the server's namespace and
+/// database and to the caller of the call being handled
+///
+/// Obtained from [`FunctionContext::db`](crate::FunctionContext::db) or
+/// [`Context::db`](crate::Context::db). The embedded engine has a single
+/// session, so queries run with the server's own access and SurrealDB does
+/// not apply table permissions for the caller. Instead the caller is bound
+/// to every query as `$caller`, the record the token was issued for, and
+/// `$claims`, every claim of the token (both `NONE` without a caller), for
+/// queries to filter on; a caller whose token names another namespace or
+/// database is refused. For a [`Tenant`], queries run in the tenant's
+/// namespace instead of the server's, and are refused if they have a
+/// statement that would leave it, such as `USE NS other`.
+#[derive(Clone)]
+pub struct ScopedDatabase {
+    db: Database,
+    namespace: String,
+    database: String,
+    identity: Option<Identity>,
+    /// Whether queries switch to `namespace` and `database` first
+    switched: bool,
+    /// Connections to the upstream server queries run over instead of `db`
+    pool: Option<DatabasePool>,
+}
+
+impl ScopedDatabase {
+    pub(crate) fn new(db: Database, namespace: impl Into<String>, database: impl Into<String>) -> Self {
+        Self {
+            db,
+            namespace: namespace.into(),
+            database: database.into(),
+            identity: None,
+            switched: false,
+            pool: None,
+        }
+    }
+
+    /// Run queries over the connections of `pool`, if any
+    pub(crate) fn with_pool(mut self, pool: Option<DatabasePool>) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Bind the handle to the namespace of `tenant`
+    pub(crate) fn for_tenant(mut self, tenant: &Tenant) -> Self {
+        self.namespace = tenant.namespace().to_string();
+        self.switched = true;
+        self
+    }
+
+    /// Bind the handle to the caller of a call
+    pub(crate) fn for_caller(mut self, identity: Option<Identity>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// SurrealDB namespace queries run against
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// SurrealDB database queries run against
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    /// Authenticated caller bound to queries, if any
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// Run SurrealQL and return the result of each statement as JSON
+    pub async fn query(&self, sql: impl Into<String>) -> Result<Vec<Value>> {
+        self.query_with(sql, Value::Null).await
+    }
+
+    /// Run SurrealQL with `vars`, an object of query parameters, and return
+    /// the result of each statement as JSON
+    ///
+    /// Unlike the `/sql` endpoint, fails on the first statement that errors.
+    /// `$caller` and `$claims` cannot be overridden by `vars`.
+    pub async fn query_with(&self, sql: impl Into<String>, vars: Value) -> Result<Vec<Value>> {
+        self.check_caller()?;
+        let mut bindings = match vars {
+            Value::Null => Map::new(),
+            Value::Object(vars) => vars,
+            other => {
+                return Err(Error::Validation(format!(
+                    "query parameters must be an object, not {}",
+                    other
+                )))
+            }
+        };
+        let identity = self.identity.as_ref();
+        bindings.insert(
+            "caller".to_string(),
+            identity.and_then(|identity| identity.record.clone()).map_or(Value::Null, Value::String),
+        );
+        bindings.insert(
+            "claims".to_string(),
+            identity.map_or(Value::Null, |identity| identity.claims.clone()),
+        );
+
+        let sql = sql.into();
+        let (sql, skip) = if self.switched {
+            crate::sql::check_bound(&sql)?;
+            (format!("{}{}", use_statement(&self.namespace, &self.database), sql), 1)
+        } else {
+            (sql, 0)
+        };
+        let db = match &self.pool {
+            Some(pool) => pool.get().await?,
+            None => self.db.clone(),
+        };
+        let mut response = db.query(sql).bind(bindings).await?;
+        let mut results = Vec::with_capacity(response.num_statements());
+        for index in skip..response.num_statements() {
+            results.push(response.take::<surrealdb::Value>(index)?.into_inner().into_json());
+        }
+        Ok(results)
+    }
+
+    /// Run a single SurrealQL statement and deserialize its result
+    pub async fn query_one<T: DeserializeOwned>(
+        &self,
+        sql: impl Into<String>,
+        vars: Value,
+    ) -> Result<T> {
+        let result = self.query_with(sql, vars).await?.pop().unwrap_or(Value::Null);
+        Ok(serde_json::from_value(result)?)
+    }
+
+    fn check_caller(&self) -> Result<()> {
+        let Some(identity) = &self.identity else {
+            return Ok(());
+        };
+        if let Some(namespace) = identity.namespace.as_deref().filter(|ns| *ns != self.namespace) {
+            return Err(Error::Unauthorized(format!(
+                "token was issued for namespace '{}'",
+                namespace
+            )));
+        }
+        if let Some(database) = identity.database.as_deref().filter(|db| *db != self.database) {
+            return Err(Error::Unauthorized(format!(
+                "token was issued for database '{}'",
+                database
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Listener retries and a dead-letter queue for events that still fail
+//!
+//! Letters are kept under a cache key ([`CacheDeadLetterSink`]), in a table
+//! ([`SurrealDeadLetterSink`]), or handed to the listeners of another
+//! pattern as events ([`EventDeadLetterSink`]).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use crate::cache::CacheProvider;
+use crate::db::Database;
+use crate::events::{Event, EventRegistry};
+use crate::error::Result;
+
+pub use crate::retry::{RetryPolicy, RetryingListener};
+
+static NEXT_LETTER: AtomicU64 = AtomicU64::new(0);
+
+/// An event a listener failed to handle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Identifier used to re-drive or discard the letter
+    pub id: String,
+    /// [Name](crate::EventRegistry::listener_name) of the listener that
+    /// failed, which stays the same across restarts
+    pub listener: String,
+    /// Pattern the listener was registered under
+    pub pattern: String,
+    /// The event it failed to handle
+    pub event: Event,
+    /// The last error the listener returned
+    pub error: String,
+    /// When the listener gave up, in milliseconds since the Unix epoch
+    pub failed_at: i64,
+}
+
+impl DeadLetter {
+    pub(crate) fn new(listener: String, pattern: String, event: Event, error: String) -> Self {
+        let failed_at = chrono::Utc::now().timestamp_millis();
+        Self {
+            id: format!("{}-{}", failed_at, NEXT_LETTER.fetch_add(1, Ordering::Relaxed)),
+            listener,
+            pattern,
+            event,
+            error,
+            failed_at,
+        }
+    }
+}
+
+/// Where events land once their listener has exhausted its retries
+#[async_trait]
+pub trait DeadLetterSink: Send + Sync {
+    /// Store a dead letter
+    async fn push(&self, letter: DeadLetter) -> Result<()>;
+
+    /// List stored dead letters, oldest first
+    async fn list(&self) -> Result<Vec<DeadLetter>>;
+
+    /// Discard a dead letter
+    async fn remove(&self, id: &str) -> Result<()>;
+}
+
+/// Dead-letter sink keeping letters under a single cache key
+pub struct CacheDeadLetterSink {
+    cache: Arc<dyn CacheProvider>,
+    key: String,
+    lock: Mutex<()>,
+}
+
+impl CacheDeadLetterSink {
+    /// Store letters under `sx:dead_letters`
+    pub fn new(cache: Arc<dyn CacheProvider>) -> Self {
+        Self {
+            cache,
+            key: "sx:dead_letters".to_string(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Store letters under a different key
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = key.into();
+        self
+    }
+
+    async fn load(&self) -> Result<Vec<DeadLetter>> {
+        match self.cache.get(&self.key).await? {
+            Some(letters) => Ok(serde_json::from_value(letters)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl DeadLetterSink for CacheDeadLetterSink {
+    async fn push(&self, letter: DeadLetter) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut letters = self.load().await?;
+        letters.push(letter);
+        self.cache.set(&self.key, serde_json::to_value(letters)?, None).await
+    }
+
+    async fn list(&self) -> Result<Vec<DeadLetter>> {
+        self.load().await
+    }
+
+    async fn remove(&self, id: &str) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut letters = self.load().await?;
+        letters.retain(|letter| letter.id != id);
+        self.cache.set(&self.key, serde_json::to_value(letters)?, None).await
+    }
+}
+
+/// Dead-letter sink storing letters in a table of the embedded database
+pub struct SurrealDeadLetterSink {
+    db: Database,
+    table: String,
+}
+
+impl SurrealDeadLetterSink {
+    /// Store letters in the `sx_dead_letter` table
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            table: "sx_dead_letter".to_string(),
+        }
+    }
+
+    /// Store letters in a different table
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+}
+
+#[async_trait]
+impl DeadLetterSink for SurrealDeadLetterSink {
+    async fn push(&self, letter: DeadLetter) -> Result<()> {
+        // Nest the letter so its id does not become the record id
+        self.db
+            .query("CREATE type::table($table) CONTENT $record RETURN NONE")
+            .bind(("table", self.table.clone()))
+            .bind(("record", json!({ "letter": serde_json::to_value(letter)? })))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<DeadLetter>> {
+        let mut response = self
+            .db
+            .query("SELECT VALUE letter FROM type::table($table)")
+            .bind(("table", self.table.clone()))
+            .await?;
+        let letters = response.take::<surrealdb::Value>(0)?.into_inner().into_json();
+        let mut letters: Vec<DeadLetter> = serde_json::from_value(letters)?;
+        letters.sort_by_key(|letter| letter.failed_at);
+        Ok(letters)
+    }
+
+    async fn remove(&self, id: &str) -> Result<()> {
+        self.db
+            .query("DELETE type::table($table) WHERE letter.id = $id")
+            .bind(("table", self.table.clone()))
+            .bind(("id", id.to_string()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+}
+
+/// Dead-letter sink emitting each letter as an event, for the listeners of
+/// another pattern to handle, such as one paging whoever is on call
+///
+/// The event has the letter as its data and the tenant of the failed event.
+/// Letters are handed on rather than kept, so there are none to list or
+/// re-drive. A letter for an event of the sink's own pattern is logged and
+/// dropped, so a failing dead-letter listener cannot loop.
+pub struct EventDeadLetterSink {
+    events: EventRegistry,
+    pattern: String,
+}
+
+impl EventDeadLetterSink {
+    /// Emit letters on `events` as `pattern`, such as `dead_letter:events`
+    pub fn new(events: EventRegistry, pattern: impl Into<String>) -> Result<Self> {
+        let pattern = pattern.into();
+        Event::custom(&pattern, Value::Null)?;
+        Ok(Self { events, pattern })
+    }
+}
+
+#[async_trait]
+impl DeadLetterSink for EventDeadLetterSink {
+    async fn push(&self, letter: DeadLetter) -> Result<()> {
+        if letter.event.pattern() == self.pattern {
+            tracing::error!(listener = %letter.listener, error = %letter.error, "dead-letter listener failed; letter dropped");
+            return Ok(());
+        }
+        let tenant = letter.event.tenant.clone();
+        let mut event = Event::custom(&self.pattern, serde_json::to_value(letter)?)?;
+        event.tenant = tenant;
+        self.events.emit(event).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<DeadLetter>> {
+        Ok(Vec::new())
+    }
+
+    async fn remove(&self, _id: &str) -> Result<()> {
+        Ok(())
+    }
+}
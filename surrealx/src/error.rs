@@ -21,19 +21,122 @@ pub enum Error {
     #[error("Configuration error: {0}")]
     Config(String),
 
+    #[error("Validation error: {0}")]
+    Validation(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// The caller lacks every role, or access method, a guarded function
+    /// accepts
+    #[error("Forbidden: '{function}' requires one of {}", .required.join(", "))]
+    Forbidden { function: String, required: Vec<String> },
+
+    /// Arguments of a call do not fit the function's validated signature
+    #[error(
+        "Invalid arguments to '{function}': {}",
+        .errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    InvalidArguments {
+        function: String,
+        errors: Vec<crate::functions::ArgumentError>,
+    },
+
+    /// A rate limit was exceeded; `retry_after` is how long until it is not
+    #[error("Rate limited: '{key}', retry after {:.1}s", .retry_after.as_secs_f64())]
+    RateLimited { key: String, retry_after: std::time::Duration },
+
+    /// A circuit breaker refused the call while the function keeps failing;
+    /// `retry_after` is how long until it lets calls through again
+    #[error("Circuit open: '{function}', retry after {:.1}s", .retry_after.as_secs_f64())]
+    CircuitOpen { function: String, retry_after: std::time::Duration },
+
+    /// The module was disabled through the admin API
+    #[error("Module disabled: {0}")]
+    ModuleDisabled(String),
+
+    /// A migration failed to apply or roll back
+    #[error("Migration error: {0}")]
+    Migration(String),
+
+    /// A secret provider failed to read a secret
+    #[error("Secret error: {0}")]
+    Secret(String),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("Database error: {0}")]
+    Database(Box<surrealdb::Error>),
+
     #[cfg(feature = "redis-cache")]
     #[error("Redis error: {0}")]
     Redis(#[from] redis::RedisError),
 
+    #[cfg(feature = "memcached-cache")]
+    #[error("Memcached error: {0}")]
+    Memcached(#[from] memcache::MemcacheError),
+
+    #[cfg(feature = "disk-cache")]
+    #[error("Disk cache error: {0}")]
+    Sled(#[from] sled::Error),
+
+    #[cfg(feature = "dylib-plugins")]
+    #[error("Plugin error: {0}")]
+    Plugin(String),
+
+    #[cfg(feature = "wasm-plugins")]
+    #[error("WASM plugin error: {0}")]
+    Wasm(String),
+
+    #[cfg(feature = "tls")]
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[cfg(feature = "webhooks")]
+    #[error("Webhook error: {0}")]
+    Webhook(String),
+
+    #[cfg(feature = "kafka")]
+    #[error("Kafka error: {0}")]
+    Kafka(String),
+
+    #[cfg(feature = "nats")]
+    #[error("NATS error: {0}")]
+    Nats(String),
+
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT error: {0}")]
+    Mqtt(String),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
+
+impl Error {
+    /// Whether the error blames the caller, such as invalid arguments, a
+    /// missing role or a rate limit, so trying again would not help
+    pub(crate) fn blames_caller(&self) -> bool {
+        matches!(
+            self,
+            Error::InvalidArguments { .. }
+                | Error::Validation(_)
+                | Error::Unauthorized(_)
+                | Error::Forbidden { .. }
+                | Error::RateLimited { .. }
+                | Error::CircuitOpen { .. }
+        )
+    }
+}
+
+impl From<surrealdb::Error> for Error {
+    fn from(err: surrealdb::Error) -> Self {
+        Error::Database(Box::new(err))
+    }
+}
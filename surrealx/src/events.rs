@@ -1,12 +1,28 @@
 //! Event system for database change notifications
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::sync::RwLock;
-use crate::error::Result;
+use tokio::sync::{broadcast, RwLock};
+use tokio::time::Instant;
+use tracing::Instrument;
+use crate::batch::{BatchEventListener, BatchOptions, BatchingListener};
+use crate::queue::{EventQueue, EventQueueConfig, QueueStats};
+use crate::functions::{FunctionHandler, InFlight};
+use crate::context::{self, Context, FunctionContext};
+use crate::eventstore::EventStore;
+use crate::deadletter::{DeadLetter, DeadLetterSink};
+use crate::telemetry::ModuleSpan;
+use crate::tenancy::Tenant;
+use crate::error::{Error, Result};
+
+/// How many events a receiver of [`EventRegistry::tap`] may fall behind
+pub(crate) const TAP_CAPACITY: usize = 1024;
 
 /// Database event types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,9 +44,13 @@ pub struct Event {
     /// Record ID
     pub record_id: Option<String>,
     /// Event data
+    #[serde(default)]
     pub data: Value,
     /// Timestamp
     pub timestamp: i64,
+    /// Tenant the event belongs to; see the [`tenancy`](crate::tenancy) module
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
 }
 
 impl Event {
@@ -42,15 +62,58 @@ impl Event {
             record_id: None,
             data,
             timestamp: chrono::Utc::now().timestamp(),
+            tenant: None,
         }
     }
 
+    /// Create a `Create` event for a record
+    pub fn created(table: impl Into<String>, id: impl Into<String>, data: Value) -> Self {
+        Self::new(EventType::Create, table, data).with_record_id(id)
+    }
+
+    /// Create an `Update` event for a record
+    pub fn updated(table: impl Into<String>, id: impl Into<String>, data: Value) -> Self {
+        Self::new(EventType::Update, table, data).with_record_id(id)
+    }
+
+    /// Create a `Delete` event for a record
+    pub fn deleted(table: impl Into<String>, id: impl Into<String>) -> Self {
+        Self::new(EventType::Delete, table, Value::Null).with_record_id(id)
+    }
+
+    /// Create a `Custom` event from a `table:name` pattern
+    ///
+    /// The name becomes both the custom event type and the record ID, so the
+    /// event matches listeners on the exact pattern as well as `table:*`.
+    pub fn custom(pattern: &str, data: Value) -> Result<Self> {
+        let (table, name) = pattern
+            .split_once(':')
+            .filter(|(table, name)| !table.is_empty() && !name.is_empty())
+            .ok_or_else(|| {
+                Error::Validation(format!("event pattern '{}' must have the form table:name", pattern))
+            })?;
+        if pattern.contains(|c: char| c == '*' || c.is_whitespace()) {
+            return Err(Error::Validation(format!(
+                "event pattern '{}' must not contain wildcards or whitespace",
+                pattern
+            )));
+        }
+
+        Ok(Self::new(EventType::Custom(name.to_string()), table, data).with_record_id(name))
+    }
+
     /// Set record ID
     pub fn with_record_id(mut self, id: impl Into<String>) -> Self {
         self.record_id = Some(id.into());
         self
     }
 
+    /// Set the tenant the event belongs to
+    pub fn with_tenant(mut self, tenant: &Tenant) -> Self {
+        self.tenant = Some(tenant.id().to_string());
+        self
+    }
+
     /// Get the pattern for this event (e.g., "orders:123" or "orders:*")
     pub fn pattern(&self) -> String {
         if let Some(id) = &self.record_id {
@@ -59,6 +122,111 @@ impl Event {
             format!("{}:*", self.table)
         }
     }
+
+    /// Deserialize the event's data into `T`
+    pub fn data_as<T: DeserializeOwned>(&self) -> Result<T> {
+        T::deserialize(&self.data).map_err(|err| {
+            Error::Validation(format!(
+                "data of event {} is not a valid {}: {}",
+                self.pattern(),
+                std::any::type_name::<T>(),
+                err
+            ))
+        })
+    }
+}
+
+/// Shape the data of the events matching a pattern must have
+///
+/// Registered with [`EventRegistry::register_schema`] or
+/// [`Module::with_event_schema`](crate::Module::with_event_schema), a schema
+/// makes emitting an event whose data does not deserialize into its type
+/// fail before the event is recorded or any listener runs.
+#[derive(Clone)]
+pub struct EventSchema {
+    name: &'static str,
+    check: Arc<dyn Fn(&Value) -> std::result::Result<(), String> + Send + Sync>,
+}
+
+impl EventSchema {
+    /// Require data that deserializes into `T`
+    pub fn of<T: DeserializeOwned + 'static>() -> Self {
+        Self {
+            name: std::any::type_name::<T>(),
+            check: Arc::new(|data| T::deserialize(data).map(|_| ()).map_err(|err| err.to_string())),
+        }
+    }
+
+    /// Name of the type the data must deserialize into
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    fn check(&self, data: &Value) -> std::result::Result<(), String> {
+        (self.check)(data)
+    }
+}
+
+impl std::fmt::Debug for EventSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("EventSchema").field(&self.name).finish()
+    }
+}
+
+/// Interceptor wrapping [`EventRegistry::emit`], like a tower layer for the
+/// event pipeline
+///
+/// A middleware sees every event before it is validated, recorded and
+/// delivered, and hands it on with [`EmitNext::run`]. It can change the
+/// event (adding a tenant ID or trace context), drop it by returning a
+/// report without calling `next` (filtering, deduplication) or inspect the
+/// report `next` returns (auditing).
+///
+/// Middleware added with [`EventRegistry::with_middleware`] wraps every
+/// emit; middleware of a module wraps only the events emitted while one of
+/// the module's handlers runs, inside the global middleware.
+#[async_trait]
+pub trait EventMiddleware: Send + Sync {
+    /// Handle an emitted event, passing it on with `next.run(event)`
+    async fn on_emit(&self, event: Event, next: EmitNext<'_>) -> Result<EmitReport>;
+}
+
+/// The rest of the middleware chain and the delivery after it
+pub struct EmitNext<'a> {
+    registry: &'a EventRegistry,
+    middleware: &'a [Arc<dyn EventMiddleware>],
+    deadline: Option<Instant>,
+}
+
+impl EmitNext<'_> {
+    /// Pass `event` to the next middleware, or deliver it to listeners after
+    /// the last one
+    pub async fn run(self, event: Event) -> Result<EmitReport> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => {
+                let next = EmitNext {
+                    registry: self.registry,
+                    middleware: rest,
+                    deadline: self.deadline,
+                };
+                middleware.on_emit(event, next).await
+            }
+            None => match self.deadline {
+                Some(deadline) => self.registry.deliver_by(event, deadline).await,
+                None => self.registry.deliver(event).await,
+            },
+        }
+    }
+}
+
+/// Whether the listeners after one that handled an event still see it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Propagation {
+    /// Pass the event on to the next listeners
+    #[default]
+    Continue,
+    /// Stop here; listeners of lower priority do not run
+    Stop,
 }
 
 /// Listener for events
@@ -66,6 +234,84 @@ impl Event {
 pub trait EventListener: Send + Sync {
     /// Handle an event
     async fn on_event(&self, event: Event) -> Result<()>;
+
+    /// Handle an event and say whether it propagates to the listeners after
+    /// this one
+    ///
+    /// This is what the registry calls; it defaults to `on_event` followed by
+    /// [`Propagation::Continue`]. Listeners that can veto events, such as
+    /// validators, override it and make `on_event` call it.
+    async fn handle(&self, event: Event) -> Result<Propagation> {
+        self.on_event(event).await?;
+        Ok(Propagation::Continue)
+    }
+
+    /// Where the listener runs among those matching an event
+    ///
+    /// Higher priorities run first; listeners of equal priority run in the
+    /// order they matched. Defaults to 0.
+    fn priority(&self) -> i32 {
+        0
+    }
+}
+
+/// Listener wrapper running at a given priority
+pub struct PrioritizedListener {
+    inner: Arc<dyn EventListener>,
+    priority: i32,
+}
+
+impl PrioritizedListener {
+    pub fn new(inner: Arc<dyn EventListener>, priority: i32) -> Self {
+        Self { inner, priority }
+    }
+}
+
+#[async_trait]
+impl EventListener for PrioritizedListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.handle(event).await.map(|_| ())
+    }
+
+    async fn handle(&self, event: Event) -> Result<Propagation> {
+        self.inner.handle(event).await
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+}
+
+/// Event listener using async closures that decide whether the event
+/// propagates
+pub struct PropagatingListener<F>
+where
+    F: Fn(Event) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Propagation>> + Send>> + Send + Sync,
+{
+    handler: F,
+}
+
+impl<F> PropagatingListener<F>
+where
+    F: Fn(Event) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Propagation>> + Send>> + Send + Sync,
+{
+    pub fn new(handler: F) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl<F> EventListener for PropagatingListener<F>
+where
+    F: Fn(Event) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Propagation>> + Send>> + Send + Sync,
+{
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.handle(event).await.map(|_| ())
+    }
+
+    async fn handle(&self, event: Event) -> Result<Propagation> {
+        (self.handler)(event).await
+    }
 }
 
 /// Simple event listener using async closures
@@ -95,79 +341,1049 @@ where
     }
 }
 
+/// Event listener receiving the event's data deserialized into `T`
+///
+/// An event whose data does not deserialize fails the listener with
+/// [`Error::Validation`] without calling the handler.
+pub struct TypedEventListener<T, F> {
+    handler: F,
+    data: PhantomData<fn() -> T>,
+}
+
+impl<T, F, Fut> TypedEventListener<T, F>
+where
+    T: DeserializeOwned + Send + 'static,
+    F: Fn(Event, T) -> Fut + Send + Sync,
+    Fut: std::future::Future<Output = Result<()>> + Send,
+{
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            data: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<T, F, Fut> EventListener for TypedEventListener<T, F>
+where
+    T: DeserializeOwned + Send + 'static,
+    F: Fn(Event, T) -> Fut + Send + Sync,
+    Fut: std::future::Future<Output = Result<()>> + Send,
+{
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let data = event.data_as::<T>()?;
+        (self.handler)(event, data).await
+    }
+}
+
+/// Event listener that runs inside a span and context identifying its module
+pub(crate) struct InstrumentedEventListener {
+    pub(crate) inner: Arc<dyn EventListener>,
+    pub(crate) context: Context,
+    pub(crate) pattern: String,
+    pub(crate) trace: ModuleSpan,
+    pub(crate) in_flight: InFlight,
+}
+
+#[async_trait]
+impl EventListener for InstrumentedEventListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.handle(event).await.map(|_| ())
+    }
+
+    async fn handle(&self, event: Event) -> Result<Propagation> {
+        if self.context.check_enabled().is_err() {
+            tracing::debug!(module = %self.context.module(), pattern = %self.pattern, "module is disabled; skipping listener");
+            return Ok(Propagation::Continue);
+        }
+        let _in_flight = self.in_flight.enter();
+        let span = self.trace.in_scope(|| {
+            tracing::info_span!(
+                "sx.listener",
+                module = %self.context.module(),
+                pattern = %self.pattern,
+            )
+        });
+        let tenant = event.tenant.clone().map(Tenant::new).transpose()?;
+        context::scope(self.context.for_tenant(tenant.as_ref()), self.inner.handle(event))
+            .instrument(span)
+            .await
+    }
+
+    fn priority(&self) -> i32 {
+        self.inner.priority()
+    }
+}
+
+/// Identifier assigned to a listener when it is registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ListenerId(u64);
+
+impl std::fmt::Display for ListenerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "listener-{}", self.0)
+    }
+}
+
+/// Outcome of dispatching an event to its listeners
+#[derive(Debug, Default)]
+pub struct EmitReport {
+    /// Number of listeners whose pattern matched the event
+    pub matched: usize,
+    /// Number of listeners that handled the event successfully
+    pub succeeded: usize,
+    /// Listeners that returned an error
+    pub failed: Vec<(ListenerId, Error)>,
+    /// Listeners abandoned or never started because the deadline passed
+    pub skipped: Vec<ListenerId>,
+    /// Listener that stopped the event's propagation; listeners of lower
+    /// priority did not run and are not counted as succeeded
+    pub stopped_by: Option<ListenerId>,
+}
+
+impl EmitReport {
+    /// Check whether every matched listener succeeded
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.skipped.is_empty()
+    }
+}
+
+/// Event listener that forwards each event to a function
+pub(crate) struct FunctionListener {
+    pub(crate) function: Arc<dyn FunctionHandler>,
+    pub(crate) context: FunctionContext,
+}
+
+#[async_trait]
+impl EventListener for FunctionListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let mut context = self.context.for_request(None);
+        if let Some(id) = &event.tenant {
+            context = context.with_tenant(Tenant::new(id.as_str())?);
+        }
+        self.function
+            .call_with_context(context, vec![serde_json::to_value(event)?])
+            .await?;
+        Ok(())
+    }
+}
+
+/// Built-in `sx::emit(pattern, data)` function
+///
+/// Dispatches a custom event and returns how many listeners handled it.
+pub(crate) struct EmitFunction {
+    pub(crate) events: EventRegistry,
+}
+
+#[async_trait]
+impl FunctionHandler for EmitFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        self.emit(args, None).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        self.emit(args, context.tenant()).await
+    }
+}
+
+impl EmitFunction {
+    /// Emit the event `args` describe, for `tenant` if there is one
+    async fn emit(&self, args: Vec<Value>, tenant: Option<&Tenant>) -> Result<Value> {
+        let mut args = args.into_iter();
+        let pattern = match args.next() {
+            Some(Value::String(pattern)) => pattern,
+            _ => return Err(Error::Function("sx::emit expects a pattern string as its first argument".to_string())),
+        };
+        let mut event = Event::custom(&pattern, args.next().unwrap_or(Value::Null))?;
+        if let Some(tenant) = tenant {
+            event = event.with_tenant(tenant);
+        }
+
+        let report = self.events.emit(event).await?;
+        for (listener, err) in &report.failed {
+            tracing::warn!(pattern = %pattern, %listener, error = %err, "sx::emit listener failed");
+        }
+
+        Ok(serde_json::json!({
+            "pattern": pattern,
+            "matched": report.matched,
+            "succeeded": report.succeeded,
+            "failed": report.failed.len(),
+        }))
+    }
+}
+
+type RegisteredListener = (ListenerId, Arc<dyn EventListener>);
+type ListenerMap = HashMap<String, Vec<RegisteredListener>>;
+
+/// Pattern syntax used to match events to listeners
+///
+/// Both syntaxes treat a pattern without wildcards as an exact match against
+/// `table:record_id`. They differ in how wildcards are interpreted:
+///
+/// | Pattern       | `Legacy`                          | `Glob`                                   |
+/// |---------------|-----------------------------------|------------------------------------------|
+/// | `orders:*`    | every `orders` event              | `orders` events that carry a record ID   |
+/// | `orders:**`   | literal, never matches            | every `orders` event                     |
+/// | `*`           | every event                       | table-level events without a record ID   |
+/// | `**`          | table-level events without an ID  | every event                              |
+/// | `orders`      | literal, never matches            | `orders` events without a record ID      |
+///
+/// Under `Glob`, patterns are split on `:` and matched segment by segment:
+/// `*` matches exactly one segment and `**` matches zero or more.
+///
+/// The catch-all pattern (`*` under `Legacy`, `**` under `Glob`) fires for
+/// every event and is subject to the registry's [`GlobalWildcardPolicy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PatternSyntax {
+    /// Exact match, `table:*` and the global `*`
+    #[default]
+    Legacy,
+    /// Multi-segment glob matching
+    Glob,
+}
+
+impl PatternSyntax {
+    /// Check whether a pattern matches an event under this syntax
+    pub fn matches(&self, pattern: &str, event: &Event) -> bool {
+        match self {
+            PatternSyntax::Legacy => {
+                pattern == "*"
+                    || (pattern == "**" && event.record_id.is_none())
+                    || pattern == event.pattern()
+                    || pattern == format!("{}:*", event.table)
+            }
+            PatternSyntax::Glob => {
+                let pattern: Vec<&str> = pattern.split(':').collect();
+                let mut subject: Vec<&str> = vec![event.table.as_str()];
+                if let Some(id) = &event.record_id {
+                    subject.extend(id.split(':'));
+                }
+                glob_match(&pattern, &subject)
+            }
+        }
+    }
+
+    /// Check whether a pattern matches every event under this syntax
+    pub fn is_catch_all(&self, pattern: &str) -> bool {
+        match self {
+            PatternSyntax::Legacy => pattern == "*",
+            PatternSyntax::Glob => pattern.split(':').all(|segment| segment == "**"),
+        }
+    }
+
+    /// Check whether a pattern matches differently under the two syntaxes
+    fn diverges(pattern: &str) -> bool {
+        pattern.contains('*') || !pattern.contains(':')
+    }
+}
+
+/// How a registry treats catch-all listeners that fire for every event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GlobalWildcardPolicy {
+    /// Accept catch-all registrations
+    #[default]
+    Allow,
+    /// Accept catch-all registrations but log a warning for each one
+    Warn,
+    /// Reject catch-all registrations with `Error::Config`
+    Deny,
+}
+
+impl GlobalWildcardPolicy {
+    /// Reject the pattern if it is a catch-all and the policy denies those
+    pub fn validate(&self, pattern: &str, syntax: PatternSyntax) -> Result<()> {
+        if *self == GlobalWildcardPolicy::Deny && syntax.is_catch_all(pattern) {
+            return Err(Error::Config(format!(
+                "catch-all event pattern '{}' is not allowed by the global wildcard policy",
+                pattern
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// How `emit()` runs the listeners matched by an event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DispatchPolicy {
+    /// Run listeners one after another in the emitting task
+    #[default]
+    Sequential,
+    /// Run listeners as separate tasks and wait for all of them; a listener
+    /// that panics is reported as failed without affecting the others
+    Concurrent,
+    /// Start listeners as separate tasks and return without waiting; their
+    /// failures are logged rather than reported
+    Spawn,
+}
+
+fn glob_match(pattern: &[&str], subject: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => subject.is_empty(),
+        Some((&"**", rest)) => (0..=subject.len()).any(|skip| glob_match(rest, &subject[skip..])),
+        Some((segment, rest)) => subject
+            .split_first()
+            .is_some_and(|(head, tail)| (*segment == "*" || segment == head) && glob_match(rest, tail)),
+    }
+}
+
 /// Registry for event listeners
 #[derive(Clone)]
 pub struct EventRegistry {
-    listeners: Arc<RwLock<HashMap<String, Vec<Arc<dyn EventListener>>>>>,
+    listeners: Arc<RwLock<ListenerMap>>,
+    syntax: PatternSyntax,
+    global_wildcard: GlobalWildcardPolicy,
+    dispatch: DispatchPolicy,
+    store: Option<Arc<dyn EventStore>>,
+    dead_letters: Option<Arc<dyn DeadLetterSink>>,
+    next_id: Arc<AtomicU64>,
+    /// Stable name of every registered listener, by ID
+    names: Arc<std::sync::RwLock<HashMap<ListenerId, String>>>,
+    warned: Arc<std::sync::Mutex<HashSet<String>>>,
+    schemas: Arc<std::sync::RwLock<Vec<(String, EventSchema)>>>,
+    /// Middleware around `emit()`, with the module it is limited to
+    middleware: Arc<std::sync::RwLock<Vec<(Option<String>, Arc<dyn EventMiddleware>)>>>,
+    /// Every emitted event, for streaming endpoints
+    tap: broadcast::Sender<Event>,
+    /// Events waiting for the delivery task, when decoupled from `emit()`
+    queue: Option<EventQueue>,
+    queue_worker: Arc<std::sync::Once>,
 }
 
 impl EventRegistry {
     pub fn new() -> Self {
         Self {
             listeners: Arc::new(RwLock::new(HashMap::new())),
+            syntax: PatternSyntax::default(),
+            global_wildcard: GlobalWildcardPolicy::default(),
+            dispatch: DispatchPolicy::default(),
+            store: None,
+            dead_letters: None,
+            next_id: Arc::new(AtomicU64::new(0)),
+            names: Arc::new(std::sync::RwLock::new(HashMap::new())),
+            warned: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            schemas: Arc::new(std::sync::RwLock::new(Vec::new())),
+            middleware: Arc::new(std::sync::RwLock::new(Vec::new())),
+            tap: broadcast::channel(TAP_CAPACITY).0,
+            queue: None,
+            queue_worker: Arc::new(std::sync::Once::new()),
+        }
+    }
+
+    /// Set the pattern syntax used when matching events
+    pub fn with_syntax(mut self, syntax: PatternSyntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Get the pattern syntax used when matching events
+    pub fn syntax(&self) -> PatternSyntax {
+        self.syntax
+    }
+
+    /// Set how catch-all listener registrations are treated
+    pub fn with_global_wildcard(mut self, policy: GlobalWildcardPolicy) -> Self {
+        self.global_wildcard = policy;
+        self
+    }
+
+    /// Set how `emit()` runs matched listeners
+    pub fn with_dispatch(mut self, policy: DispatchPolicy) -> Self {
+        self.dispatch = policy;
+        self
+    }
+
+    /// Get how `emit()` runs matched listeners
+    pub fn dispatch(&self) -> DispatchPolicy {
+        self.dispatch
+    }
+
+    /// Deliver emitted events from a bounded queue instead of during `emit()`
+    ///
+    /// `emit()` then returns once the event is queued, with an empty report,
+    /// and a background task delivers queued events in order under the
+    /// registry's [`DispatchPolicy`], logging and dead-lettering failures.
+    /// See the [`queue`](crate::queue) module.
+    pub fn with_queue(mut self, config: EventQueueConfig) -> Self {
+        self.queue = Some(EventQueue::new(config));
+        self.queue_worker = Arc::new(std::sync::Once::new());
+        self
+    }
+
+    /// Counters of the event queue, if the registry has one
+    pub fn queue_stats(&self) -> Option<QueueStats> {
+        self.queue.as_ref().map(EventQueue::stats)
+    }
+
+    /// Record every emitted event in a store so it can be replayed
+    pub fn with_store(mut self, store: Arc<dyn EventStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Send events that a listener failed to handle to a dead-letter sink
+    ///
+    /// Wrap a listener in a [`RetryingListener`](crate::retry::RetryingListener),
+    /// or give its module a policy with `Module::with_listener_retry`, to
+    /// retry it before the event is dead-lettered.
+    pub fn with_dead_letters(mut self, sink: Arc<dyn DeadLetterSink>) -> Self {
+        self.dead_letters = Some(sink);
+        self
+    }
+
+    /// Run every emitted event through `middleware`
+    ///
+    /// Middleware runs in the order it was added, the first added seeing the
+    /// event first.
+    pub fn with_middleware<M>(self, middleware: M) -> Self
+    where
+        M: EventMiddleware + 'static,
+    {
+        self.add_middleware(None, Arc::new(middleware));
+        self
+    }
+
+    /// Run the events emitted by the handlers of `module` through
+    /// `middleware`, after the global middleware
+    pub fn register_module_middleware(&self, module: impl Into<String>, middleware: Arc<dyn EventMiddleware>) {
+        self.add_middleware(Some(module.into()), middleware);
+    }
+
+    fn add_middleware(&self, module: Option<String>, middleware: Arc<dyn EventMiddleware>) {
+        let mut chain = self.middleware.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        chain.push((module, middleware));
+    }
+
+    /// Middleware wrapping an emit from the current task: the global chain,
+    /// then that of the module whose handler is running
+    fn middleware_chain(&self) -> Vec<Arc<dyn EventMiddleware>> {
+        let registered = self.middleware.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if registered.is_empty() {
+            return Vec::new();
         }
+        let current = Context::current();
+        let module = current.as_ref().map(Context::module);
+        let global = registered.iter().filter(|(scope, _)| scope.is_none());
+        let own = registered
+            .iter()
+            .filter(|(scope, _)| scope.is_some() && scope.as_deref() == module);
+        global.chain(own).map(|(_, middleware)| middleware.clone()).collect()
     }
 
     /// Register an event listener for a pattern
     /// Pattern examples: "orders:*", "orders:123", "users:*"
-    pub async fn register<L>(&self, pattern: impl Into<String>, listener: L)
+    ///
+    /// Returns the listener's ID, which [`unregister`](Self::unregister)
+    /// takes to remove it.
+    pub async fn register<L>(&self, pattern: impl Into<String>, listener: L) -> Result<ListenerId>
     where
         L: EventListener + 'static,
     {
-        let mut listeners = self.listeners.write().await;
-        listeners
-            .entry(pattern.into())
-            .or_insert_with(Vec::new)
-            .push(Arc::new(listener));
+        self.register_arc(pattern, Arc::new(listener)).await
     }
 
     /// Register a listener that's already wrapped in Arc
-    pub async fn register_arc(&self, pattern: impl Into<String>, listener: Arc<dyn EventListener>) {
+    pub async fn register_arc(&self, pattern: impl Into<String>, listener: Arc<dyn EventListener>) -> Result<ListenerId> {
+        let pattern = pattern.into();
+        self.check_pattern(&pattern)?;
+
         let mut listeners = self.listeners.write().await;
-        listeners
-            .entry(pattern.into())
-            .or_insert_with(Vec::new)
-            .push(listener);
+        Ok(self.insert(&mut listeners, None, pattern, listener))
     }
 
-    /// Emit an event to matching listeners
-    pub async fn emit(&self, event: Event) -> Result<()> {
-        let listeners = self.listeners.read().await;
-        let pattern = event.pattern();
+    /// Register a listener on behalf of `module`, which its
+    /// [name](Self::listener_name) starts with
+    pub async fn register_for(
+        &self,
+        module: &str,
+        pattern: impl Into<String>,
+        listener: Arc<dyn EventListener>,
+    ) -> Result<ListenerId> {
+        let pattern = pattern.into();
+        self.check_pattern(&pattern)?;
 
-        // Find matching patterns
-        let mut matched_listeners = Vec::new();
+        let mut listeners = self.listeners.write().await;
+        Ok(self.insert(&mut listeners, Some(module), pattern, listener))
+    }
+
+    /// Stable name of a registered listener
+    ///
+    /// Unlike its ID, which counts registrations in this process, the name
+    /// is the same after a restart that registers the same listeners in the
+    /// same order: `module/pattern#n` for listeners of a module, or
+    /// `pattern#n`, where `n` tells apart the listeners under the same
+    /// pattern. Dead letters record it to find their listener again.
+    pub fn listener_name(&self, id: ListenerId) -> Option<String> {
+        let names = self.names.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        names.get(&id).cloned()
+    }
 
-        // Exact match
-        if let Some(exact) = listeners.get(&pattern) {
-            matched_listeners.extend(exact.iter().cloned());
+    /// Add a listener under `pattern`, naming it after `module` and the
+    /// first number not taken by another listener of the same module and
+    /// pattern
+    fn insert(
+        &self,
+        listeners: &mut ListenerMap,
+        module: Option<&str>,
+        pattern: String,
+        listener: Arc<dyn EventListener>,
+    ) -> ListenerId {
+        let id = ListenerId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let base = match module {
+            Some(module) => format!("{}/{}", module, pattern),
+            None => pattern.clone(),
+        };
+        let mut names = self.names.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut n = 0;
+        while names.values().any(|taken| *taken == format!("{}#{}", base, n)) {
+            n += 1;
         }
+        names.insert(id, format!("{}#{}", base, n));
+        listeners.entry(pattern).or_insert_with(Vec::new).push((id, listener));
+        id
+    }
 
-        // Wildcard match (table:*)
-        let wildcard_pattern = format!("{}:*", event.table);
-        if let Some(wildcard) = listeners.get(&wildcard_pattern) {
-            matched_listeners.extend(wildcard.iter().cloned());
+    /// Forget the names of listeners no longer registered
+    fn forget(&self, ids: &[ListenerId]) {
+        let mut names = self.names.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for id in ids {
+            names.remove(id);
         }
+    }
+
+    /// Remove a listener, returning whether it was registered
+    ///
+    /// Events emitted from then on no longer reach it; an emit already
+    /// running it is not interrupted.
+    pub async fn unregister(&self, id: ListenerId) -> bool {
+        let mut listeners = self.listeners.write().await;
+        let mut found = false;
+        listeners.retain(|_, registered| {
+            let count = registered.len();
+            registered.retain(|(registered_id, _)| *registered_id != id);
+            found |= registered.len() != count;
+            !registered.is_empty()
+        });
+        self.forget(&[id]);
+        found
+    }
 
-        // Global wildcard (*)
-        if let Some(global) = listeners.get("*") {
-            matched_listeners.extend(global.iter().cloned());
+    /// Remove every listener registered under exactly `pattern`, returning
+    /// their IDs
+    ///
+    /// Listeners under other patterns that match the same events, such as
+    /// `orders:*` for `orders:123`, are kept.
+    pub async fn clear_pattern(&self, pattern: &str) -> Vec<ListenerId> {
+        let mut listeners = self.listeners.write().await;
+        let ids: Vec<ListenerId> = listeners
+            .remove(pattern)
+            .map(|registered| registered.into_iter().map(|(id, _)| id).collect())
+            .unwrap_or_default();
+        self.forget(&ids);
+        ids
+    }
+
+    /// Register a listener receiving the events matching `pattern` in
+    /// batches; see [`BatchOptions`]
+    pub async fn register_batch<L>(&self, pattern: impl Into<String>, options: BatchOptions, listener: L) -> Result<ListenerId>
+    where
+        L: BatchEventListener + 'static,
+    {
+        self.register(pattern, BatchingListener::new(Arc::new(listener), options)).await
+    }
+
+    /// Register a listener to run with `priority` instead of its own
+    pub async fn register_with_priority(
+        &self,
+        pattern: impl Into<String>,
+        priority: i32,
+        listener: Arc<dyn EventListener>,
+    ) -> Result<ListenerId> {
+        self.register_arc(pattern, Arc::new(PrioritizedListener::new(listener, priority))).await
+    }
+
+    /// Require the data of events matching `pattern` to have `schema`'s shape
+    ///
+    /// Emitting an event that matches a pattern with a schema fails with
+    /// [`Error::Validation`] unless its data fits every such schema.
+    pub fn register_schema(&self, pattern: impl Into<String>, schema: EventSchema) {
+        let mut schemas = self.schemas.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        schemas.push((pattern.into(), schema));
+    }
+
+    /// Check `event` against the schemas of the patterns it matches
+    fn validate(&self, event: &Event) -> Result<()> {
+        let schemas = self.schemas.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (pattern, schema) in schemas.iter() {
+            if !self.syntax.matches(pattern, event) {
+                continue;
+            }
+            if let Err(err) = schema.check(&event.data) {
+                return Err(Error::Validation(format!(
+                    "data of event {} does not match the schema {} of '{}': {}",
+                    event.pattern(),
+                    schema.name(),
+                    pattern,
+                    err
+                )));
+            }
         }
+        Ok(())
+    }
 
-        // Notify all matched listeners
-        for listener in matched_listeners {
-            // Clone event for each listener
-            listener.on_event(event.clone()).await?;
+    /// Remove the `old` listeners and register `new` ones, each with its
+    /// module and pattern, under a single lock, so no event is dispatched to
+    /// both or to neither
+    ///
+    /// The new listeners take the names the old ones free, so dead letters
+    /// of a reloaded module find its listeners again.
+    pub(crate) async fn replace(
+        &self,
+        old: &[ListenerId],
+        new: Vec<(String, String, Arc<dyn EventListener>)>,
+    ) -> Result<Vec<ListenerId>> {
+        for (_, pattern, _) in &new {
+            self.check_pattern(pattern)?;
+        }
+
+        let mut listeners = self.listeners.write().await;
+        for registered in listeners.values_mut() {
+            registered.retain(|(id, _)| !old.contains(id));
         }
+        listeners.retain(|_, registered| !registered.is_empty());
+        self.forget(old);
 
+        let ids = new
+            .into_iter()
+            .map(|(module, pattern, listener)| self.insert(&mut listeners, Some(&module), pattern, listener))
+            .collect();
+        Ok(ids)
+    }
+
+    fn check_pattern(&self, pattern: &str) -> Result<()> {
+        self.global_wildcard.validate(pattern, self.syntax)?;
+        if self.global_wildcard == GlobalWildcardPolicy::Warn && self.syntax.is_catch_all(pattern) {
+            tracing::warn!(pattern, "catch-all event listener registered; it runs for every event");
+        }
+        self.warn_if_divergent(pattern);
         Ok(())
     }
 
+    /// Emit an event to matching listeners
+    ///
+    /// Matched listeners run by [priority](EventListener::priority), highest
+    /// first, and every one runs even if another fails, unless one returns
+    /// [`Propagation::Stop`]; failures are collected in the returned report.
+    /// How the listeners run depends on the registry's [`DispatchPolicy`]:
+    /// with `Concurrent` and `Spawn`, listeners of the same priority run at
+    /// once, and a stop lets the others of its priority finish. With `Spawn`
+    /// the report only counts the listeners matched.
+    ///
+    /// With an event store attached the event is recorded first; if that
+    /// fails, no listener runs and the error is returned. The same goes for
+    /// an event whose data does not fit a registered [`EventSchema`].
+    ///
+    /// Any [`EventMiddleware`] runs before all of this and may change or
+    /// drop the event. With a [queue](Self::with_queue), listeners run later
+    /// and the report is empty.
+    pub async fn emit(&self, event: Event) -> Result<EmitReport> {
+        self.emit_through(event, None).await
+    }
+
+    async fn emit_through(&self, event: Event, deadline: Option<Instant>) -> Result<EmitReport> {
+        let span = emit_span(&event);
+        let middleware = self.middleware_chain();
+        let next = EmitNext {
+            registry: self,
+            middleware: &middleware,
+            deadline,
+        };
+        next.run(event).instrument(span).await
+    }
+
+    async fn deliver(&self, event: Event) -> Result<EmitReport> {
+        self.validate(&event)?;
+        self.record(&event).await?;
+        self.publish(&event);
+        match &self.queue {
+            Some(queue) => {
+                self.start_queue_worker(queue);
+                queue.push(event).await?;
+                Ok(EmitReport::default())
+            }
+            None => self.dispatch(event).await,
+        }
+    }
+
+    /// Start the task delivering queued events, once per queue
+    fn start_queue_worker(&self, queue: &EventQueue) {
+        self.queue_worker.call_once(|| {
+            let registry = self.clone();
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                loop {
+                    let event = queue.pop().await;
+                    let span = emit_span(&event);
+                    match registry.dispatch(event).instrument(span).await {
+                        Ok(report) => {
+                            for (listener, err) in report.failed {
+                                tracing::warn!(%listener, error = %err, "queued event listener failed");
+                            }
+                        }
+                        Err(err) => tracing::warn!(error = %err, "failed to deliver queued event"),
+                    }
+                }
+            });
+        });
+    }
+
+    /// Run the listeners matching `event`
+    async fn dispatch(&self, event: Event) -> Result<EmitReport> {
+        let matched_listeners = self.matching(&event).await;
+        let mut report = EmitReport {
+            matched: matched_listeners.len(),
+            ..EmitReport::default()
+        };
+
+        match self.dispatch {
+            DispatchPolicy::Sequential => {
+                for (id, listener) in matched_listeners {
+                    match listener.handle(event.clone()).await {
+                        Ok(propagation) => {
+                            report.succeeded += 1;
+                            if propagation == Propagation::Stop {
+                                report.stopped_by = Some(id);
+                                break;
+                            }
+                        }
+                        Err(err) => report.failed.push((id, err)),
+                    }
+                }
+            }
+            DispatchPolicy::Concurrent => {
+                run_by_priority(&event, matched_listeners, &mut report).await;
+            }
+            DispatchPolicy::Spawn => {
+                let registry = self.clone();
+                tokio::spawn(
+                    async move {
+                        let mut spawned = EmitReport::default();
+                        run_by_priority(&event, matched_listeners, &mut spawned).await;
+                        for (id, err) in &spawned.failed {
+                            tracing::warn!(pattern = %event.pattern(), listener = %id, error = %err, "spawned event listener failed");
+                            registry.dead_letter(&event, *id, err).await;
+                        }
+                    }
+                    .in_current_span(),
+                );
+                return Ok(report);
+            }
+        }
+
+        for (id, err) in &report.failed {
+            self.dead_letter(&event, *id, err).await;
+        }
+        Ok(report)
+    }
+
+    /// Emit an event, bounding the total time spent across all listeners
+    ///
+    /// Listeners run in order until the deadline passes. The listener running
+    /// at that point is cancelled and it and any remaining listeners are
+    /// reported as skipped, and dead-lettered like failed ones so they can be
+    /// re-driven. The event bypasses the registry's queue, if any.
+    pub async fn emit_with_deadline(&self, event: Event, deadline: Instant) -> Result<EmitReport> {
+        self.emit_through(event, Some(deadline)).await
+    }
+
+    async fn deliver_by(&self, event: Event, deadline: Instant) -> Result<EmitReport> {
+        self.validate(&event)?;
+        self.record(&event).await?;
+        self.publish(&event);
+        let matched_listeners = self.matching(&event).await;
+        let mut report = EmitReport {
+            matched: matched_listeners.len(),
+            ..EmitReport::default()
+        };
+
+        let mut remaining = matched_listeners.into_iter();
+        for (id, listener) in remaining.by_ref() {
+            match tokio::time::timeout_at(deadline, listener.handle(event.clone())).await {
+                Ok(Ok(propagation)) => {
+                    report.succeeded += 1;
+                    if propagation == Propagation::Stop {
+                        report.stopped_by = Some(id);
+                        break;
+                    }
+                }
+                Ok(Err(err)) => report.failed.push((id, err)),
+                Err(_) => {
+                    report.skipped.push(id);
+                    break;
+                }
+            }
+        }
+        if report.stopped_by.is_none() {
+            report.skipped.extend(remaining.map(|(id, _)| id));
+        }
+
+        for (id, err) in &report.failed {
+            self.dead_letter(&event, *id, err).await;
+        }
+
+        if !report.skipped.is_empty() {
+            tracing::warn!(
+                pattern = %event.pattern(),
+                skipped = report.skipped.len(),
+                "emit deadline passed before all listeners ran"
+            );
+            let err = Error::Event("the emit deadline passed before the listener finished".to_string());
+            for id in &report.skipped {
+                self.dead_letter(&event, *id, &err).await;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Emit an event, returning the first listener failure as an error
+    pub async fn emit_quiet(&self, event: Event) -> Result<()> {
+        let report = self.emit(event).await?;
+        match report.failed.into_iter().next() {
+            Some((_, err)) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Re-deliver stored events to the listeners registered for `pattern`
+    ///
+    /// Events emitted at or after `from_timestamp` (seconds) that match the
+    /// pattern are passed, oldest first, to the listeners registered under
+    /// exactly that pattern, so a new or recovering listener can catch up
+    /// without other listeners seeing the events again. Returns the combined
+    /// report across all replayed events.
+    pub async fn replay(&self, from_timestamp: i64, pattern: &str) -> Result<EmitReport> {
+        let store = self
+            .store
+            .as_ref()
+            .ok_or_else(|| Error::Event("replay requires an event store".to_string()))?;
+        let mut listeners = self.listeners.read().await.get(pattern).cloned().unwrap_or_default();
+        listeners.sort_by_key(|(_, listener)| std::cmp::Reverse(listener.priority()));
+
+        let mut report = EmitReport::default();
+        for event in store.read_since(from_timestamp).await? {
+            if !self.syntax.matches(pattern, &event) {
+                continue;
+            }
+            for (id, listener) in &listeners {
+                report.matched += 1;
+                match listener.handle(event.clone()).await {
+                    Ok(Propagation::Continue) => report.succeeded += 1,
+                    Ok(Propagation::Stop) => {
+                        report.succeeded += 1;
+                        break;
+                    }
+                    Err(err) => report.failed.push((*id, err)),
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Read the stored events matching `pattern` emitted at or after
+    /// `from_timestamp` (seconds), oldest first
+    pub(crate) async fn history(&self, from_timestamp: i64, pattern: &str) -> Result<Vec<Event>> {
+        let store = self
+            .store
+            .as_ref()
+            .ok_or_else(|| Error::Event("reading past events requires an event store".to_string()))?;
+        Ok(store
+            .read_since(from_timestamp)
+            .await?
+            .into_iter()
+            .filter(|event| self.syntax.matches(pattern, event))
+            .collect())
+    }
+
+    /// List the events listeners failed to handle
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>> {
+        self.dead_letter_sink()?.list().await
+    }
+
+    /// Deliver a dead letter to its listener again
+    ///
+    /// The listener is found by the [name](Self::listener_name) the letter
+    /// recorded, and must still be registered under the letter's pattern.
+    /// The letter is removed once the listener succeeds; if it fails again
+    /// the letter stays in the sink and the error is returned.
+    pub async fn redrive(&self, id: &str) -> Result<()> {
+        let sink = self.dead_letter_sink()?;
+        let letter = sink
+            .list()
+            .await?
+            .into_iter()
+            .find(|letter| letter.id == id)
+            .ok_or_else(|| Error::NotFound(format!("dead letter '{}'", id)))?;
+        let (pattern, listener) = self
+            .named_listener(&letter.listener)
+            .await
+            .ok_or_else(|| Error::NotFound(format!("listener '{}'", letter.listener)))?;
+        if pattern != letter.pattern {
+            return Err(Error::Event(format!(
+                "listener '{}' is registered under '{}', not '{}' as when the event failed",
+                letter.listener, pattern, letter.pattern
+            )));
+        }
+
+        listener.on_event(letter.event).await?;
+        sink.remove(id).await
+    }
+
+    /// Discard a dead letter without delivering it
+    pub async fn discard_dead_letter(&self, id: &str) -> Result<()> {
+        self.dead_letter_sink()?.remove(id).await
+    }
+
+    fn dead_letter_sink(&self) -> Result<&Arc<dyn DeadLetterSink>> {
+        self.dead_letters
+            .as_ref()
+            .ok_or_else(|| Error::Event("no dead-letter sink configured".to_string()))
+    }
+
+    async fn dead_letter(&self, event: &Event, id: ListenerId, err: &Error) {
+        let Some(sink) = &self.dead_letters else {
+            return;
+        };
+        let pattern = self.listener(id).await.map(|(pattern, _)| pattern).unwrap_or_default();
+        let name = self.listener_name(id).unwrap_or_else(|| id.to_string());
+        let letter = DeadLetter::new(name, pattern, event.clone(), err.to_string());
+        if let Err(sink_err) = sink.push(letter).await {
+            tracing::error!(listener = %id, error = %sink_err, "failed to store dead letter; event lost");
+        }
+    }
+
+    async fn listener(&self, id: ListenerId) -> Option<(String, Arc<dyn EventListener>)> {
+        let listeners = self.listeners.read().await;
+        listeners.iter().find_map(|(pattern, registered)| {
+            registered
+                .iter()
+                .find(|(registered_id, _)| *registered_id == id)
+                .map(|(_, listener)| (pattern.clone(), listener.clone()))
+        })
+    }
+
+    async fn named_listener(&self, name: &str) -> Option<(String, Arc<dyn EventListener>)> {
+        let id = {
+            let names = self.names.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            names.iter().find(|(_, taken)| *taken == name).map(|(id, _)| *id)?
+        };
+        self.listener(id).await
+    }
+
+    async fn record(&self, event: &Event) -> Result<()> {
+        match &self.store {
+            Some(store) => store.append(event).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Listeners matching `event`, highest priority first
+    async fn matching(&self, event: &Event) -> Vec<RegisteredListener> {
+        let mut matched = self.matched(event).await;
+        matched.sort_by_key(|(_, listener)| std::cmp::Reverse(listener.priority()));
+        matched
+    }
+
+    async fn matched(&self, event: &Event) -> Vec<RegisteredListener> {
+        let listeners = self.listeners.read().await;
+
+        match self.syntax {
+            PatternSyntax::Legacy => {
+                let mut matched = Vec::new();
+
+                // Exact match
+                let pattern = event.pattern();
+                if let Some(exact) = listeners.get(&pattern) {
+                    matched.extend(exact.iter().cloned());
+                }
+
+                // Wildcard match (table:*), unless the exact match already covered it
+                let wildcard_pattern = format!("{}:*", event.table);
+                if wildcard_pattern != pattern {
+                    if let Some(wildcard) = listeners.get(&wildcard_pattern) {
+                        matched.extend(wildcard.iter().cloned());
+                    }
+                }
+
+                // Global wildcard (*)
+                if let Some(global) = listeners.get("*") {
+                    matched.extend(global.iter().cloned());
+                }
+
+                // Table-level wildcard (**), for events without a record ID
+                if event.record_id.is_none() {
+                    if let Some(table_level) = listeners.get("**") {
+                        matched.extend(table_level.iter().cloned());
+                    }
+                }
+
+                matched
+            }
+            PatternSyntax::Glob => listeners
+                .iter()
+                .filter(|(pattern, _)| self.syntax.matches(pattern, event))
+                .flat_map(|(_, listeners)| listeners.iter().cloned())
+                .collect(),
+        }
+    }
+
+    /// Flush the event store, if one is attached
+    pub async fn flush(&self) -> Result<()> {
+        match &self.store {
+            Some(store) => store.flush().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Receive every event emitted from now on, whether or not a listener
+    /// matches it
+    ///
+    /// A receiver that falls more than `TAP_CAPACITY` events behind misses
+    /// the oldest ones and is told how many on its next receive.
+    pub(crate) fn tap(&self) -> broadcast::Receiver<Event> {
+        self.tap.subscribe()
+    }
+
+    fn publish(&self, event: &Event) {
+        if self.tap.receiver_count() > 0 {
+            let _ = self.tap.send(event.clone());
+        }
+    }
+
     /// List all registered patterns
     pub async fn patterns(&self) -> Vec<String> {
         let listeners = self.listeners.read().await;
         listeners.keys().cloned().collect()
     }
+
+    fn warn_if_divergent(&self, pattern: &str) {
+        if !PatternSyntax::diverges(pattern) {
+            return;
+        }
+
+        let mut warned = self.warned.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if warned.insert(pattern.to_string()) {
+            tracing::warn!(
+                pattern,
+                syntax = ?self.syntax,
+                "event pattern matches differently under PatternSyntax::Legacy and PatternSyntax::Glob"
+            );
+        }
+    }
 }
 
 impl Default for EventRegistry {
@@ -176,4 +1392,85 @@ impl Default for EventRegistry {
     }
 }
 
-// Add chrono dependency for timestamps
+/// Run `listeners`, sorted by priority, as separate tasks: all of one
+/// priority at once, after those of higher priorities finished and unless
+/// one of them stopped the event
+async fn run_by_priority(event: &Event, listeners: Vec<RegisteredListener>, report: &mut EmitReport) {
+    let mut listeners = listeners.into_iter().peekable();
+    while let Some((_, first)) = listeners.peek() {
+        let priority = first.priority();
+        let mut tasks = Vec::new();
+        while let Some((id, listener)) = listeners.next_if(|(_, listener)| listener.priority() == priority) {
+            let event = event.clone();
+            tasks.push((id, tokio::spawn(async move { listener.handle(event).await }.in_current_span())));
+        }
+
+        for (id, task) in tasks {
+            match task.await {
+                Ok(Ok(propagation)) => {
+                    report.succeeded += 1;
+                    if propagation == Propagation::Stop && report.stopped_by.is_none() {
+                        report.stopped_by = Some(id);
+                    }
+                }
+                Ok(Err(err)) => report.failed.push((id, err)),
+                Err(err) => report.failed.push((id, Error::Event(err.to_string()))),
+            }
+        }
+        if report.stopped_by.is_some() {
+            return;
+        }
+    }
+}
+
+/// Span covering the dispatch of an event to its listeners
+fn emit_span(event: &Event) -> tracing::Span {
+    tracing::info_span!("sx.emit", pattern = %event.pattern())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_star_matches_one_segment() {
+        assert!(glob_match(&["orders", "*"], &["orders", "1"]));
+        assert!(!glob_match(&["orders", "*"], &["orders"]));
+        assert!(!glob_match(&["orders", "*"], &["orders", "1", "items"]));
+        assert!(glob_match(&["*", "created"], &["payments", "created"]));
+        assert!(!glob_match(&["orders", "1"], &["orders", "2"]));
+    }
+
+    #[test]
+    fn glob_double_star_matches_any_number_of_segments() {
+        assert!(glob_match(&["orders", "**"], &["orders"]));
+        assert!(glob_match(&["orders", "**"], &["orders", "1", "items"]));
+        assert!(glob_match(&["**", "items"], &["orders", "1", "items"]));
+        assert!(glob_match(&["orders", "**", "items"], &["orders", "items"]));
+        assert!(!glob_match(&["orders", "**", "items"], &["orders", "1", "lines"]));
+        assert!(glob_match(&["**"], &[]));
+        assert!(glob_match(&["**", "**"], &["orders", "1"]));
+    }
+
+    #[test]
+    fn glob_syntax_splits_record_ids_into_segments() {
+        let event = Event::created("orders", "eu:1", Value::Null);
+        assert!(PatternSyntax::Glob.matches("orders:eu:*", &event));
+        assert!(PatternSyntax::Glob.matches("orders:**", &event));
+        assert!(!PatternSyntax::Glob.matches("orders:*", &event));
+        assert!(PatternSyntax::Legacy.matches("orders:*", &event));
+
+        let table_event = Event::new(EventType::Custom("flushed".to_string()), "orders", Value::Null);
+        assert!(PatternSyntax::Glob.matches("orders", &table_event));
+        assert!(!PatternSyntax::Glob.matches("orders:*", &table_event));
+    }
+
+    #[test]
+    fn only_double_stars_catch_everything_under_glob() {
+        assert!(PatternSyntax::Glob.is_catch_all("**"));
+        assert!(PatternSyntax::Glob.is_catch_all("**:**"));
+        assert!(!PatternSyntax::Glob.is_catch_all("*"));
+        assert!(!PatternSyntax::Glob.is_catch_all("orders:**"));
+        assert!(PatternSyntax::Legacy.is_catch_all("*"));
+    }
+}
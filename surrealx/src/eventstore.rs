@@ -0,0 +1,139 @@
+//! Persistent log of emitted events
+
+use std::path::PathBuf;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use crate::db::Database;
+use crate::events::Event;
+use crate::error::Result;
+
+/// Storage for emitted events, read back by `EventRegistry::replay`
+#[async_trait]
+pub trait EventStore: Send + Sync {
+    /// Record an event
+    async fn append(&self, event: &Event) -> Result<()>;
+
+    /// Read events with a timestamp at or after `from_timestamp`, oldest first
+    async fn read_since(&self, from_timestamp: i64) -> Result<Vec<Event>>;
+
+    /// Write buffered events to durable storage
+    ///
+    /// Called by `BuiltSurrealX::shutdown()`; stores that write each event
+    /// as it is appended need not override it.
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Event store backed by a table in the embedded database
+pub struct SurrealEventStore {
+    db: Database,
+    table: String,
+}
+
+impl SurrealEventStore {
+    /// Store events in the `sx_event_log` table
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            table: "sx_event_log".to_string(),
+        }
+    }
+
+    /// Store events in a different table
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+}
+
+#[async_trait]
+impl EventStore for SurrealEventStore {
+    async fn append(&self, event: &Event) -> Result<()> {
+        // Event timestamps are in seconds, so order by a finer-grained field
+        let mut record = serde_json::to_value(event)?;
+        if let Value::Object(fields) = &mut record {
+            fields.insert("logged_at".to_string(), json!(chrono::Utc::now().timestamp_nanos_opt()));
+        }
+
+        self.db
+            .query("CREATE type::table($table) CONTENT $record RETURN NONE")
+            .bind(("table", self.table.clone()))
+            .bind(("record", record))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn read_since(&self, from_timestamp: i64) -> Result<Vec<Event>> {
+        let mut response = self
+            .db
+            .query("SELECT * OMIT id FROM type::table($table) WHERE timestamp >= $from ORDER BY logged_at")
+            .bind(("table", self.table.clone()))
+            .bind(("from", from_timestamp))
+            .await?;
+        let events = response.take::<surrealdb::Value>(0)?.into_inner().into_json();
+        Ok(serde_json::from_value(events)?)
+    }
+}
+
+/// Event store appending JSON lines to a file
+pub struct FileEventStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileEventStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl EventStore for FileEventStore {
+    async fn append(&self, event: &Event) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        Ok(())
+    }
+
+    async fn read_since(&self, from_timestamp: i64) -> Result<Vec<Event>> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut events = Vec::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let event: Event = serde_json::from_str(line)?;
+            if event.timestamp >= from_timestamp {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        // Taking the lock waits for appends in progress
+        let _guard = self.lock.lock().await;
+        match tokio::fs::File::open(&self.path).await {
+            Ok(file) => Ok(file.sync_all().await?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
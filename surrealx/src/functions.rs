@@ -1,16 +1,265 @@
 //! Custom function registry and handlers
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use crate::error::Result;
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+use crate::auth::Identity;
+use crate::cache::{self, CacheProvider};
+use crate::context::{self, Context, FunctionContext};
+use crate::retry::RetryPolicy;
+use crate::stats::{CallStats, FunctionStats};
+use crate::telemetry::ModuleSpan;
+use crate::error::{Error, Result};
 
 /// Handler for custom SQL functions
 #[async_trait]
 pub trait FunctionHandler: Send + Sync {
     /// Execute the function with given arguments
     async fn call(&self, args: Vec<Value>) -> Result<Value>;
+
+    /// Execute the function with information about the call
+    ///
+    /// This is what the framework calls; the default ignores the context and
+    /// runs `call`. Override it to use the context.
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let _ = context;
+        self.call(args).await
+    }
+}
+
+/// Values of a streaming function, produced one at a time
+pub type ValueStream = BoxStream<'static, Result<Value>>;
+
+/// Handler for functions producing their result piece by piece, such as
+/// large exports or generated text
+///
+/// Callers that can stream, `POST /sx/fn/{name}` asked for
+/// `application/x-ndjson` and the `call` message of the event WebSocket,
+/// receive each value as it is produced. Others, SurrealQL included,
+/// receive the values collected into an array. The first error ends the
+/// stream.
+#[async_trait]
+pub trait StreamingFunctionHandler: Send + Sync {
+    /// Start the function, returning its values as they come
+    async fn call_stream(&self, context: FunctionContext, args: Vec<Value>) -> Result<ValueStream>;
+}
+
+/// Streaming function handler using closures that return a stream
+pub struct SimpleStreamingHandler<F>
+where
+    F: Fn(FunctionContext, Vec<Value>) -> ValueStream + Send + Sync,
+{
+    handler: F,
+}
+
+impl<F> SimpleStreamingHandler<F>
+where
+    F: Fn(FunctionContext, Vec<Value>) -> ValueStream + Send + Sync,
+{
+    pub fn new(handler: F) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl<F> StreamingFunctionHandler for SimpleStreamingHandler<F>
+where
+    F: Fn(FunctionContext, Vec<Value>) -> ValueStream + Send + Sync,
+{
+    async fn call_stream(&self, context: FunctionContext, args: Vec<Value>) -> Result<ValueStream> {
+        Ok((self.handler)(context, args))
+    }
+}
+
+/// Function handler collecting the values of a streaming function into an
+/// array, for callers that cannot stream
+pub(crate) struct CollectedStream {
+    pub(crate) inner: Arc<dyn StreamingFunctionHandler>,
+}
+
+#[async_trait]
+impl FunctionHandler for CollectedStream {
+    async fn call(&self, _args: Vec<Value>) -> Result<Value> {
+        Err(Error::Function(
+            "function requires a FunctionContext; call it through call_with_context".to_string(),
+        ))
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let values = self.inner.call_stream(context, args).await?;
+        Ok(Value::Array(values.try_collect().await?))
+    }
+}
+
+/// Streaming function handler whose stream runs inside a span and context
+/// identifying its module, and counts as in flight until it is dropped
+pub(crate) struct InstrumentedStreamingHandler {
+    pub(crate) inner: Arc<dyn StreamingFunctionHandler>,
+    pub(crate) context: Context,
+    pub(crate) function: String,
+    pub(crate) trace: ModuleSpan,
+    pub(crate) in_flight: InFlight,
+}
+
+#[async_trait]
+impl StreamingFunctionHandler for InstrumentedStreamingHandler {
+    async fn call_stream(&self, context: FunctionContext, args: Vec<Value>) -> Result<ValueStream> {
+        self.context.check_enabled()?;
+        let in_flight = self.in_flight.enter();
+        let span = self.trace.in_scope(|| {
+            tracing::info_span!(
+                "sx.function",
+                module = %self.context.module(),
+                function = %self.function,
+                request_id = context.request_id(),
+            )
+        });
+        let module = self.context.for_tenant(context.tenant());
+        let mut values = context::scope(module.clone(), self.inner.call_stream(context, args))
+            .instrument(span.clone())
+            .await?;
+
+        let values = futures::stream::poll_fn(move |cx| {
+            let _in_flight = &in_flight;
+            let _entered = span.enter();
+            context::sync_scope(module.clone(), || values.poll_next_unpin(cx))
+        });
+        Ok(values.boxed())
+    }
+}
+
+/// A call of a function, as seen by [`FunctionMiddleware`]
+pub struct FunctionCall {
+    /// Fully qualified name of the function
+    pub function: String,
+    pub args: Vec<Value>,
+    /// Context of the call; `None` for calls through [`FunctionHandler::call`]
+    pub context: Option<FunctionContext>,
+}
+
+/// Code run around calls of functions, for logging, caching, checks or
+/// timeouts
+///
+/// Middleware receives each call before the function does and hands it on
+/// with [`CallNext::run`]. It can change the arguments, return a result of
+/// its own without running the function, or change what the function
+/// returned.
+///
+/// Middleware added with [`SurrealX::with_function_middleware`] wraps the
+/// functions of every module; middleware of a module wraps its functions
+/// inside that, and middleware of one function inside that in turn. Within
+/// each, middleware added first sees calls first. It runs in the module's
+/// context and tracing span.
+///
+/// [`SurrealX::with_function_middleware`]: crate::SurrealX::with_function_middleware
+#[async_trait]
+pub trait FunctionMiddleware: Send + Sync {
+    /// Handle a call, passing it on with `next.run(call)`
+    async fn on_call(&self, call: FunctionCall, next: CallNext<'_>) -> Result<Value>;
+}
+
+/// The rest of the middleware chain and the function after it
+pub struct CallNext<'a> {
+    handler: &'a dyn FunctionHandler,
+    middleware: &'a [Arc<dyn FunctionMiddleware>],
+}
+
+impl CallNext<'_> {
+    /// Pass `call` to the next middleware, or to the function after the last
+    /// one
+    pub async fn run(self, call: FunctionCall) -> Result<Value> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => {
+                let next = CallNext {
+                    handler: self.handler,
+                    middleware: rest,
+                };
+                middleware.on_call(call, next).await
+            }
+            None => match call.context {
+                Some(context) => self.handler.call_with_context(context, call.args).await,
+                None => self.handler.call(call.args).await,
+            },
+        }
+    }
+}
+
+/// Function handler running the middleware of its function around every call
+pub(crate) struct LayeredFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) function: String,
+    pub(crate) middleware: Vec<Arc<dyn FunctionMiddleware>>,
+}
+
+impl LayeredFunctionHandler {
+    async fn run(&self, context: Option<FunctionContext>, args: Vec<Value>) -> Result<Value> {
+        let call = FunctionCall {
+            function: self.function.clone(),
+            args,
+            context,
+        };
+        let next = CallNext {
+            handler: self.inner.as_ref(),
+            middleware: &self.middleware,
+        };
+        next.run(call).await
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for LayeredFunctionHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        self.run(None, args).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        self.run(Some(context), args).await
+    }
+}
+
+/// Function handler using async closures that take a [`FunctionContext`]
+///
+/// Calling it without a context, through `call`, fails.
+pub struct ContextFunctionHandler<F>
+where
+    F: Fn(FunctionContext, Vec<Value>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Value>> + Send>> + Send + Sync,
+{
+    handler: F,
+}
+
+impl<F> ContextFunctionHandler<F>
+where
+    F: Fn(FunctionContext, Vec<Value>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Value>> + Send>> + Send + Sync,
+{
+    pub fn new(handler: F) -> Self {
+        Self { handler }
+    }
+}
+
+#[async_trait]
+impl<F> FunctionHandler for ContextFunctionHandler<F>
+where
+    F: Fn(FunctionContext, Vec<Value>) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<Value>> + Send>> + Send + Sync,
+{
+    async fn call(&self, _args: Vec<Value>) -> Result<Value> {
+        Err(Error::Function(
+            "function requires a FunctionContext; call it through call_with_context".to_string(),
+        ))
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        (self.handler)(context, args).await
+    }
 }
 
 /// Simple function handler using async closures
@@ -40,49 +289,1021 @@ where
     }
 }
 
+/// A function declared with the `#[surrealx::function]` attribute
+///
+/// Requires the `macros` feature to declare; add declared functions to a
+/// module with [`Module::with_declared_function`](crate::Module::with_declared_function).
+pub trait DeclaredFunction: FunctionHandler {
+    /// Name of the function within its module
+    const NAME: &'static str;
+
+    /// Parameters and result of the function, taken from the fn's types
+    fn signature() -> FunctionSignature {
+        FunctionSignature::default()
+    }
+}
+
+/// JSON type of a function parameter or result, as far as it is known
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    Bool,
+    /// Integer that fits in 32 bits
+    Int,
+    Float,
+    String,
+    Array(Box<ValueType>),
+    /// Any JSON value
+    #[default]
+    Any,
+}
+
+impl ValueType {
+    /// Whether `value` is of this type
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            ValueType::Bool => value.is_boolean(),
+            ValueType::Int => value.as_i64().is_some_and(|int| i32::try_from(int).is_ok()),
+            ValueType::Float => value.is_number(),
+            ValueType::String => value.is_string(),
+            ValueType::Array(item) => value
+                .as_array()
+                .is_some_and(|items| items.iter().all(|value| item.accepts(value))),
+            ValueType::Any => true,
+        }
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::Bool => f.write_str("bool"),
+            ValueType::Int => f.write_str("int"),
+            ValueType::Float => f.write_str("float"),
+            ValueType::String => f.write_str("string"),
+            ValueType::Array(item) => write!(f, "array<{}>", item),
+            ValueType::Any => f.write_str("any"),
+        }
+    }
+}
+
+/// Why one argument of a call does not fit the function's signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArgumentError {
+    /// Position of the argument, from 0
+    pub index: usize,
+    /// Parameter at that position; `None` for arguments past the last one
+    pub name: Option<String>,
+    /// Type the parameter takes; `None` for arguments past the last one
+    pub expected: Option<ValueType>,
+    /// JSON type of the argument given, or `missing`
+    pub found: String,
+}
+
+impl std::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.name, &self.expected) {
+            (Some(name), Some(expected)) => write!(
+                f,
+                "argument {} ({}): expected {}, found {}",
+                self.index, name, expected, self.found
+            ),
+            _ => write!(f, "argument {}: unexpected {}", self.index, self.found),
+        }
+    }
+}
+
+/// Parameter of a [`FunctionSignature`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionParam {
+    pub name: String,
+    pub ty: ValueType,
+    /// Whether the argument may be null or left out
+    pub optional: bool,
+}
+
+/// Typed parameters and result of a function
+///
+/// Functions are called with positional JSON arguments whatever their
+/// signature; signatures describe them to interfaces that need types, such
+/// as the GraphQL endpoint. Declared functions get theirs from their fn;
+/// others can be given one with
+/// [`Module::with_function_signature`](crate::Module::with_function_signature).
+/// A [validated](Self::validated) signature is also checked against every
+/// call before the handler runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionSignature {
+    pub params: Vec<FunctionParam>,
+    pub returns: ValueType,
+    /// Whether calls change state, so should be offered as a mutation
+    /// rather than a query
+    pub mutation: bool,
+    /// Whether calls whose arguments do not fit are rejected
+    #[serde(default)]
+    pub validate: bool,
+}
+
+impl FunctionSignature {
+    /// Signature without parameters returning any JSON value
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a required parameter
+    pub fn param(mut self, name: impl Into<String>, ty: ValueType) -> Self {
+        self.params.push(FunctionParam {
+            name: name.into(),
+            ty,
+            optional: false,
+        });
+        self
+    }
+
+    /// Add a parameter that may be null or left out
+    pub fn optional_param(mut self, name: impl Into<String>, ty: ValueType) -> Self {
+        self.params.push(FunctionParam {
+            name: name.into(),
+            ty,
+            optional: true,
+        });
+        self
+    }
+
+    /// Set the type of the result
+    pub fn returns(mut self, ty: ValueType) -> Self {
+        self.returns = ty;
+        self
+    }
+
+    /// Mark the function as changing state
+    pub fn mutation(mut self) -> Self {
+        self.mutation = true;
+        self
+    }
+
+    /// Reject calls whose arguments do not fit the parameters with
+    /// [`Error::InvalidArguments`], before the handler runs
+    pub fn validated(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
+    /// Check `args` against the parameters, listing every argument that
+    /// does not fit
+    pub fn check(&self, args: &[Value]) -> Vec<ArgumentError> {
+        let mut errors: Vec<ArgumentError> = self
+            .params
+            .iter()
+            .enumerate()
+            .filter_map(|(index, param)| {
+                let found = match args.get(index) {
+                    None | Some(Value::Null) if param.optional => return None,
+                    None => "missing",
+                    Some(value) if param.ty.accepts(value) => return None,
+                    Some(value) => json_type(value),
+                };
+                Some(ArgumentError {
+                    index,
+                    name: Some(param.name.clone()),
+                    expected: Some(param.ty.clone()),
+                    found: found.to_string(),
+                })
+            })
+            .collect();
+        errors.extend(args.iter().enumerate().skip(self.params.len()).map(|(index, value)| ArgumentError {
+            index,
+            name: None,
+            expected: None,
+            found: json_type(value).to_string(),
+        }));
+        errors
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(number) if number.is_f64() => "float",
+        Value::Number(_) => "int",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Function handler rejecting calls that do not fit its signature
+pub(crate) struct ValidatedFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) function: String,
+    pub(crate) signature: FunctionSignature,
+}
+
+impl ValidatedFunctionHandler {
+    fn validate(&self, args: &[Value]) -> Result<()> {
+        let errors = self.signature.check(args);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(Error::InvalidArguments {
+            function: self.function.clone(),
+            errors,
+        })
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for ValidatedFunctionHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        self.validate(&args)?;
+        self.inner.call(args).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        self.validate(&args)?;
+        self.inner.call_with_context(context, args).await
+    }
+}
+
+/// Check the number of arguments passed to a declared function
+#[doc(hidden)]
+pub fn check_arity(function: &str, expected: usize, given: usize) -> Result<()> {
+    if given > expected {
+        return Err(Error::Function(format!(
+            "{} takes at most {} arguments but {} were given",
+            function, expected, given
+        )));
+    }
+    Ok(())
+}
+
+/// Deserialize one argument of a declared function, treating a missing one as null
+#[doc(hidden)]
+pub fn parse_argument<T: DeserializeOwned>(
+    function: &str,
+    index: usize,
+    name: &str,
+    value: Option<Value>,
+) -> Result<T> {
+    serde_json::from_value(value.unwrap_or(Value::Null)).map_err(|err| {
+        Error::Function(format!("{}: invalid argument {} ({}): {}", function, index, name, err))
+    })
+}
+
+/// Convert the output of a declared function into a call result
+#[doc(hidden)]
+pub fn function_output<T, E>(output: std::result::Result<T, E>) -> Result<Value>
+where
+    T: Serialize,
+    E: Into<Error>,
+{
+    Ok(serde_json::to_value(output.map_err(Into::into)?)?)
+}
+
+/// Function handler that runs stages in sequence
+///
+/// The first stage receives the call's arguments; every later stage receives
+/// the previous stage's result as its single argument. The first error stops
+/// the pipeline.
+pub struct PipelineHandler {
+    stages: Vec<Arc<dyn FunctionHandler>>,
+}
+
+impl PipelineHandler {
+    pub fn new(stages: Vec<Arc<dyn FunctionHandler>>) -> Self {
+        Self { stages }
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for PipelineHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        let (first, rest) = self
+            .stages
+            .split_first()
+            .ok_or_else(|| Error::Function("pipeline has no stages".to_string()))?;
+
+        let mut value = first.call(args).await?;
+        for stage in rest {
+            value = stage.call(vec![value]).await?;
+        }
+
+        Ok(value)
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let (first, rest) = self
+            .stages
+            .split_first()
+            .ok_or_else(|| Error::Function("pipeline has no stages".to_string()))?;
+
+        let mut value = first.call_with_context(context.clone(), args).await?;
+        for stage in rest {
+            value = stage.call_with_context(context.clone(), vec![value]).await?;
+        }
+
+        Ok(value)
+    }
+}
+
+/// What a guarded function requires of its caller
+pub(crate) enum Guard {
+    /// One of these roles
+    Roles(Vec<String>),
+    /// A token issued by one of these access methods
+    Access(Vec<String>),
+}
+
+impl Guard {
+    fn admits(&self, identity: &Identity) -> bool {
+        match self {
+            Guard::Roles(roles) => roles.iter().any(|role| identity.has_role(role)),
+            Guard::Access(methods) => methods.iter().any(|access| identity.signed_in_with(access)),
+        }
+    }
+}
+
+/// Function handler that only runs for callers its guard admits
+///
+/// Calls without an authenticated [`Identity`](crate::auth::Identity) in their
+/// context, including calls through `call`, are rejected too.
+pub(crate) struct GuardedFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) function: String,
+    pub(crate) guard: Guard,
+}
+
+impl GuardedFunctionHandler {
+    fn forbidden(&self) -> Error {
+        let required = match &self.guard {
+            Guard::Roles(roles) => roles.clone(),
+            Guard::Access(methods) => methods.iter().map(|access| format!("access method {}", access)).collect(),
+        };
+        Error::Forbidden {
+            function: self.function.clone(),
+            required,
+        }
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for GuardedFunctionHandler {
+    async fn call(&self, _args: Vec<Value>) -> Result<Value> {
+        Err(self.forbidden())
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let allowed = context.identity().is_some_and(|identity| self.guard.admits(identity));
+        if !allowed {
+            return Err(self.forbidden());
+        }
+        self.inner.call_with_context(context, args).await
+    }
+}
+
+/// Options of a function added with
+/// [`Module::with_function_opts`](crate::Module::with_function_opts)
+#[derive(Debug, Clone, Default)]
+pub struct FunctionOpts {
+    /// Cancel calls running longer than this, failing them with
+    /// `Error::Function("timeout")`
+    pub timeout: Option<Duration>,
+    /// Memoize results; see [`cached`](Self::cached)
+    pub cache: Option<ResultCache>,
+    /// Replay results by idempotency key; see [`idempotent`](Self::idempotent)
+    pub idempotency: Option<Idempotency>,
+    /// Retry failed calls; see [`with_retry`](Self::with_retry)
+    pub retry: Option<RetryPolicy>,
+}
+
+impl FunctionOpts {
+    /// Keep results in the module's cache for `ttl`, under the function's
+    /// name and the key `key` derives from the arguments
+    ///
+    /// Meant for deterministic functions, such as exchange rate or geocoding
+    /// lookups; pass [`hash_args`] to key results by all of their arguments.
+    /// Results are kept per caller, so one caller is never answered with
+    /// what was computed for another; unauthenticated calls share theirs.
+    /// Concurrent calls with the same key run the function once and share
+    /// its result. Errors are not cached. Caches keep entries for whole
+    /// seconds, so `ttl` is rounded up to the next second.
+    pub fn cached<K>(mut self, ttl: Duration, key: K) -> Self
+    where
+        K: Fn(&[Value]) -> String + Send + Sync + 'static,
+    {
+        self.cache = Some(ResultCache {
+            ttl,
+            key: Arc::new(key),
+        });
+        self
+    }
+
+    /// Answer repeated calls carrying the same idempotency key with the
+    /// result of the first, for `window` after it
+    ///
+    /// Meant for functions with effects that must not happen twice, such as
+    /// charging a card. The key is the `Idempotency-Key` header of calls
+    /// through `POST /sx/fn/{name}`; calls without one run normally. Results
+    /// are kept in the module's cache per caller, so two callers cannot
+    /// share a key, and concurrent calls with the same key run the function
+    /// once. A key reused with different arguments fails with
+    /// `Error::Validation`. Errors are not kept, so a failed call can be
+    /// retried with its key. Callers are told apart by
+    /// [`Identity::subject`]; unauthenticated calls share their keys. The
+    /// window is rounded up to whole seconds.
+    pub fn idempotent(mut self, window: Duration) -> Self {
+        self.idempotency = Some(Idempotency { window, argument: None });
+        self
+    }
+
+    /// Retry failed calls under `policy`, each attempt with its own timeout
+    ///
+    /// Same as [`Module::with_function_retry`](crate::Module::with_function_retry).
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Like [`idempotent`](Self::idempotent), also taking the key from the
+    /// argument at `index`, a string or number, for callers that cannot set
+    /// a header, such as SurrealQL
+    ///
+    /// The header wins when a call carries both.
+    pub fn idempotent_by_argument(mut self, window: Duration, index: usize) -> Self {
+        self.idempotency = Some(Idempotency {
+            window,
+            argument: Some(index),
+        });
+        self
+    }
+}
+
+/// How a function's results are replayed by idempotency key; see
+/// [`FunctionOpts::idempotent`]
+#[derive(Debug, Clone)]
+pub struct Idempotency {
+    window: Duration,
+    argument: Option<usize>,
+}
+
+impl Idempotency {
+    /// The key of a call: the context's, or else the key argument's
+    fn key(&self, context: Option<&FunctionContext>, args: &[Value]) -> Option<String> {
+        if let Some(key) = context.and_then(FunctionContext::idempotency_key) {
+            return Some(key.to_string());
+        }
+        match args.get(self.argument?)? {
+            Value::String(key) => Some(key.clone()),
+            Value::Number(key) => Some(key.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// How a function's results are cached; see [`FunctionOpts::cached`]
+#[derive(Clone)]
+pub struct ResultCache {
+    ttl: Duration,
+    key: Arc<dyn Fn(&[Value]) -> String + Send + Sync>,
+}
+
+impl std::fmt::Debug for ResultCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResultCache").field("ttl", &self.ttl).finish_non_exhaustive()
+    }
+}
+
+/// `duration` in the whole seconds cache entries live for, rounded up
+fn ttl_secs(duration: Duration) -> u64 {
+    (duration.as_secs_f64().ceil() as u64).max(1)
+}
+
+/// SHA-256 of the JSON of `args`, to key cached results by all arguments
+pub fn hash_args(args: &[Value]) -> String {
+    let json = serde_json::to_vec(args).unwrap_or_default();
+    format!("{:x}", Sha256::digest(json))
+}
+
+/// Function handler memoizing results in the cache of the module it runs in
+///
+/// Calls outside a module's context, where there is no cache to use, are
+/// not cached.
+pub(crate) struct CachedFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) function: String,
+    pub(crate) cache: ResultCache,
+    /// Loads in progress by key, so concurrent misses run the function once
+    /// whatever the cache provider
+    pub(crate) loads: Arc<cache::InFlightLoads>,
+}
+
+impl CachedFunctionHandler {
+    async fn cached(
+        &self,
+        caller: Option<&str>,
+        args: &[Value],
+        call: BoxFuture<'_, Result<Value>>,
+    ) -> Result<Value> {
+        let Some(current) = Context::current() else {
+            return call.await;
+        };
+        let key = format!(
+            "fn:{}:{}:{}",
+            self.function,
+            caller.unwrap_or_default(),
+            (self.cache.key)(args)
+        );
+        let in_flight = cache::InFlight::join(&self.loads, &key);
+        let _guard = in_flight.lock.lock().await;
+        let ttl = ttl_secs(self.cache.ttl);
+        current.cache().get_or_set(&key, Some(ttl), call).await
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for CachedFunctionHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        let call = self.inner.call(args.clone());
+        self.cached(None, &args, call).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let caller = context.identity().and_then(Identity::subject).map(str::to_string);
+        let call = self.inner.call_with_context(context, args.clone());
+        self.cached(caller.as_deref(), &args, call).await
+    }
+}
+
+/// Function handler replaying the first result for each idempotency key
+/// from the cache of the module it runs in
+///
+/// Calls outside a module's context, where there is no cache to use, always
+/// run.
+pub(crate) struct IdempotentFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) function: String,
+    pub(crate) idempotency: Idempotency,
+    /// Calls in progress by key, so concurrent retries run the function once
+    pub(crate) loads: Arc<cache::InFlightLoads>,
+}
+
+impl IdempotentFunctionHandler {
+    async fn replayed(
+        &self,
+        key: Option<String>,
+        caller: Option<&str>,
+        args: &[Value],
+        call: BoxFuture<'_, Result<Value>>,
+    ) -> Result<Value> {
+        let (Some(key), Some(current)) = (key, Context::current()) else {
+            return call.await;
+        };
+        let key = format!("idem:{}:{}:{}", self.function, caller.unwrap_or_default(), key);
+        let in_flight = cache::InFlight::join(&self.loads, &key);
+        let _guard = in_flight.lock.lock().await;
+
+        let fingerprint = hash_args(args);
+        if let Some(stored) = current.cache().get(&key).await? {
+            if stored.get("args").and_then(Value::as_str) != Some(fingerprint.as_str()) {
+                return Err(Error::Validation(
+                    "the idempotency key was already used with different arguments".to_string(),
+                ));
+            }
+            tracing::debug!(function = %self.function, "replaying idempotent call");
+            return Ok(stored.get("result").cloned().unwrap_or_default());
+        }
+
+        let result = call.await?;
+        let stored = serde_json::json!({ "args": fingerprint, "result": result });
+        let window = ttl_secs(self.idempotency.window);
+        // The call already took effect, so failing it now would only invite
+        // the retry the key is there to absorb
+        if let Err(err) = current.cache().set(&key, stored, Some(window)).await {
+            tracing::warn!(function = %self.function, error = %err, "failed to store idempotent result");
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for IdempotentFunctionHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        let key = self.idempotency.key(None, &args);
+        let call = self.inner.call(args.clone());
+        self.replayed(key, None, &args, call).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let key = self.idempotency.key(Some(&context), &args);
+        let caller = context.identity().and_then(Identity::subject).map(str::to_string);
+        let call = self.inner.call_with_context(context, args.clone());
+        self.replayed(key, caller.as_deref(), &args, call).await
+    }
+}
+
+/// Function handler cancelling calls that run past its timeout
+///
+/// The call's future is dropped on timeout, so whatever it was awaiting,
+/// such as an HTTP request, is abandoned.
+pub(crate) struct TimeoutFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) timeout: Duration,
+}
+
+impl TimeoutFunctionHandler {
+    async fn bounded(&self, call: impl std::future::Future<Output = Result<Value>>) -> Result<Value> {
+        match tokio::time::timeout(self.timeout, call).await {
+            Ok(result) => result,
+            Err(_) => {
+                tracing::warn!(timeout = ?self.timeout, "function call timed out; cancelled");
+                Err(Error::Function("timeout".to_string()))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for TimeoutFunctionHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        self.bounded(self.inner.call(args)).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        self.bounded(self.inner.call_with_context(context, args)).await
+    }
+}
+
+/// Function handler logging a warning the first time its deprecated function
+/// is called
+pub(crate) struct DeprecatedFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) function: String,
+    pub(crate) note: String,
+    pub(crate) warned: AtomicBool,
+}
+
+impl DeprecatedFunctionHandler {
+    fn warn(&self) {
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            tracing::warn!(function = %self.function, note = %self.note, "deprecated function called");
+        }
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for DeprecatedFunctionHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        self.warn();
+        self.inner.call(args).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        self.warn();
+        self.inner.call_with_context(context, args).await
+    }
+}
+
+/// Number of handler calls in progress, shared by every handler given a clone
+#[derive(Clone, Default)]
+pub(crate) struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// Count a call until the returned guard is dropped
+    pub(crate) fn enter(&self) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.0.clone())
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Wait until no call is in progress, for at most `timeout`
+    ///
+    /// Returns whether every call finished in time.
+    pub(crate) async fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.count() > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        true
+    }
+}
+
+pub(crate) struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Function handler that runs inside a span and context identifying its module
+pub(crate) struct InstrumentedFunctionHandler {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) context: Context,
+    pub(crate) function: String,
+    pub(crate) trace: ModuleSpan,
+    pub(crate) in_flight: InFlight,
+}
+
+#[async_trait]
+impl FunctionHandler for InstrumentedFunctionHandler {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        self.context.check_enabled()?;
+        let _in_flight = self.in_flight.enter();
+        context::scope(self.context.clone(), self.inner.call(args))
+            .instrument(self.span(None))
+            .await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        self.context.check_enabled()?;
+        let _in_flight = self.in_flight.enter();
+        let span = self.span(Some(context.request_id()));
+        let module = self.context.for_tenant(context.tenant());
+        context::scope(module, self.inner.call_with_context(context, args))
+            .instrument(span)
+            .await
+    }
+}
+
+impl InstrumentedFunctionHandler {
+    fn span(&self, request_id: Option<&str>) -> tracing::Span {
+        self.trace.in_scope(|| {
+            tracing::info_span!(
+                "sx.function",
+                module = %self.context.module(),
+                function = %self.function,
+                request_id = request_id,
+            )
+        })
+    }
+}
+
+/// One version of a function registered as `name@version`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FunctionVersion {
+    pub version: String,
+    /// Name the version is registered under, `name@version`
+    pub function: String,
+    /// Whether calls without a version reach this one
+    pub default: bool,
+    /// Why callers should move off this version, if it is deprecated
+    pub deprecated: Option<String>,
+}
+
+/// Order versions such as `v2` and `v10`, or `1.2` and `1.10`, by their
+/// numbers, then by text
+pub(crate) fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let numbers = |version: &str| -> Vec<u64> {
+        version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|part| part.parse().ok())
+            .collect()
+    };
+    numbers(a).cmp(&numbers(b)).then_with(|| a.cmp(b))
+}
+
+/// Functions registered directly, with their aliases and metadata
+#[derive(Clone, Default)]
+struct Registered {
+    functions: HashMap<String, Arc<dyn FunctionHandler>>,
+    aliases: HashMap<String, String>,
+    signatures: HashMap<String, FunctionSignature>,
+    /// Notes of deprecated functions, by registered name
+    deprecations: HashMap<String, String>,
+    streams: HashMap<String, Arc<dyn StreamingFunctionHandler>>,
+}
+
+impl Registered {
+    fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.aliases.get(name) {
+            Some(target) if !self.functions.contains_key(name) => target,
+            _ => name,
+        }
+    }
+}
+
 /// Registry for custom functions
+///
+/// A function can have several versions, registered as `name@version` such
+/// as `ext::tax@v2`. Calls to the plain name reach the default version,
+/// which is an alias of it; see [`versions`](Self::versions).
+///
+/// Clones share their functions, so functions registered or removed after
+/// the server is built, through
+/// [`BuiltSurrealX::function_registry`](crate::server::BuiltSurrealX::function_registry),
+/// are seen by every endpoint that calls functions. Each change replaces the
+/// registrations as a whole, so calls never wait on it.
 #[derive(Clone)]
 pub struct FunctionRegistry {
-    functions: Arc<HashMap<String, Arc<dyn FunctionHandler>>>,
+    registered: Arc<ArcSwap<Registered>>,
+    /// Functions of hot-reloaded modules, consulted after the registered ones
+    reloadable: Arc<ArcSwap<HashMap<String, Arc<dyn FunctionHandler>>>>,
+    stats: Arc<CallStats>,
 }
 
 impl FunctionRegistry {
     pub fn new() -> Self {
         Self {
-            functions: Arc::new(HashMap::new()),
+            registered: Arc::new(ArcSwap::from_pointee(Registered::default())),
+            reloadable: Arc::new(ArcSwap::from_pointee(HashMap::new())),
+            stats: Arc::new(CallStats::default()),
         }
     }
 
-    /// Register a new function
-    pub fn register<H>(&mut self, name: impl Into<String>, handler: H)
+    /// Apply a change to a copy of the registrations and publish it
+    fn update(&self, change: impl Fn(&mut Registered)) {
+        self.registered.rcu(|current| {
+            let mut next = Registered::clone(current);
+            change(&mut next);
+            next
+        });
+    }
+
+    /// Register a new function, replacing any of the same name
+    pub fn register<H>(&self, name: impl Into<String>, handler: H)
     where
         H: FunctionHandler + 'static,
     {
-        let functions = Arc::get_mut(&mut self.functions)
-            .expect("Cannot modify registry with existing references");
-        functions.insert(name.into(), Arc::new(handler));
+        self.register_arc(name, Arc::new(handler));
     }
 
     /// Register a function that's already wrapped in Arc
-    pub fn register_arc(&mut self, name: impl Into<String>, handler: Arc<dyn FunctionHandler>) {
-        let functions = Arc::get_mut(&mut self.functions)
-            .expect("Cannot modify registry with existing references");
-        functions.insert(name.into(), handler);
+    pub fn register_arc(&self, name: impl Into<String>, handler: Arc<dyn FunctionHandler>) {
+        let name = name.into();
+        self.update(|registered| {
+            registered.functions.insert(name.clone(), handler.clone());
+        });
+    }
+
+    /// Remove a registered function along with its streaming handler,
+    /// signature, deprecation and the aliases that refer to it
+    ///
+    /// Returns whether the function was registered. Calls already running
+    /// finish with the removed handler; hot-reloaded functions are not
+    /// affected.
+    pub fn unregister(&self, name: &str) -> bool {
+        let mut removed = false;
+        self.registered.rcu(|current| {
+            let mut next = Registered::clone(current);
+            removed = next.functions.remove(name).is_some();
+            next.streams.remove(name);
+            next.signatures.remove(name);
+            next.deprecations.remove(name);
+            next.aliases.retain(|_, target| target != name);
+            next
+        });
+        self.stats.remove(name);
+        removed
+    }
+
+    /// Let a registered function be called as a stream
+    ///
+    /// The function itself, registered with `register`, serves callers that
+    /// cannot stream.
+    pub fn register_stream(&self, name: impl Into<String>, handler: Arc<dyn StreamingFunctionHandler>) {
+        let name = name.into();
+        self.update(|registered| {
+            registered.streams.insert(name.clone(), handler.clone());
+        });
+    }
+
+    /// Get the streaming handler of a function by name or alias, if it
+    /// streams
+    pub fn get_stream(&self, name: &str) -> Option<Arc<dyn StreamingFunctionHandler>> {
+        let registered = self.registered.load();
+        registered.streams.get(registered.resolve(name)).cloned()
+    }
+
+    /// Make a function callable under another name
+    ///
+    /// Registered names take precedence over aliases.
+    pub fn register_alias(&self, alias: impl Into<String>, target: impl Into<String>) {
+        let (alias, target) = (alias.into(), target.into());
+        self.update(|registered| {
+            registered.aliases.insert(alias.clone(), target.clone());
+        });
+    }
+
+    /// Describe the parameters and result of a registered function
+    pub fn register_signature(&self, name: impl Into<String>, signature: FunctionSignature) {
+        let name = name.into();
+        self.update(|registered| {
+            registered.signatures.insert(name.clone(), signature.clone());
+        });
+    }
+
+    /// Mark a registered function, usually an old version, as deprecated
+    pub fn register_deprecation(&self, name: impl Into<String>, note: impl Into<String>) {
+        let (name, note) = (name.into(), note.into());
+        self.update(|registered| {
+            registered.deprecations.insert(name.clone(), note.clone());
+        });
+    }
+
+    /// Why a function should no longer be called, if it is deprecated
+    pub fn deprecation(&self, name: &str) -> Option<String> {
+        let registered = self.registered.load();
+        registered.deprecations.get(registered.resolve(name)).cloned()
+    }
+
+    /// List the versions of `name`, registered as `name@version`, oldest
+    /// first
+    pub fn versions(&self, name: &str) -> Vec<FunctionVersion> {
+        let prefix = format!("{}@", name);
+        let registered = self.registered.load();
+        let default = registered.resolve(name);
+        let mut versions: Vec<FunctionVersion> = self
+            .list()
+            .into_iter()
+            .filter_map(|function| {
+                let version = function.strip_prefix(&prefix)?.to_string();
+                Some(FunctionVersion {
+                    default: function == default,
+                    deprecated: registered.deprecations.get(&function).cloned(),
+                    version,
+                    function,
+                })
+            })
+            .collect();
+        versions.sort_by(|a, b| compare_versions(&a.version, &b.version));
+        versions
+    }
+
+    /// Get the signature of a function by name or alias, if it has one
+    pub fn signature(&self, name: &str) -> Option<FunctionSignature> {
+        let registered = self.registered.load();
+        registered.signatures.get(registered.resolve(name)).cloned()
     }
 
-    /// Get a function handler by name
+    /// Get a function handler by name or alias
     pub fn get(&self, name: &str) -> Option<Arc<dyn FunctionHandler>> {
-        self.functions.get(name).cloned()
+        self.get_registered(name)
+            .or_else(|| self.reloadable.load().get(name).cloned())
     }
 
-    /// Check if a function exists
+    /// Check if a function exists under a name or alias
     pub fn contains(&self, name: &str) -> bool {
-        self.functions.contains_key(name)
+        let registered = self.registered.load();
+        registered.functions.contains_key(registered.resolve(name)) || self.reloadable.load().contains_key(name)
+    }
+
+    /// Get a function registered directly, ignoring hot-reloaded ones
+    pub(crate) fn get_registered(&self, name: &str) -> Option<Arc<dyn FunctionHandler>> {
+        let registered = self.registered.load();
+        registered.functions.get(registered.resolve(name)).cloned()
     }
 
-    /// List all registered function names
+    /// Replace every hot-reloaded function at once
+    pub(crate) fn swap_reloadable(&self, functions: HashMap<String, Arc<dyn FunctionHandler>>) {
+        self.reloadable.store(Arc::new(functions));
+    }
+
+    /// Get the registered name a name or alias refers to
+    pub fn resolve(&self, name: &str) -> String {
+        self.registered.load().resolve(name).to_string()
+    }
+
+    /// List all aliases as (alias, registered name) pairs
+    pub fn aliases(&self) -> Vec<(String, String)> {
+        self.registered
+            .load()
+            .aliases
+            .iter()
+            .map(|(alias, target)| (alias.clone(), target.clone()))
+            .collect()
+    }
+
+    /// List all registered function names, including hot-reloaded ones
     pub fn list(&self) -> Vec<String> {
-        self.functions.keys().cloned().collect()
+        self.registered
+            .load()
+            .functions
+            .keys()
+            .chain(self.reloadable.load().keys())
+            .cloned()
+            .collect()
+    }
+
+    /// Call statistics of every module function called so far, by name; see
+    /// the [`stats`](crate::stats) module
+    pub fn stats(&self) -> Vec<FunctionStats> {
+        self.stats.snapshot()
+    }
+
+    /// Call statistics of one function, if it has been called
+    pub fn function_stats(&self, name: &str) -> Option<FunctionStats> {
+        self.stats.get(&self.resolve(name))
+    }
+
+    /// Counters calls of module functions are recorded in
+    pub(crate) fn call_stats(&self) -> &Arc<CallStats> {
+        &self.stats
     }
 }
 
@@ -91,3 +1312,125 @@ impl Default for FunctionRegistry {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::admin::DisabledModules;
+    use crate::cache::{MemoryCacheProvider, ScopedCache, TenantCaches};
+    use crate::events::EventRegistry;
+
+    /// Counts its calls, failing the first if asked to
+    #[derive(Default)]
+    struct Counter {
+        calls: AtomicUsize,
+        fail_first: AtomicBool,
+    }
+
+    #[async_trait]
+    impl FunctionHandler for Counter {
+        async fn call(&self, args: Vec<Value>) -> Result<Value> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if self.fail_first.swap(false, Ordering::SeqCst) {
+                return Err(Error::Function("unavailable".to_string()));
+            }
+            Ok(json!({ "call": call, "args": args }))
+        }
+    }
+
+    fn idempotent(inner: Arc<Counter>) -> IdempotentFunctionHandler {
+        IdempotentFunctionHandler {
+            inner,
+            function: "billing::charge".to_string(),
+            idempotency: Idempotency {
+                window: Duration::from_secs(60),
+                argument: None,
+            },
+            loads: Arc::default(),
+        }
+    }
+
+    fn module_context() -> Context {
+        let cache = ScopedCache::new(Arc::new(MemoryCacheProvider::new()), "module:billing");
+        Context::new("billing", cache, None, None, DisabledModules::default(), TenantCaches::default(), None)
+    }
+
+    /// A call by the system user `subject`, which has no record, with the
+    /// idempotency key `key`
+    fn call_by(subject: &str, key: &str) -> FunctionContext {
+        let identity = Identity {
+            namespace: None,
+            database: None,
+            access: None,
+            record: None,
+            roles: Vec::new(),
+            claims: json!({ "sub": subject }),
+        };
+        FunctionContext::new("test", "test", Arc::new(MemoryCacheProvider::new()), EventRegistry::new())
+            .with_identity(identity)
+            .with_idempotency_key(key)
+    }
+
+    #[tokio::test]
+    async fn replays_the_first_result_for_a_key() -> Result<()> {
+        let counter = Arc::new(Counter::default());
+        let handler = idempotent(counter.clone());
+        context::scope(module_context(), async {
+            let first = handler.call_with_context(call_by("alice", "k1"), vec![json!(10)]).await?;
+            let second = handler.call_with_context(call_by("alice", "k1"), vec![json!(10)]).await?;
+            assert_eq!(first, second);
+            assert_eq!(counter.calls.load(Ordering::SeqCst), 1);
+            Ok::<_, Error>(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn refuses_a_key_reused_with_other_arguments() -> Result<()> {
+        let handler = idempotent(Arc::default());
+        context::scope(module_context(), async {
+            handler.call_with_context(call_by("alice", "k1"), vec![json!(10)]).await?;
+            let reused = handler.call_with_context(call_by("alice", "k1"), vec![json!(20)]).await;
+            assert!(matches!(reused, Err(Error::Validation(_))));
+            Ok::<_, Error>(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn does_not_keep_failed_calls() -> Result<()> {
+        let counter = Arc::new(Counter::default());
+        counter.fail_first.store(true, Ordering::SeqCst);
+        let handler = idempotent(counter.clone());
+        context::scope(module_context(), async {
+            let failed = handler.call_with_context(call_by("alice", "k1"), vec![json!(10)]).await;
+            assert!(matches!(failed, Err(Error::Function(_))));
+            let retried = handler.call_with_context(call_by("alice", "k1"), vec![json!(10)]).await?;
+            assert_eq!(retried["call"], json!(2));
+            Ok::<_, Error>(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn keeps_callers_sharing_a_key_apart() -> Result<()> {
+        let counter = Arc::new(Counter::default());
+        let handler = idempotent(counter.clone());
+        context::scope(module_context(), async {
+            let alice = handler.call_with_context(call_by("alice", "k1"), vec![json!(10)]).await?;
+            let bob = handler.call_with_context(call_by("bob", "k1"), vec![json!(10)]).await?;
+            assert_ne!(alice, bob);
+            assert_eq!(counter.calls.load(Ordering::SeqCst), 2);
+            Ok::<_, Error>(())
+        })
+        .await
+    }
+
+    #[test]
+    fn rounds_windows_up_to_whole_seconds() {
+        assert_eq!(ttl_secs(Duration::from_millis(1500)), 2);
+        assert_eq!(ttl_secs(Duration::from_millis(200)), 1);
+        assert_eq!(ttl_secs(Duration::from_secs(60)), 60);
+    }
+}
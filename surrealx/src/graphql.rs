@@ -0,0 +1,238 @@
+//! GraphQL endpoint for registered functions (requires graphql feature)
+//!
+//! With `ServerConfig::graphql_route` set, `POST /sx/graphql` answers GraphQL
+//! requests whose fields are the server's functions and `GET /sx/graphql`
+//! serves GraphiQL to explore them. A function's field is its name with
+//! every character GraphQL does not allow replaced by `_`, so
+//! `ext::business::tax` becomes `ext_business_tax`. Functions whose
+//! [`FunctionSignature`] marks them as mutations are fields of `Mutation`,
+//! the others of `Query`, which also has a `_functions` field listing every
+//! function name.
+//!
+//! A function with a signature takes its parameters as named arguments, in
+//! the GraphQL types of their [`ValueType`], and returns its result type;
+//! untyped parameters and results use the `JSON` scalar, which holds any JSON
+//! value. A function without a signature takes its positional arguments as
+//! one `args: [JSON]` argument and returns `JSON`.
+//!
+//! The route is authenticated and rate limited like module routes, and
+//! functions see the caller's identity as they do from `/sql`. Failures are
+//! reported as GraphQL errors, with `FORBIDDEN`, `RATE_LIMITED` or
+//! `UNAUTHENTICATED` as the `code` extension where it applies. The schema is
+//! built with the server, so functions of hot-reloaded modules are not part
+//! of it.
+
+use std::collections::HashSet;
+use async_graphql::dynamic::{
+    Field, FieldFuture, FieldValue, InputValue, Object, ResolverContext, Scalar, Schema, TypeRef,
+};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::ErrorExtensions;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::Value;
+use crate::auth::Identity;
+use crate::context::FunctionContext;
+use crate::functions::{FunctionParam, FunctionRegistry, FunctionSignature, ValueType};
+use crate::outbox::Outbox;
+use crate::tenancy::Tenant;
+use crate::error::{Error, Result};
+
+/// Scalar holding any JSON value
+const JSON: &str = "JSON";
+
+/// Functions and what their calls run with
+#[derive(Clone)]
+struct Caller {
+    functions: FunctionRegistry,
+    outbox: Outbox,
+    context: FunctionContext,
+}
+
+/// Per-request data handed to resolvers
+struct RequestData {
+    context: FunctionContext,
+}
+
+/// Serve `/sx/graphql` for the functions of `functions`
+pub(crate) fn router(functions: FunctionRegistry, outbox: Outbox, context: FunctionContext) -> Result<Router> {
+    let caller = Caller {
+        functions,
+        outbox,
+        context,
+    };
+    let schema = schema(&caller)?;
+    Ok(Router::new()
+        .route("/sx/graphql", get(graphiql).post(execute))
+        .with_state((schema, caller)))
+}
+
+fn schema(caller: &Caller) -> Result<Schema> {
+    let mut names = caller.functions.list();
+    names.sort();
+
+    let listed = names.clone();
+    let mut query = Object::new("Query").field(Field::new("_functions", TypeRef::named_nn_list_nn(TypeRef::STRING), move |_| {
+        let listed = listed.clone();
+        FieldFuture::new(async move {
+            Ok(Some(FieldValue::list(listed.into_iter().map(FieldValue::value))))
+        })
+    }));
+    let mut mutation = Object::new("Mutation");
+    let mut mutations = 0;
+
+    let mut fields = HashSet::new();
+    for name in names {
+        let field_name = graphql_name(&name);
+        if !fields.insert(field_name.clone()) {
+            tracing::warn!(function = %name, field = %field_name, "another function has the same GraphQL field; skipping");
+            continue;
+        }
+        let signature = caller.functions.signature(&name);
+        let field = function_field(caller.clone(), name, field_name, signature.clone());
+        if signature.is_some_and(|signature| signature.mutation) {
+            mutation = mutation.field(field);
+            mutations += 1;
+        } else {
+            query = query.field(field);
+        }
+    }
+
+    let mut builder = Schema::build("Query", (mutations > 0).then_some("Mutation"), None)
+        .register(Scalar::new(JSON))
+        .register(query);
+    if mutations > 0 {
+        builder = builder.register(mutation);
+    }
+    builder
+        .finish()
+        .map_err(|err| Error::Config(format!("failed to build the GraphQL schema: {}", err)))
+}
+
+/// Field calling the function `name`
+fn function_field(caller: Caller, name: String, field_name: String, signature: Option<FunctionSignature>) -> Field {
+    let returns = signature
+        .as_ref()
+        .map_or_else(|| TypeRef::named(JSON), |signature| type_ref(&signature.returns));
+    let params = signature.map(|signature| signature.params);
+
+    let mut field = Field::new(field_name, returns, {
+        let params = params.clone();
+        move |ctx| {
+            let (caller, name, params) = (caller.clone(), name.clone(), params.clone());
+            FieldFuture::new(async move {
+                let args = arguments(&ctx, params.as_deref())?;
+                let context = ctx.data::<RequestData>()?.context.clone();
+                let handler = caller
+                    .functions
+                    .get(&name)
+                    .ok_or_else(|| graphql_error(Error::NotFound(format!("function '{}'", name))))?;
+                let value = caller
+                    .outbox
+                    .call(handler.as_ref(), context, args)
+                    .await
+                    .map_err(graphql_error)?;
+                Ok(Some(FieldValue::value(async_graphql::Value::from_json(value)?)))
+            })
+        }
+    });
+
+    match params {
+        Some(params) => {
+            for param in params {
+                let ty = type_ref(&param.ty);
+                let ty = if param.optional { ty } else { TypeRef::NonNull(Box::new(ty)) };
+                field = field.argument(InputValue::new(graphql_name(&param.name), ty));
+            }
+        }
+        None => field = field.argument(InputValue::new("args", TypeRef::named_list(JSON))),
+    }
+    field
+}
+
+/// Positional arguments of a call from the field's GraphQL arguments
+fn arguments(ctx: &ResolverContext<'_>, params: Option<&[FunctionParam]>) -> async_graphql::Result<Vec<Value>> {
+    let json = |name: &str| -> async_graphql::Result<Value> {
+        match ctx.args.get(name) {
+            Some(value) => Ok(value.as_value().clone().into_json()?),
+            None => Ok(Value::Null),
+        }
+    };
+    match params {
+        Some(params) => params.iter().map(|param| json(&graphql_name(&param.name))).collect(),
+        None => match json("args")? {
+            Value::Array(args) => Ok(args),
+            Value::Null => Ok(Vec::new()),
+            other => Ok(vec![other]),
+        },
+    }
+}
+
+fn type_ref(ty: &ValueType) -> TypeRef {
+    match ty {
+        ValueType::Bool => TypeRef::named(TypeRef::BOOLEAN),
+        ValueType::Int => TypeRef::named(TypeRef::INT),
+        ValueType::Float => TypeRef::named(TypeRef::FLOAT),
+        ValueType::String => TypeRef::named(TypeRef::STRING),
+        ValueType::Array(item) => TypeRef::List(Box::new(type_ref(item))),
+        ValueType::Any => TypeRef::named(JSON),
+    }
+}
+
+/// `name` with every character GraphQL names do not allow replaced by `_`
+fn graphql_name(name: &str) -> String {
+    let mut graphql: String = name
+        .replace("::", "_")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if graphql.starts_with(|c: char| c.is_ascii_digit()) || graphql.is_empty() {
+        graphql.insert(0, '_');
+    }
+    graphql
+}
+
+fn graphql_error(err: Error) -> async_graphql::Error {
+    let code = match &err {
+        Error::Forbidden { .. } => Some("FORBIDDEN"),
+        Error::RateLimited { .. } => Some("RATE_LIMITED"),
+        Error::Unauthorized(_) => Some("UNAUTHENTICATED"),
+        Error::InvalidArguments { .. } => Some("INVALID_ARGUMENTS"),
+        Error::ModuleDisabled(_) => Some("MODULE_DISABLED"),
+        Error::CircuitOpen { .. } => Some("CIRCUIT_OPEN"),
+        _ => None,
+    };
+    let error = async_graphql::Error::new(err.to_string());
+    match code {
+        Some(code) => error.extend_with(|_, extensions| extensions.set("code", code)),
+        None => error,
+    }
+}
+
+async fn execute(
+    State((schema, caller)): State<(Schema, Caller)>,
+    identity: Option<Identity>,
+    tenant: Option<Tenant>,
+    headers: HeaderMap,
+    Json(request): Json<async_graphql::Request>,
+) -> Response {
+    let request_id = headers
+        .get(crate::telemetry::REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut context = caller.context.for_request(request_id);
+    if let Some(identity) = identity {
+        context = context.with_identity(identity);
+    }
+    if let Some(tenant) = tenant {
+        context = context.with_tenant(tenant);
+    }
+    Json(schema.execute(request.data(RequestData { context })).await).into_response()
+}
+
+async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/sx/graphql").finish())
+}
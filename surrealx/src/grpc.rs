@@ -0,0 +1,275 @@
+//! gRPC service for calling functions and receiving events (requires grpc feature)
+//!
+//! With `ServerConfig::grpc_service` set, the server answers the `SurrealX`
+//! service of `proto/surrealx.proto`, package `surrealx.v1`, on its HTTP
+//! port; clients connect over HTTP/2, with TLS when the server has it and
+//! in cleartext otherwise.
+//!
+//! - `CallFunction` calls a registered function with the JSON array of
+//!   `args_json` and returns its result as JSON text, like
+//!   `POST /sx/fn/{name}`.
+//! - `SubscribeEvents` streams the events matching any of the requested
+//!   patterns, like `/sx/events`, honoring the roles set with
+//!   [`SurrealX::with_event_access`]. A stream that falls more than 1024
+//!   events behind misses the oldest and reports how many in the `missed`
+//!   field of its next message.
+//!
+//! Requests are authenticated and rate limited like module routes, with the
+//! token in the `authorization` metadata, and requests refused there get
+//! plain HTTP errors rather than gRPC statuses. Failures of the calls
+//! themselves map to gRPC codes: `NOT_FOUND`, `PERMISSION_DENIED`,
+//! `UNAUTHENTICATED`, `RESOURCE_EXHAUSTED`, `INVALID_ARGUMENT` and
+//! `INTERNAL`.
+//!
+//! [`SurrealX::with_event_access`]: crate::SurrealX::with_event_access
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use axum::http;
+use axum::Router;
+use futures::future::BoxFuture;
+use futures::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+use tonic::server::Grpc;
+use tonic::{Request, Response, Status};
+use tonic_prost::ProstCodec;
+use tower::Service;
+use crate::auth::Identity;
+use crate::context::FunctionContext;
+use crate::events::{Event, EventRegistry, EventType};
+use crate::functions::FunctionRegistry;
+use crate::outbox::Outbox;
+use crate::subscriptions::{EventAcl, Session};
+use crate::tenancy::Tenant;
+use crate::error::Error;
+
+/// Name of the service, as clients address it
+pub const SERVICE_NAME: &str = "surrealx.v1.SurrealX";
+
+/// Request of `CallFunction`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CallFunctionRequest {
+    /// Fully qualified name, such as `ext::business::calculate_tax`
+    #[prost(string, tag = "1")]
+    pub name: String,
+    /// JSON array of arguments; empty for none
+    #[prost(string, tag = "2")]
+    pub args_json: String,
+}
+
+/// Response of `CallFunction`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CallFunctionResponse {
+    /// JSON result
+    #[prost(string, tag = "1")]
+    pub result_json: String,
+}
+
+/// Request of `SubscribeEvents`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubscribeEventsRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub patterns: Vec<String>,
+}
+
+/// Event sent by `SubscribeEvents`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EventMessage {
+    /// `CREATE`, `UPDATE`, `DELETE` or `CUSTOM`
+    #[prost(string, tag = "1")]
+    pub event_type: String,
+    /// Name of a `CUSTOM` event
+    #[prost(string, tag = "2")]
+    pub custom_type: String,
+    #[prost(string, tag = "3")]
+    pub table: String,
+    #[prost(string, optional, tag = "4")]
+    pub record_id: Option<String>,
+    /// JSON data
+    #[prost(string, tag = "5")]
+    pub data_json: String,
+    /// Unix timestamp in seconds
+    #[prost(int64, tag = "6")]
+    pub timestamp: i64,
+    /// Events missed before this one because the stream fell behind
+    #[prost(uint64, tag = "7")]
+    pub missed: u64,
+}
+
+impl From<Event> for EventMessage {
+    fn from(event: Event) -> Self {
+        let (event_type, custom_type) = match event.event_type {
+            EventType::Create => ("CREATE", String::new()),
+            EventType::Update => ("UPDATE", String::new()),
+            EventType::Delete => ("DELETE", String::new()),
+            EventType::Custom(name) => ("CUSTOM", name),
+        };
+        Self {
+            event_type: event_type.to_string(),
+            custom_type,
+            table: event.table,
+            record_id: event.record_id,
+            data_json: event.data.to_string(),
+            timestamp: event.timestamp,
+            missed: 0,
+        }
+    }
+}
+
+/// Registries the service answers from
+struct Shared {
+    functions: FunctionRegistry,
+    outbox: Outbox,
+    context: FunctionContext,
+    events: EventRegistry,
+    acl: EventAcl,
+}
+
+/// Router serving the `SurrealX` gRPC service
+pub(crate) fn router(
+    functions: FunctionRegistry,
+    outbox: Outbox,
+    context: FunctionContext,
+    events: EventRegistry,
+    acl: EventAcl,
+) -> Router {
+    let service = GrpcService {
+        shared: Arc::new(Shared {
+            functions,
+            outbox,
+            context,
+            events,
+            acl,
+        }),
+    };
+    Router::new().route_service(&format!("/{}/*rpc", SERVICE_NAME), service)
+}
+
+#[derive(Clone)]
+struct GrpcService {
+    shared: Arc<Shared>,
+}
+
+impl Service<http::Request<axum::body::Body>> for GrpcService {
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<axum::body::Body>) -> Self::Future {
+        let shared = self.shared.clone();
+        let method = request.uri().path().rsplit('/').next().unwrap_or_default().to_string();
+        Box::pin(async move {
+            Ok(match method.as_str() {
+                "CallFunction" => {
+                    let service = tower::service_fn(move |request| call_function(shared.clone(), request));
+                    Grpc::new(ProstCodec::default()).unary(service, request).await
+                }
+                "SubscribeEvents" => {
+                    let service = tower::service_fn(move |request| subscribe_events(shared.clone(), request));
+                    Grpc::new(ProstCodec::default()).server_streaming(service, request).await
+                }
+                _ => Status::unimplemented(format!("unknown method '{}'", method)).into_http(),
+            })
+        })
+    }
+}
+
+async fn call_function(
+    shared: Arc<Shared>,
+    request: Request<CallFunctionRequest>,
+) -> Result<Response<CallFunctionResponse>, Status> {
+    let request_id = request
+        .metadata()
+        .get(crate::telemetry::REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut context = shared.context.for_request(request_id);
+    if let Some(identity) = request.extensions().get::<Identity>() {
+        context = context.with_identity(identity.clone());
+    }
+    if let Some(tenant) = request.extensions().get::<Tenant>() {
+        context = context.with_tenant(tenant.clone());
+    }
+    let request = request.into_inner();
+
+    let handler = shared
+        .functions
+        .get(&request.name)
+        .ok_or_else(|| Status::not_found(format!("function '{}'", request.name)))?;
+    let args = if request.args_json.trim().is_empty() {
+        Vec::new()
+    } else {
+        match serde_json::from_str(&request.args_json) {
+            Ok(serde_json::Value::Array(args)) => args,
+            Ok(other) => vec![other],
+            Err(err) => return Err(Status::invalid_argument(format!("invalid args_json: {}", err))),
+        }
+    };
+    let result = shared
+        .outbox
+        .call(handler.as_ref(), context, args)
+        .await
+        .map_err(status)?;
+    Ok(Response::new(CallFunctionResponse {
+        result_json: result.to_string(),
+    }))
+}
+
+type EventStream = std::pin::Pin<Box<dyn Stream<Item = Result<EventMessage, Status>> + Send>>;
+
+async fn subscribe_events(
+    shared: Arc<Shared>,
+    request: Request<SubscribeEventsRequest>,
+) -> Result<Response<EventStream>, Status> {
+    let mut session = Session::new(
+        request.extensions().get::<Identity>().cloned(),
+        request.extensions().get::<Tenant>().cloned(),
+    );
+    let request = request.into_inner();
+    if request.patterns.is_empty() {
+        return Err(Status::invalid_argument("no pattern to subscribe to"));
+    }
+    for pattern in &request.patterns {
+        session.subscribe(&shared.acl, pattern).map_err(|(code, message)| match code {
+            http::StatusCode::FORBIDDEN => Status::permission_denied(message),
+            _ => Status::invalid_argument(message),
+        })?;
+    }
+
+    let tap = shared.events.tap();
+    let events = stream::unfold((tap, session, shared), |(mut tap, session, shared)| async move {
+        let mut missed = 0;
+        loop {
+            match tap.recv().await {
+                Ok(event) if session.receives(&shared.events, &shared.acl, &event) => {
+                    let mut message = EventMessage::from(event);
+                    message.missed = missed;
+                    return Some((Ok(message), (tap, session, shared)));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(count)) => missed += count,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Ok(Response::new(Box::pin(events) as EventStream))
+}
+
+fn status(err: Error) -> Status {
+    match err {
+        Error::NotFound(_) => Status::not_found(err.to_string()),
+        Error::Unauthorized(_) => Status::unauthenticated(err.to_string()),
+        Error::Forbidden { .. } => Status::permission_denied(err.to_string()),
+        Error::RateLimited { .. } => Status::resource_exhausted(err.to_string()),
+        Error::ModuleDisabled(_) | Error::CircuitOpen { .. } => Status::unavailable(err.to_string()),
+        Error::Function(_) | Error::Validation(_) | Error::Serialization(_) | Error::InvalidArguments { .. } => {
+            Status::invalid_argument(err.to_string())
+        }
+        _ => Status::internal(err.to_string()),
+    }
+}
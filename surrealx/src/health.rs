@@ -0,0 +1,156 @@
+//! Health, readiness and version endpoints
+//!
+//! With `ServerConfig::health_routes` set, the server answers:
+//!
+//! - `GET /health`: a [`HealthReport`] of the embedded database, the cache
+//!   provider and every module's health check. Always `200 OK` while the
+//!   server is up, so a liveness probe does not restart it over an outage a
+//!   restart cannot fix.
+//! - `GET /ready`: the same report, with `503 Service Unavailable` until the
+//!   modules have started, once shutdown begins, and while any component is
+//!   unhealthy. Meant for readiness probes.
+//! - `GET /version`: the SurrealX and SurrealDB versions.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::cache::CacheProvider;
+use crate::db::Database;
+use crate::module::ModuleHealth;
+use crate::reload::Reloader;
+use crate::server::{self, ModuleLifecycle};
+use crate::error::Result;
+
+/// How long a single component may take to answer before it counts as unhealthy
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Status of every component of a running server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// Whether every component is healthy
+    pub healthy: bool,
+    /// Whether the modules have started and shutdown has not begun
+    pub ready: bool,
+    pub database: ComponentHealth,
+    pub cache: ComponentHealth,
+    pub modules: Vec<ModuleHealth>,
+}
+
+/// Status of the database or cache provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComponentHealth {
+    pub healthy: bool,
+    /// Why the check failed
+    pub error: Option<String>,
+}
+
+impl ComponentHealth {
+    fn from_result(result: Result<()>) -> Self {
+        Self {
+            healthy: result.is_ok(),
+            error: result.err().map(|err| err.to_string()),
+        }
+    }
+}
+
+/// Everything the health endpoints check
+#[derive(Clone)]
+pub(crate) struct HealthChecks {
+    db: Database,
+    cache: Arc<dyn CacheProvider>,
+    lifecycles: Arc<Vec<ModuleLifecycle>>,
+    reloader: Option<Arc<Reloader>>,
+    ready: Arc<AtomicBool>,
+}
+
+impl HealthChecks {
+    pub(crate) fn new(
+        db: Database,
+        cache: Arc<dyn CacheProvider>,
+        lifecycles: Arc<Vec<ModuleLifecycle>>,
+        reloader: Option<Arc<Reloader>>,
+    ) -> Self {
+        Self {
+            db,
+            cache,
+            lifecycles,
+            reloader,
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(crate) fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// Run every module's health check concurrently, hot-reloaded ones last
+    pub(crate) async fn modules(&self) -> Vec<ModuleHealth> {
+        let mut health = server::check_modules(&self.lifecycles).await;
+        if let Some(reloader) = &self.reloader {
+            health.extend(reloader.health().await);
+        }
+        health
+    }
+
+    pub(crate) async fn report(&self) -> HealthReport {
+        let database = check(async { Ok(self.db.health().await?) });
+        let cache = check(async { self.cache.exists("sx:health").await.map(|_| ()) });
+        let (database, cache, modules) = tokio::join!(database, cache, self.modules());
+
+        HealthReport {
+            healthy: database.healthy && cache.healthy && modules.iter().all(|module| module.healthy),
+            ready: self.ready.load(Ordering::SeqCst),
+            database,
+            cache,
+            modules,
+        }
+    }
+}
+
+async fn check(probe: impl Future<Output = Result<()>>) -> ComponentHealth {
+    match tokio::time::timeout(CHECK_TIMEOUT, probe).await {
+        Ok(result) => ComponentHealth::from_result(result),
+        Err(_) => ComponentHealth {
+            healthy: false,
+            error: Some(format!("no answer within {}s", CHECK_TIMEOUT.as_secs())),
+        },
+    }
+}
+
+/// Router exposing `GET /health`, `/ready` and `/version`
+pub(crate) fn router(checks: HealthChecks) -> Router {
+    Router::new()
+        .route("/health", get(health_handler))
+        .route("/ready", get(ready_handler))
+        .route("/version", get(version_handler))
+        .with_state(checks)
+}
+
+async fn health_handler(State(checks): State<HealthChecks>) -> Json<HealthReport> {
+    Json(checks.report().await)
+}
+
+async fn ready_handler(State(checks): State<HealthChecks>) -> (StatusCode, Json<HealthReport>) {
+    let report = checks.report().await;
+    let status = if report.ready && report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+async fn version_handler(State(checks): State<HealthChecks>) -> Json<Value> {
+    let surrealdb = checks.db.version().await.ok().map(|version| version.to_string());
+    Json(json!({
+        "surrealx": env!("CARGO_PKG_VERSION"),
+        "surrealdb": surrealdb,
+    }))
+}
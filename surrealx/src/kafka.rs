@@ -0,0 +1,358 @@
+//! Kafka event bridge (requires kafka feature)
+//!
+//! [`KafkaBridge`] publishes events matching configured patterns to Kafka
+//! topics and emits the messages of consumed topics as `Custom` events. A
+//! published message is keyed by the event's pattern, such as `orders:42`, so
+//! the events of one record stay in order on one partition, and carries the
+//! [`Event`] itself. A consumed message becomes an event of pattern
+//! `<topic>:<key>`, or `<topic>:message` without a key, whose data is the
+//! decoded payload; offsets are committed only after its listeners ran.
+//!
+//! Payloads are JSON by default. With the `kafka-avro` feature they can be
+//! Avro in the Confluent wire format instead, with schemas taken from a
+//! schema registry: the latest version of the `<topic>-value` subject when
+//! publishing, the id embedded in each message when consuming.
+//!
+//! A bridge that publishes to a topic it also consumes emits every event
+//! back into the registry; keep the two sets of topics apart.
+
+use std::sync::Mutex;
+#[cfg(feature = "kafka-avro")]
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::Message;
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+use crate::bridge::EventBridge;
+use crate::events::{Event, EventListener, EventRegistry};
+use crate::error::{Error, Result};
+
+/// How long a send may wait for room in the producer's queue
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `stop()` waits for buffered messages to be delivered
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Encoding of message payloads
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KafkaFormat {
+    /// JSON text
+    #[default]
+    Json,
+    /// Avro in the Confluent wire format, with schemas from the schema
+    /// registry at this URL (requires kafka-avro feature)
+    #[cfg(feature = "kafka-avro")]
+    Avro { registry_url: String },
+}
+
+/// Bridge between the event registry and Kafka topics
+pub struct KafkaBridge {
+    config: ClientConfig,
+    publish: Vec<(String, String)>,
+    consume: Vec<String>,
+    format: KafkaFormat,
+    producer: Mutex<Option<FutureProducer>>,
+    consumer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl KafkaBridge {
+    /// Connect to the comma-separated `brokers`, consuming as group `surrealx`
+    pub fn new(brokers: impl Into<String>) -> Self {
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", brokers.into())
+            .set("group.id", "surrealx")
+            .set("enable.auto.commit", "false");
+        Self {
+            config,
+            publish: Vec::new(),
+            consume: Vec::new(),
+            format: KafkaFormat::default(),
+            producer: Mutex::new(None),
+            consumer: Mutex::new(None),
+        }
+    }
+
+    /// Publish events matching `pattern` to `topic`
+    pub fn publish(mut self, pattern: impl Into<String>, topic: impl Into<String>) -> Self {
+        self.publish.push((pattern.into(), topic.into()));
+        self
+    }
+
+    /// Emit the messages of `topic` as events
+    pub fn consume(mut self, topic: impl Into<String>) -> Self {
+        self.consume.push(topic.into());
+        self
+    }
+
+    /// Consume as a different consumer group
+    pub fn with_group_id(self, group_id: impl Into<String>) -> Self {
+        self.with_option("group.id", group_id)
+    }
+
+    /// Set a librdkafka client option, such as `security.protocol`
+    pub fn with_option(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.set(key, value);
+        self
+    }
+
+    /// Set how payloads are encoded
+    pub fn with_format(mut self, format: KafkaFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    fn codec(&self) -> Codec {
+        match &self.format {
+            KafkaFormat::Json => Codec::Json,
+            #[cfg(feature = "kafka-avro")]
+            KafkaFormat::Avro { registry_url } => Codec::Avro(Arc::new(avro::SchemaRegistry::new(registry_url))),
+        }
+    }
+}
+
+#[async_trait]
+impl EventBridge for KafkaBridge {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn start(&self, events: EventRegistry) -> Result<()> {
+        let codec = self.codec();
+
+        if !self.publish.is_empty() {
+            let producer: FutureProducer = self.config.create().map_err(kafka_error)?;
+            for (pattern, topic) in &self.publish {
+                let publisher = KafkaPublisher {
+                    producer: producer.clone(),
+                    topic: topic.clone(),
+                    codec: codec.clone(),
+                };
+                events.register(pattern.clone(), publisher).await?;
+            }
+            *self.producer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(producer);
+        }
+
+        if !self.consume.is_empty() {
+            let consumer: StreamConsumer = self.config.create().map_err(kafka_error)?;
+            let topics: Vec<&str> = self.consume.iter().map(String::as_str).collect();
+            consumer.subscribe(&topics).map_err(kafka_error)?;
+            let task = tokio::spawn(consume(consumer, events, codec));
+            *self.consumer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(task);
+        }
+        Ok(())
+    }
+
+    async fn stop(&self) {
+        if let Some(task) = self.consumer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            task.abort();
+        }
+        let producer = self.producer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        if let Some(producer) = producer {
+            let flushed = tokio::task::spawn_blocking(move || producer.flush(FLUSH_TIMEOUT)).await;
+            if let Ok(Err(err)) = flushed {
+                tracing::warn!(error = %err, "failed to deliver buffered Kafka messages");
+            }
+        }
+    }
+}
+
+/// Listener sending events to a topic
+struct KafkaPublisher {
+    producer: FutureProducer,
+    topic: String,
+    codec: Codec,
+}
+
+#[async_trait]
+impl EventListener for KafkaPublisher {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let key = event.pattern();
+        let payload = self.codec.encode(&self.topic, &serde_json::to_value(&event)?).await?;
+        self.producer
+            .send(FutureRecord::to(&self.topic).key(&key).payload(&payload), QUEUE_TIMEOUT)
+            .await
+            .map_err(|(err, _)| kafka_error(err))?;
+        Ok(())
+    }
+}
+
+/// Emit every message `consumer` receives, committing each once handled
+async fn consume(consumer: StreamConsumer, events: EventRegistry, codec: Codec) {
+    loop {
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to receive Kafka message");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let topic = message.topic().to_string();
+        let key = message.key().map(|key| String::from_utf8_lossy(key).into_owned());
+        let data = match message.payload() {
+            Some(payload) => codec.decode(payload).await,
+            None => Ok(Value::Null),
+        };
+        let event = data.and_then(|data| {
+            Event::custom(&format!("{}:{}", topic, key.as_deref().unwrap_or("message")), data)
+        });
+        match event {
+            Ok(event) => {
+                if let Err(err) = events.emit_quiet(event).await {
+                    tracing::warn!(topic = %topic, error = %err, "listener failed on Kafka message");
+                }
+            }
+            Err(err) => tracing::warn!(topic = %topic, error = %err, "skipping undecodable Kafka message"),
+        }
+
+        if let Err(err) = consumer.commit_message(&message, CommitMode::Async) {
+            tracing::warn!(topic = %topic, error = %err, "failed to commit Kafka offset");
+        }
+    }
+}
+
+/// Encoder and decoder of payloads for a `KafkaFormat`
+#[derive(Clone)]
+enum Codec {
+    Json,
+    #[cfg(feature = "kafka-avro")]
+    Avro(Arc<avro::SchemaRegistry>),
+}
+
+impl Codec {
+    async fn encode(&self, topic: &str, value: &Value) -> Result<Vec<u8>> {
+        let _ = topic;
+        match self {
+            Codec::Json => Ok(serde_json::to_vec(value)?),
+            #[cfg(feature = "kafka-avro")]
+            Codec::Avro(registry) => registry.encode(&format!("{}-value", topic), value).await,
+        }
+    }
+
+    async fn decode(&self, payload: &[u8]) -> Result<Value> {
+        match self {
+            Codec::Json => Ok(serde_json::from_slice(payload)?),
+            #[cfg(feature = "kafka-avro")]
+            Codec::Avro(registry) => registry.decode(payload).await,
+        }
+    }
+}
+
+fn kafka_error(err: rdkafka::error::KafkaError) -> Error {
+    Error::Kafka(err.to_string())
+}
+
+#[cfg(feature = "kafka-avro")]
+mod avro {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use apache_avro::Schema;
+    use serde::Deserialize;
+    use serde_json::Value;
+    use tokio::sync::RwLock;
+    use crate::error::{Error, Result};
+
+    /// Magic byte opening every payload in the Confluent wire format
+    const MAGIC: u8 = 0;
+
+    #[derive(Deserialize)]
+    struct RegisteredSchema {
+        id: Option<u32>,
+        schema: String,
+    }
+
+    /// Client of a Confluent-compatible schema registry, caching what it fetched
+    pub(super) struct SchemaRegistry {
+        client: reqwest::Client,
+        url: String,
+        by_subject: RwLock<HashMap<String, (u32, Arc<Schema>)>>,
+        by_id: RwLock<HashMap<u32, Arc<Schema>>>,
+    }
+
+    impl SchemaRegistry {
+        pub(super) fn new(url: &str) -> Self {
+            Self {
+                client: reqwest::Client::new(),
+                url: url.trim_end_matches('/').to_string(),
+                by_subject: RwLock::new(HashMap::new()),
+                by_id: RwLock::new(HashMap::new()),
+            }
+        }
+
+        /// Encode `value` with the latest schema of `subject`
+        pub(super) async fn encode(&self, subject: &str, value: &Value) -> Result<Vec<u8>> {
+            let (id, schema) = self.latest(subject).await?;
+            let value = apache_avro::to_value(value)
+                .and_then(|value| value.resolve(&schema))
+                .map_err(|err| Error::Kafka(format!("value does not match schema of {}: {}", subject, err)))?;
+            let datum = apache_avro::to_avro_datum(&schema, value).map_err(avro_error)?;
+
+            let mut payload = Vec::with_capacity(5 + datum.len());
+            payload.push(MAGIC);
+            payload.extend_from_slice(&id.to_be_bytes());
+            payload.extend_from_slice(&datum);
+            Ok(payload)
+        }
+
+        /// Decode a payload with the schema whose id it carries
+        pub(super) async fn decode(&self, payload: &[u8]) -> Result<Value> {
+            let (id, mut datum) = match payload {
+                [MAGIC, a, b, c, d, datum @ ..] => (u32::from_be_bytes([*a, *b, *c, *d]), datum),
+                _ => return Err(Error::Kafka("payload is not in the Confluent wire format".to_string())),
+            };
+            let schema = self.schema(id).await?;
+            let value = apache_avro::from_avro_datum(&schema, &mut datum, None).map_err(avro_error)?;
+            apache_avro::from_value::<Value>(&value).map_err(avro_error)
+        }
+
+        async fn latest(&self, subject: &str) -> Result<(u32, Arc<Schema>)> {
+            if let Some(found) = self.by_subject.read().await.get(subject) {
+                return Ok(found.clone());
+            }
+            let registered = self.fetch(&format!("{}/subjects/{}/versions/latest", self.url, subject)).await?;
+            let id = registered
+                .id
+                .ok_or_else(|| Error::Kafka(format!("schema registry returned no id for {}", subject)))?;
+            let schema = Arc::new(Schema::parse_str(&registered.schema).map_err(avro_error)?);
+            self.by_subject
+                .write()
+                .await
+                .insert(subject.to_string(), (id, schema.clone()));
+            Ok((id, schema))
+        }
+
+        async fn schema(&self, id: u32) -> Result<Arc<Schema>> {
+            if let Some(schema) = self.by_id.read().await.get(&id) {
+                return Ok(schema.clone());
+            }
+            let registered = self.fetch(&format!("{}/schemas/ids/{}", self.url, id)).await?;
+            let schema = Arc::new(Schema::parse_str(&registered.schema).map_err(avro_error)?);
+            self.by_id.write().await.insert(id, schema.clone());
+            Ok(schema)
+        }
+
+        async fn fetch(&self, url: &str) -> Result<RegisteredSchema> {
+            let response = self
+                .client
+                .get(url)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|err| Error::Kafka(format!("schema registry: {}", err)))?;
+            let body = response
+                .bytes()
+                .await
+                .map_err(|err| Error::Kafka(format!("schema registry: {}", err)))?;
+            Ok(serde_json::from_slice(&body)?)
+        }
+    }
+
+    fn avro_error(err: apache_avro::Error) -> Error {
+        Error::Kafka(format!("avro: {}", err))
+    }
+}
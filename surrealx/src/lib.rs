@@ -7,12 +7,14 @@
 //!
 //! ```rust,no_run
 //! use surrealx::{SurrealX, Module, ServerConfig};
+//! use serde_json::{json, Value};
 //!
 //! #[tokio::main]
 //! async fn main() -> anyhow::Result<()> {
 //!     let business = Module::new("business")
-//!         .with_function("calculate_tax", |price: f64| async move {
-//!             Ok(price * 0.15)
+//!         .with_function("calculate_tax", |args: Vec<Value>| async move {
+//!             let price = args.first().and_then(Value::as_f64).unwrap_or_default();
+//!             Ok(json!(price * 0.15))
 //!         });
 //!
 //!     SurrealX::new()
@@ -27,25 +29,175 @@
 pub mod module;
 pub mod functions;
 pub mod events;
+pub mod batch;
+pub mod queue;
 pub mod cache;
 pub mod server;
 pub mod error;
+pub mod streaming;
+pub mod ratelimit;
+pub mod outbox;
+pub mod db;
+pub mod sql;
+pub mod changefeed;
+pub mod eventstore;
+pub mod deadletter;
+pub mod context;
+pub mod config;
+pub mod health;
+pub mod auth;
+pub mod scheduler;
+pub mod workflow;
+pub mod bridge;
+pub mod subscriptions;
+pub mod openapi;
+pub mod rest;
+pub mod admin;
+pub mod migration;
+pub mod schema;
+pub mod tenancy;
+pub mod transform;
+pub mod pool;
+pub mod stats;
+pub mod audit;
+pub mod secrets;
+pub mod circuit;
+pub mod retry;
+mod reload;
+mod telemetry;
+mod middleware;
 
-pub use module::Module;
-pub use server::{SurrealX, ServerConfig};
-pub use functions::{FunctionHandler, FunctionRegistry};
-pub use events::{Event, EventListener, EventRegistry};
-pub use cache::{CacheProvider, MemoryCacheProvider};
+#[cfg(feature = "dylib-plugins")]
+pub mod plugin;
+
+#[cfg(feature = "wasm-plugins")]
+pub mod wasm;
+
+#[cfg(feature = "tls")]
+mod tls;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "webhooks")]
+pub mod webhook;
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+
+#[cfg(feature = "nats")]
+pub mod nats;
+
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+
+#[cfg(feature = "graphql")]
+pub mod graphql;
+
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+#[cfg(feature = "cli")]
+pub mod cli;
+
+#[cfg(feature = "upstream")]
+pub mod upstream;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use module::{Module, ModuleHealth};
+pub use server::{AuthConfig, BuildReport, CacheBackend, ConfigStrictness, ConflictPolicy, DeadLetterTarget, FunctionNamespacing, JobRunStore, LogFormat, MigrationPolicy, SchemaPolicy, ModuleFailure, ModuleInitFailurePolicy, SqlAccess, SurrealX, ServerConfig, PoolConfig, TlsConfig, UpstreamConfig};
+pub use functions::{ArgumentError, CallNext, ContextFunctionHandler, DeclaredFunction, FunctionCall, FunctionHandler, FunctionMiddleware, FunctionOpts, FunctionParam, FunctionRegistry, FunctionSignature, FunctionVersion, Idempotency, PipelineHandler, ResultCache, SimpleStreamingHandler, StreamingFunctionHandler, ValueStream, ValueType, hash_args};
+pub use events::{DispatchPolicy, EmitNext, EmitReport, Event, EventListener, EventMiddleware, EventRegistry, EventSchema, GlobalWildcardPolicy, ListenerId, PatternSyntax, PrioritizedListener, Propagation, PropagatingListener, TypedEventListener};
+pub use cache::{CacheBackendInfo, CacheExt, CacheProvider, CacheStats, DecodeErrorPolicy, MaintenanceHandle, MemoryCacheProvider, ScopedCache, StatsCache, TenantCache, TenantCacheUsage, TenantQuota, TieredCacheProvider, TtlBucket, ValidatingCache};
 pub use error::{Error, Result};
+pub use streaming::{EventFrame, StreamCompression};
+pub use ratelimit::RateLimiter;
+pub use outbox::{Outbox, OutboxRelay};
+pub use queue::{EventQueueConfig, OverflowPolicy, QueueStats};
+pub use batch::{BatchEventListener, BatchOptions, BatchingListener, SimpleBatchListener};
+pub use db::{Database, ScopedDatabase};
+pub use pool::{DatabasePool, PoolStats};
+pub use stats::{ArgumentRedactor, FunctionStats, KeyRedactor, RedactAll};
+pub use audit::{AuditEntry, AuditKind, AuditLog, AuditOutcome, FileAuditLog, SurrealAuditLog};
+pub use secrets::{EnvSecretProvider, FileSecretProvider, SecretProvider, Secrets};
+pub use circuit::{CircuitBreaker, CircuitStatus};
+pub use changefeed::{ChangeFeedBridge, ChangeFeedHandle};
+pub use eventstore::{EventStore, FileEventStore, SurrealEventStore};
+pub use context::{Context, FunctionContext};
+pub use health::{ComponentHealth, HealthReport};
+pub use auth::Identity;
+pub use scheduler::{OverlapPolicy, ScheduledJob};
+pub use migration::{Migration, MigrationRecord};
+pub use tenancy::{ClaimTenantResolver, HeaderTenantResolver, Tenant, TenantResolver};
+pub use transform::RouteTransform;
+pub use schema::{Field, FieldType, Index, SchemaChange, SchemaDrift, SchemaElement, Table};
+pub use workflow::{Workflow, WorkflowContext, WorkflowInstance, WorkflowStatus, WorkflowStep};
+pub use bridge::EventBridge;
+pub use openapi::ApiOperation;
+
+#[cfg(feature = "dylib-plugins")]
+pub use plugin::DynamicModule;
+
+#[cfg(feature = "wasm-plugins")]
+pub use wasm::WasmModule;
+
+#[cfg(feature = "metrics")]
+pub use metrics::Metrics;
+
+#[cfg(feature = "webhooks")]
+pub use webhook::WebhookListener;
+
+#[cfg(feature = "kafka")]
+pub use kafka::{KafkaBridge, KafkaFormat};
+
+#[cfg(feature = "nats")]
+pub use nats::NatsEventTransport;
+
+#[cfg(feature = "mqtt")]
+pub use mqtt::MqttBridge;
+
+#[cfg(feature = "vault-secrets")]
+pub use secrets::VaultSecretProvider;
+
+#[cfg(feature = "aws-secrets")]
+pub use secrets::AwsSecretProvider;
+
+/// The prometheus version `Metrics::registry` belongs to
+#[cfg(feature = "metrics")]
+pub use prometheus;
+
+/// The rustls version `SurrealX::with_tls_config` expects
+#[cfg(feature = "tls")]
+pub use rustls;
+pub use config::ConfigSource;
+pub use deadletter::{DeadLetter, DeadLetterSink};
+pub use retry::{RetryKind, RetryPolicy, RetryStats, RetryingListener};
 
 #[cfg(feature = "redis-cache")]
 pub use cache::RedisCacheProvider;
 
+#[cfg(feature = "memcached-cache")]
+pub use cache::MemcachedCacheProvider;
+
+#[cfg(feature = "disk-cache")]
+pub use cache::DiskCacheProvider;
+
+#[cfg(feature = "macros")]
+pub use surrealx_macros::function;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
+    pub use crate::functions::{check_arity, function_output, parse_argument};
+}
+
 /// Re-exports for convenience
 pub mod prelude {
     pub use crate::{
         SurrealX, ServerConfig, Module,
-        FunctionHandler, FunctionRegistry,
+        FunctionHandler, FunctionRegistry, FunctionContext, Identity,
         Event, EventListener, EventRegistry,
         CacheProvider, MemoryCacheProvider,
         Error, Result,
@@ -53,4 +205,7 @@ pub mod prelude {
 
     #[cfg(feature = "redis-cache")]
     pub use crate::RedisCacheProvider;
+
+    #[cfg(feature = "memcached-cache")]
+    pub use crate::MemcachedCacheProvider;
 }
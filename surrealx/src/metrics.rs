@@ -0,0 +1,426 @@
+//! Prometheus metrics (requires metrics feature)
+//!
+//! The server records:
+//!
+//! - `surrealx_function_calls_total{function, outcome}` and
+//!   `surrealx_function_duration_seconds{function}` for module functions,
+//!   and `surrealx_deprecated_function_calls_total{function}` for those
+//!   marked deprecated
+//! - `surrealx_listener_calls_total{module, pattern, outcome}` and
+//!   `surrealx_listener_duration_seconds{module, pattern}` for event listeners
+//! - `surrealx_http_requests_total{method, path, status}` and
+//!   `surrealx_http_request_duration_seconds{method, path}`, where `path` is
+//!   the route pattern rather than the requested path
+//! - `surrealx_cache_hits_total`, `surrealx_cache_misses_total`,
+//!   `surrealx_cache_sets_total`, `surrealx_cache_evictions_total`,
+//!   `surrealx_cache_entries` and `surrealx_cache_hit_ratio`, if the cache
+//!   provider tracks statistics
+//! - `surrealx_event_queue_depth`, `surrealx_event_queue_capacity`,
+//!   `surrealx_event_queue_enqueued_total` and
+//!   `surrealx_event_queue_dropped_total`, if events go through a queue
+//! - `surrealx_retries_total{kind, module, name}` and
+//!   `surrealx_retries_exhausted_total{kind, module, name}` for functions
+//!   and listeners with a [retry policy](crate::retry), where `kind` is
+//!   `function` or `listener` and `name` the function or pattern
+//!
+//! `outcome` is `ok` or `error`. Everything is served in the Prometheus text
+//! format at `GET /metrics` and available from
+//! [`BuiltSurrealX::metrics`](crate::server::BuiltSurrealX::metrics).
+
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+use async_trait::async_trait;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use prometheus::{Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use serde_json::Value;
+use crate::cache::CacheProvider;
+use crate::context::FunctionContext;
+use crate::events::{Event, EventListener, EventRegistry, Propagation};
+use crate::functions::FunctionHandler;
+use crate::retry::{RetryCounters, RetryKind};
+use crate::error::{Error, Result};
+
+/// Metrics of a server, shared by everything that records them
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    registry: Registry,
+    function_calls: IntCounterVec,
+    function_duration: HistogramVec,
+    deprecated_calls: IntCounterVec,
+    listener_calls: IntCounterVec,
+    listener_duration: HistogramVec,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    cache: OnceLock<Arc<dyn CacheProvider>>,
+    /// Cache counters, brought up to date with the provider's on every render
+    cache_counters: Mutex<CacheCounters>,
+    events: OnceLock<EventRegistry>,
+    /// Event queue counters, brought up to date on every render
+    queue_counters: Mutex<QueueCounters>,
+    retries: OnceLock<Arc<RetryCounters>>,
+    /// Retry counters, brought up to date with the server's on every render
+    retry_counters: Mutex<RetryMetrics>,
+}
+
+struct RetryMetrics {
+    retries: IntCounterVec,
+    exhausted: IntCounterVec,
+}
+
+struct QueueCounters {
+    depth: IntGauge,
+    capacity: IntGauge,
+    enqueued: IntCounter,
+    dropped: IntCounter,
+}
+
+struct CacheCounters {
+    hits: IntCounter,
+    misses: IntCounter,
+    sets: IntCounter,
+    evictions: IntCounter,
+    entries: IntGauge,
+    hit_ratio: Gauge,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let registry = Registry::new();
+        let cache_counters = CacheCounters {
+            hits: register(&registry, IntCounter::new("surrealx_cache_hits_total", "Cache reads that found a live entry")),
+            misses: register(&registry, IntCounter::new("surrealx_cache_misses_total", "Cache reads that found nothing")),
+            sets: register(&registry, IntCounter::new("surrealx_cache_sets_total", "Cache writes")),
+            evictions: register(
+                &registry,
+                IntCounter::new("surrealx_cache_evictions_total", "Cache entries expired or evicted by the provider"),
+            ),
+            entries: register(&registry, IntGauge::new("surrealx_cache_entries", "Entries in the cache")),
+            hit_ratio: register(&registry, Gauge::new("surrealx_cache_hit_ratio", "Fraction of cache reads that were hits")),
+        };
+        let queue_counters = QueueCounters {
+            depth: register(&registry, IntGauge::new("surrealx_event_queue_depth", "Events waiting for delivery")),
+            capacity: register(&registry, IntGauge::new("surrealx_event_queue_capacity", "Events the queue can hold")),
+            enqueued: register(
+                &registry,
+                IntCounter::new("surrealx_event_queue_enqueued_total", "Events accepted into the event queue"),
+            ),
+            dropped: register(
+                &registry,
+                IntCounter::new("surrealx_event_queue_dropped_total", "Events discarded because the queue was full"),
+            ),
+        };
+
+        let retry_counters = RetryMetrics {
+            retries: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("surrealx_retries_total", "Retried attempts of functions and listeners"),
+                    &["kind", "module", "name"],
+                ),
+            ),
+            exhausted: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "surrealx_retries_exhausted_total",
+                        "Function calls and events that retried and still failed",
+                    ),
+                    &["kind", "module", "name"],
+                ),
+            ),
+        };
+
+        let inner = Inner {
+            function_calls: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("surrealx_function_calls_total", "Calls of module functions"),
+                    &["function", "outcome"],
+                ),
+            ),
+            function_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("surrealx_function_duration_seconds", "Time spent in module functions"),
+                    &["function"],
+                ),
+            ),
+            deprecated_calls: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("surrealx_deprecated_function_calls_total", "Calls of deprecated module functions"),
+                    &["function"],
+                ),
+            ),
+            listener_calls: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("surrealx_listener_calls_total", "Events handled by module listeners"),
+                    &["module", "pattern", "outcome"],
+                ),
+            ),
+            listener_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("surrealx_listener_duration_seconds", "Time spent handling events"),
+                    &["module", "pattern"],
+                ),
+            ),
+            http_requests: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("surrealx_http_requests_total", "HTTP requests served"),
+                    &["method", "path", "status"],
+                ),
+            ),
+            http_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("surrealx_http_request_duration_seconds", "Time spent serving HTTP requests"),
+                    &["method", "path"],
+                ),
+            ),
+            registry,
+            cache: OnceLock::new(),
+            cache_counters: Mutex::new(cache_counters),
+            events: OnceLock::new(),
+            queue_counters: Mutex::new(queue_counters),
+            retries: OnceLock::new(),
+            retry_counters: Mutex::new(retry_counters),
+        };
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Registry holding the server's metrics
+    ///
+    /// Metrics an application registers here are served alongside them.
+    pub fn registry(&self) -> &Registry {
+        &self.inner.registry
+    }
+
+    /// Every metric in the Prometheus text format
+    pub fn render(&self) -> Result<String> {
+        self.sync_cache();
+        self.sync_queue();
+        self.sync_retries();
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.inner.registry.gather(), &mut buffer)
+            .map_err(|err| Error::Server(format!("failed to encode metrics: {}", err)))?;
+        String::from_utf8(buffer).map_err(|err| Error::Server(format!("failed to encode metrics: {}", err)))
+    }
+
+    /// Report the statistics of `cache` from now on
+    pub(crate) fn observe_cache(&self, cache: Arc<dyn CacheProvider>) {
+        let _ = self.inner.cache.set(cache);
+    }
+
+    fn sync_cache(&self) {
+        let Some(stats) = self.inner.cache.get().and_then(|cache| cache.stats()) else {
+            return;
+        };
+        let counters = self.inner.cache_counters.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (counter, value) in [
+            (&counters.hits, stats.hits),
+            (&counters.misses, stats.misses),
+            (&counters.sets, stats.sets),
+            (&counters.evictions, stats.evictions),
+        ] {
+            counter.inc_by(value.saturating_sub(counter.get()));
+        }
+        if let Some(entries) = stats.entries {
+            counters.entries.set(i64::try_from(entries).unwrap_or(i64::MAX));
+        }
+        if let Some(hit_ratio) = stats.hit_ratio() {
+            counters.hit_ratio.set(hit_ratio);
+        }
+    }
+
+    /// Report the queue of `events` from now on
+    pub(crate) fn observe_events(&self, events: EventRegistry) {
+        let _ = self.inner.events.set(events);
+    }
+
+    fn sync_queue(&self) {
+        let Some(stats) = self.inner.events.get().and_then(EventRegistry::queue_stats) else {
+            return;
+        };
+        let counters = self.inner.queue_counters.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        counters.depth.set(i64::try_from(stats.depth).unwrap_or(i64::MAX));
+        counters.capacity.set(i64::try_from(stats.capacity).unwrap_or(i64::MAX));
+        counters.enqueued.inc_by(stats.enqueued.saturating_sub(counters.enqueued.get()));
+        counters.dropped.inc_by(stats.dropped.saturating_sub(counters.dropped.get()));
+    }
+}
+
+impl Metrics {
+    /// Report the retries counted in `counters` from now on
+    pub(crate) fn observe_retries(&self, counters: Arc<RetryCounters>) {
+        let _ = self.inner.retries.set(counters);
+    }
+
+    fn sync_retries(&self) {
+        let Some(counters) = self.inner.retries.get() else {
+            return;
+        };
+        let metrics = self.inner.retry_counters.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for stats in counters.snapshot() {
+            let kind = match stats.kind {
+                RetryKind::Function => "function",
+                RetryKind::Listener => "listener",
+            };
+            let labels = [kind, stats.module.as_str(), stats.name.as_str()];
+            for (counter, value) in [(&metrics.retries, stats.retries), (&metrics.exhausted, stats.exhausted)] {
+                let counter = counter.with_label_values(&labels);
+                counter.inc_by(value.saturating_sub(counter.get()));
+            }
+        }
+    }
+}
+
+/// Register a metric whose name and labels are known to be valid
+fn register<M>(registry: &Registry, metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("metric options are valid");
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("metric names are unique");
+    metric
+}
+
+fn outcome<T>(result: &Result<T>) -> &'static str {
+    if result.is_ok() {
+        "ok"
+    } else {
+        "error"
+    }
+}
+
+/// Function handler that records its calls
+pub(crate) struct MeteredFunction {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) metrics: Metrics,
+    pub(crate) function: String,
+    pub(crate) deprecated: bool,
+}
+
+impl MeteredFunction {
+    fn record<T>(&self, started: Instant, result: &Result<T>) {
+        let inner = &self.metrics.inner;
+        if self.deprecated {
+            inner.deprecated_calls.with_label_values(&[self.function.as_str()]).inc();
+        }
+        inner
+            .function_calls
+            .with_label_values(&[self.function.as_str(), outcome(result)])
+            .inc();
+        inner
+            .function_duration
+            .with_label_values(&[self.function.as_str()])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for MeteredFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        let started = Instant::now();
+        let result = self.inner.call(args).await;
+        self.record(started, &result);
+        result
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let started = Instant::now();
+        let result = self.inner.call_with_context(context, args).await;
+        self.record(started, &result);
+        result
+    }
+}
+
+/// Event listener that records the events it handles
+pub(crate) struct MeteredListener {
+    pub(crate) inner: Arc<dyn EventListener>,
+    pub(crate) metrics: Metrics,
+    pub(crate) module: String,
+    pub(crate) pattern: String,
+}
+
+#[async_trait]
+impl EventListener for MeteredListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.handle(event).await.map(|_| ())
+    }
+
+    async fn handle(&self, event: Event) -> Result<Propagation> {
+        let started = Instant::now();
+        let result = self.inner.handle(event).await;
+
+        let inner = &self.metrics.inner;
+        inner
+            .listener_calls
+            .with_label_values(&[self.module.as_str(), self.pattern.as_str(), outcome(&result)])
+            .inc();
+        inner
+            .listener_duration
+            .with_label_values(&[self.module.as_str(), self.pattern.as_str()])
+            .observe(started.elapsed().as_secs_f64());
+        result
+    }
+
+    fn priority(&self) -> i32 {
+        self.inner.priority()
+    }
+}
+
+/// Router exposing `GET /metrics`
+pub(crate) fn router(metrics: Metrics) -> Router {
+    Router::new().route("/metrics", get(metrics_handler)).with_state(metrics)
+}
+
+async fn metrics_handler(State(metrics): State<Metrics>) -> Response {
+    match metrics.render() {
+        Ok(body) => ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// Record every request `router` serves
+pub(crate) fn track_requests(router: Router, metrics: Metrics) -> Router {
+    router.layer(middleware::from_fn_with_state(metrics, track_request))
+}
+
+async fn track_request(State(metrics): State<Metrics>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    // Label by route pattern so request paths cannot blow up the series count
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+
+    let response = next.run(request).await;
+
+    let inner = &metrics.inner;
+    inner
+        .http_requests
+        .with_label_values(&[method.as_str(), path.as_str(), response.status().as_str()])
+        .inc();
+    inner
+        .http_duration
+        .with_label_values(&[method.as_str(), path.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
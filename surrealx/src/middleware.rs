@@ -0,0 +1,31 @@
+//! Tower layers applied to the server's HTTP routes
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use axum::extract::Request;
+use axum::response::IntoResponse;
+use axum::routing::Route;
+use axum::Router;
+use tower::{Layer, Service};
+
+/// A layer, type-erased into a function wrapping a router with it
+pub(crate) type Middleware = dyn Fn(Router) -> Router + Send + Sync;
+
+/// Erase a layer so layers of different types can be kept together
+///
+/// Takes the bounds `Router::layer` requires, plus `Sync`.
+pub(crate) fn erase<L>(layer: L) -> Arc<Middleware>
+where
+    L: Layer<Route> + Clone + Send + Sync + 'static,
+    L::Service: Service<Request> + Clone + Send + 'static,
+    <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+    <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+    <L::Service as Service<Request>>::Future: Send + 'static,
+{
+    Arc::new(move |router: Router| router.layer(layer.clone()))
+}
+
+/// Wrap `router` in `middleware`, the first added innermost
+pub(crate) fn apply(router: Router, middleware: &[Arc<Middleware>]) -> Router {
+    middleware.iter().fold(router, |router, layer| layer(router))
+}
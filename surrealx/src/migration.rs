@@ -0,0 +1,416 @@
+//! Versioned schema migrations owned by modules
+//!
+//! A module declares its migrations with `Module::with_migration`, each
+//! with a version unique within the module:
+//!
+//! ```rust,ignore
+//! Module::new("billing")
+//!     .with_migration(1, "DEFINE TABLE invoice SCHEMAFULL", "REMOVE TABLE invoice")
+//!     .with_migration(2, "DEFINE FIELD total ON invoice TYPE number", "REMOVE FIELD total ON invoice")
+//! ```
+//!
+//! When the server is built, every migration not yet recorded in the
+//! `_sx_migrations` table is applied, as `ServerConfig::migrations` says.
+//! A module's migrations run in version order; a migration declared with
+//! [`Migration::after`] also waits for a migration of another module.
+//! Between independent migrations, modules go in registration order.
+//!
+//! A SurrealQL migration runs in one transaction with the row recording it,
+//! so it is applied entirely or not at all. A migration written in Rust is
+//! recorded once it returns successfully. Modules loaded by hot reload do not
+//! run migrations.
+//!
+//! With a tenant resolver, the SurrealQL migrations are also applied in each
+//! tenant's namespace, which records them in its own `_sx_migrations` table;
+//! see [`tenancy`](crate::tenancy). Migrations written in Rust are given the
+//! server's database handle and cannot run there, so building a server with
+//! a tenant resolver fails if a module has one.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::db::{self, Database};
+use crate::error::{Error, Result};
+
+/// Table recording the applied migrations, one row per module and version
+pub const MIGRATION_TABLE: &str = "_sx_migrations";
+
+pub(crate) type MigrationFn = dyn Fn(Database) -> BoxFuture<'static, Result<()>> + Send + Sync;
+
+/// One direction of a migration
+#[derive(Clone)]
+enum Change {
+    Sql(String),
+    Code(Arc<MigrationFn>),
+}
+
+/// A schema change of a module, applied once per database
+#[derive(Clone)]
+pub struct Migration {
+    version: u64,
+    up: Change,
+    down: Option<Change>,
+    after: Vec<(String, u64)>,
+}
+
+impl Migration {
+    /// Apply the SurrealQL statements `up`
+    pub fn sql(version: u64, up: impl Into<String>) -> Self {
+        Self {
+            version,
+            up: Change::Sql(up.into()),
+            down: None,
+            after: Vec::new(),
+        }
+    }
+
+    /// Apply the migration by running `up` against the database
+    pub fn new<F, Fut>(version: u64, up: F) -> Self
+    where
+        F: Fn(Database) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        Self {
+            version,
+            up: Change::Code(Arc::new(move |db| Box::pin(up(db)))),
+            down: None,
+            after: Vec::new(),
+        }
+    }
+
+    /// Roll the migration back with the SurrealQL statements `down`
+    pub fn with_down_sql(mut self, down: impl Into<String>) -> Self {
+        self.down = Some(Change::Sql(down.into()));
+        self
+    }
+
+    /// Roll the migration back by running `down` against the database
+    pub fn with_down<F, Fut>(mut self, down: F) -> Self
+    where
+        F: Fn(Database) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        self.down = Some(Change::Code(Arc::new(move |db| Box::pin(down(db)))));
+        self
+    }
+
+    /// Apply only after migration `version` of `module`
+    pub fn after(mut self, module: impl Into<String>, version: u64) -> Self {
+        self.after.push((module.into(), version));
+        self
+    }
+
+    /// Version of the migration within its module
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Migrations of other modules this one is applied after
+    pub fn dependencies(&self) -> &[(String, u64)] {
+        &self.after
+    }
+
+    /// Whether the migration can be rolled back
+    pub fn is_reversible(&self) -> bool {
+        self.down.is_some()
+    }
+
+    /// Whether the migration is written in SurrealQL, both ways
+    pub fn is_sql(&self) -> bool {
+        matches!(self.up, Change::Sql(_)) && !matches!(self.down, Some(Change::Code(_)))
+    }
+}
+
+/// A migration of a module, applied or pending
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationRecord {
+    /// Module name
+    pub module: String,
+    /// Version within the module
+    pub version: u64,
+    /// When the migration was applied, in milliseconds since the epoch;
+    /// `None` while it is pending
+    pub applied_at: Option<i64>,
+}
+
+/// A migration with the module it belongs to
+struct Planned {
+    module: String,
+    migration: Migration,
+}
+
+impl Planned {
+    fn id(&self) -> String {
+        format!("{}@{}", self.module, self.migration.version)
+    }
+
+    async fn apply(&self, db: &Database, switch: Option<&str>) -> Result<i64> {
+        let applied_at = chrono::Utc::now().timestamp_millis();
+        let record = "UPSERT type::thing($table, [$module, $version]) \
+            CONTENT { module: $module, version: $version, applied_at: $applied_at } RETURN NONE";
+        self.run(db, switch, &self.migration.up, record, Some(applied_at)).await?;
+        Ok(applied_at)
+    }
+
+    async fn revert(&self, db: &Database, switch: Option<&str>) -> Result<()> {
+        let down = self
+            .migration
+            .down
+            .as_ref()
+            .ok_or_else(|| Error::Migration(format!("{} has no down migration", self.id())))?;
+        self.run(db, switch, down, "DELETE type::thing($table, [$module, $version])", None).await
+    }
+
+    /// Run `change`, then `bookkeeping` on the migration table, after
+    /// `switch` if any; a SurrealQL change runs in the same transaction
+    async fn run(
+        &self,
+        db: &Database,
+        switch: Option<&str>,
+        change: &Change,
+        bookkeeping: &str,
+        applied_at: Option<i64>,
+    ) -> Result<()> {
+        let failed = |err: Error| Error::Migration(format!("{}: {}", self.id(), err));
+        let sql = match change {
+            Change::Sql(sql) => {
+                format!("BEGIN TRANSACTION;\n{};\n{};\nCOMMIT TRANSACTION;", sql.trim().trim_end_matches(';'), bookkeeping)
+            }
+            Change::Code(_) if switch.is_some() => {
+                return Err(failed(Error::Config(
+                    "migrations written in Rust only run in the server's namespace".to_string(),
+                )));
+            }
+            Change::Code(run) => {
+                run(db.clone()).await.map_err(failed)?;
+                bookkeeping.to_string()
+            }
+        };
+        let result: Result<()> = async {
+            db.query(format!("{}{}", switch.unwrap_or_default(), sql))
+                .bind(("table", MIGRATION_TABLE))
+                .bind(("module", self.module.clone()))
+                .bind(("version", self.migration.version))
+                .bind(("applied_at", applied_at))
+                .await?
+                .check()?;
+            Ok(())
+        }
+        .await;
+        result.map_err(failed)
+    }
+}
+
+/// The migrations of every module, in the order they are applied
+#[derive(Clone)]
+pub(crate) struct Migrations {
+    db: Database,
+    plan: Arc<Vec<Planned>>,
+    /// `USE` statement every query starts with, outside the server's
+    /// namespace
+    switch: Option<String>,
+}
+
+impl Migrations {
+    /// Order the migrations of `modules`, given in registration order
+    ///
+    /// Fails if a module declares a version twice, if a migration is applied
+    /// after one that does not exist, or if the dependencies form a cycle.
+    pub(crate) fn new(db: Database, modules: Vec<(String, Vec<Migration>)>) -> Result<Self> {
+        let mut planned = Vec::new();
+        let mut index = HashMap::new();
+        for (position, (module, mut migrations)) in modules.into_iter().enumerate() {
+            migrations.sort_by_key(Migration::version);
+            for migration in migrations {
+                let key = (module.clone(), migration.version);
+                if index.insert(key, planned.len()).is_some() {
+                    return Err(Error::Config(format!(
+                        "module '{}' declares migration {} twice",
+                        module, migration.version
+                    )));
+                }
+                planned.push((position, Planned { module: module.clone(), migration }));
+            }
+        }
+
+        // Each migration waits for the previous version of its module and
+        // for the migrations it is declared after
+        let mut waiting_on = vec![0usize; planned.len()];
+        let mut unblocks: Vec<Vec<usize>> = vec![Vec::new(); planned.len()];
+        for (at, (_, current)) in planned.iter().enumerate() {
+            if at > 0 && planned[at - 1].1.module == current.module {
+                waiting_on[at] += 1;
+                unblocks[at - 1].push(at);
+            }
+            for (module, version) in current.migration.dependencies() {
+                let &before = index.get(&(module.clone(), *version)).ok_or_else(|| {
+                    Error::Config(format!(
+                        "migration {} is applied after {}@{}, which no module declares",
+                        current.id(),
+                        module,
+                        version
+                    ))
+                })?;
+                waiting_on[at] += 1;
+                unblocks[before].push(at);
+            }
+        }
+
+        let mut ready: BTreeSet<(usize, u64, usize)> = (0..planned.len())
+            .filter(|&at| waiting_on[at] == 0)
+            .map(|at| (planned[at].0, planned[at].1.migration.version, at))
+            .collect();
+        let mut order = Vec::with_capacity(planned.len());
+        while let Some(next) = ready.pop_first() {
+            let at = next.2;
+            order.push(at);
+            for &unblocked in &unblocks[at] {
+                waiting_on[unblocked] -= 1;
+                if waiting_on[unblocked] == 0 {
+                    ready.insert((planned[unblocked].0, planned[unblocked].1.migration.version, unblocked));
+                }
+            }
+        }
+        if order.len() < planned.len() {
+            let cycle: Vec<String> = (0..planned.len())
+                .filter(|at| waiting_on[*at] > 0)
+                .map(|at| planned[at].1.id())
+                .collect();
+            return Err(Error::Config(format!("migrations depend on each other in a cycle: {}", cycle.join(", "))));
+        }
+
+        let mut planned: Vec<Option<Planned>> = planned.into_iter().map(|(_, planned)| Some(planned)).collect();
+        let plan = order.into_iter().filter_map(|at| planned[at].take()).collect();
+        Ok(Self {
+            db,
+            plan: Arc::new(plan),
+            switch: None,
+        })
+    }
+
+    /// The same migrations, applied in `namespace` and `database`
+    pub(crate) fn in_namespace(&self, namespace: &str, database: &str) -> Self {
+        Self {
+            db: self.db.clone(),
+            plan: self.plan.clone(),
+            switch: Some(db::use_statement(namespace, database)),
+        }
+    }
+
+    /// When each applied migration was applied, by module and version
+    async fn applied(&self) -> Result<HashMap<(String, u64), i64>> {
+        let switch = self.switch.as_deref();
+        let mut response = self
+            .db
+            .query(format!("{}SELECT module, version, applied_at FROM type::table($table)", switch.unwrap_or_default()))
+            .bind(("table", MIGRATION_TABLE))
+            .await?;
+        let rows = response.take::<surrealdb::Value>(usize::from(switch.is_some()))?.into_inner().into_json();
+        let rows: Vec<MigrationRecord> = serde_json::from_value(match rows {
+            Value::Null => Value::Array(Vec::new()),
+            rows => rows,
+        })?;
+        Ok(rows
+            .into_iter()
+            .map(|row| ((row.module, row.version), row.applied_at.unwrap_or_default()))
+            .collect())
+    }
+
+    /// Every declared migration in the order it is applied, with when it was
+    pub(crate) async fn status(&self) -> Result<Vec<MigrationRecord>> {
+        let applied = self.applied().await?;
+        Ok(self
+            .plan
+            .iter()
+            .map(|planned| MigrationRecord {
+                module: planned.module.clone(),
+                version: planned.migration.version,
+                applied_at: applied.get(&(planned.module.clone(), planned.migration.version)).copied(),
+            })
+            .collect())
+    }
+
+    /// The migrations `apply` would run, in order
+    pub(crate) async fn pending(&self) -> Result<Vec<MigrationRecord>> {
+        let mut status = self.status().await?;
+        status.retain(|migration| migration.applied_at.is_none());
+        Ok(status)
+    }
+
+    /// Apply every pending migration in order, stopping at the first that
+    /// fails; returns those applied
+    pub(crate) async fn apply(&self) -> Result<Vec<MigrationRecord>> {
+        let applied = self.applied().await?;
+        let mut done = Vec::new();
+        for planned in self.plan.iter() {
+            if applied.contains_key(&(planned.module.clone(), planned.migration.version)) {
+                continue;
+            }
+            let applied_at = planned.apply(&self.db, self.switch.as_deref()).await?;
+            tracing::info!(module = %planned.module, version = planned.migration.version, "applied migration");
+            done.push(MigrationRecord {
+                module: planned.module.clone(),
+                version: planned.migration.version,
+                applied_at: Some(applied_at),
+            });
+        }
+        Ok(done)
+    }
+
+    /// Roll back the applied migrations of `module` newer than `to_version`,
+    /// newest first; returns those rolled back
+    ///
+    /// Nothing is rolled back if one of them has no down migration or an
+    /// applied migration of another module depends on one of them.
+    pub(crate) async fn rollback(&self, module: &str, to_version: u64) -> Result<Vec<MigrationRecord>> {
+        if !self.plan.iter().any(|planned| planned.module == module) {
+            return Err(Error::NotFound(format!("migrations of module '{}'", module)));
+        }
+        let applied = self.applied().await?;
+        let is_applied = |planned: &Planned| applied.contains_key(&(planned.module.clone(), planned.migration.version));
+        let targets: Vec<&Planned> = self
+            .plan
+            .iter()
+            .filter(|planned| planned.module == module && planned.migration.version > to_version && is_applied(planned))
+            .collect();
+
+        let reverted: HashSet<(&str, u64)> = targets
+            .iter()
+            .map(|planned| (planned.module.as_str(), planned.migration.version))
+            .collect();
+        for planned in &targets {
+            if !planned.migration.is_reversible() {
+                return Err(Error::Migration(format!("{} has no down migration", planned.id())));
+            }
+        }
+        for planned in self.plan.iter().filter(|planned| planned.module != module && is_applied(planned)) {
+            if let Some((_, version)) = planned
+                .migration
+                .dependencies()
+                .iter()
+                .find(|(on, version)| reverted.contains(&(on.as_str(), *version)))
+            {
+                return Err(Error::Migration(format!(
+                    "{}@{} cannot be rolled back while {} depends on it",
+                    module,
+                    version,
+                    planned.id()
+                )));
+            }
+        }
+
+        let mut done = Vec::new();
+        for planned in targets.into_iter().rev() {
+            planned.revert(&self.db, self.switch.as_deref()).await?;
+            tracing::info!(module = %planned.module, version = planned.migration.version, "rolled back migration");
+            done.push(MigrationRecord {
+                module: planned.module.clone(),
+                version: planned.migration.version,
+                applied_at: None,
+            });
+        }
+        Ok(done)
+    }
+}
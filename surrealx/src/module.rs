@@ -1,18 +1,85 @@
 //! Module system for organizing extensions
 
-use std::sync::Arc;
+use std::convert::Infallible;
+use std::sync::{Arc, OnceLock};
+use axum::extract::Request;
+use axum::response::IntoResponse;
+use axum::routing::Route;
 use axum::Router;
+use tower::{Layer, Service};
+use futures::future::BoxFuture;
 use serde_json::Value;
-use crate::functions::{FunctionHandler, SimpleFunctionHandler};
-use crate::events::{EventListener, SimpleEventListener};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::config::{self, ConfigParser};
+use crate::middleware::{self, Middleware};
+use crate::context::{Context, FunctionContext};
+use crate::functions::{CachedFunctionHandler, CollectedStream, ContextFunctionHandler, DeclaredFunction, FunctionHandler, FunctionMiddleware, FunctionOpts, FunctionSignature, Guard, GuardedFunctionHandler, IdempotentFunctionHandler, PipelineHandler, SimpleFunctionHandler, SimpleStreamingHandler, StreamingFunctionHandler, TimeoutFunctionHandler};
+use crate::batch::{BatchOptions, BatchingListener, SimpleBatchListener};
+use crate::events::{EventListener, EventMiddleware, EventSchema, PrioritizedListener, Propagation, PropagatingListener, SimpleEventListener, TypedEventListener};
+use crate::openapi::ApiOperation;
+use crate::scheduler::ScheduledJob;
+use crate::workflow::Workflow;
+use crate::migration::Migration;
+use crate::schema::Table;
+use crate::telemetry::ModuleSpan;
+use crate::transform::RouteTransform;
+use crate::retry::RetryPolicy;
+#[cfg(feature = "webhooks")]
+use crate::webhook::WebhookListener;
 use crate::error::Result;
 
 /// A module encapsulating related functionality
 pub struct Module {
     name: String,
     functions: Vec<(String, Arc<dyn FunctionHandler>)>,
+    signatures: Vec<(String, FunctionSignature)>,
+    default_versions: Vec<(String, String)>,
+    deprecations: Vec<(String, String)>,
+    function_middleware: Vec<(Option<String>, Arc<dyn FunctionMiddleware>)>,
+    function_retries: Vec<(String, RetryPolicy)>,
+    streaming_functions: Vec<(String, Arc<dyn StreamingFunctionHandler>)>,
     listeners: Vec<(String, Arc<dyn EventListener>)>,
+    listener_retries: Vec<(String, RetryPolicy)>,
+    function_listeners: Vec<(String, String)>,
+    event_schemas: Vec<(String, EventSchema)>,
+    event_middleware: Vec<Arc<dyn EventMiddleware>>,
     routes: Vec<(&'static str, Router)>,
+    route_docs: Vec<(String, ApiOperation)>,
+    route_transforms: Vec<RouteTransform>,
+    middleware: Vec<Arc<Middleware>>,
+    trace_fields: Vec<(String, String)>,
+    trace_span: OnceLock<ModuleSpan>,
+    config: Option<Arc<ConfigParser>>,
+    lifecycle: Lifecycle,
+    jobs: Vec<ScheduledJob>,
+    workflows: Vec<Workflow>,
+    migrations: Vec<Migration>,
+    tables: Vec<Table>,
+    #[cfg(feature = "webhooks")]
+    webhooks: Vec<(String, WebhookListener)>,
+}
+
+pub(crate) type LifecycleHook = dyn Fn(Context) -> BoxFuture<'static, Result<()>> + Send + Sync;
+pub(crate) type HealthCheck = dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync;
+
+/// Hooks run around the lifetime of a served module
+#[derive(Clone, Default)]
+pub(crate) struct Lifecycle {
+    pub(crate) on_start: Option<Arc<LifecycleHook>>,
+    pub(crate) on_shutdown: Option<Arc<LifecycleHook>>,
+    pub(crate) health: Option<Arc<HealthCheck>>,
+}
+
+/// Result of a module's health check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleHealth {
+    /// Module name
+    pub module: String,
+    /// Whether the check passed; modules without a check are always healthy
+    pub healthy: bool,
+    /// Why the check failed
+    pub error: Option<String>,
 }
 
 impl Module {
@@ -21,8 +88,31 @@ impl Module {
         Self {
             name: name.into(),
             functions: Vec::new(),
+            signatures: Vec::new(),
+            default_versions: Vec::new(),
+            deprecations: Vec::new(),
+            function_middleware: Vec::new(),
+            function_retries: Vec::new(),
+            streaming_functions: Vec::new(),
             listeners: Vec::new(),
+            listener_retries: Vec::new(),
+            function_listeners: Vec::new(),
+            event_schemas: Vec::new(),
+            event_middleware: Vec::new(),
             routes: Vec::new(),
+            route_docs: Vec::new(),
+            route_transforms: Vec::new(),
+            middleware: Vec::new(),
+            trace_fields: Vec::new(),
+            trace_span: OnceLock::new(),
+            config: None,
+            lifecycle: Lifecycle::default(),
+            jobs: Vec::new(),
+            workflows: Vec::new(),
+            migrations: Vec::new(),
+            tables: Vec::new(),
+            #[cfg(feature = "webhooks")]
+            webhooks: Vec::new(),
         }
     }
 
@@ -37,6 +127,127 @@ impl Module {
         self
     }
 
+    /// Add a custom function with options, such as a timeout or caching
+    ///
+    /// A call running past `opts.timeout` is cancelled and fails with
+    /// `Error::Function("timeout")`, so a hung call cannot block the query
+    /// waiting for it. With [`FunctionOpts::cached`], results are kept in the
+    /// module's cache and calls finding one do not run the handler. With
+    /// [`FunctionOpts::idempotent`], retries carrying the key of an earlier
+    /// call get its result instead.
+    pub fn with_function_opts<F, Fut>(mut self, name: impl Into<String>, handler: F, opts: FunctionOpts) -> Self
+    where
+        F: Fn(Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+    {
+        let mut handler: Arc<dyn FunctionHandler> =
+            Arc::new(SimpleFunctionHandler::new(move |args| Box::pin(handler(args))));
+        if let Some(timeout) = opts.timeout {
+            handler = Arc::new(TimeoutFunctionHandler { inner: handler, timeout });
+        }
+        let name = name.into();
+        if let Some(cache) = opts.cache {
+            handler = Arc::new(CachedFunctionHandler {
+                inner: handler,
+                function: name.clone(),
+                cache,
+                loads: Default::default(),
+            });
+        }
+        if let Some(idempotency) = opts.idempotency {
+            handler = Arc::new(IdempotentFunctionHandler {
+                inner: handler,
+                function: name.clone(),
+                idempotency,
+                loads: Default::default(),
+            });
+        }
+        if let Some(policy) = opts.retry {
+            self.function_retries.push((name.clone(), policy));
+        }
+        self.functions.push((name, handler));
+        self
+    }
+
+    /// Add a function that receives the [`FunctionContext`] of each call
+    pub fn with_context_function<F, Fut>(mut self, name: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(FunctionContext, Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+    {
+        let handler = ContextFunctionHandler::new(move |context, args| Box::pin(handler(context, args)));
+        self.functions.push((name.into(), Arc::new(handler)));
+        self
+    }
+
+    /// Add a function producing its result as a stream of values
+    ///
+    /// Callers that can stream receive each value as it is produced; others,
+    /// such as SurrealQL, receive the values collected into an array. See
+    /// [`StreamingFunctionHandler`].
+    pub fn with_streaming_function<F, S>(mut self, name: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(FunctionContext, Vec<Value>) -> S + Send + Sync + 'static,
+        S: futures::Stream<Item = Result<Value>> + Send + 'static,
+    {
+        use futures::StreamExt;
+
+        let name = name.into();
+        let handler: Arc<dyn StreamingFunctionHandler> =
+            Arc::new(SimpleStreamingHandler::new(move |context, args| handler(context, args).boxed()));
+        self.functions.push((name.clone(), Arc::new(CollectedStream { inner: handler.clone() })));
+        self.streaming_functions.push((name, handler));
+        self
+    }
+
+    /// Add a function only callers holding one of `roles` may call
+    ///
+    /// A role is held through the `RL` claim of the caller's token; see
+    /// [`Identity::has_role`](crate::auth::Identity::has_role). Other calls,
+    /// anonymous ones included, fail with [`Error::Forbidden`](crate::Error::Forbidden)
+    /// before the handler runs.
+    pub fn with_function_guarded<F, Fut, R>(self, name: impl Into<String>, roles: R, handler: F) -> Self
+    where
+        F: Fn(FunctionContext, Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+        R: IntoIterator,
+        R::Item: Into<String>,
+    {
+        let roles = roles.into_iter().map(Into::into).collect();
+        self.with_guarded(name.into(), Guard::Roles(roles), handler)
+    }
+
+    /// Add a function only callers signed in through one of the access
+    /// methods `access` may call, such as record users of `account`
+    ///
+    /// Other calls fail with [`Error::Forbidden`](crate::Error::Forbidden)
+    /// before the handler runs, like those of
+    /// [`with_function_guarded`](Self::with_function_guarded).
+    pub fn with_function_guarded_by_access<F, Fut, A>(self, name: impl Into<String>, access: A, handler: F) -> Self
+    where
+        F: Fn(FunctionContext, Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+        A: IntoIterator,
+        A::Item: Into<String>,
+    {
+        let access = access.into_iter().map(Into::into).collect();
+        self.with_guarded(name.into(), Guard::Access(access), handler)
+    }
+
+    fn with_guarded<F, Fut>(mut self, name: String, guard: Guard, handler: F) -> Self
+    where
+        F: Fn(FunctionContext, Vec<Value>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+    {
+        let handler = GuardedFunctionHandler {
+            inner: Arc::new(ContextFunctionHandler::new(move |context, args| Box::pin(handler(context, args)))),
+            function: name.clone(),
+            guard,
+        };
+        self.functions.push((name, Arc::new(handler)));
+        self
+    }
+
     /// Add a raw function handler to the module
     pub fn with_raw_function<H>(mut self, name: impl Into<String>, handler: H) -> Self
     where
@@ -46,6 +257,100 @@ impl Module {
         self
     }
 
+    /// Add a function declared with `#[surrealx::function]`, with the
+    /// signature of its fn
+    pub fn with_declared_function<F>(self, function: F) -> Self
+    where
+        F: DeclaredFunction + 'static,
+    {
+        self.with_raw_function(F::NAME, function)
+            .with_function_signature(F::NAME, F::signature())
+    }
+
+    /// Describe the parameters and result of one of the module's functions
+    ///
+    /// It tells typed interfaces such as the GraphQL endpoint how to offer
+    /// the function. Calls are checked against it only if it is
+    /// [validated](FunctionSignature::validated).
+    pub fn with_function_signature(mut self, name: impl Into<String>, signature: FunctionSignature) -> Self {
+        let name = name.into();
+        self.signatures.retain(|(existing, _)| *existing != name);
+        self.signatures.push((name, signature));
+        self
+    }
+
+    /// Make calls to `name` without a version reach `name@version`
+    ///
+    /// Versions of a function are added as functions named `name@version`,
+    /// such as `tax@v2`, with any of the `with_*function` methods and called
+    /// as `ext::tax@v2(..)`. Without a default, calls to `name` reach the
+    /// highest version, comparing the numbers in versions.
+    pub fn with_default_version(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        let name = name.into();
+        self.default_versions.retain(|(existing, _)| *existing != name);
+        self.default_versions.push((name, version.into()));
+        self
+    }
+
+    /// Mark one of the module's functions, usually an old version such as
+    /// `tax@v1`, as deprecated
+    ///
+    /// It keeps working; the first call logs a warning with `note`, and
+    /// every call is counted by the `metrics` feature.
+    pub fn deprecate_function(mut self, name: impl Into<String>, note: impl Into<String>) -> Self {
+        self.deprecations.push((name.into(), note.into()));
+        self
+    }
+
+    /// Run calls of every function of the module through `middleware`
+    ///
+    /// It wraps the module's functions inside any middleware of the server;
+    /// see [`FunctionMiddleware`].
+    pub fn with_function_middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: FunctionMiddleware + 'static,
+    {
+        self.function_middleware.push((None, Arc::new(middleware)));
+        self
+    }
+
+    /// Run calls of the module's function `function` through `middleware`
+    ///
+    /// It wraps the function inside the middleware of the server and of the
+    /// module.
+    pub fn with_function_middleware_for<M>(mut self, function: impl Into<String>, middleware: M) -> Self
+    where
+        M: FunctionMiddleware + 'static,
+    {
+        self.function_middleware.push((Some(function.into()), Arc::new(middleware)));
+        self
+    }
+
+    /// Retry failed calls of the module's function `function` under `policy`
+    ///
+    /// Retries run inside the middleware of the function, which sees a
+    /// single call with its final result; see the [`retry`](crate::retry)
+    /// module.
+    pub fn with_function_retry(mut self, function: impl Into<String>, policy: RetryPolicy) -> Self {
+        self.function_retries.push((function.into(), policy));
+        self
+    }
+
+    /// Retry the module's listeners of `pattern` under `policy` when they
+    /// fail, before the event is dead-lettered
+    pub fn with_listener_retry(mut self, pattern: impl Into<String>, policy: RetryPolicy) -> Self {
+        self.listener_retries.push((pattern.into(), policy));
+        self
+    }
+
+    /// Add a function built from stages run in sequence
+    ///
+    /// Each stage's result is passed as the single argument to the next; see
+    /// [`PipelineHandler`].
+    pub fn with_pipeline(self, name: impl Into<String>, stages: Vec<Arc<dyn FunctionHandler>>) -> Self {
+        self.with_raw_function(name, PipelineHandler::new(stages))
+    }
+
     /// Add an event listener to the module
     pub fn with_listener<F, Fut>(mut self, pattern: impl Into<String>, handler: F) -> Self
     where
@@ -57,6 +362,74 @@ impl Module {
         self
     }
 
+    /// Add an event listener receiving the event's data as a `T`
+    ///
+    /// The data is deserialized before the handler runs; an event whose data
+    /// does not fit fails the listener with a validation error instead.
+    pub fn with_typed_listener<T, F, Fut>(mut self, pattern: impl Into<String>, handler: F) -> Self
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(crate::events::Event, T) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        self.listeners.push((pattern.into(), Arc::new(TypedEventListener::new(handler))));
+        self
+    }
+
+    /// Add an event listener receiving the events matching `pattern` in
+    /// batches, such as `BatchOptions::batch(100, Duration::from_secs(1))`
+    /// or `BatchOptions::debounce(Duration::from_millis(500))`
+    pub fn with_batch_listener<F, Fut>(mut self, pattern: impl Into<String>, options: BatchOptions, handler: F) -> Self
+    where
+        F: Fn(Vec<crate::events::Event>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        let handler = SimpleBatchListener::new(move |events| Box::pin(handler(events)));
+        let listener = BatchingListener::new(Arc::new(handler), options);
+        self.listeners.push((pattern.into(), Arc::new(listener)));
+        self
+    }
+
+    /// Add an event listener that runs before listeners of lower `priority`
+    /// and can stop the event from reaching them
+    ///
+    /// Listeners added otherwise have priority 0. Returning
+    /// [`Propagation::Stop`] skips every listener after this one, such as a
+    /// validator refusing an event before the listeners acting on it.
+    pub fn with_prioritized_listener<F, Fut>(mut self, pattern: impl Into<String>, priority: i32, handler: F) -> Self
+    where
+        F: Fn(crate::events::Event) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Propagation>> + Send + 'static,
+    {
+        let handler = PropagatingListener::new(move |event| Box::pin(handler(event)));
+        let listener = PrioritizedListener::new(Arc::new(handler), priority);
+        self.listeners.push((pattern.into(), Arc::new(listener)));
+        self
+    }
+
+    /// Require the data of events matching `pattern` to deserialize into `T`
+    ///
+    /// Registered with the server's event registry, so emitting a
+    /// non-conforming event fails wherever it is emitted; see
+    /// [`EventSchema`].
+    pub fn with_event_schema<T: DeserializeOwned + 'static>(mut self, pattern: impl Into<String>) -> Self {
+        self.event_schemas.push((pattern.into(), EventSchema::of::<T>()));
+        self
+    }
+
+    /// Run the events emitted by the module's handlers through `middleware`
+    ///
+    /// It wraps only events emitted while one of the module's functions,
+    /// listeners or jobs runs, inside any middleware of the server; see
+    /// [`EventMiddleware`].
+    pub fn with_event_middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: EventMiddleware + 'static,
+    {
+        self.event_middleware.push(Arc::new(middleware));
+        self
+    }
+
     /// Add a raw event listener to the module
     pub fn with_raw_listener<L>(mut self, pattern: impl Into<String>, listener: L) -> Self
     where
@@ -66,12 +439,196 @@ impl Module {
         self
     }
 
+    /// Call a registered function for every event matching a pattern
+    ///
+    /// The function receives the event as its single argument and its result
+    /// is discarded. The name is resolved at build time, as given, in this
+    /// module's namespace or under the `ext::` prefix, and an unknown name
+    /// fails the build.
+    pub fn with_function_listener(mut self, pattern: impl Into<String>, function: impl Into<String>) -> Self {
+        self.function_listeners.push((pattern.into(), function.into()));
+        self
+    }
+
+    /// POST events matching a pattern to `url` as JSON (requires webhooks feature)
+    ///
+    /// Uses the defaults of [`WebhookListener::new`]; see
+    /// [`with_webhook_listener`](Self::with_webhook_listener) to sign requests
+    /// or change retries.
+    #[cfg(feature = "webhooks")]
+    pub fn with_webhook(self, pattern: impl Into<String>, url: impl Into<String>) -> Self {
+        self.with_webhook_listener(pattern, WebhookListener::new(url))
+    }
+
+    /// Forward events matching a pattern with a configured webhook (requires webhooks feature)
+    ///
+    /// The server has it emit `webhook:delivered` and `webhook:failed` events.
+    #[cfg(feature = "webhooks")]
+    pub fn with_webhook_listener(mut self, pattern: impl Into<String>, listener: WebhookListener) -> Self {
+        self.webhooks.push((pattern.into(), listener));
+        self
+    }
+
     /// Add an HTTP route to the module
     pub fn with_route(mut self, path: &'static str, router: Router) -> Self {
         self.routes.push((path, router));
         self
     }
 
+    /// Describe an operation of one of the module's routes for the OpenAPI
+    /// document
+    ///
+    /// `path` is the full path the operation is served at, such as
+    /// `/orders/:id` for a route added at `/orders`.
+    pub fn with_route_doc(mut self, path: impl Into<String>, operation: ApiOperation) -> Self {
+        self.route_docs.push((path.into(), operation));
+        self
+    }
+
+    /// Rewrite the bodies of requests to `path` with `request` and of their
+    /// JSON responses with `response`, whichever module or built-in endpoint
+    /// serves them; see the [`transform`](crate::transform) module
+    ///
+    /// Pass `Ok` for a hook that should leave bodies as they are.
+    pub fn with_route_transform<Req, Res>(self, path: impl Into<String>, request: Req, response: Res) -> Self
+    where
+        Req: Fn(Value) -> Result<Value> + Send + Sync + 'static,
+        Res: Fn(Value) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.with_raw_route_transform(RouteTransform::new(path).on_request(request).on_response(response))
+    }
+
+    /// Add a route transform built with [`RouteTransform`]
+    pub fn with_raw_route_transform(mut self, transform: RouteTransform) -> Self {
+        self.route_transforms.push(transform);
+        self
+    }
+
+    /// Wrap this module's HTTP routes in a tower layer, such as authentication
+    ///
+    /// Applies to every route of the module, whenever it was added, and to
+    /// no other module's. Layers added later wrap those added earlier, so
+    /// they see requests first; layers added with
+    /// [`SurrealX::with_middleware`](crate::SurrealX::with_middleware) wrap
+    /// them all.
+    pub fn with_middleware<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.middleware.push(middleware::erase(layer));
+        self
+    }
+
+    /// Attach a field to every span originating from this module's handlers
+    ///
+    /// Spans always carry `module`; the spans of a module with custom fields
+    /// are nested in an `sx.module` span carrying each as a field of its own.
+    pub fn with_trace_field(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.trace_fields.push((key.into(), value.to_string()));
+        self
+    }
+
+    /// Read this module's section of the configuration sources as `T`
+    ///
+    /// The section is deserialized when the server is built, and a section
+    /// that does not match `T` fails the module. Handlers read it with
+    /// [`Context::config`](crate::Context::config).
+    pub fn with_config<T>(mut self) -> Self
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.config = Some(config::parser::<T>());
+        self
+    }
+
+    /// Run a hook before the server starts listening
+    ///
+    /// Hooks run in registration order with the module's [`Context`], which
+    /// is also current while they run. A failing hook stops the server from
+    /// starting, after the modules already started have been shut down.
+    pub fn on_start<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        self.lifecycle.on_start = Some(Arc::new(move |context| Box::pin(hook(context))));
+        self
+    }
+
+    /// Run a hook when the server shuts down
+    ///
+    /// Hooks run in reverse registration order; a failure is logged and does
+    /// not prevent other modules from shutting down.
+    pub fn on_shutdown<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        self.lifecycle.on_shutdown = Some(Arc::new(move |context| Box::pin(hook(context))));
+        self
+    }
+
+    /// Check whether the module can serve requests, e.g. by pinging a connection
+    pub fn with_health_check<F, Fut>(mut self, check: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        self.lifecycle.health = Some(Arc::new(move || Box::pin(check())));
+        self
+    }
+
+    /// Run a job on a cron schedule, such as `0 * * * *` for every hour
+    ///
+    /// The job is named `job-<n>` after its position among the module's
+    /// jobs, skips runs that are due while the previous one is still going
+    /// and has no jitter; use [`with_job`](Self::with_job) to choose. See the
+    /// [`scheduler`](crate::scheduler) module.
+    pub fn with_scheduled_job<F, Fut>(self, schedule: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = format!("job-{}", self.jobs.len() + 1);
+        self.with_job(ScheduledJob::new(name, schedule, handler))
+    }
+
+    /// Run a scheduled job
+    pub fn with_job(mut self, job: ScheduledJob) -> Self {
+        self.jobs.push(job);
+        self
+    }
+
+    /// Add a workflow, started as `<module>::<name>`; see the
+    /// [`workflow`](crate::workflow) module
+    pub fn with_workflow(mut self, workflow: Workflow) -> Self {
+        self.workflows.push(workflow);
+        self
+    }
+
+    /// Add a migration applying the SurrealQL statements `up`, rolled back
+    /// with `down`; see the [`migration`](crate::migration) module
+    pub fn with_migration(self, version: u64, up: impl Into<String>, down: impl Into<String>) -> Self {
+        self.with_raw_migration(Migration::sql(version, up).with_down_sql(down))
+    }
+
+    /// Add a migration written in Rust or applied after another module's
+    pub fn with_raw_migration(mut self, migration: Migration) -> Self {
+        self.migrations.push(migration);
+        self
+    }
+
+    /// Declare a table, defined when the server is built; see the
+    /// [`schema`](crate::schema) module
+    pub fn with_table(mut self, table: Table) -> Self {
+        self.tables.push(table);
+        self
+    }
+
     /// Get module name
     pub fn name(&self) -> &str {
         &self.name
@@ -82,13 +639,155 @@ impl Module {
         &self.functions
     }
 
+    /// Get the streaming functions, which are among the functions too
+    pub fn streaming_functions(&self) -> &[(String, Arc<dyn StreamingFunctionHandler>)] {
+        &self.streaming_functions
+    }
+
+    /// Get the signatures given to functions, by function name
+    pub fn signatures(&self) -> &[(String, FunctionSignature)] {
+        &self.signatures
+    }
+
+    /// Get the default versions of versioned functions, as (name, version)
+    /// pairs
+    pub fn default_versions(&self) -> &[(String, String)] {
+        &self.default_versions
+    }
+
+    /// Get the deprecated functions, as (name, note) pairs
+    pub fn deprecations(&self) -> &[(String, String)] {
+        &self.deprecations
+    }
+
+    /// The versioned functions of the module, as (name, name of the default
+    /// version) pairs
+    pub(crate) fn versioned_functions(&self) -> Vec<(String, String)> {
+        let mut latest: Vec<(&str, &str)> = Vec::new();
+        for (function, _) in &self.functions {
+            let Some((name, version)) = function.split_once('@') else {
+                continue;
+            };
+            match latest.iter_mut().find(|(existing, _)| *existing == name) {
+                Some((_, highest)) if crate::functions::compare_versions(version, *highest).is_gt() => {
+                    *highest = version
+                }
+                Some(_) => {}
+                None => latest.push((name, version)),
+            }
+        }
+        latest
+            .into_iter()
+            .map(|(name, highest)| {
+                let version = self
+                    .default_versions
+                    .iter()
+                    .find(|(function, _)| function == name)
+                    .map_or(highest, |(_, version)| version.as_str());
+                (name.to_string(), format!("{}@{}", name, version))
+            })
+            .collect()
+    }
+
+    /// Get the middleware wrapping the module's functions, with the function
+    /// it is limited to, if any
+    pub fn function_middleware(&self) -> &[(Option<String>, Arc<dyn FunctionMiddleware>)] {
+        &self.function_middleware
+    }
+
     /// Get all listeners
     pub fn listeners(&self) -> &[(String, Arc<dyn EventListener>)] {
         &self.listeners
     }
 
+    /// Get the retry policies of functions, as (name, policy) pairs
+    pub fn function_retries(&self) -> &[(String, RetryPolicy)] {
+        &self.function_retries
+    }
+
+    /// Get the retry policies of listeners, as (pattern, policy) pairs
+    pub fn listener_retries(&self) -> &[(String, RetryPolicy)] {
+        &self.listener_retries
+    }
+
+    /// Get the event schemas as (pattern, schema) pairs
+    pub fn event_schemas(&self) -> &[(String, EventSchema)] {
+        &self.event_schemas
+    }
+
+    /// Get the middleware wrapping the module's emitted events
+    pub fn event_middleware(&self) -> &[Arc<dyn EventMiddleware>] {
+        &self.event_middleware
+    }
+
+    /// Get all function listeners as (pattern, function name) pairs
+    pub fn function_listeners(&self) -> &[(String, String)] {
+        &self.function_listeners
+    }
+
     /// Get all routes
     pub fn routes(&self) -> &[(&'static str, Router)] {
         &self.routes
     }
+
+    /// Get the operations described for the OpenAPI document, by path
+    pub fn route_docs(&self) -> &[(String, ApiOperation)] {
+        &self.route_docs
+    }
+
+    /// Get all route transforms
+    pub fn route_transforms(&self) -> &[RouteTransform] {
+        &self.route_transforms
+    }
+
+    /// Layers wrapping the module's routes, in the order they were added
+    pub(crate) fn middleware(&self) -> &[Arc<Middleware>] {
+        &self.middleware
+    }
+
+    /// Get all trace fields
+    pub fn trace_fields(&self) -> &[(String, String)] {
+        &self.trace_fields
+    }
+
+    pub(crate) fn config_parser(&self) -> Option<&Arc<ConfigParser>> {
+        self.config.as_ref()
+    }
+
+    pub(crate) fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    /// Get all webhooks as (pattern, listener) pairs
+    #[cfg(feature = "webhooks")]
+    pub fn webhooks(&self) -> &[(String, WebhookListener)] {
+        &self.webhooks
+    }
+
+    /// Get all scheduled jobs
+    pub fn jobs(&self) -> &[ScheduledJob] {
+        &self.jobs
+    }
+
+    /// Get all workflows
+    pub fn workflows(&self) -> &[Workflow] {
+        &self.workflows
+    }
+
+    /// Get all migrations
+    pub fn migrations(&self) -> &[Migration] {
+        &self.migrations
+    }
+
+    /// Get all declared tables
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    /// The `sx.module` span of the trace fields, set up on first use
+    pub(crate) fn trace_span(&self) -> ModuleSpan {
+        self.trace_span
+            .get_or_init(|| ModuleSpan::new(&self.name, &self.trace_fields))
+            .clone()
+    }
 }
@@ -0,0 +1,266 @@
+//! MQTT event bridge (requires mqtt feature)
+//!
+//! [`MqttBridge`] maps event patterns to MQTT topics in both directions, so
+//! device fleets can publish into the event registry and receive table
+//! changes. Segments of a pattern are levels of a topic: an event of pattern
+//! `orders:42` published under the prefix `surrealx` goes to the topic
+//! `surrealx/orders/42` and carries the [`Event`] as JSON, while a message
+//! received on `devices/sensor-1/telemetry` is emitted as a `Custom` event of
+//! pattern `devices:sensor-1:telemetry`, or `<topic>:message` for a topic of
+//! one level. Its data is the payload, parsed as JSON when it is JSON and
+//! kept as text otherwise.
+//!
+//! The bridge keeps its session across reconnects and subscribes again after
+//! each one, backing off up to 30 seconds while the broker is unreachable.
+//! Received messages are acknowledged only after their listeners ran. A
+//! bridge that publishes to topics it also subscribes to emits every event
+//! back into the registry; keep the two sets of topics apart.
+
+use std::sync::Mutex;
+use std::time::Duration;
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, EventLoop, MqttOptions, Outgoing, Packet, Publish};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::bridge::EventBridge;
+use crate::events::{Event, EventListener, EventRegistry};
+use crate::error::{Error, Result};
+
+pub use rumqttc::QoS;
+
+/// How many requests and received messages may wait to be handled
+const QUEUE_CAPACITY: usize = 100;
+/// Longest wait between reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// How long `stop()` waits for the disconnect to be sent
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bridge between the event registry and MQTT topics
+pub struct MqttBridge {
+    options: MqttOptions,
+    publish: Vec<(String, String)>,
+    subscribe: Vec<String>,
+    qos: QoS,
+    retain: bool,
+    client: Mutex<Option<AsyncClient>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl MqttBridge {
+    /// Connect to the broker at `host:port` as `client_id`, with QoS 1
+    pub fn new(client_id: impl Into<String>, host: impl Into<String>, port: u16) -> Self {
+        let mut options = MqttOptions::new(client_id, host, port);
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_clean_session(false)
+            .set_manual_acks(true);
+        Self {
+            options,
+            publish: Vec::new(),
+            subscribe: Vec::new(),
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            client: Mutex::new(None),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Publish events matching `pattern` to topics under `prefix`
+    ///
+    /// An empty prefix publishes to `<table>/<id>` directly.
+    pub fn publish(mut self, pattern: impl Into<String>, prefix: impl Into<String>) -> Self {
+        self.publish.push((pattern.into(), prefix.into()));
+        self
+    }
+
+    /// Emit the messages of topics matching `filter`, such as `devices/+/telemetry`
+    pub fn subscribe(mut self, filter: impl Into<String>) -> Self {
+        self.subscribe.push(filter.into());
+        self
+    }
+
+    /// Authenticate with a user name and password
+    pub fn with_credentials(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.options.set_credentials(user, password);
+        self
+    }
+
+    /// Set the quality of service of publishes and subscriptions
+    pub fn with_qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Ask the broker to keep the last message of each topic for new subscribers
+    pub fn with_retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    /// Set how often the connection is checked while idle
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.options.set_keep_alive(keep_alive);
+        self
+    }
+}
+
+#[async_trait]
+impl EventBridge for MqttBridge {
+    fn name(&self) -> &str {
+        "mqtt"
+    }
+
+    async fn start(&self, events: EventRegistry) -> Result<()> {
+        let (client, eventloop) = AsyncClient::new(self.options.clone(), QUEUE_CAPACITY);
+        for (pattern, prefix) in &self.publish {
+            let publisher = MqttPublisher {
+                client: client.clone(),
+                prefix: prefix.clone(),
+                qos: self.qos,
+                retain: self.retain,
+            };
+            events.register(pattern.clone(), publisher).await?;
+        }
+
+        let (received, incoming) = mpsc::channel(QUEUE_CAPACITY);
+        let subscriptions: Vec<(String, QoS)> = self.subscribe.iter().map(|filter| (filter.clone(), self.qos)).collect();
+        let mut tasks = self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        tasks.push(tokio::spawn(poll(eventloop, client.clone(), subscriptions, received)));
+        tasks.push(tokio::spawn(handle(incoming, client.clone(), events)));
+        *self.client.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(client);
+        Ok(())
+    }
+
+    async fn stop(&self) {
+        let client = self.client.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        let mut tasks: Vec<_> = self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).drain(..).collect();
+        if let Some(client) = client {
+            if client.disconnect().await.is_ok() && !tasks.is_empty() {
+                // The event loop ends once it has sent the disconnect
+                let poller = tasks.remove(0);
+                let abort = poller.abort_handle();
+                if tokio::time::timeout(DISCONNECT_TIMEOUT, poller).await.is_err() {
+                    abort.abort();
+                }
+            }
+        }
+        for task in tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Listener publishing events to topics under a prefix
+struct MqttPublisher {
+    client: AsyncClient,
+    prefix: String,
+    qos: QoS,
+    retain: bool,
+}
+
+#[async_trait]
+impl EventListener for MqttPublisher {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let payload = serde_json::to_vec(&event)?;
+        self.client
+            .publish(topic(&self.prefix, &event), self.qos, self.retain, payload)
+            .await
+            .map_err(mqtt_error)
+    }
+}
+
+/// Drive the connection, reconnecting with backoff, and pass received
+/// messages on to be handled
+async fn poll(
+    mut eventloop: EventLoop,
+    client: AsyncClient,
+    subscriptions: Vec<(String, QoS)>,
+    received: mpsc::Sender<Publish>,
+) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match eventloop.poll().await {
+            Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                backoff = Duration::from_secs(1);
+                tracing::info!("connected to MQTT broker");
+                if !subscriptions.is_empty() {
+                    if let Err(err) = client.try_subscribe_many(subscriptions.iter().map(|(filter, qos)| {
+                        rumqttc::SubscribeFilter::new(filter.clone(), *qos)
+                    })) {
+                        tracing::warn!(error = %err, "failed to subscribe to MQTT topics");
+                    }
+                }
+            }
+            Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => {
+                if received.send(publish).await.is_err() {
+                    return;
+                }
+            }
+            Ok(rumqttc::Event::Outgoing(Outgoing::Disconnect)) => return,
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!(error = %err, retry_in = ?backoff, "MQTT connection failed");
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Emit every received message, acknowledging each once handled
+async fn handle(mut incoming: mpsc::Receiver<Publish>, client: AsyncClient, events: EventRegistry) {
+    while let Some(publish) = incoming.recv().await {
+        let data = match serde_json::from_slice(&publish.payload) {
+            Ok(data) => data,
+            Err(_) => Value::String(String::from_utf8_lossy(&publish.payload).into_owned()),
+        };
+        match Event::custom(&pattern(&publish.topic), data) {
+            Ok(event) => {
+                if let Err(err) = events.emit_quiet(event).await {
+                    tracing::warn!(topic = %publish.topic, error = %err, "listener failed on MQTT message");
+                }
+            }
+            Err(err) => tracing::warn!(topic = %publish.topic, error = %err, "skipping MQTT message"),
+        }
+
+        if let Err(err) = client.ack(&publish).await {
+            tracing::warn!(topic = %publish.topic, error = %err, "failed to acknowledge MQTT message");
+        }
+    }
+}
+
+/// Topic of `event` under `prefix`: the table, then each segment of its record id
+fn topic(prefix: &str, event: &Event) -> String {
+    let mut levels: Vec<String> = Vec::new();
+    if !prefix.is_empty() {
+        levels.push(prefix.trim_end_matches('/').to_string());
+    }
+    levels.push(level(&event.table));
+    if let Some(id) = &event.record_id {
+        levels.extend(id.split(':').map(level));
+    }
+    levels.join("/")
+}
+
+/// Event pattern of a message received on `topic`
+fn pattern(topic: &str) -> String {
+    let levels: Vec<&str> = topic.split('/').filter(|level| !level.is_empty()).collect();
+    match levels.as_slice() {
+        [] => "mqtt:message".to_string(),
+        [table] => format!("{}:message", table),
+        _ => levels.join(":"),
+    }
+}
+
+/// Make `segment` usable as a single topic level
+fn level(segment: &str) -> String {
+    segment
+        .chars()
+        .map(|c| if c == '/' || c == '+' || c == '#' { '_' } else { c })
+        .collect()
+}
+
+fn mqtt_error(err: rumqttc::ClientError) -> Error {
+    Error::Mqtt(err.to_string())
+}
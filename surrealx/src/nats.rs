@@ -0,0 +1,348 @@
+//! NATS event transport for multi-node deployments (requires nats feature)
+//!
+//! [`NatsEventTransport`] publishes the events emitted on one node to a
+//! JetStream stream and emits the events published by every other node, so
+//! listeners fire wherever they are registered. Each event goes to a subject
+//! derived from its pattern: `orders:42` is published on
+//! `sx.events.orders.42` and a table-wide event on `sx.events.orders`, with
+//! `.`, `*`, `>` and whitespace replaced by `_`. The message carries the
+//! [`Event`] itself, so the subject only serves routing and filtering.
+//!
+//! Delivery is at least once. A publish completes only once the stream has
+//! stored the message, and every node consumes through a durable consumer
+//! named after its node id, acknowledging a message only after its listeners
+//! ran. Messages whose listeners fail are redelivered, up to 5 times in all.
+//! Keep node ids stable across restarts so a node resumes where it stopped.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_nats::jetstream::{self, consumer, stream, AckKind};
+use async_nats::{Client, ConnectOptions, HeaderMap};
+use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::task::JoinHandle;
+use crate::bridge::EventBridge;
+use crate::events::{Event, EventListener, EventRegistry, PatternSyntax};
+use crate::error::{Error, Result};
+
+/// Header carrying the id of the node that published a message
+pub const ORIGIN_HEADER: &str = "Sx-Origin";
+
+/// How many received events are remembered to avoid publishing them back
+const RECENT_CAPACITY: usize = 1024;
+
+/// Credentials presented to the NATS server
+#[derive(Clone)]
+enum Credentials {
+    None,
+    Token(String),
+    UserAndPassword(String, String),
+}
+
+/// Transport sharing events between nodes through NATS JetStream
+pub struct NatsEventTransport {
+    url: String,
+    credentials: Credentials,
+    patterns: Vec<String>,
+    subject_prefix: String,
+    stream: String,
+    node_id: String,
+    max_age: Duration,
+    client: Mutex<Option<Client>>,
+    consumer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl NatsEventTransport {
+    /// Connect to the NATS server at `url`, sharing every event on the
+    /// `SURREALX_EVENTS` stream and keeping messages for a day
+    ///
+    /// The node id defaults to the `HOSTNAME` environment variable, or a
+    /// random id if that is not set.
+    pub fn new(url: impl Into<String>) -> Self {
+        let node_id = std::env::var("HOSTNAME")
+            .ok()
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| format!("surrealx-{:016x}", fastrand::u64(..)));
+        Self {
+            url: url.into(),
+            credentials: Credentials::None,
+            patterns: Vec::new(),
+            subject_prefix: "sx.events".to_string(),
+            stream: "SURREALX_EVENTS".to_string(),
+            node_id,
+            max_age: Duration::from_secs(24 * 60 * 60),
+            client: Mutex::new(None),
+            consumer: Mutex::new(None),
+        }
+    }
+
+    /// Share only the events matching `pattern`; may be called repeatedly
+    ///
+    /// Without any pattern, every event is shared through the registry's
+    /// catch-all pattern. Patterns should not overlap, or events matching
+    /// several are published once for each.
+    pub fn with_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    /// Authenticate with a token
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.credentials = Credentials::Token(token.into());
+        self
+    }
+
+    /// Authenticate with a user and password
+    pub fn with_user_and_password(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Credentials::UserAndPassword(user.into(), password.into());
+        self
+    }
+
+    /// Publish under subjects starting with `prefix` instead of `sx.events`
+    pub fn with_subject_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.subject_prefix = prefix.into();
+        self
+    }
+
+    /// Use the JetStream stream `name`, created if missing
+    pub fn with_stream(mut self, name: impl Into<String>) -> Self {
+        self.stream = name.into();
+        self
+    }
+
+    /// Set how long the stream keeps messages when it is created
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Identify this node, naming its durable consumer
+    pub fn with_node_id(mut self, node_id: impl Into<String>) -> Self {
+        self.node_id = node_id.into();
+        self
+    }
+
+    /// Id of this node
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Subject an event is published on
+    pub fn subject(&self, event: &Event) -> String {
+        subject(&self.subject_prefix, event)
+    }
+
+    async fn connect(&self) -> Result<Client> {
+        let options = match self.credentials.clone() {
+            Credentials::None => ConnectOptions::new(),
+            Credentials::Token(token) => ConnectOptions::new().token(token),
+            Credentials::UserAndPassword(user, password) => ConnectOptions::new().user_and_password(user, password),
+        };
+        options
+            .name(format!("surrealx {}", self.node_id))
+            .connect(self.url.as_str())
+            .await
+            .map_err(nats_error)
+    }
+}
+
+#[async_trait]
+impl EventBridge for NatsEventTransport {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    async fn start(&self, events: EventRegistry) -> Result<()> {
+        let client = self.connect().await?;
+        let context = jetstream::new(client.clone());
+        let stream = context
+            .get_or_create_stream(stream::Config {
+                name: self.stream.clone(),
+                subjects: vec![format!("{}.>", self.subject_prefix)],
+                max_age: self.max_age,
+                ..Default::default()
+            })
+            .await
+            .map_err(nats_error)?;
+
+        let durable = token(&self.node_id);
+        let consumer = stream
+            .get_or_create_consumer::<consumer::pull::Config>(
+                &durable,
+                consumer::pull::Config {
+                    durable_name: Some(durable.clone()),
+                    deliver_policy: consumer::DeliverPolicy::New,
+                    ack_policy: consumer::AckPolicy::Explicit,
+                    max_deliver: 5,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(nats_error)?;
+        let messages = consumer.messages().await.map_err(nats_error)?;
+
+        let recent = Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)));
+        let patterns = if self.patterns.is_empty() {
+            vec![catch_all(&events)]
+        } else {
+            self.patterns.clone()
+        };
+        for pattern in patterns {
+            let publisher = NatsPublisher {
+                context: context.clone(),
+                subject_prefix: self.subject_prefix.clone(),
+                node_id: self.node_id.clone(),
+                recent: recent.clone(),
+            };
+            events.register(pattern, publisher).await?;
+        }
+
+        let task = tokio::spawn(consume(messages, events, self.node_id.clone(), recent));
+        *self.consumer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(task);
+        *self.client.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(client);
+        Ok(())
+    }
+
+    async fn stop(&self) {
+        if let Some(task) = self.consumer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            task.abort();
+        }
+        let client = self.client.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        if let Some(client) = client {
+            if let Err(err) = client.flush().await {
+                tracing::warn!(error = %err, "failed to flush the NATS connection");
+            }
+        }
+    }
+}
+
+/// Listener publishing local events to the stream
+struct NatsPublisher {
+    context: jetstream::Context,
+    subject_prefix: String,
+    node_id: String,
+    /// Events received from other nodes, which must not be sent back
+    recent: Arc<Mutex<VecDeque<String>>>,
+}
+
+#[async_trait]
+impl EventListener for NatsPublisher {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let payload = serde_json::to_string(&event)?;
+        {
+            let mut recent = self.recent.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(position) = recent.iter().position(|received| *received == payload) {
+                recent.remove(position);
+                return Ok(());
+            }
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN_HEADER, self.node_id.as_str());
+        self.context
+            .publish_with_headers(subject(&self.subject_prefix, &event), headers, payload.into())
+            .await
+            .map_err(nats_error)?
+            .await
+            .map_err(nats_error)?;
+        Ok(())
+    }
+}
+
+/// Emit the events other nodes published, acknowledging each once handled
+async fn consume(
+    mut messages: consumer::pull::Stream,
+    events: EventRegistry,
+    node_id: String,
+    recent: Arc<Mutex<VecDeque<String>>>,
+) {
+    while let Some(message) = messages.next().await {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to receive NATS message");
+                continue;
+            }
+        };
+
+        let origin = message
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.get(ORIGIN_HEADER))
+            .map(|origin| origin.as_str());
+        let handled = if origin == Some(node_id.as_str()) {
+            Ok(())
+        } else {
+            match serde_json::from_slice::<Event>(&message.payload) {
+                Ok(event) => {
+                    remember(&recent, &event);
+                    events.emit_quiet(event).await
+                }
+                Err(err) => {
+                    tracing::warn!(subject = %message.subject, error = %err, "skipping undecodable NATS message");
+                    Ok(())
+                }
+            }
+        };
+
+        let acked = match handled {
+            Ok(()) => message.ack().await,
+            Err(err) => {
+                tracing::warn!(subject = %message.subject, error = %err, "listener failed on NATS message; redelivering");
+                message.ack_with(AckKind::Nak(None)).await
+            }
+        };
+        if let Err(err) = acked {
+            tracing::warn!(error = %err, "failed to acknowledge NATS message");
+        }
+    }
+}
+
+/// Note that `event` came from another node before emitting it locally
+fn remember(recent: &Mutex<VecDeque<String>>, event: &Event) {
+    let Ok(payload) = serde_json::to_string(event) else {
+        return;
+    };
+    let mut recent = recent.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if recent.len() == RECENT_CAPACITY {
+        recent.pop_front();
+    }
+    recent.push_back(payload);
+}
+
+/// `prefix`, the event's table and each segment of its record id
+fn subject(prefix: &str, event: &Event) -> String {
+    let mut subject = format!("{}.{}", prefix, token(&event.table));
+    if let Some(id) = &event.record_id {
+        for segment in id.split(':') {
+            subject.push('.');
+            subject.push_str(&token(segment));
+        }
+    }
+    subject
+}
+
+fn catch_all(events: &EventRegistry) -> String {
+    match events.syntax() {
+        PatternSyntax::Legacy => "*".to_string(),
+        PatternSyntax::Glob => "**".to_string(),
+    }
+}
+
+/// Make `segment` usable as a single subject token
+fn token(segment: &str) -> String {
+    let token: String = segment
+        .chars()
+        .map(|c| if c == '.' || c == '*' || c == '>' || c.is_whitespace() { '_' } else { c })
+        .collect();
+    if token.is_empty() {
+        "_".to_string()
+    } else {
+        token
+    }
+}
+
+fn nats_error(err: impl std::fmt::Display) -> Error {
+    Error::Nats(err.to_string())
+}
@@ -0,0 +1,365 @@
+//! OpenAPI document of the server's HTTP API
+//!
+//! With `ServerConfig::openapi_route` set, the server answers:
+//!
+//! - `GET /sx/openapi.json`: an OpenAPI 3.0 document of `POST /sql`, when
+//!   served, the routes of every module and, when they are exposed, the
+//!   function endpoints, typed from the functions' signatures.
+//! - `GET /sx/docs`: Swagger UI showing that document.
+//!
+//! A module route is an axum router whose own paths and methods cannot be
+//! inspected, so it appears as a path without operations unless the module
+//! describes what it serves with [`Module::with_route_doc`]. The document is
+//! built with the server and, like the route paths it lists, is not updated
+//! by hot reloads. Neither route requires authentication; the document
+//! declares bearer tokens when `ServerConfig::auth` is set.
+//!
+//! [`Module::with_route_doc`]: crate::Module::with_route_doc
+
+use std::collections::BTreeMap;
+use axum::extract::State;
+use axum::http::Method;
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Map, Value};
+use crate::functions::{FunctionRegistry, ValueType};
+use crate::module::Module;
+use crate::server::AuthConfig;
+
+/// Description of one operation of a module route, for the OpenAPI document
+#[derive(Debug, Clone)]
+pub struct ApiOperation {
+    method: Method,
+    summary: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+    parameters: Vec<Value>,
+    request_body: Option<(String, Value)>,
+    responses: Vec<(u16, String, Option<Value>)>,
+}
+
+impl ApiOperation {
+    /// Describe the operation answering `method`
+    pub fn new(method: Method) -> Self {
+        Self {
+            method,
+            summary: None,
+            description: None,
+            tags: Vec::new(),
+            parameters: Vec::new(),
+            request_body: None,
+            responses: Vec::new(),
+        }
+    }
+
+    /// Describe a `GET` operation
+    pub fn get() -> Self {
+        Self::new(Method::GET)
+    }
+
+    /// Describe a `POST` operation
+    pub fn post() -> Self {
+        Self::new(Method::POST)
+    }
+
+    /// Describe a `PUT` operation
+    pub fn put() -> Self {
+        Self::new(Method::PUT)
+    }
+
+    /// Describe a `PATCH` operation
+    pub fn patch() -> Self {
+        Self::new(Method::PATCH)
+    }
+
+    /// Describe a `DELETE` operation
+    pub fn delete() -> Self {
+        Self::new(Method::DELETE)
+    }
+
+    /// Set the one-line summary
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    /// Set the longer description, which may use Markdown
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Group the operation under `tag`; operations without one are grouped
+    /// under their module's name
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Document a path parameter; parameters of the path that are not
+    /// documented are listed as strings
+    pub fn path_parameter(self, name: impl Into<String>, schema: Value) -> Self {
+        self.parameter("path", name.into(), schema, true)
+    }
+
+    /// Document a query parameter
+    pub fn query_parameter(self, name: impl Into<String>, schema: Value, required: bool) -> Self {
+        self.parameter("query", name.into(), schema, required)
+    }
+
+    fn parameter(mut self, location: &str, name: String, schema: Value, required: bool) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": location,
+            "required": required,
+            "schema": schema,
+        }));
+        self
+    }
+
+    /// Document a JSON request body with the JSON Schema `schema`
+    pub fn request_body(self, schema: Value) -> Self {
+        self.request_body_as("application/json", schema)
+    }
+
+    /// Document a request body of another content type
+    pub fn request_body_as(mut self, content_type: impl Into<String>, schema: Value) -> Self {
+        self.request_body = Some((content_type.into(), schema));
+        self
+    }
+
+    /// Document a response without a body
+    pub fn response(mut self, status: u16, description: impl Into<String>) -> Self {
+        self.responses.push((status, description.into(), None));
+        self
+    }
+
+    /// Document a JSON response with the JSON Schema `schema`
+    pub fn json_response(mut self, status: u16, description: impl Into<String>, schema: Value) -> Self {
+        self.responses.push((status, description.into(), Some(schema)));
+        self
+    }
+
+    /// Method of the operation
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    fn to_json(&self, path: &str, default_tag: &str) -> Value {
+        let mut operation = Map::new();
+        if let Some(summary) = &self.summary {
+            operation.insert("summary".into(), json!(summary));
+        }
+        if let Some(description) = &self.description {
+            operation.insert("description".into(), json!(description));
+        }
+        let tags = if self.tags.is_empty() {
+            vec![default_tag.to_string()]
+        } else {
+            self.tags.clone()
+        };
+        operation.insert("tags".into(), json!(tags));
+
+        let mut parameters = self.parameters.clone();
+        for name in path_parameters(path) {
+            let documented = parameters
+                .iter()
+                .any(|parameter| parameter["in"] == "path" && parameter["name"] == name);
+            if !documented {
+                parameters.push(json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                }));
+            }
+        }
+        if !parameters.is_empty() {
+            operation.insert("parameters".into(), Value::Array(parameters));
+        }
+
+        if let Some((content_type, schema)) = &self.request_body {
+            operation.insert(
+                "requestBody".into(),
+                json!({ "required": true, "content": { content_type: { "schema": schema } } }),
+            );
+        }
+
+        let mut responses = Map::new();
+        for (status, description, schema) in &self.responses {
+            let mut response = json!({ "description": description });
+            if let Some(schema) = schema {
+                response["content"] = json!({ "application/json": { "schema": schema } });
+            }
+            responses.insert(status.to_string(), response);
+        }
+        if responses.is_empty() {
+            responses.insert("200".into(), json!({ "description": "Success" }));
+        }
+        operation.insert("responses".into(), Value::Object(responses));
+        Value::Object(operation)
+    }
+}
+
+/// OpenAPI document being assembled while the server is built
+pub(crate) struct ApiDocument {
+    paths: BTreeMap<String, Map<String, Value>>,
+    auth: Option<(bool, String)>,
+}
+
+impl ApiDocument {
+    /// Start a document, declaring bearer tokens if `auth` is set
+    pub(crate) fn new(auth: Option<&AuthConfig>) -> Self {
+        Self {
+            paths: BTreeMap::new(),
+            auth: auth.map(|auth| (auth.required, auth.algorithm.clone())),
+        }
+    }
+
+    /// Add `operation` at `path`, which may use axum's `:param` syntax
+    pub(crate) fn operation(&mut self, path: &str, operation: &ApiOperation, default_tag: &str) {
+        let path = openapi_path(path);
+        let method = operation.method.as_str().to_ascii_lowercase();
+        let item = operation.to_json(&path, default_tag);
+        self.paths.entry(path).or_default().insert(method, item);
+    }
+
+    /// Add the routes of `module` and the operations it describes
+    pub(crate) fn module(&mut self, module: &Module) {
+        for (path, operation) in module.route_docs() {
+            self.operation(path, operation, module.name());
+        }
+        for (path, _) in module.routes() {
+            let prefix = openapi_path(path);
+            let described = module
+                .route_docs()
+                .iter()
+                .any(|(documented, _)| openapi_path(documented).starts_with(prefix.as_str()));
+            if !described {
+                self.paths.entry(prefix).or_default().insert(
+                    "summary".into(),
+                    json!(format!("Routes of module '{}'", module.name())),
+                );
+            }
+        }
+    }
+
+    /// Add `POST /sx/fn/{name}` for every function of `functions`
+    pub(crate) fn functions(&mut self, functions: &FunctionRegistry) {
+        let mut names = functions.list();
+        names.sort();
+        for name in names {
+            let signature = functions.signature(&name).unwrap_or_default();
+            let arguments = if signature.params.is_empty() {
+                json!({ "type": "array", "items": {} })
+            } else {
+                let required = signature.params.iter().filter(|param| !param.optional).count();
+                let names: Vec<&str> = signature.params.iter().map(|param| param.name.as_str()).collect();
+                json!({
+                    "type": "array",
+                    "description": format!("Arguments in order: {}", names.join(", ")),
+                    "items": {},
+                    "minItems": required,
+                    "maxItems": signature.params.len(),
+                })
+            };
+            let operation = ApiOperation::post()
+                .summary(format!("Call {}", name))
+                .tag("functions")
+                .request_body(arguments)
+                .json_response(200, "The function's result", schema(&signature.returns))
+                .response(400, "The function rejected its arguments")
+                .response(403, "The function requires a role the caller lacks")
+                .response(404, "No such function")
+                .response(429, "A rate limit was exceeded");
+            self.operation(&format!("/sx/fn/{}", name), &operation, "functions");
+        }
+    }
+
+    pub(crate) fn into_json(self) -> Value {
+        let mut document = json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "SurrealX",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+        });
+        if let Some((required, algorithm)) = self.auth {
+            document["components"] = json!({
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": format!("JWT ({})", algorithm) },
+                },
+            });
+            document["security"] = if required {
+                json!([{ "bearer": [] }])
+            } else {
+                json!([{ "bearer": [] }, {}])
+            };
+        }
+        document
+    }
+}
+
+/// JSON Schema of values of type `ty`
+fn schema(ty: &ValueType) -> Value {
+    match ty {
+        ValueType::Bool => json!({ "type": "boolean" }),
+        ValueType::Int => json!({ "type": "integer" }),
+        ValueType::Float => json!({ "type": "number" }),
+        ValueType::String => json!({ "type": "string" }),
+        ValueType::Array(item) => json!({ "type": "array", "items": schema(item) }),
+        ValueType::Any => json!({}),
+    }
+}
+
+/// Router exposing `GET /sx/openapi.json` and Swagger UI at `GET /sx/docs`
+pub(crate) fn router(document: Value) -> Router {
+    Router::new()
+        .route("/sx/openapi.json", get(document_handler))
+        .route("/sx/docs", get(swagger_ui))
+        .with_state(document)
+}
+
+async fn document_handler(State(document): State<Value>) -> Json<Value> {
+    Json(document)
+}
+
+async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI)
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>SurrealX API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/sx/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// `path` with axum's `:param` and `*param` segments written as `{param}`
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':').or_else(|| segment.strip_prefix('*')) {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Names of the `{param}` segments of an OpenAPI path
+fn path_parameters(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')))
+}
@@ -0,0 +1,337 @@
+//! Outbox for emitting events only after a function call succeeds
+//!
+//! Two kinds of events can be held back:
+//!
+//! - Events recorded with [`record`] are emitted once the call returns
+//!   successfully, kept in the cache provider until delivered.
+//! - Events recorded with [`FunctionContext::emit_after_commit`] are written
+//!   to the `sx_outbox` table of the embedded database in the same
+//!   transaction as the query that called the function, and emitted by a
+//!   background relay once that transaction commits. A rolled back query
+//!   leaves no event behind. Calls that do not come from SurrealQL write
+//!   them once the function succeeds.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use crate::cache::CacheProvider;
+use crate::context::FunctionContext;
+use crate::db::Database;
+use crate::events::{Event, EventRegistry};
+use crate::functions::FunctionHandler;
+use crate::error::{Error, Result};
+
+const PENDING_KEY: &str = "sx:outbox:pending";
+
+/// Table holding events waiting for their transaction to commit
+pub const OUTBOX_TABLE: &str = "sx_outbox";
+
+/// How many outbox rows the relay emits per query
+const RELAY_BATCH: usize = 100;
+
+tokio::task_local! {
+    static RECORDED: RefCell<Vec<Event>>;
+    static AFTER_COMMIT: RefCell<Vec<Event>>;
+}
+
+static NEXT_BATCH: AtomicU64 = AtomicU64::new(0);
+
+/// Record an event to be emitted once the current function call succeeds
+///
+/// Only works inside a call dispatched through an [`Outbox`]; events recorded
+/// from tasks spawned by the handler are not captured.
+pub fn record(event: Event) -> Result<()> {
+    RECORDED
+        .try_with(|recorded| recorded.borrow_mut().push(event))
+        .map_err(|_| Error::Event("no outbox in scope; events can only be recorded during a function call".to_string()))
+}
+
+/// Run a future with an empty outbox, returning its output and the recorded events
+pub(crate) async fn scope<F: Future>(future: F) -> (F::Output, Vec<Event>) {
+    RECORDED
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = future.await;
+            let recorded = RECORDED.with(|recorded| recorded.take());
+            (output, recorded)
+        })
+        .await
+}
+
+/// Record an event to be written to the outbox table with the current
+/// transaction; see [`FunctionContext::emit_after_commit`]
+pub(crate) fn record_after_commit(event: Event) -> Result<()> {
+    AFTER_COMMIT
+        .try_with(|recorded| recorded.borrow_mut().push(event))
+        .map_err(|_| Error::Event("no transaction in scope; events can only be emitted after commit during a function call".to_string()))
+}
+
+/// Run a future as one transaction, returning its output and the events it
+/// recorded to emit after commit
+pub(crate) async fn transaction<F: Future>(future: F) -> (F::Output, Vec<Event>) {
+    AFTER_COMMIT
+        .scope(RefCell::new(Vec::new()), async move {
+            let output = future.await;
+            let recorded = AFTER_COMMIT.with(|recorded| recorded.take());
+            (output, recorded)
+        })
+        .await
+}
+
+/// SurrealQL inserting `events` into the outbox table, returning nothing
+pub(crate) fn insert_statement(events: &[Event]) -> Result<String> {
+    Ok(format!("INSERT INTO {} {} RETURN NONE", OUTBOX_TABLE, serde_json::to_string(&rows(events)?)?))
+}
+
+fn rows(events: &[Event]) -> Result<Vec<Value>> {
+    let created_at = chrono::Utc::now().timestamp_millis();
+    events
+        .iter()
+        .map(|event| {
+            Ok(json!({
+                "key": format!("{}-{}", created_at, NEXT_BATCH.fetch_add(1, Ordering::Relaxed)),
+                "event": serde_json::to_value(event)?,
+                "created_at": created_at,
+            }))
+        })
+        .collect()
+}
+
+/// Dispatches events recorded during a function call after it succeeds
+///
+/// Recorded events are persisted to the cache provider before delivery and
+/// removed once every listener has run, giving at-least-once delivery when
+/// the cache is durable. Events recorded by a failing call are discarded.
+/// With [`DispatchPolicy::Spawn`](crate::DispatchPolicy::Spawn) a batch counts
+/// as delivered once its listeners have been started.
+#[derive(Clone)]
+pub struct Outbox {
+    events: EventRegistry,
+    cache: Arc<dyn CacheProvider>,
+    pending: Arc<Mutex<()>>,
+    db: Option<Database>,
+    committed: Arc<Notify>,
+}
+
+impl Outbox {
+    pub fn new(events: EventRegistry, cache: Arc<dyn CacheProvider>) -> Self {
+        Self {
+            events,
+            cache,
+            pending: Arc::new(Mutex::new(())),
+            db: None,
+            committed: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Keep events emitted after commit in `db`
+    ///
+    /// Without a database, recording such an event fails.
+    pub fn with_database(mut self, db: Database) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Call a function, emitting the events it recorded only if it succeeds
+    ///
+    /// Outside a query's transaction, events the function emits after commit
+    /// are written to the outbox table once it succeeds, and the call fails
+    /// if they cannot be.
+    pub async fn call(&self, handler: &dyn FunctionHandler, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        if AFTER_COMMIT.try_with(|_| ()).is_ok() {
+            return self.call_recorded(handler, context, args).await;
+        }
+
+        let (result, committed) = transaction(self.call_recorded(handler, context, args)).await;
+        let value = result?;
+        if !committed.is_empty() {
+            self.commit(&committed).await?;
+        }
+        Ok(value)
+    }
+
+    async fn call_recorded(&self, handler: &dyn FunctionHandler, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let (result, recorded) = scope(handler.call_with_context(context, args)).await;
+        let value = result?;
+
+        if !recorded.is_empty() {
+            let batch = format!(
+                "{}-{}",
+                chrono::Utc::now().timestamp_millis(),
+                NEXT_BATCH.fetch_add(1, Ordering::Relaxed)
+            );
+            self.persist(&batch, &recorded).await?;
+            self.deliver(&batch, recorded).await;
+        }
+
+        Ok(value)
+    }
+
+    /// Write events emitted after commit outside any query to the outbox table
+    async fn commit(&self, events: &[Event]) -> Result<()> {
+        let db = self.database()?;
+        db.query(format!("INSERT INTO {} $rows RETURN NONE", OUTBOX_TABLE))
+            .bind(("rows", rows(events)?))
+            .await?
+            .check()?;
+        self.committed();
+        Ok(())
+    }
+
+    fn database(&self) -> Result<&Database> {
+        self.db
+            .as_ref()
+            .ok_or_else(|| Error::Event("events emitted after commit need the outbox to have a database".to_string()))
+    }
+
+    /// Wake the relay after a transaction wrote to the outbox table
+    pub(crate) fn committed(&self) {
+        self.committed.notify_one();
+    }
+
+    /// Start emitting the events of the outbox table
+    ///
+    /// The relay emits rows oldest first as soon as a transaction writing
+    /// them commits, and every `interval` in case a notification was missed,
+    /// such as rows left by a previous run. A row is deleted once its event
+    /// has been emitted, so events are delivered at least once; listener
+    /// failures go to the registry's dead-letter sink like any other. A row
+    /// whose event no longer fits its schema is dropped with an error.
+    pub fn start_relay(&self, interval: Duration) -> Result<OutboxRelay> {
+        let db = self.database()?.clone();
+        let events = self.events.clone();
+        let committed = self.committed.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                if let Err(err) = relay(&db, &events).await {
+                    tracing::warn!(error = %err, "outbox relay failed; retrying");
+                }
+                let _ = tokio::time::timeout(interval, committed.notified()).await;
+            }
+        });
+        Ok(OutboxRelay { task })
+    }
+
+    /// Re-deliver batches left pending by calls that did not finish delivery
+    pub async fn recover(&self) -> Result<usize> {
+        let batches = self.pending_batches().await?;
+        let mut delivered = 0;
+
+        for batch in batches {
+            match self.cache.get(&batch_key(&batch)).await? {
+                Some(events) => {
+                    let events: Vec<Event> = serde_json::from_value(events)?;
+                    if self.deliver(&batch, events).await {
+                        delivered += 1;
+                    }
+                }
+                None => self.remove(&batch).await?,
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    async fn persist(&self, batch: &str, events: &[Event]) -> Result<()> {
+        let _guard = self.pending.lock().await;
+        self.cache.set(&batch_key(batch), serde_json::to_value(events)?, None).await?;
+
+        let mut batches = self.pending_batches().await?;
+        batches.push(batch.to_string());
+        self.cache.set(PENDING_KEY, serde_json::to_value(batches)?, None).await
+    }
+
+    async fn deliver(&self, batch: &str, events: Vec<Event>) -> bool {
+        for event in events {
+            if let Err(err) = self.events.emit_quiet(event).await {
+                tracing::warn!(batch, error = %err, "outbox delivery failed; batch left pending");
+                return false;
+            }
+        }
+
+        if let Err(err) = self.remove(batch).await {
+            tracing::warn!(batch, error = %err, "failed to clear delivered outbox batch");
+        }
+        true
+    }
+
+    async fn remove(&self, batch: &str) -> Result<()> {
+        let _guard = self.pending.lock().await;
+        self.cache.delete(&batch_key(batch)).await?;
+
+        let mut batches = self.pending_batches().await?;
+        batches.retain(|pending| pending != batch);
+        self.cache.set(PENDING_KEY, serde_json::to_value(batches)?, None).await
+    }
+
+    async fn pending_batches(&self) -> Result<Vec<String>> {
+        match self.cache.get(PENDING_KEY).await? {
+            Some(batches) => Ok(serde_json::from_value(batches)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+fn batch_key(batch: &str) -> String {
+    format!("sx:outbox:batch:{}", batch)
+}
+
+/// Running outbox relay, stopped when dropped
+pub struct OutboxRelay {
+    task: JoinHandle<()>,
+}
+
+impl OutboxRelay {
+    /// Stop emitting events
+    pub fn stop(self) {}
+}
+
+impl Drop for OutboxRelay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Deserialize)]
+struct OutboxRow {
+    key: String,
+    event: Event,
+}
+
+/// Emit the rows of the outbox table until it is empty
+async fn relay(db: &Database, events: &EventRegistry) -> Result<()> {
+    loop {
+        let mut response = db
+            .query("SELECT key, event, created_at FROM type::table($table) ORDER BY created_at, key LIMIT $limit")
+            .bind(("table", OUTBOX_TABLE))
+            .bind(("limit", RELAY_BATCH))
+            .await?;
+        let rows = response.take::<surrealdb::Value>(0)?.into_inner().into_json();
+        let rows: Vec<OutboxRow> = serde_json::from_value(rows)?;
+        let count = rows.len();
+
+        for row in rows {
+            match events.emit(row.event).await {
+                Ok(_) => {}
+                Err(Error::Validation(err)) => {
+                    tracing::error!(key = %row.key, error = %err, "dropping outbox event that fails validation");
+                }
+                Err(err) => return Err(err),
+            }
+            db.query("DELETE type::table($table) WHERE key = $key")
+                .bind(("table", OUTBOX_TABLE))
+                .bind(("key", row.key))
+                .await?
+                .check()?;
+        }
+
+        if count < RELAY_BATCH {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Loading modules from dynamic libraries (requires dylib-plugins feature)
+//!
+//! A plugin is a `cdylib` crate that depends on `surrealx` with the
+//! `dylib-plugins` feature, builds a [`Module`] and exports it with
+//! [`export_plugin!`](crate::export_plugin):
+//!
+//! ```rust,ignore
+//! use surrealx::Module;
+//!
+//! fn billing() -> Module {
+//!     Module::new("billing")
+//! }
+//!
+//! surrealx::export_plugin!(billing);
+//! ```
+//!
+//! The macro exports a [`PluginDeclaration`] that the loader checks before
+//! calling into the plugin. Set `ServerConfig::plugin_dir` to load every
+//! plugin in a directory when the server is built.
+//!
+//! # Safety
+//!
+//! `Module` is a Rust type, not a C-compatible one, so the host and plugin
+//! must be built with the same compiler version and the same `surrealx`
+//! version. The loader checks [`ABI_VERSION`] and the `surrealx` version
+//! before calling into the plugin, but cannot detect a compiler mismatch.
+//!
+//! Plugins are never unloaded: their handlers may be referenced for as long
+//! as the process runs, so the library stays mapped until exit.
+
+use std::ffi::{c_char, CStr};
+use std::path::{Path, PathBuf};
+use libloading::{Library, Symbol};
+use crate::module::Module;
+use crate::error::{Error, Result};
+
+/// Version of the [`PluginDeclaration`] layout
+pub const ABI_VERSION: u32 = 2;
+
+/// NUL-terminated `surrealx` version the plugin was built against
+#[doc(hidden)]
+pub const SURREALX_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+/// Entry point a plugin exports as `surrealx_plugin_declaration`
+///
+/// `abi_version` comes first and keeps its place in every version, so the
+/// loader can read it before trusting the rest of the layout.
+#[repr(C)]
+pub struct PluginDeclaration {
+    /// [`ABI_VERSION`] the plugin was built with
+    pub abi_version: u32,
+    /// NUL-terminated `surrealx` version the plugin was built against
+    pub surrealx_version: *const c_char,
+    /// Build the plugin's module; ownership passes to the caller
+    pub module: extern "C" fn() -> *mut Module,
+}
+
+// The declaration only points at static data and code
+unsafe impl Sync for PluginDeclaration {}
+
+/// Export a module constructor as the plugin entry point
+#[macro_export]
+macro_rules! export_plugin {
+    ($constructor:path) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static surrealx_plugin_declaration: $crate::plugin::PluginDeclaration = {
+            extern "C" fn module() -> *mut $crate::Module {
+                let constructor: fn() -> $crate::Module = $constructor;
+                ::std::boxed::Box::into_raw(::std::boxed::Box::new(constructor()))
+            }
+
+            $crate::plugin::PluginDeclaration {
+                abi_version: $crate::plugin::ABI_VERSION,
+                surrealx_version: $crate::plugin::SURREALX_VERSION.as_ptr().cast(),
+                module,
+            }
+        };
+    };
+}
+
+/// Loader for modules built as plugin libraries
+pub struct DynamicModule;
+
+impl DynamicModule {
+    /// Load a module from a plugin library
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initialisation code, and the plugin must
+    /// have been built with [`export_plugin!`](crate::export_plugin) by the
+    /// same compiler and `surrealx` version as the host. See the module docs.
+    pub unsafe fn load(path: impl AsRef<Path>) -> Result<Module> {
+        let path = path.as_ref();
+        let plugin_error = |err: libloading::Error| Error::Plugin(format!("{}: {}", path.display(), err));
+
+        let library = Library::new(path).map_err(plugin_error)?;
+
+        let declaration: Symbol<*const PluginDeclaration> = library
+            .get(b"surrealx_plugin_declaration\0")
+            .map_err(plugin_error)?;
+        let declaration = &**declaration;
+
+        if declaration.abi_version != ABI_VERSION {
+            return Err(Error::Plugin(format!(
+                "{}: plugin ABI version {} does not match host ABI version {}",
+                path.display(),
+                declaration.abi_version,
+                ABI_VERSION
+            )));
+        }
+
+        let version = CStr::from_ptr(declaration.surrealx_version).to_string_lossy();
+        if version != env!("CARGO_PKG_VERSION") {
+            return Err(Error::Plugin(format!(
+                "{}: plugin built against surrealx {} but host is {}",
+                path.display(),
+                version,
+                env!("CARGO_PKG_VERSION")
+            )));
+        }
+
+        let module = (declaration.module)();
+        if module.is_null() {
+            return Err(Error::Plugin(format!("{}: plugin returned no module", path.display())));
+        }
+        let module = *Box::from_raw(module);
+
+        // Handlers point into the library, so it must outlive every registry
+        std::mem::forget(library);
+
+        Ok(module)
+    }
+
+    /// Load every plugin library in a directory, in file name order
+    ///
+    /// Only files with the platform's library extension (`.so`, `.dylib` or
+    /// `.dll`) are loaded; any plugin failing to load fails the whole call.
+    ///
+    /// # Safety
+    ///
+    /// Every library in the directory must meet the requirements of
+    /// [`load`](Self::load).
+    pub unsafe fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Module>> {
+        let dir = dir.as_ref();
+        let dir_error = |err: std::io::Error| Error::Plugin(format!("{}: {}", dir.display(), err));
+
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .map_err(dir_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()
+            .map_err(dir_error)?;
+        paths.retain(|path| {
+            path.is_file() && path.extension().is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION)
+        });
+        paths.sort();
+
+        paths.iter().map(|path| Self::load(path)).collect()
+    }
+}
+
+/// Load a module from a plugin library
+///
+/// # Safety
+///
+/// See [`DynamicModule::load`].
+pub unsafe fn load(path: impl AsRef<Path>) -> Result<Module> {
+    DynamicModule::load(path)
+}
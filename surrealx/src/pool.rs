@@ -0,0 +1,171 @@
+//! Pooled connections to an upstream SurrealDB server
+//!
+//! In [upstream mode](crate::upstream), queries of function contexts and
+//! module contexts are spread over a small pool of connections to the
+//! upstream server, each multiplexing many queries, so a burst of calls
+//! neither waits on a single WebSocket nor opens one per call.
+//!
+//! Connections are opened when first needed, up to `PoolConfig::size`, and
+//! handed out in turn. A background task closes those unused for
+//! `PoolConfig::idle_timeout` and checks the others every
+//! `PoolConfig::health_check_interval`; one that fails the check is closed
+//! and reopened by the next query that needs it.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use crate::db::{self, Database};
+use crate::server::{PoolConfig, ServerConfig};
+use crate::error::{Error, Result};
+
+/// Counters of a connection pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolStats {
+    /// Most connections the pool opens
+    pub size: usize,
+    /// Connections currently open
+    pub open: usize,
+    /// Connections opened, including reconnections
+    pub connects: u64,
+    /// Connections closed after failing a health check
+    pub failed: u64,
+    /// Connections closed after going unused for the idle timeout
+    pub idle_closed: u64,
+}
+
+/// Connections to the upstream server, shared by clones
+#[derive(Clone)]
+pub struct DatabasePool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: ServerConfig,
+    pool: PoolConfig,
+    slots: Vec<Mutex<Slot>>,
+    next: AtomicUsize,
+    connects: AtomicU64,
+    failed: AtomicU64,
+    idle_closed: AtomicU64,
+    health_task: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    db: Option<Database>,
+    last_used: Option<Instant>,
+}
+
+impl DatabasePool {
+    /// Pool connections to the server `config` names, as configured by its
+    /// `upstream.pool`, and start checking them
+    pub(crate) fn new(config: ServerConfig) -> Result<Self> {
+        let pool = config
+            .upstream
+            .as_ref()
+            .map(|upstream| upstream.pool)
+            .ok_or_else(|| Error::Config("a connection pool requires an upstream server".to_string()))?;
+        if pool.size == 0 {
+            return Err(Error::Config("the upstream pool size must be at least 1".to_string()));
+        }
+
+        let inner = Arc::new(Inner {
+            config,
+            pool,
+            slots: (0..pool.size).map(|_| Mutex::new(Slot::default())).collect(),
+            next: AtomicUsize::new(0),
+            connects: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            idle_closed: AtomicU64::new(0),
+            health_task: std::sync::Mutex::new(None),
+        });
+        let task = tokio::spawn(check_health(Arc::downgrade(&inner)));
+        *inner.health_task.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(task);
+        Ok(Self { inner })
+    }
+
+    /// A connection for the next query, opened if its slot has none
+    pub async fn get(&self) -> Result<Database> {
+        let index = self.inner.next.fetch_add(1, Ordering::Relaxed) % self.inner.slots.len();
+        let mut slot = self.inner.slots[index].lock().await;
+        let db = match &slot.db {
+            Some(db) => db.clone(),
+            None => {
+                let db = db::connect(&self.inner.config).await?;
+                self.inner.connects.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(slot = index, "opened upstream connection");
+                slot.db = Some(db.clone());
+                db
+            }
+        };
+        slot.last_used = Some(Instant::now());
+        Ok(db)
+    }
+
+    /// Current counters of the pool
+    pub fn stats(&self) -> PoolStats {
+        let open = self
+            .inner
+            .slots
+            .iter()
+            .filter(|slot| slot.try_lock().map_or(true, |slot| slot.db.is_some()))
+            .count();
+        PoolStats {
+            size: self.inner.pool.size,
+            open,
+            connects: self.inner.connects.load(Ordering::Relaxed),
+            failed: self.inner.failed.load(Ordering::Relaxed),
+            idle_closed: self.inner.idle_closed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let task = self.health_task.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(task) = task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Close idle and broken connections every health check interval, until
+/// the pool is dropped
+async fn check_health(inner: Weak<Inner>) {
+    let Some(interval) = inner.upgrade().map(|inner| inner.pool.health_check_interval) else {
+        return;
+    };
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+        for (index, slot) in inner.slots.iter().enumerate() {
+            let mut slot = slot.lock().await;
+            let Some(db) = slot.db.clone() else {
+                continue;
+            };
+            if slot.last_used.is_some_and(|used| used.elapsed() >= inner.pool.idle_timeout) {
+                slot.db = None;
+                inner.idle_closed.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(slot = index, "closed idle upstream connection");
+                continue;
+            }
+            let healthy = match tokio::time::timeout(interval, db.health()).await {
+                Ok(Ok(())) => Ok(()),
+                Ok(Err(err)) => Err(err.to_string()),
+                Err(_) => Err("timed out".to_string()),
+            };
+            if let Err(err) = healthy {
+                slot.db = None;
+                inner.failed.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(slot = index, error = %err, "upstream connection failed its health check; reconnecting on next use");
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Bounded queue between emitting events and delivering them
+//!
+//! With a queue attached, [`EventRegistry::emit`] validates, records and
+//! publishes an event, then leaves it in the queue for a background task to
+//! deliver to listeners in order. A slow listener then holds up the queue
+//! instead of the writes emitting events, such as the change feed, until
+//! the queue is full and its [`OverflowPolicy`] applies.
+//!
+//! [`EventRegistry::emit`]: crate::EventRegistry::emit
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use crate::events::Event;
+use crate::error::{Error, Result};
+
+/// What emitting an event does when the queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait for room, slowing the emitter down to the listeners' pace
+    #[default]
+    Block,
+    /// Discard the oldest queued event to make room
+    DropOldest,
+    /// Discard the event being emitted
+    DropNewest,
+    /// Fail the emit with [`Error::Event`]
+    Error,
+}
+
+/// Size of the event queue and what happens when it fills up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventQueueConfig {
+    /// Events that may wait for delivery
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for EventQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Counters of an event queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueStats {
+    /// Events waiting for delivery
+    pub depth: usize,
+    pub capacity: usize,
+    /// Events accepted into the queue
+    pub enqueued: u64,
+    /// Events discarded because the queue was full
+    pub dropped: u64,
+}
+
+/// Queue of events waiting for delivery, shared by clones of a registry
+#[derive(Clone)]
+pub(crate) struct EventQueue {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    events: Mutex<VecDeque<Event>>,
+    config: EventQueueConfig,
+    /// Signalled when an event is queued
+    ready: Notify,
+    /// Signalled when an event leaves the queue
+    space: Notify,
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl EventQueue {
+    pub(crate) fn new(config: EventQueueConfig) -> Self {
+        let config = EventQueueConfig {
+            capacity: config.capacity.max(1),
+            ..config
+        };
+        Self {
+            inner: Arc::new(Inner {
+                events: Mutex::new(VecDeque::new()),
+                config,
+                ready: Notify::new(),
+                space: Notify::new(),
+                enqueued: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Queue `event`, applying the overflow policy if the queue is full
+    pub(crate) async fn push(&self, event: Event) -> Result<()> {
+        let inner = &self.inner;
+        loop {
+            {
+                let mut events = inner.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                if events.len() >= inner.config.capacity {
+                    match inner.config.overflow {
+                        OverflowPolicy::Block => {}
+                        OverflowPolicy::DropOldest => {
+                            if let Some(oldest) = events.pop_front() {
+                                inner.dropped.fetch_add(1, Ordering::Relaxed);
+                                tracing::debug!(pattern = %oldest.pattern(), "event queue full; dropping oldest event");
+                            }
+                        }
+                        OverflowPolicy::DropNewest => {
+                            inner.dropped.fetch_add(1, Ordering::Relaxed);
+                            tracing::debug!(pattern = %event.pattern(), "event queue full; dropping event");
+                            return Ok(());
+                        }
+                        OverflowPolicy::Error => {
+                            return Err(Error::Event(format!(
+                                "event queue is full ({} events)",
+                                inner.config.capacity
+                            )));
+                        }
+                    }
+                }
+                if events.len() < inner.config.capacity {
+                    events.push_back(event);
+                    inner.enqueued.fetch_add(1, Ordering::Relaxed);
+                    inner.ready.notify_one();
+                    return Ok(());
+                }
+            }
+            inner.space.notified().await;
+        }
+    }
+
+    /// Take the oldest event, waiting for one if the queue is empty
+    pub(crate) async fn pop(&self) -> Event {
+        let inner = &self.inner;
+        loop {
+            let next = inner
+                .events
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .pop_front();
+            if let Some(event) = next {
+                inner.space.notify_one();
+                return event;
+            }
+            inner.ready.notified().await;
+        }
+    }
+
+    pub(crate) fn stats(&self) -> QueueStats {
+        let inner = &self.inner;
+        QueueStats {
+            depth: inner.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len(),
+            capacity: inner.config.capacity,
+            enqueued: inner.enqueued.load(Ordering::Relaxed),
+            dropped: inner.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,449 @@
+//! Token bucket rate limiting
+//!
+//! Besides being used directly, limiters can be attached to a server's
+//! functions with [`SurrealX::with_function_rate_limit`] and to its HTTP
+//! routes with [`SurrealX::with_route_rate_limit`]. Calls over the limit fail
+//! with [`Error::RateLimited`]; requests over it get `429 Too Many Requests`
+//! with a `Retry-After` header.
+//!
+//! [`SurrealX::with_function_rate_limit`]: crate::SurrealX::with_function_rate_limit
+//! [`SurrealX::with_route_rate_limit`]: crate::SurrealX::with_route_rate_limit
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use crate::auth::Identity;
+use crate::cache::CacheProvider;
+use crate::context::FunctionContext;
+use crate::functions::{FunctionHandler, StreamingFunctionHandler, ValueStream};
+use crate::error::{Error, Result};
+
+/// Token bucket rate limiter keyed by caller-defined strings
+///
+/// Buckets live in process memory by default; those full again are dropped
+/// as the map grows. With a cache provider attached, several instances share
+/// the same limits: each bucket is approximated by a sliding window of
+/// `capacity / refill_per_second` seconds admitting `capacity` tokens,
+/// counted with the cache's atomic [`incr`](CacheProvider::incr), so
+/// concurrent requests on any instance cannot all take the last token.
+#[derive(Clone)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_second: f64,
+    backend: Backend,
+    /// Switch to the server's cache provider when attached to a server
+    server_cache: bool,
+    per_identity: bool,
+}
+
+#[derive(Clone)]
+enum Backend {
+    Local(Arc<Mutex<LocalBuckets>>),
+    Cache(Arc<dyn CacheProvider>),
+}
+
+/// Buckets kept in process memory
+struct LocalBuckets {
+    buckets: HashMap<String, Bucket>,
+    /// Size at which full buckets are next dropped
+    sweep_at: usize,
+}
+
+/// Buckets the local map keeps before dropping full ones
+const LOCAL_SWEEP: usize = 1024;
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated_at: i64,
+}
+
+impl RateLimiter {
+    /// Create a limiter holding up to `capacity` tokens, refilled at
+    /// `refill_per_second` tokens per second
+    pub fn new(capacity: u32, refill_per_second: f64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            refill_per_second,
+            backend: Backend::Local(Arc::new(Mutex::new(LocalBuckets {
+                buckets: HashMap::new(),
+                sweep_at: LOCAL_SWEEP,
+            }))),
+            server_cache: false,
+            per_identity: false,
+        }
+    }
+
+    /// Store buckets in a cache provider to share limits across instances
+    pub fn with_cache(mut self, cache: Arc<dyn CacheProvider>) -> Self {
+        self.backend = Backend::Cache(cache);
+        self
+    }
+
+    /// Store buckets in the cache provider of the server the limiter is
+    /// attached to, whichever `ServerConfig::cache` selects
+    ///
+    /// Until it is attached, or when used directly, buckets stay in memory.
+    pub fn with_server_cache(mut self) -> Self {
+        self.server_cache = true;
+        self
+    }
+
+    /// Give each caller a bucket of its own when attached to a server
+    ///
+    /// Callers are told apart by [`Identity::subject`], the record or
+    /// user of their token; unauthenticated callers share one bucket.
+    pub fn per_identity(mut self) -> Self {
+        self.per_identity = true;
+        self
+    }
+
+    /// Take `cost` tokens from the bucket for `key`, failing with
+    /// [`Error::RateLimited`] if they are not available
+    pub async fn check(&self, key: &str, cost: u32) -> Result<()> {
+        match self.take(key, cost).await? {
+            None => Ok(()),
+            Some(retry_after) => Err(Error::RateLimited {
+                key: key.to_string(),
+                retry_after,
+            }),
+        }
+    }
+
+    /// Take `cost` tokens from the bucket for `key` if they are available
+    pub async fn try_acquire(&self, key: &str, cost: u32) -> Result<bool> {
+        Ok(self.take(key, cost).await?.is_none())
+    }
+
+    /// Wait until `cost` tokens are available for `key`, then take them
+    pub async fn acquire(&self, key: &str, cost: u32) -> Result<()> {
+        while let Some(wait) = self.take(key, cost).await? {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    /// Try to take tokens, returning how long to wait if there are not enough
+    async fn take(&self, key: &str, cost: u32) -> Result<Option<Duration>> {
+        let cost = cost as f64;
+        if self.refill_per_second.is_nan() || self.refill_per_second <= 0.0 {
+            return Err(Error::Config(format!(
+                "rate limit refill rate must be positive, got {}",
+                self.refill_per_second
+            )));
+        }
+        if cost > self.capacity {
+            return Err(Error::Config(format!(
+                "rate limit cost {} exceeds bucket capacity {}",
+                cost, self.capacity
+            )));
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        match &self.backend {
+            Backend::Local(local) => {
+                let mut local = local.lock().await;
+                let bucket = local.buckets.entry(key.to_string()).or_insert_with(|| self.full(now));
+                let wait = self.consume(bucket, cost, now);
+                if local.buckets.len() >= local.sweep_at {
+                    self.sweep(&mut local, now);
+                }
+                Ok(wait)
+            }
+            Backend::Cache(cache) => self.take_shared(cache.as_ref(), key, cost, now).await,
+        }
+    }
+
+    /// Take tokens from the window counters of `key` in `cache`
+    ///
+    /// The window is the time a bucket takes to refill. Tokens taken in the
+    /// previous window count for the share of it still inside a window
+    /// ending now. Tokens are added before checking and given back if they
+    /// were over the limit, so concurrent callers never both get the last
+    /// one.
+    async fn take_shared(&self, cache: &dyn CacheProvider, key: &str, cost: f64, now: i64) -> Result<Option<Duration>> {
+        let window = ((self.capacity / self.refill_per_second) * 1000.0).ceil().max(1.0) as i64;
+        let slot = now / window;
+        let current_key = format!("sx:ratelimit:{}:{}", key, slot);
+        let previous_key = format!("sx:ratelimit:{}:{}", key, slot - 1);
+        // Counters outlive the window after theirs, then expire
+        let ttl = (2 * window / 1000 + 1) as u64;
+
+        cache.set_if_absent(&current_key, Value::from(0), Some(ttl)).await?;
+        let taken = cache.incr(&current_key, cost as i64).await? as f64;
+        let previous = match cache.get(&previous_key).await? {
+            Some(value) => value.as_f64().unwrap_or_default(),
+            None => 0.0,
+        };
+        let elapsed = (now - slot * window) as f64 / window as f64;
+        let estimate = previous * (1.0 - elapsed) + taken;
+        if estimate <= self.capacity {
+            return Ok(None);
+        }
+
+        cache.incr(&current_key, -(cost as i64)).await?;
+        let remaining = (window - (now - slot * window)) as f64;
+        let wait = if previous > 0.0 {
+            // Until the previous window's share has shrunk enough
+            ((estimate - self.capacity) * window as f64 / previous).min(remaining)
+        } else {
+            remaining
+        };
+        Ok(Some(Duration::from_millis(wait.ceil() as u64)))
+    }
+
+    /// Drop the buckets that have refilled, since a new bucket starts full
+    fn sweep(&self, local: &mut LocalBuckets, now: i64) {
+        let refill_ms = self.capacity / self.refill_per_second * 1000.0;
+        local
+            .buckets
+            .retain(|_, bucket| ((now - bucket.updated_at) as f64) < refill_ms);
+        local.sweep_at = (local.buckets.len() * 2).max(LOCAL_SWEEP);
+    }
+
+    fn full(&self, now: i64) -> Bucket {
+        Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        }
+    }
+
+    fn consume(&self, bucket: &mut Bucket, cost: f64, now: i64) -> Option<Duration> {
+        let elapsed = (now - bucket.updated_at).max(0) as f64 / 1000.0;
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            None
+        } else {
+            let missing = cost - bucket.tokens;
+            Some(Duration::from_secs_f64(missing / self.refill_per_second))
+        }
+    }
+
+    /// Use `cache` if the limiter was told to share the server's
+    pub(crate) fn attach(&self, cache: &Arc<dyn CacheProvider>) -> Self {
+        let mut limiter = self.clone();
+        if limiter.server_cache {
+            limiter.backend = Backend::Cache(cache.clone());
+        }
+        limiter
+    }
+
+    /// Key of the bucket for `name`, per caller if the limiter is per identity
+    fn key(&self, name: &str, identity: Option<&Identity>) -> String {
+        if !self.per_identity {
+            return name.to_string();
+        }
+        match identity.and_then(Identity::subject) {
+            Some(caller) => format!("{}:{}", name, caller),
+            None => format!("{}:anonymous", name),
+        }
+    }
+}
+
+/// Limiters attached to a server's functions and routes
+#[derive(Clone, Default)]
+pub(crate) struct RateLimits {
+    /// By fully qualified function name
+    pub(crate) functions: HashMap<String, RateLimiter>,
+    /// By path prefix, in registration order
+    pub(crate) routes: Vec<(String, RateLimiter)>,
+}
+
+impl RateLimits {
+    /// Move the limiters sharing the server's cache onto `cache`
+    pub(crate) fn attach(&self, cache: &Arc<dyn CacheProvider>) -> Self {
+        Self {
+            functions: self
+                .functions
+                .iter()
+                .map(|(function, limiter)| (function.clone(), limiter.attach(cache)))
+                .collect(),
+            routes: self
+                .routes
+                .iter()
+                .map(|(prefix, limiter)| (prefix.clone(), limiter.attach(cache)))
+                .collect(),
+        }
+    }
+}
+
+/// Function handler that takes a token from its limiter for every call
+pub(crate) struct RateLimitedFunction {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) limiter: RateLimiter,
+    pub(crate) function: String,
+}
+
+#[async_trait]
+impl FunctionHandler for RateLimitedFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        self.limiter.check(&self.limiter.key(&format!("fn:{}", self.function), None), 1).await?;
+        self.inner.call(args).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let key = self.limiter.key(&format!("fn:{}", self.function), context.identity());
+        self.limiter.check(&key, 1).await?;
+        self.inner.call_with_context(context, args).await
+    }
+}
+
+/// Streaming function handler that takes a token from its limiter for every
+/// call
+pub(crate) struct RateLimitedStream {
+    pub(crate) inner: Arc<dyn StreamingFunctionHandler>,
+    pub(crate) limiter: RateLimiter,
+    pub(crate) function: String,
+}
+
+#[async_trait]
+impl StreamingFunctionHandler for RateLimitedStream {
+    async fn call_stream(&self, context: FunctionContext, args: Vec<Value>) -> Result<ValueStream> {
+        let key = self.limiter.key(&format!("fn:{}", self.function), context.identity());
+        self.limiter.check(&key, 1).await?;
+        self.inner.call_stream(context, args).await
+    }
+}
+
+/// Limit the requests `router` serves under each path prefix of `routes`
+pub(crate) fn limit_routes(router: Router, routes: Vec<(String, RateLimiter)>) -> Router {
+    if routes.is_empty() {
+        return router;
+    }
+    router.layer(middleware::from_fn_with_state(Arc::new(routes), limit_request))
+}
+
+async fn limit_request(
+    State(routes): State<Arc<Vec<(String, RateLimiter)>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // Owned, since the request body is not `Sync` and must not be borrowed
+    // across the checks
+    let path = request.uri().path().to_string();
+    let identity = request.extensions().get::<Identity>().cloned();
+    for (prefix, limiter) in routes.iter().filter(|(prefix, _)| path.starts_with(prefix.as_str())) {
+        let key = limiter.key(&format!("route:{}", prefix), identity.as_ref());
+        match limiter.check(&key, 1).await {
+            Ok(()) => {}
+            Err(Error::RateLimited { retry_after, .. }) => return too_many_requests(retry_after),
+            Err(err) => {
+                // Fail open: an unreachable cache should not take the routes down
+                tracing::warn!(error = %err, prefix = %prefix, "rate limiter failed; admitting request");
+            }
+        }
+    }
+    next.run(request).await
+}
+
+/// `429 Too Many Requests`, telling the caller when to retry
+pub(crate) fn too_many_requests(retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs_f64().ceil() as u64;
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(json!({ "error": "rate limit exceeded", "retry_after": seconds })),
+    )
+        .into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryCacheProvider;
+
+    #[test]
+    fn bucket_refills_at_its_rate_up_to_capacity() {
+        let limiter = RateLimiter::new(2, 1.0);
+        let mut bucket = limiter.full(0);
+
+        assert_eq!(limiter.consume(&mut bucket, 2.0, 0), None);
+        assert_eq!(limiter.consume(&mut bucket, 1.0, 500), Some(Duration::from_millis(500)));
+        assert_eq!(limiter.consume(&mut bucket, 1.0, 1000), None);
+        // Idle time past a full refill does not add tokens beyond capacity
+        assert_eq!(limiter.consume(&mut bucket, 2.0, 60_000), None);
+        assert_eq!(limiter.consume(&mut bucket, 1.0, 60_000), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn sweep_drops_only_refilled_buckets() {
+        let limiter = RateLimiter::new(10, 1.0);
+        let mut local = LocalBuckets {
+            buckets: HashMap::from([
+                ("idle".to_string(), Bucket { tokens: 0.0, updated_at: 0 }),
+                ("busy".to_string(), Bucket { tokens: 0.0, updated_at: 5_000 }),
+            ]),
+            sweep_at: 2,
+        };
+
+        limiter.sweep(&mut local, 10_000);
+        assert!(local.buckets.contains_key("busy"));
+        assert!(!local.buckets.contains_key("idle"));
+        assert_eq!(local.sweep_at, LOCAL_SWEEP);
+    }
+
+    #[tokio::test]
+    async fn rejects_costs_over_capacity_and_bad_rates() {
+        assert!(matches!(RateLimiter::new(2, 1.0).take("k", 3).await, Err(Error::Config(_))));
+        assert!(matches!(RateLimiter::new(2, 0.0).take("k", 1).await, Err(Error::Config(_))));
+        assert!(matches!(RateLimiter::new(2, f64::NAN).take("k", 1).await, Err(Error::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn shared_window_counts_the_previous_window_by_overlap() -> Result<()> {
+        // A one second window
+        let limiter = RateLimiter::new(10, 10.0);
+        let cache = MemoryCacheProvider::new();
+
+        assert_eq!(limiter.take_shared(&cache, "k", 10.0, 5_000).await?, None);
+        assert_eq!(limiter.take_shared(&cache, "k", 1.0, 5_000).await?, Some(Duration::from_secs(1)));
+        // The rejected token was given back
+        assert_eq!(cache.get("sx:ratelimit:k:5").await?, Some(Value::from(10)));
+
+        // Halfway through the next window, half of the previous one counts
+        assert_eq!(limiter.take_shared(&cache, "k", 5.0, 6_500).await?, None);
+        assert_eq!(
+            limiter.take_shared(&cache, "k", 1.0, 6_500).await?,
+            Some(Duration::from_millis(100))
+        );
+        Ok(())
+    }
+
+    fn caller(claims: Value) -> Identity {
+        Identity {
+            namespace: None,
+            database: None,
+            access: None,
+            record: claims.get("ID").and_then(Value::as_str).map(str::to_string),
+            roles: Vec::new(),
+            claims,
+        }
+    }
+
+    #[test]
+    fn keys_buckets_by_caller_when_per_identity() {
+        let record = caller(json!({ "ID": "user:tobie", "sub": "tobie" }));
+        let system = caller(json!({ "sub": "root" }));
+        let limiter = RateLimiter::new(10, 1.0).per_identity();
+        assert_eq!(limiter.key("orders::create", Some(&record)), "orders::create:user:tobie");
+        assert_eq!(limiter.key("orders::create", Some(&system)), "orders::create:root");
+        assert_eq!(limiter.key("orders::create", None), "orders::create:anonymous");
+
+        let shared = RateLimiter::new(10, 1.0);
+        assert_eq!(shared.key("orders::create", Some(&record)), "orders::create");
+    }
+}
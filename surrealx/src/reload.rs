@@ -0,0 +1,356 @@
+//! Reloading modules from a directory while the server runs
+//!
+//! See [`SurrealX::with_hot_reload`](crate::SurrealX::with_hot_reload). The
+//! directory is polled for changes; every reload loads all of its modules
+//! again and replaces the previous generation as a whole.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use axum::extract::Request;
+use axum::Router;
+use serde_json::Value;
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+use crate::config::ConfigSource;
+use crate::context::FunctionContext;
+use crate::events::{Event, EventListener, ListenerId, Propagation};
+use crate::functions::{FunctionHandler, FunctionRegistry, InFlight};
+use crate::module::{Module, ModuleHealth};
+use crate::server::{self, ModuleFailure, ModuleLifecycle, SurrealX};
+use crate::error::{Error, Result};
+
+/// How often the directory is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a replaced generation's in-flight calls may take to finish
+/// before its modules are shut down anyway
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Path, modification time and size of a file in the directory
+type FileStamp = (PathBuf, Option<SystemTime>, u64);
+
+/// Loads the modules of a directory and swaps them in whenever it changes
+pub(crate) struct Reloader {
+    dir: PathBuf,
+    template: SurrealX,
+    functions: FunctionRegistry,
+    function_context: FunctionContext,
+    current: ArcSwap<Generation>,
+    /// Serializes reloads; holds the files of the last reload that failed,
+    /// so a broken directory is not retried until it changes again
+    reloading: tokio::sync::Mutex<Option<Vec<FileStamp>>>,
+    watcher: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Modules loaded from one state of the directory
+struct Generation {
+    files: Vec<FileStamp>,
+    listener_ids: Vec<ListenerId>,
+    router: Router,
+    lifecycles: Vec<ModuleLifecycle>,
+    in_flight: InFlight,
+}
+
+impl Generation {
+    fn empty() -> Self {
+        Self {
+            files: Vec::new(),
+            listener_ids: Vec::new(),
+            router: Router::new(),
+            lifecycles: Vec::new(),
+            in_flight: InFlight::default(),
+        }
+    }
+}
+
+impl Reloader {
+    /// `template` is a server without modules whose configuration and
+    /// registries reloaded modules are registered against
+    pub(crate) fn new(
+        dir: PathBuf,
+        template: SurrealX,
+        functions: FunctionRegistry,
+        function_context: FunctionContext,
+    ) -> Self {
+        Self {
+            dir,
+            template,
+            functions,
+            function_context,
+            current: ArcSwap::from_pointee(Generation::empty()),
+            reloading: tokio::sync::Mutex::new(None),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    /// Load the directory and watch it for changes
+    pub(crate) async fn start(self: &Arc<Self>) -> Result<()> {
+        self.reload().await?;
+
+        // Hold a weak reference so dropping the server stops the watcher
+        let reloader = Arc::downgrade(self);
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(reloader) = reloader.upgrade() else {
+                    break;
+                };
+                if let Err(err) = reloader.reload().await {
+                    tracing::error!(
+                        dir = %reloader.dir.display(),
+                        error = %err,
+                        "hot reload failed; keeping the loaded modules"
+                    );
+                }
+            }
+        });
+
+        let mut watcher = self.watcher.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(previous) = watcher.replace(task) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    /// Stop watching and unload the current modules
+    pub(crate) async fn stop(&self) -> Vec<ModuleFailure> {
+        if let Some(watcher) = self.watcher.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            watcher.abort();
+        }
+
+        let _reloading = self.reloading.lock().await;
+        let previous = self.current.load_full();
+        if let Err(err) = self.function_context.events().replace(&previous.listener_ids, Vec::new()).await {
+            tracing::error!(error = %err, "failed to remove hot-reloaded listeners");
+        }
+        self.functions.swap_reloadable(Default::default());
+        let previous = self.current.swap(Arc::new(Generation::empty()));
+
+        drain(&previous.in_flight).await;
+        server::shut_down_modules(&previous.lifecycles).await
+    }
+
+    /// Replace the loaded modules if the directory changed
+    ///
+    /// The new modules are validated and started before anything is swapped,
+    /// so a failure leaves the current ones in place.
+    pub(crate) async fn reload(&self) -> Result<bool> {
+        let mut failed = self.reloading.lock().await;
+        let files = scan(&self.dir)?;
+        if files == self.current.load().files || failed.as_ref() == Some(&files) {
+            return Ok(false);
+        }
+
+        match self.replace(files.clone()).await {
+            Ok(()) => {
+                *failed = None;
+                Ok(true)
+            }
+            Err(err) => {
+                *failed = Some(files);
+                Err(err)
+            }
+        }
+    }
+
+    async fn replace(&self, files: Vec<FileStamp>) -> Result<()> {
+        let (modules, sources) = load(&files)?;
+        let staged = self.template.stage(modules, sources, &self.function_context)?;
+        server::start_modules(&staged.lifecycles).await?;
+
+        let in_flight = InFlight::default();
+        let functions = staged
+            .functions
+            .into_iter()
+            .map(|(name, inner)| {
+                let handler = TrackedFunction {
+                    inner,
+                    in_flight: in_flight.clone(),
+                };
+                (name, Arc::new(handler) as Arc<dyn FunctionHandler>)
+            })
+            .collect();
+        let listeners = staged
+            .listeners
+            .into_iter()
+            .map(|(module, pattern, inner)| {
+                let listener = TrackedListener {
+                    inner,
+                    in_flight: in_flight.clone(),
+                };
+                (module, pattern, Arc::new(listener) as Arc<dyn EventListener>)
+            })
+            .collect();
+
+        let previous = self.current.load_full();
+        let listener_ids = match self
+            .function_context
+            .events()
+            .replace(&previous.listener_ids, listeners)
+            .await
+        {
+            Ok(ids) => ids,
+            Err(err) => {
+                server::shut_down_modules(&staged.lifecycles).await;
+                return Err(err);
+            }
+        };
+        self.functions.swap_reloadable(functions);
+        let previous = self.current.swap(Arc::new(Generation {
+            files,
+            listener_ids,
+            router: staged.router,
+            lifecycles: staged.lifecycles,
+            in_flight,
+        }));
+        tracing::info!(
+            dir = %self.dir.display(),
+            modules = ?staged.report.modules,
+            functions = ?staged.report.functions,
+            "reloaded modules"
+        );
+
+        drain(&previous.in_flight).await;
+        server::shut_down_modules(&previous.lifecycles).await;
+        Ok(())
+    }
+
+    /// Health of the currently loaded modules
+    pub(crate) async fn health(&self) -> Vec<ModuleHealth> {
+        let current = self.current.load_full();
+        server::check_modules(&current.lifecycles).await
+    }
+}
+
+impl Drop for Reloader {
+    fn drop(&mut self) {
+        if let Some(watcher) = self.watcher.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            watcher.abort();
+        }
+    }
+}
+
+/// Serve requests no other route matched from the current generation's routes
+pub(crate) fn fallback(router: Router, reloader: Arc<Reloader>) -> Router {
+    router.fallback(move |request: Request| async move {
+        let generation = reloader.current.load_full();
+        let _in_flight = generation.in_flight.enter();
+        match generation.router.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        }
+    })
+}
+
+/// Wait until a generation's in-flight calls finish, up to `DRAIN_TIMEOUT`
+async fn drain(in_flight: &InFlight) {
+    if !in_flight.drain(DRAIN_TIMEOUT).await {
+        tracing::warn!(
+            in_flight = in_flight.count(),
+            "in-flight calls did not finish in time; unloading modules anyway"
+        );
+    }
+}
+
+/// What a file in the directory is loaded as
+enum Entry {
+    Config,
+    #[cfg(feature = "wasm-plugins")]
+    Wasm,
+    #[cfg(feature = "dylib-plugins")]
+    Library,
+}
+
+fn classify(path: &Path) -> Option<Entry> {
+    let extension = path.extension()?.to_str()?;
+    match extension {
+        "toml" | "json" => Some(Entry::Config),
+        #[cfg(feature = "wasm-plugins")]
+        "wasm" => Some(Entry::Wasm),
+        #[cfg(feature = "dylib-plugins")]
+        extension if extension == std::env::consts::DLL_EXTENSION => Some(Entry::Library),
+        _ => None,
+    }
+}
+
+/// List the loadable files of the directory, sorted by path
+fn scan(dir: &Path) -> Result<Vec<FileStamp>> {
+    let dir_error = |err: std::io::Error| Error::Config(format!("{}: {}", dir.display(), err));
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(dir_error)? {
+        let path = entry.map_err(dir_error)?.path();
+        if classify(&path).is_none() {
+            continue;
+        }
+        let metadata = std::fs::metadata(&path).map_err(dir_error)?;
+        if metadata.is_file() {
+            files.push((path, metadata.modified().ok(), metadata.len()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Load the modules and configuration sources among `files`
+fn load(files: &[FileStamp]) -> Result<(Vec<Module>, Vec<ConfigSource>)> {
+    let mut modules = Vec::new();
+    let mut sources = Vec::new();
+    for (path, _, _) in files {
+        match classify(path) {
+            Some(Entry::Config) => sources.push(ConfigSource::file(path)),
+            #[cfg(feature = "wasm-plugins")]
+            Some(Entry::Wasm) => modules.push(crate::wasm::WasmModule::load(path)?),
+            // SAFETY: the directory is trusted as documented on `with_hot_reload`
+            #[cfg(feature = "dylib-plugins")]
+            Some(Entry::Library) => modules.push(unsafe { crate::plugin::DynamicModule::load(path)? }),
+            None => {}
+        }
+    }
+    Ok((modules, sources))
+}
+
+struct TrackedFunction {
+    inner: Arc<dyn FunctionHandler>,
+    in_flight: InFlight,
+}
+
+#[async_trait]
+impl FunctionHandler for TrackedFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        let _in_flight = self.in_flight.enter();
+        self.inner.call(args).await
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let _in_flight = self.in_flight.enter();
+        self.inner.call_with_context(context, args).await
+    }
+}
+
+struct TrackedListener {
+    inner: Arc<dyn EventListener>,
+    in_flight: InFlight,
+}
+
+#[async_trait]
+impl EventListener for TrackedListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.handle(event).await.map(|_| ())
+    }
+
+    async fn handle(&self, event: Event) -> Result<Propagation> {
+        let _in_flight = self.in_flight.enter();
+        self.inner.handle(event).await
+    }
+
+    fn priority(&self) -> i32 {
+        self.inner.priority()
+    }
+}
@@ -0,0 +1,177 @@
+//! REST endpoints calling registered functions
+//!
+//! With [`SurrealX::expose_functions_over_http`] enabled, `POST /sx/fn/{name}`
+//! calls the function `name`, such as `/sx/fn/ext::business::calculate_tax`,
+//! with the JSON array in the request body as its arguments and answers its
+//! result as JSON. An empty body calls it without arguments, and a body that
+//! is not an array is a single argument.
+//!
+//! The routes are authenticated and rate limited like module routes, and
+//! calls go through the same role guards, function rate limits and outbox
+//! as calls from `/sql`, with the request ID taken from `X-Request-Id` and
+//! the idempotency key, for [idempotent](crate::FunctionOpts::idempotent)
+//! functions, from `Idempotency-Key`.
+//! Failures answer `{"error": ...}` with the status of the error: `404` for
+//! an unknown function, `401`, `403` or `429` when the caller is refused,
+//! `400` when the function rejects its arguments and `500` otherwise.
+//!
+//! A request accepting `application/x-ndjson` receives the values of a
+//! [streaming function](crate::StreamingFunctionHandler) as they are
+//! produced, one JSON document per line, and other functions' result as a
+//! single line. An error after the first line is sent as a last
+//! `{"error": ...}` line, since the status is already sent.
+//!
+//! [`SurrealX::expose_functions_over_http`]: crate::SurrealX::expose_functions_over_http
+
+use std::convert::Infallible;
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures::stream::{self, StreamExt};
+use serde_json::{json, Value};
+use crate::auth::Identity;
+use crate::context::FunctionContext;
+use crate::functions::{FunctionRegistry, ValueStream};
+use crate::outbox::Outbox;
+use crate::ratelimit;
+use crate::tenancy::Tenant;
+use crate::error::{Error, Result};
+
+const NDJSON: &str = "application/x-ndjson";
+
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Functions and what their calls run with
+#[derive(Clone)]
+pub(crate) struct Functions {
+    registry: FunctionRegistry,
+    outbox: Outbox,
+    pub(crate) context: FunctionContext,
+}
+
+impl Functions {
+    pub(crate) fn new(registry: FunctionRegistry, outbox: Outbox, context: FunctionContext) -> Self {
+        Self {
+            registry,
+            outbox,
+            context,
+        }
+    }
+
+    /// Call the function `name` through the outbox
+    pub(crate) async fn call(&self, name: &str, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let handler = self
+            .registry
+            .get(name)
+            .ok_or_else(|| Error::NotFound(format!("function '{}'", name)))?;
+        self.outbox.call(handler.as_ref(), context, args).await
+    }
+
+    /// Call the function `name` as a stream: the values of a streaming
+    /// function, or the result of any other as a single value
+    pub(crate) async fn stream(&self, name: &str, context: FunctionContext, args: Vec<Value>) -> Result<ValueStream> {
+        if let Some(handler) = self.registry.get_stream(name) {
+            return handler.call_stream(context, args).await;
+        }
+        let result = self.call(name, context, args).await?;
+        Ok(stream::once(async move { Ok(result) }).boxed())
+    }
+}
+
+/// Router exposing `POST /sx/fn/:name`
+pub(crate) fn router(registry: FunctionRegistry, outbox: Outbox, context: FunctionContext) -> Router {
+    Router::new()
+        .route("/sx/fn/:name", post(call_handler))
+        .with_state(Functions::new(registry, outbox, context))
+}
+
+async fn call_handler(
+    State(functions): State<Functions>,
+    Path(name): Path<String>,
+    identity: Option<Identity>,
+    tenant: Option<Tenant>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request_id = headers
+        .get(crate::telemetry::REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut context = functions.context.for_request(request_id);
+    if let Some(identity) = identity {
+        context = context.with_identity(identity);
+    }
+    if let Some(tenant) = tenant {
+        context = context.with_tenant(tenant);
+    }
+    if let Some(key) = headers.get(IDEMPOTENCY_KEY).and_then(|value| value.to_str().ok()) {
+        context = context.with_idempotency_key(key);
+    }
+
+    let streaming = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains(NDJSON));
+    let args = match arguments(&body) {
+        Ok(args) => args,
+        Err(err) => return failure(err),
+    };
+    if streaming {
+        return match functions.stream(&name, context, args).await {
+            Ok(values) => ([(header::CONTENT_TYPE, NDJSON)], Body::from_stream(lines(values))).into_response(),
+            Err(err) => failure(err),
+        };
+    }
+
+    match functions.call(&name, context, args).await {
+        Ok(result) => Json(result).into_response(),
+        Err(err) => failure(err),
+    }
+}
+
+fn failure(err: Error) -> Response {
+    if let Error::RateLimited { retry_after, .. } = err {
+        return ratelimit::too_many_requests(retry_after);
+    }
+    let status = match err {
+        Error::NotFound(_) => StatusCode::NOT_FOUND,
+        Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+        Error::Forbidden { .. } => StatusCode::FORBIDDEN,
+        Error::ModuleDisabled(_) | Error::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        Error::Function(_) | Error::Validation(_) | Error::Serialization(_) | Error::InvalidArguments { .. } => {
+            StatusCode::BAD_REQUEST
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let mut body = json!({ "error": err.to_string() });
+    if let Error::InvalidArguments { errors, .. } = &err {
+        body["arguments"] = json!(errors);
+    }
+    (status, Json(body)).into_response()
+}
+
+/// The arguments in a request body
+fn arguments(body: &[u8]) -> Result<Vec<Value>> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Vec::new());
+    }
+    Ok(match serde_json::from_slice(body)? {
+        Value::Array(args) => args,
+        other => vec![other],
+    })
+}
+
+/// One line of JSON per value, ending with an error line if one fails
+fn lines(values: ValueStream) -> impl futures::Stream<Item = std::result::Result<String, Infallible>> {
+    stream::unfold(Some(values), |values| async move {
+        let mut values = values?;
+        let line = match values.next().await? {
+            Ok(value) => return Some((Ok(format!("{}\n", value)), Some(values))),
+            Err(err) => json!({ "error": err.to_string() }),
+        };
+        Some((Ok(format!("{}\n", line)), None))
+    })
+}
@@ -0,0 +1,473 @@
+//! Retrying failed function calls and event listeners
+//!
+//! A [`RetryPolicy`] says how many attempts an operation gets, how long to
+//! wait between them, with exponential backoff and optional jitter, and
+//! which errors are worth retrying. Modules attach one at registration:
+//!
+//! - to a function with `Module::with_function_retry`, or
+//!   [`FunctionOpts::with_retry`](crate::FunctionOpts::with_retry);
+//! - to the listeners of a pattern with `Module::with_listener_retry`, before
+//!   events they still fail on are dead-lettered.
+//!
+//! [`RetryPolicy::retry`] runs any other operation under a policy, such as a
+//! call to an external service from a handler.
+//!
+//! The server counts the retries of every function and listener, and the
+//! operations that retried and still failed, in
+//! `BuiltSurrealX::retry_stats` and, with the `metrics` feature,
+//! `surrealx_retries_total` and `surrealx_retries_exhausted_total`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::context::FunctionContext;
+use crate::events::{Event, EventListener, Propagation};
+use crate::functions::FunctionHandler;
+use crate::error::{Error, Result};
+
+/// How often and how patiently a failing operation is retried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first; 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after each further failure
+    pub backoff: Duration,
+    /// Upper bound on the delay between attempts
+    pub max_backoff: Duration,
+    /// Share of each delay, from 0 to 1, replaced by a random amount, so
+    /// operations failing together do not all retry at the same moment
+    #[serde(default)]
+    pub jitter: f64,
+    /// Errors worth retrying; unless set, every error but those blaming the
+    /// caller, such as invalid arguments, a missing role or a rate limit
+    #[serde(skip)]
+    retryable: Retryable,
+}
+
+/// Predicate of the errors a policy retries
+#[derive(Clone, Default)]
+struct Retryable(Option<Arc<dyn Fn(&Error) -> bool + Send + Sync>>);
+
+impl std::fmt::Debug for Retryable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Some(..)"),
+            None => f.write_str("None"),
+        }
+    }
+}
+
+impl PartialEq for Retryable {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Run once without retrying
+    pub fn none() -> Self {
+        Self::exponential(1, Duration::ZERO)
+    }
+
+    /// Retry up to `max_attempts` in total, doubling `backoff` each time
+    pub fn exponential(max_attempts: u32, backoff: Duration) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff,
+            max_backoff: Duration::from_secs(60),
+            jitter: 0.0,
+            retryable: Retryable::default(),
+        }
+    }
+
+    /// Cap the delay between attempts
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Replace up to `jitter` of each delay, from 0 to 1, by a random
+    /// amount; 1 waits anywhere from nothing to the full delay
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Retry only the errors `retryable` accepts, failing at once on others
+    pub fn with_retryable<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Retryable(Some(Arc::new(retryable)));
+        self
+    }
+
+    /// Whether `err` is worth retrying under this policy
+    pub fn is_retryable(&self, err: &Error) -> bool {
+        match &self.retryable.0 {
+            Some(retryable) => retryable(err),
+            None => !err.blames_caller(),
+        }
+    }
+
+    /// Delay before the given retry, counting from 1, without jitter
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    /// Delay before the given retry, counting from 1, with jitter applied
+    pub fn jittered_delay(&self, retry: u32) -> Duration {
+        let delay = self.delay(retry);
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+        delay.mul_f64(1.0 - self.jitter * fastrand::f64())
+    }
+
+    /// Run `operation` until it succeeds, fails with an error that is not
+    /// retryable, or has used every attempt, returning its last result
+    pub async fn retry<T, F, Fut>(&self, operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.run(operation, |_, _, _| {}).await.0
+    }
+
+    /// Like [`retry`](Self::retry), calling `on_retry` with the number of
+    /// the coming retry, the error and the delay before each retry, and
+    /// returning how many retries there were
+    pub(crate) async fn run<T, F, Fut>(
+        &self,
+        mut operation: F,
+        on_retry: impl Fn(u32, &Error, Duration),
+    ) -> (Result<T>, u32)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Err(err) if attempt < self.max_attempts && self.is_retryable(&err) => {
+                    let delay = self.jittered_delay(attempt);
+                    on_retry(attempt, &err, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return (result, attempt - 1),
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// What retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetryKind {
+    Function,
+    Listener,
+}
+
+/// Retries of a function or of the listeners of a pattern, as reported by
+/// `BuiltSurrealX::retry_stats`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryStats {
+    pub kind: RetryKind,
+    /// Module of the function or listener
+    pub module: String,
+    /// Fully qualified function name, or listener pattern
+    pub name: String,
+    /// Attempts after the first
+    pub retries: u64,
+    /// Operations that retried and still failed
+    pub exhausted: u64,
+}
+
+/// Retry counters of every function and listener, shared by clones of a
+/// server
+#[derive(Default)]
+pub(crate) struct RetryCounters {
+    counts: Mutex<HashMap<(RetryKind, String, String), (u64, u64)>>,
+}
+
+/// Where a retrying wrapper counts its retries
+#[derive(Clone)]
+pub(crate) struct RetryCount {
+    pub(crate) counters: Arc<RetryCounters>,
+    pub(crate) kind: RetryKind,
+    pub(crate) module: String,
+    pub(crate) name: String,
+}
+
+impl RetryCount {
+    fn record(&self, retries: u32, exhausted: bool) {
+        if retries == 0 {
+            return;
+        }
+        let mut counts = self.counters.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = (self.kind, self.module.clone(), self.name.clone());
+        let (total, exhausted_total) = counts.entry(key).or_default();
+        *total += u64::from(retries);
+        *exhausted_total += u64::from(exhausted);
+    }
+}
+
+impl RetryCounters {
+    /// Counters of everything that retried so far, by kind and name
+    pub(crate) fn snapshot(&self) -> Vec<RetryStats> {
+        let counts = self.counts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut stats: Vec<RetryStats> = counts
+            .iter()
+            .map(|((kind, module, name), (retries, exhausted))| RetryStats {
+                kind: *kind,
+                module: module.clone(),
+                name: name.clone(),
+                retries: *retries,
+                exhausted: *exhausted,
+            })
+            .collect();
+        stats.sort_by(|a, b| (a.kind as u8, &a.name).cmp(&(b.kind as u8, &b.name)));
+        stats
+    }
+}
+
+/// Listener wrapper that retries failures according to a [`RetryPolicy`]
+///
+/// Only the last error is returned once every attempt has failed, which is
+/// when the registry hands the event to its dead-letter sink.
+pub struct RetryingListener {
+    inner: Arc<dyn EventListener>,
+    policy: RetryPolicy,
+    count: Option<RetryCount>,
+}
+
+impl RetryingListener {
+    pub fn new(inner: Arc<dyn EventListener>, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            count: None,
+        }
+    }
+
+    /// Count retries in `count`
+    pub(crate) fn with_count(mut self, count: RetryCount) -> Self {
+        self.count = Some(count);
+        self
+    }
+}
+
+#[async_trait]
+impl EventListener for RetryingListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.handle(event).await.map(|_| ())
+    }
+
+    async fn handle(&self, event: Event) -> Result<Propagation> {
+        let pattern = event.pattern();
+        let (result, retries) = self
+            .policy
+            .run(
+                || self.inner.handle(event.clone()),
+                |attempt, err, delay| {
+                    tracing::debug!(pattern = %pattern, attempt, error = %err, ?delay, "retrying event listener");
+                },
+            )
+            .await;
+        if let Some(count) = &self.count {
+            count.record(retries, result.is_err());
+        }
+        result
+    }
+
+    fn priority(&self) -> i32 {
+        self.inner.priority()
+    }
+}
+
+/// Function handler that retries failed calls according to a [`RetryPolicy`]
+pub(crate) struct RetryingFunction {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) policy: RetryPolicy,
+    pub(crate) count: RetryCount,
+}
+
+impl RetryingFunction {
+    fn log_retry(&self, attempt: u32, err: &Error, delay: Duration) {
+        tracing::debug!(function = %self.count.name, attempt, error = %err, ?delay, "retrying function call");
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for RetryingFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        let (result, retries) = self
+            .policy
+            .run(
+                || self.inner.call(args.clone()),
+                |attempt, err, delay| self.log_retry(attempt, err, delay),
+            )
+            .await;
+        self.count.record(retries, result.is_err());
+        result
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let (result, retries) = self
+            .policy
+            .run(
+                || self.inner.call_with_context(context.clone(), args.clone()),
+                |attempt, err, delay| self.log_retry(attempt, err, delay),
+            )
+            .await;
+        self.count.record(retries, result.is_err());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails with `error` until it has been called `failures` times
+    struct Flaky {
+        calls: AtomicU32,
+        failures: u32,
+        error: fn() -> Error,
+    }
+
+    impl Flaky {
+        fn new(failures: u32, error: fn() -> Error) -> Self {
+            Self {
+                calls: AtomicU32::new(0),
+                failures,
+                error,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl FunctionHandler for Flaky {
+        async fn call(&self, _args: Vec<Value>) -> Result<Value> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err((self.error)());
+            }
+            Ok(Value::Null)
+        }
+    }
+
+    fn unavailable() -> Error {
+        Error::Function("unavailable".to_string())
+    }
+
+    fn retrying(inner: Arc<Flaky>, policy: RetryPolicy, counters: &Arc<RetryCounters>) -> RetryingFunction {
+        RetryingFunction {
+            inner,
+            policy,
+            count: RetryCount {
+                counters: counters.clone(),
+                kind: RetryKind::Function,
+                module: "billing".to_string(),
+                name: "billing::charge".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn doubles_the_delay_up_to_the_cap() {
+        let policy = RetryPolicy::exponential(10, Duration::from_millis(100)).with_max_backoff(Duration::from_millis(500));
+        let delays: Vec<_> = (1..=5).map(|retry| policy.delay(retry).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn keeps_jittered_delays_within_the_jitter() {
+        let policy = RetryPolicy::exponential(3, Duration::from_millis(1000)).with_jitter(0.25);
+        for _ in 0..100 {
+            let delay = policy.jittered_delay(1);
+            assert!(delay >= Duration::from_millis(750) && delay <= Duration::from_millis(1000));
+        }
+        assert_eq!(RetryPolicy::exponential(3, Duration::from_millis(1000)).with_jitter(7.0).jitter, 1.0);
+        assert_eq!(RetryPolicy::exponential(3, Duration::from_millis(1000)).jittered_delay(1), Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn stops_after_the_last_attempt() {
+        let policy = RetryPolicy::exponential(3, Duration::from_millis(1));
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = policy
+            .retry(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(unavailable())
+            })
+            .await;
+        assert!(matches!(result, Err(Error::Function(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(RetryPolicy::exponential(0, Duration::ZERO).max_attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_errors_blaming_the_caller() {
+        let policy = RetryPolicy::exponential(3, Duration::from_millis(1));
+        assert!(policy.is_retryable(&unavailable()));
+        assert!(!policy.is_retryable(&Error::InvalidArguments {
+            function: "billing::charge".to_string(),
+            errors: Vec::new(),
+        }));
+        assert!(!policy.is_retryable(&Error::RateLimited {
+            key: "billing::charge".to_string(),
+            retry_after: Duration::from_secs(1),
+        }));
+        assert!(!policy.is_retryable(&Error::Validation("amount".to_string())));
+
+        let flaky = Arc::new(Flaky::new(1, || Error::Validation("amount".to_string())));
+        let counters = Arc::default();
+        let result = retrying(flaky.clone(), policy, &counters).call(Vec::new()).await;
+        assert!(matches!(result, Err(Error::Validation(_))));
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retries_only_what_a_custom_predicate_accepts() {
+        let policy = RetryPolicy::exponential(3, Duration::from_millis(1))
+            .with_retryable(|err| matches!(err, Error::Validation(_)));
+        assert!(policy.is_retryable(&Error::Validation("amount".to_string())));
+        assert!(!policy.is_retryable(&unavailable()));
+    }
+
+    #[tokio::test]
+    async fn counts_retries_and_exhausted_calls() {
+        let counters = Arc::new(RetryCounters::default());
+        let policy = RetryPolicy::exponential(3, Duration::from_millis(1));
+
+        let recovering = retrying(Arc::new(Flaky::new(2, unavailable)), policy.clone(), &counters);
+        assert!(recovering.call(Vec::new()).await.is_ok());
+        let failing = retrying(Arc::new(Flaky::new(u32::MAX, unavailable)), policy.clone(), &counters);
+        assert!(failing.call(Vec::new()).await.is_err());
+        let healthy = retrying(Arc::new(Flaky::new(0, unavailable)), policy, &counters);
+        assert!(healthy.call(Vec::new()).await.is_ok());
+
+        let stats = counters.snapshot();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].retries, stats[0].exhausted), (4, 1));
+        assert_eq!(stats[0].name, "billing::charge");
+    }
+}
@@ -0,0 +1,374 @@
+//! Jobs run on a cron schedule
+//!
+//! Modules add jobs with [`Module::with_scheduled_job`](crate::Module::with_scheduled_job)
+//! or [`Module::with_job`](crate::Module::with_job). Schedules are cron
+//! expressions of five fields, or six with seconds first, evaluated in UTC.
+//! The scheduler starts with [`BuiltSurrealX::start`](crate::server::BuiltSurrealX::start),
+//! so `serve()` runs jobs while the server is up, and stops at shutdown.
+//!
+//! Every run emits `job:started`, then `job:finished` or `job:failed`, with
+//! the job, its module and, once done, how long it took. The start time of
+//! the last run is kept where `ServerConfig::job_runs` says; after a restart
+//! a job whose run was due while the server was down runs once right away.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, TimeZone, Utc};
+use croner::Cron;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+use crate::cache::CacheProvider;
+use crate::context::{self, Context};
+use crate::db::Database;
+use crate::events::{Event, EventRegistry};
+use crate::functions::InFlight;
+use crate::error::{Error, Result};
+
+pub(crate) type JobHandler = dyn Fn(Context) -> BoxFuture<'static, Result<()>> + Send + Sync;
+
+/// What happens when a job is due while its previous run is still going
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapPolicy {
+    /// Skip the run
+    #[default]
+    Skip,
+    /// Run once the previous runs have finished, in order
+    Queue,
+    /// Run alongside the previous runs
+    Concurrent,
+}
+
+/// A job run on a cron schedule
+#[derive(Clone)]
+pub struct ScheduledJob {
+    name: String,
+    schedule: String,
+    handler: Arc<JobHandler>,
+    overlap: OverlapPolicy,
+    jitter: Duration,
+}
+
+impl ScheduledJob {
+    /// Run `handler` with its module's [`Context`] whenever `schedule` is due
+    pub fn new<F, Fut>(name: impl Into<String>, schedule: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            schedule: schedule.into(),
+            handler: Arc::new(move |context| Box::pin(handler(context))),
+            overlap: OverlapPolicy::default(),
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Set what happens when a run is due before the previous one finished
+    pub fn with_overlap(mut self, overlap: OverlapPolicy) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Delay each run by a random duration up to `jitter`, so instances
+    /// sharing a schedule do not all start at once
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Name of the job within its module
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Cron expression the job runs on
+    pub fn schedule(&self) -> &str {
+        &self.schedule
+    }
+}
+
+/// Where the scheduler keeps the start time of each job's last run
+pub(crate) enum RunStore {
+    None,
+    Cache(Arc<dyn CacheProvider>),
+    Database(Database),
+}
+
+impl RunStore {
+    async fn last_run(&self, job: &str) -> Result<Option<i64>> {
+        match self {
+            RunStore::None => Ok(None),
+            RunStore::Cache(cache) => Ok(cache
+                .get(&format!("sx:job:{}", job))
+                .await?
+                .and_then(|run| run.get("last_run").and_then(Value::as_i64))),
+            RunStore::Database(db) => {
+                let mut response = db
+                    .query("SELECT VALUE last_run FROM type::thing('sx_job', $job)")
+                    .bind(("job", job.to_string()))
+                    .await?;
+                let runs = response.take::<surrealdb::Value>(0)?.into_inner().into_json();
+                Ok(runs.get(0).and_then(Value::as_i64))
+            }
+        }
+    }
+
+    async fn record(&self, job: &str, started_at: i64) -> Result<()> {
+        match self {
+            RunStore::None => Ok(()),
+            RunStore::Cache(cache) => {
+                cache
+                    .set(&format!("sx:job:{}", job), json!({ "last_run": started_at }), None)
+                    .await
+            }
+            RunStore::Database(db) => {
+                db.query("UPSERT type::thing('sx_job', $job) MERGE { last_run: $started_at } RETURN NONE")
+                    .bind(("job", job.to_string()))
+                    .bind(("started_at", started_at))
+                    .await?
+                    .check()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A job with its parsed schedule and the context it runs in
+struct Job {
+    /// `module::name`
+    id: String,
+    cron: Cron,
+    context: Context,
+    job: ScheduledJob,
+    /// Held by the run in progress under `Skip` and `Queue`
+    running: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// A job's schedule and runs, as the admin API lists them
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct JobSchedule {
+    /// `module::name`
+    job: String,
+    module: String,
+    schedule: String,
+    overlap: OverlapPolicy,
+    /// Start time of the last run, in milliseconds since the epoch, when
+    /// `ServerConfig::job_runs` keeps it
+    last_run: Option<i64>,
+    /// When the job is next due, in milliseconds since the epoch
+    next_run: Option<i64>,
+}
+
+/// Parse the schedule of `job` in `module`
+pub(crate) fn parse_schedule(module: &str, job: &ScheduledJob) -> Result<Cron> {
+    Cron::new(&job.schedule).with_seconds_optional().parse().map_err(|err| {
+        Error::Config(format!(
+            "job '{}::{}' has an invalid schedule '{}': {}",
+            module, job.name, job.schedule, err
+        ))
+    })
+}
+
+/// Runs the jobs of a built server
+pub(crate) struct Scheduler {
+    inner: Arc<Inner>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+struct Inner {
+    jobs: Vec<Arc<Job>>,
+    runs: RunStore,
+    events: EventRegistry,
+    in_flight: InFlight,
+}
+
+impl Scheduler {
+    /// Parse the schedules of `jobs`, given with the context of their module
+    pub(crate) fn new(
+        jobs: Vec<(Context, ScheduledJob)>,
+        runs: RunStore,
+        events: EventRegistry,
+        in_flight: InFlight,
+    ) -> Result<Self> {
+        let jobs = jobs
+            .into_iter()
+            .map(|(context, job)| {
+                let id = format!("{}::{}", context.module(), job.name);
+                let cron = parse_schedule(context.module(), &job)?;
+                Ok(Arc::new(Job {
+                    id,
+                    cron,
+                    context,
+                    job,
+                    running: Arc::new(tokio::sync::Mutex::new(())),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                jobs,
+                runs,
+                events,
+                in_flight,
+            }),
+            tasks: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Start waiting for every job's schedule
+    pub(crate) fn start(&self) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for job in &self.inner.jobs {
+            tasks.push(tokio::spawn(self.inner.clone().follow(job.clone())));
+        }
+    }
+
+    /// Stop scheduling runs; runs in progress keep going
+    pub(crate) fn stop(&self) {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for task in tasks.drain(..) {
+            task.abort();
+        }
+    }
+
+    /// Describe every job with its last and next run
+    pub(crate) async fn schedules(&self) -> Vec<JobSchedule> {
+        let mut schedules = Vec::with_capacity(self.inner.jobs.len());
+        for job in &self.inner.jobs {
+            let last_run = match self.inner.runs.last_run(&job.id).await {
+                Ok(last_run) => last_run,
+                Err(err) => {
+                    tracing::warn!(job = %job.id, error = %err, "failed to read the last run of a job");
+                    None
+                }
+            };
+            schedules.push(JobSchedule {
+                job: job.id.clone(),
+                module: job.context.module().to_string(),
+                schedule: job.job.schedule.clone(),
+                overlap: job.job.overlap,
+                last_run,
+                next_run: job
+                    .cron
+                    .find_next_occurrence(&Utc::now(), false)
+                    .ok()
+                    .map(|next| next.timestamp_millis()),
+            });
+        }
+        schedules
+    }
+}
+
+impl Inner {
+    /// Dispatch a run of `job` every time its schedule is due
+    async fn follow(self: Arc<Self>, job: Arc<Job>) {
+        let mut after = match self.runs.last_run(&job.id).await {
+            Ok(last_run) => last_run
+                .and_then(|started_at| Utc.timestamp_millis_opt(started_at).single())
+                .unwrap_or_else(Utc::now),
+            Err(err) => {
+                tracing::warn!(job = %job.id, error = %err, "failed to read the last run of a job");
+                Utc::now()
+            }
+        };
+
+        loop {
+            let next = match job.cron.find_next_occurrence(&after, false) {
+                Ok(next) => next,
+                Err(err) => {
+                    tracing::error!(job = %job.id, error = %err, "job has no further runs");
+                    return;
+                }
+            };
+            let wait = (next - Utc::now()).to_std().unwrap_or_default() + jitter(job.job.jitter);
+            tokio::time::sleep(wait).await;
+            // Runs missed while the server was down collapse into this one
+            after = next.max(Utc::now());
+            self.dispatch(&job, next);
+        }
+    }
+
+    fn dispatch(self: &Arc<Self>, job: &Arc<Job>, due: DateTime<Utc>) {
+        let (inner, job) = (self.clone(), job.clone());
+        match job.job.overlap {
+            OverlapPolicy::Skip => match job.running.clone().try_lock_owned() {
+                Ok(running) => {
+                    tokio::spawn(async move {
+                        inner.run(&job, due).await;
+                        drop(running);
+                    });
+                }
+                Err(_) => tracing::info!(job = %job.id, "previous run still in progress; skipping"),
+            },
+            OverlapPolicy::Queue => {
+                tokio::spawn(async move {
+                    let running = job.running.clone().lock_owned().await;
+                    inner.run(&job, due).await;
+                    drop(running);
+                });
+            }
+            OverlapPolicy::Concurrent => {
+                tokio::spawn(async move { inner.run(&job, due).await });
+            }
+        }
+    }
+
+    async fn run(&self, job: &Job, due: DateTime<Utc>) {
+        if job.context.check_enabled().is_err() {
+            tracing::info!(job = %job.id, "module is disabled; skipping run");
+            return;
+        }
+        let _in_flight = self.in_flight.enter();
+        let started_at = Utc::now();
+        if let Err(err) = self.runs.record(&job.id, started_at.timestamp_millis()).await {
+            tracing::warn!(job = %job.id, error = %err, "failed to record the run of a job");
+        }
+        let run = json!({
+            "job": job.id,
+            "module": job.context.module(),
+            "schedule": job.job.schedule,
+            "due_at": due.timestamp_millis(),
+            "started_at": started_at.timestamp_millis(),
+        });
+        self.emit("job:started", run.clone()).await;
+
+        let span = tracing::info_span!("sx.job", module = %job.context.module(), job = %job.job.name);
+        let result = context::scope(job.context.clone(), (job.job.handler)(job.context.clone()))
+            .instrument(span)
+            .await;
+
+        let mut run = run;
+        run["duration_ms"] = json!((Utc::now() - started_at).num_milliseconds());
+        match result {
+            Ok(()) => self.emit("job:finished", run).await,
+            Err(err) => {
+                tracing::error!(job = %job.id, error = %err, "job failed");
+                run["error"] = json!(err.to_string());
+                self.emit("job:failed", run).await;
+            }
+        }
+    }
+
+    async fn emit(&self, pattern: &str, data: Value) {
+        let result = match Event::custom(pattern, data) {
+            Ok(event) => self.events.emit(event).await.map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            tracing::warn!(event = pattern, error = %err, "failed to emit a job event");
+        }
+    }
+}
+
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(fastrand::u64(0..=max.as_millis() as u64))
+}
@@ -0,0 +1,461 @@
+//! Declarative table, field and index definitions owned by modules
+//!
+//! A module declares the tables it uses with `Module::with_table`:
+//!
+//! ```rust,ignore
+//! Module::new("orders").with_table(
+//!     Table::new("order")
+//!         .schemafull()
+//!         .field("customer", FieldType::record("customer"))
+//!         .field("total", FieldType::Decimal)
+//!         .with_field(Field::new("placed_at", FieldType::Datetime).with_default("time::now()").readonly())
+//!         .index("by_customer", &["customer"]),
+//! )
+//! ```
+//!
+//! When the server is built, each declared table is compared with the
+//! database and, as `ServerConfig::schema` says, defined again with
+//! `DEFINE ... OVERWRITE` statements run in one transaction per table. The
+//! differences found are logged and kept in `BuildReport::schema_drift`:
+//! definitions missing from the database, definitions reconciling changed,
+//! and fields or indexes of a declared table that no module declares, which
+//! are left alone. Tables are reconciled before migrations run. With a tenant
+//! resolver, each tenant's namespace is reconciled the same way before its
+//! first request; see [`tenancy`](crate::tenancy).
+//!
+//! Names are written into the statements as they are, so they must be valid
+//! SurrealQL identifiers; `DEFAULT` and `ASSERT` clauses are SurrealQL
+//! expressions.
+
+use std::collections::{BTreeMap, HashMap};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::db::Database;
+use crate::server::SchemaPolicy;
+use crate::error::{Error, Result};
+
+/// Type of a declared field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldType {
+    Any,
+    Bool,
+    Int,
+    Float,
+    Decimal,
+    Number,
+    String,
+    Datetime,
+    Duration,
+    Uuid,
+    Bytes,
+    Object,
+    /// Array of the given type
+    Array(Box<FieldType>),
+    /// Record of the given table, or of any table when empty
+    Record(String),
+    /// `NONE` or the given type
+    Option(Box<FieldType>),
+    /// A type written in SurrealQL, such as `geometry<point>`
+    Custom(String),
+}
+
+impl FieldType {
+    /// Array of `item`
+    pub fn array(item: FieldType) -> Self {
+        FieldType::Array(Box::new(item))
+    }
+
+    /// Record of `table`
+    pub fn record(table: impl Into<String>) -> Self {
+        FieldType::Record(table.into())
+    }
+
+    /// `NONE` or `inner`
+    pub fn option(inner: FieldType) -> Self {
+        FieldType::Option(Box::new(inner))
+    }
+
+    /// The type as SurrealQL writes it
+    pub fn to_surql(&self) -> String {
+        match self {
+            FieldType::Any => "any".to_string(),
+            FieldType::Bool => "bool".to_string(),
+            FieldType::Int => "int".to_string(),
+            FieldType::Float => "float".to_string(),
+            FieldType::Decimal => "decimal".to_string(),
+            FieldType::Number => "number".to_string(),
+            FieldType::String => "string".to_string(),
+            FieldType::Datetime => "datetime".to_string(),
+            FieldType::Duration => "duration".to_string(),
+            FieldType::Uuid => "uuid".to_string(),
+            FieldType::Bytes => "bytes".to_string(),
+            FieldType::Object => "object".to_string(),
+            FieldType::Array(item) => format!("array<{}>", item.to_surql()),
+            FieldType::Record(table) if table.is_empty() => "record".to_string(),
+            FieldType::Record(table) => format!("record<{}>", table),
+            FieldType::Option(inner) => format!("option<{}>", inner.to_surql()),
+            FieldType::Custom(surql) => surql.clone(),
+        }
+    }
+}
+
+/// A declared field of a table
+#[derive(Debug, Clone)]
+pub struct Field {
+    name: String,
+    kind: FieldType,
+    default: Option<String>,
+    assert: Option<String>,
+    readonly: bool,
+}
+
+impl Field {
+    /// Field `name` of type `kind`; nested fields are named like `address.city`
+    pub fn new(name: impl Into<String>, kind: FieldType) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            default: None,
+            assert: None,
+            readonly: false,
+        }
+    }
+
+    /// Set the field to the SurrealQL expression `default` when it is not given
+    pub fn with_default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    /// Reject values for which the SurrealQL expression `assert` is false,
+    /// such as `$value > 0`
+    pub fn with_assert(mut self, assert: impl Into<String>) -> Self {
+        self.assert = Some(assert.into());
+        self
+    }
+
+    /// Reject changes to the field once the record is created
+    pub fn readonly(mut self) -> Self {
+        self.readonly = true;
+        self
+    }
+
+    /// Field name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn statement(&self, table: &str) -> String {
+        let mut statement = format!(
+            "DEFINE FIELD OVERWRITE {} ON TABLE {} TYPE {}",
+            self.name,
+            table,
+            self.kind.to_surql()
+        );
+        if let Some(default) = &self.default {
+            statement.push_str(&format!(" DEFAULT {}", default));
+        }
+        if self.readonly {
+            statement.push_str(" READONLY");
+        }
+        if let Some(assert) = &self.assert {
+            statement.push_str(&format!(" ASSERT {}", assert));
+        }
+        statement
+    }
+}
+
+/// A declared index of a table
+#[derive(Debug, Clone)]
+pub struct Index {
+    name: String,
+    fields: Vec<String>,
+    unique: bool,
+}
+
+impl Index {
+    /// Index `name` over `fields`
+    pub fn new(name: impl Into<String>, fields: &[&str]) -> Self {
+        Self {
+            name: name.into(),
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            unique: false,
+        }
+    }
+
+    /// Reject records whose indexed fields equal another record's
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    /// Index name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn statement(&self, table: &str) -> String {
+        format!(
+            "DEFINE INDEX OVERWRITE {} ON TABLE {} FIELDS {}{}",
+            self.name,
+            table,
+            self.fields.join(", "),
+            if self.unique { " UNIQUE" } else { "" }
+        )
+    }
+}
+
+/// A table a module declares, with its fields and indexes
+#[derive(Debug, Clone)]
+pub struct Table {
+    name: String,
+    schemafull: bool,
+    fields: Vec<Field>,
+    indexes: Vec<Index>,
+}
+
+impl Table {
+    /// Schemaless table `name`
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            schemafull: false,
+            fields: Vec::new(),
+            indexes: Vec::new(),
+        }
+    }
+
+    /// Reject fields that are not declared
+    pub fn schemafull(mut self) -> Self {
+        self.schemafull = true;
+        self
+    }
+
+    /// Declare field `name` of type `kind`
+    pub fn field(self, name: impl Into<String>, kind: FieldType) -> Self {
+        self.with_field(Field::new(name, kind))
+    }
+
+    /// Declare a field with a default, assertion or other options
+    pub fn with_field(mut self, field: Field) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// Declare index `name` over `fields`
+    pub fn index(self, name: impl Into<String>, fields: &[&str]) -> Self {
+        self.with_index(Index::new(name, fields))
+    }
+
+    /// Declare a unique index `name` over `fields`
+    pub fn unique_index(self, name: impl Into<String>, fields: &[&str]) -> Self {
+        self.with_index(Index::new(name, fields).unique())
+    }
+
+    /// Declare an index
+    pub fn with_index(mut self, index: Index) -> Self {
+        self.indexes.push(index);
+        self
+    }
+
+    /// Table name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Declared fields
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Declared indexes
+    pub fn indexes(&self) -> &[Index] {
+        &self.indexes
+    }
+
+    /// The `DEFINE` statements creating or overwriting the table, its fields
+    /// and its indexes, in that order
+    pub fn statements(&self) -> Vec<String> {
+        let mut statements = vec![format!(
+            "DEFINE TABLE OVERWRITE {} {}",
+            self.name,
+            if self.schemafull { "SCHEMAFULL" } else { "SCHEMALESS" }
+        )];
+        statements.extend(self.fields.iter().map(|field| field.statement(&self.name)));
+        statements.extend(self.indexes.iter().map(|index| index.statement(&self.name)));
+        statements
+    }
+}
+
+/// Part of a table's definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaElement {
+    Table,
+    Field,
+    Index,
+}
+
+/// How the database differed from a declared table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaChange {
+    /// Declared but not defined in the database
+    Missing,
+    /// Defined differently in the database
+    Changed,
+    /// Defined in the database but not declared
+    Undeclared,
+}
+
+/// A difference between a declared table and the database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaDrift {
+    /// Module declaring the table
+    pub module: String,
+    pub table: String,
+    pub element: SchemaElement,
+    /// Name of the table, field or index
+    pub name: String,
+    pub change: SchemaChange,
+}
+
+/// Definitions of a table as `INFO FOR TABLE` reports them
+#[derive(Default)]
+struct Snapshot {
+    table: Option<String>,
+    fields: BTreeMap<String, String>,
+    indexes: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    async fn read(db: &Database, switch: Option<&str>, table: &str) -> Result<Self> {
+        let tables = definitions(&info(db, switch, "INFO FOR DB").await?, "tables");
+        let Some(definition) = tables.get(table) else {
+            return Ok(Self::default());
+        };
+        let info = info(db, switch, &format!("INFO FOR TABLE {}", table)).await?;
+        Ok(Self {
+            table: Some(definition.clone()),
+            fields: definitions(&info, "fields"),
+            indexes: definitions(&info, "indexes"),
+        })
+    }
+
+    fn get(&self, element: SchemaElement, name: &str) -> Option<&String> {
+        match element {
+            SchemaElement::Table => self.table.as_ref(),
+            SchemaElement::Field => self.fields.get(name),
+            SchemaElement::Index => self.indexes.get(name),
+        }
+    }
+}
+
+async fn info(db: &Database, switch: Option<&str>, sql: &str) -> Result<Value> {
+    let mut response = db.query(format!("{}{}", switch.unwrap_or_default(), sql)).await?.check()?;
+    Ok(response.take::<surrealdb::Value>(usize::from(switch.is_some()))?.into_inner().into_json())
+}
+
+fn definitions(info: &Value, key: &str) -> BTreeMap<String, String> {
+    info.get(key)
+        .and_then(Value::as_object)
+        .map(|definitions| {
+            definitions
+                .iter()
+                .map(|(name, definition)| (name.clone(), definition.as_str().unwrap_or_default().to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Compare the tables `modules` declare with the database and, under
+/// `SchemaPolicy::Reconcile`, define them; returns the drift found
+///
+/// Every query starts with `switch`, a `USE` statement, if given. Fails if
+/// two modules declare the same table.
+pub(crate) async fn reconcile(
+    db: &Database,
+    switch: Option<&str>,
+    modules: Vec<(String, Vec<Table>)>,
+    policy: SchemaPolicy,
+) -> Result<Vec<SchemaDrift>> {
+    let mut owners: HashMap<String, String> = HashMap::new();
+    for (module, tables) in &modules {
+        for table in tables {
+            if let Some(owner) = owners.insert(table.name.clone(), module.clone()) {
+                return Err(Error::Config(format!(
+                    "table '{}' is declared by both '{}' and '{}'",
+                    table.name, owner, module
+                )));
+            }
+        }
+    }
+    if policy == SchemaPolicy::Skip {
+        return Ok(Vec::new());
+    }
+
+    let mut drift = Vec::new();
+    for (module, tables) in &modules {
+        for table in tables {
+            let found = |element: SchemaElement, name: &str, change: SchemaChange| SchemaDrift {
+                module: module.clone(),
+                table: table.name.clone(),
+                element,
+                name: name.to_string(),
+                change,
+            };
+            let declared: Vec<(SchemaElement, &str)> = std::iter::once((SchemaElement::Table, table.name.as_str()))
+                .chain(table.fields.iter().map(|field| (SchemaElement::Field, field.name.as_str())))
+                .chain(table.indexes.iter().map(|index| (SchemaElement::Index, index.name.as_str())))
+                .collect();
+
+            let before = Snapshot::read(db, switch, &table.name).await?;
+            for &(element, name) in &declared {
+                if before.get(element, name).is_none() {
+                    drift.push(found(element, name, SchemaChange::Missing));
+                }
+            }
+            // Array fields define their items as `<field>[*]` themselves
+            let is_declared_field = |name: &str| {
+                table.fields.iter().any(|field| {
+                    name == field.name || name.strip_prefix(field.name.as_str()).is_some_and(|rest| rest.starts_with("[*]"))
+                })
+            };
+            for name in before.fields.keys().filter(|name| !is_declared_field(name.as_str())) {
+                drift.push(found(SchemaElement::Field, name, SchemaChange::Undeclared));
+            }
+            for name in before.indexes.keys().filter(|name| !table.indexes.iter().any(|index| &index.name == *name)) {
+                drift.push(found(SchemaElement::Index, name, SchemaChange::Undeclared));
+            }
+
+            if policy == SchemaPolicy::Reconcile {
+                let sql = format!(
+                    "{}BEGIN TRANSACTION;\n{};\nCOMMIT TRANSACTION;",
+                    switch.unwrap_or_default(),
+                    table.statements().join(";\n")
+                );
+                db.query(sql).await?.check()?;
+                let after = Snapshot::read(db, switch, &table.name).await?;
+                for &(element, name) in &declared {
+                    if let (Some(was), Some(is)) = (before.get(element, name), after.get(element, name)) {
+                        if was != is {
+                            drift.push(found(element, name, SchemaChange::Changed));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for difference in &drift {
+        tracing::warn!(
+            module = %difference.module,
+            table = %difference.table,
+            element = ?difference.element,
+            name = %difference.name,
+            change = ?difference.change,
+            "schema drift"
+        );
+    }
+    Ok(drift)
+}
@@ -0,0 +1,418 @@
+//! Credentials for modules, read from a secret store
+//!
+//! With a [`SecretProvider`] set through `SurrealX::with_secret_provider`,
+//! handlers read credentials with `ctx.secret("stripe_api_key")` instead of
+//! embedding them in code or module configuration:
+//!
+//! - [`EnvSecretProvider`] reads environment variables;
+//! - [`FileSecretProvider`] reads one file per secret, as mounted by Docker
+//!   and Kubernetes;
+//! - `VaultSecretProvider` reads a HashiCorp Vault KV v2 secret, with the
+//!   `vault-secrets` feature;
+//! - `AwsSecretProvider` reads AWS Secrets Manager, with the `aws-secrets`
+//!   feature.
+//!
+//! Values are cached for `ServerConfig::secret_ttl`. Secrets with rotation
+//! callbacks, registered with `Context::on_secret_rotation`, are read again
+//! every TTL in the background, and the callbacks run whenever the value
+//! changed, so a module can reconnect with rotated credentials.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use tokio::task::JoinHandle;
+use crate::error::{Error, Result};
+
+/// Where secrets are read from
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// Current value of the secret `name`, or `None` if there is no such
+    /// secret
+    async fn get(&self, name: &str) -> Result<Option<String>>;
+}
+
+/// Runs when a secret's value changes, with the new value
+pub type RotationFn = dyn Fn(&str) + Send + Sync;
+
+/// Reads secrets from environment variables
+///
+/// The variable of a secret is its name in upper case, with `-` and `.`
+/// replaced by `_` and the prefix, if any, prepended: `stripe_api_key` is
+/// read from `STRIPE_API_KEY`, or `APP_STRIPE_API_KEY` with the prefix
+/// `APP_`.
+#[derive(Debug, Clone, Default)]
+pub struct EnvSecretProvider {
+    prefix: String,
+}
+
+impl EnvSecretProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prepend `prefix` to the name of every variable
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn variable(&self, name: &str) -> String {
+        let name: String = name
+            .chars()
+            .map(|c| match c {
+                '-' | '.' => '_',
+                c => c.to_ascii_uppercase(),
+            })
+            .collect();
+        format!("{}{}", self.prefix, name)
+    }
+}
+
+#[async_trait]
+impl SecretProvider for EnvSecretProvider {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        match std::env::var(self.variable(name)) {
+            Ok(value) => Ok(Some(value)),
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(err) => Err(Error::Secret(format!("secret '{}': {}", name, err))),
+        }
+    }
+}
+
+/// Reads each secret from the file of its name in a directory, such as
+/// `/run/secrets`
+///
+/// A single trailing newline is removed from the value.
+#[derive(Debug, Clone)]
+pub struct FileSecretProvider {
+    dir: PathBuf,
+}
+
+impl FileSecretProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl SecretProvider for FileSecretProvider {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        let mut components = Path::new(name).components();
+        if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+            return Err(Error::Validation(format!("invalid secret name '{}'", name)));
+        }
+        match tokio::fs::read_to_string(self.dir.join(name)).await {
+            Ok(mut value) => {
+                if value.ends_with('\n') {
+                    value.pop();
+                    if value.ends_with('\r') {
+                        value.pop();
+                    }
+                }
+                Ok(Some(value))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Reads secrets from the fields of a HashiCorp Vault KV version 2 secret
+///
+/// Every secret is a field of the same Vault secret, `surrealx` in the
+/// `secret` mount unless configured otherwise.
+#[cfg(feature = "vault-secrets")]
+#[derive(Clone)]
+pub struct VaultSecretProvider {
+    address: String,
+    token: String,
+    mount: String,
+    path: String,
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "vault-secrets")]
+impl VaultSecretProvider {
+    /// Read secrets from the Vault server at `address`, such as
+    /// `https://vault.internal:8200`, with `token`
+    pub fn new(address: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            address: address.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+            mount: "secret".to_string(),
+            path: "surrealx".to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Read from the KV engine mounted at `mount`
+    pub fn with_mount(mut self, mount: impl Into<String>) -> Self {
+        self.mount = mount.into();
+        self
+    }
+
+    /// Read the fields of the secret at `path`
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+}
+
+#[cfg(feature = "vault-secrets")]
+impl std::fmt::Debug for VaultSecretProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultSecretProvider")
+            .field("address", &self.address)
+            .field("token", &"[redacted]")
+            .field("mount", &self.mount)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+#[cfg(feature = "vault-secrets")]
+#[async_trait]
+impl SecretProvider for VaultSecretProvider {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        let url = format!("{}/v1/{}/data/{}", self.address, self.mount, self.path);
+        let response = self
+            .client
+            .get(&url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .map_err(|err| Error::Secret(format!("Vault request failed: {}", err)))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(Error::Secret(format!("Vault answered {}", response.status())));
+        }
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| Error::Secret(format!("unreadable Vault response: {}", err)))?;
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+        match body.pointer("/data/data").and_then(|fields| fields.get(name)) {
+            None | Some(serde_json::Value::Null) => Ok(None),
+            Some(serde_json::Value::String(value)) => Ok(Some(value.clone())),
+            Some(value) => Ok(Some(value.to_string())),
+        }
+    }
+}
+
+/// Reads secrets from AWS Secrets Manager
+///
+/// The secret ID is the secret's name, with the prefix, if any, prepended.
+/// Only secrets stored as strings are read.
+#[cfg(feature = "aws-secrets")]
+#[derive(Debug, Clone)]
+pub struct AwsSecretProvider {
+    client: aws_sdk_secretsmanager::Client,
+    prefix: String,
+}
+
+#[cfg(feature = "aws-secrets")]
+impl AwsSecretProvider {
+    pub fn new(client: aws_sdk_secretsmanager::Client) -> Self {
+        Self {
+            client,
+            prefix: String::new(),
+        }
+    }
+
+    /// Read secrets with the region and credentials of the environment
+    pub async fn from_env() -> Self {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        Self::new(aws_sdk_secretsmanager::Client::new(&config))
+    }
+
+    /// Prepend `prefix` to the ID of every secret, such as `prod/`
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+}
+
+#[cfg(feature = "aws-secrets")]
+#[async_trait]
+impl SecretProvider for AwsSecretProvider {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        let result = self
+            .client
+            .get_secret_value()
+            .secret_id(format!("{}{}", self.prefix, name))
+            .send()
+            .await;
+        match result {
+            Ok(output) => Ok(output.secret_string().map(str::to_string)),
+            Err(err) => {
+                let err = err.into_service_error();
+                if err.is_resource_not_found_exception() {
+                    Ok(None)
+                } else {
+                    Err(Error::Secret(format!("AWS Secrets Manager: {}", err)))
+                }
+            }
+        }
+    }
+}
+
+/// Cached secrets of a server, shared by clones
+#[derive(Clone)]
+pub struct Secrets {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    provider: Arc<dyn SecretProvider>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, Cached>>,
+    rotations: Mutex<HashMap<String, Vec<Arc<RotationFn>>>>,
+    refresh_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct Cached {
+    value: String,
+    read_at: Instant,
+}
+
+impl Secrets {
+    /// Secrets of `provider`, cached for `ttl`, with rotated ones checked
+    /// every `ttl`
+    pub(crate) fn new(provider: Arc<dyn SecretProvider>, ttl: Duration) -> Self {
+        let inner = Arc::new(Inner {
+            provider,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+            rotations: Mutex::new(HashMap::new()),
+            refresh_task: Mutex::new(None),
+        });
+        if !ttl.is_zero() {
+            let task = tokio::spawn(refresh(Arc::downgrade(&inner)));
+            *inner.refresh_task.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(task);
+        }
+        Self { inner }
+    }
+
+    /// Value of the secret `name`, from the cache while it is fresh
+    ///
+    /// Fails with [`Error::NotFound`] if the provider has no such secret.
+    pub async fn get(&self, name: &str) -> Result<String> {
+        {
+            let cache = self.inner.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(cached) = cache.get(name) {
+                if cached.read_at.elapsed() < self.inner.ttl {
+                    return Ok(cached.value.clone());
+                }
+            }
+        }
+        self.inner
+            .read(name)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("secret '{}'", name)))
+    }
+
+    /// Run `callback` with the new value whenever the secret `name` changes
+    pub fn on_rotate<F>(&self, name: impl Into<String>, callback: F)
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.inner
+            .rotations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(name.into())
+            .or_default()
+            .push(Arc::new(callback));
+    }
+
+    /// Drop the cached value of `name`, so the next read goes to the provider
+    pub fn invalidate(&self, name: &str) {
+        self.inner
+            .cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(name);
+    }
+}
+
+impl Inner {
+    /// Read `name` from the provider into the cache, running its rotation
+    /// callbacks if the value changed
+    async fn read(&self, name: &str) -> Result<Option<String>> {
+        let value = self.provider.get(name).await?;
+        let previous = {
+            let mut cache = self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match &value {
+                Some(value) => cache.insert(
+                    name.to_string(),
+                    Cached {
+                        value: value.clone(),
+                        read_at: Instant::now(),
+                    },
+                ),
+                None => cache.remove(name),
+            }
+        };
+        if let (Some(previous), Some(value)) = (previous, &value) {
+            if previous.value != *value {
+                self.rotated(name, value);
+            }
+        }
+        Ok(value)
+    }
+
+    fn rotated(&self, name: &str, value: &str) {
+        let callbacks = self
+            .rotations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+        tracing::info!(secret = %name, callbacks = callbacks.len(), "secret rotated");
+        for callback in callbacks {
+            callback(value);
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let task = self.refresh_task.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(task) = task.take() {
+            task.abort();
+        }
+    }
+}
+
+/// Read the secrets with rotation callbacks again every TTL, until the
+/// secrets are dropped
+async fn refresh(inner: Weak<Inner>) {
+    let Some(ttl) = inner.upgrade().map(|inner| inner.ttl) else {
+        return;
+    };
+    let mut ticker = tokio::time::interval(ttl);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+        let names: Vec<String> = inner
+            .rotations
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .keys()
+            .cloned()
+            .collect();
+        for name in names {
+            if let Err(err) = inner.read(&name).await {
+                tracing::warn!(secret = %name, error = %err, "failed to refresh secret");
+            }
+        }
+    }
+}
@@ -1,18 +1,471 @@
 //! Server configuration and main API
 
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::future::IntoFuture;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::Request;
+use axum::response::IntoResponse;
+use axum::routing::Route;
 use axum::Router;
-use crate::module::Module;
-use crate::functions::FunctionRegistry;
-use crate::events::EventRegistry;
-use crate::cache::{CacheProvider, MemoryCacheProvider};
-use crate::error::Result;
+use tower::{Layer, Service};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::module::{Lifecycle, Module, ModuleHealth};
+use crate::functions::{FunctionHandler, FunctionMiddleware, FunctionRegistry, FunctionSignature, DeprecatedFunctionHandler, InFlight, InstrumentedFunctionHandler, InstrumentedStreamingHandler, StreamingFunctionHandler, LayeredFunctionHandler, ValidatedFunctionHandler};
+use crate::events::{
+    DispatchPolicy, EmitFunction, EventListener, EventMiddleware, EventRegistry, FunctionListener,
+    GlobalWildcardPolicy, InstrumentedEventListener,
+};
+use crate::cache::{self, CacheBackendInfo, CacheProvider, CacheStats, MaintenanceHandle, MemoryCacheProvider, ScopedCache, TenantCacheUsage, TenantCaches, TenantQuota};
+use crate::config::{self, ConfigSource};
+use crate::context::{self, Context, FunctionContext};
+use crate::outbox::{Outbox, OutboxRelay};
+use crate::db::{self, Database, ScopedDatabase};
+use crate::pool::DatabasePool;
+use crate::secrets::{SecretProvider, Secrets};
+use crate::retry::{RetryCount, RetryCounters, RetryKind, RetryStats, RetryingFunction, RetryingListener};
+use crate::audit::{AuditListener, AuditLog, AuditedFunction, SurrealAuditLog};
+use crate::stats::{ArgumentRedactor, FunctionStats, KeyRedactor, SlowCallLog, TrackedFunction};
+use crate::sql::{self, QueryEngine};
+use crate::changefeed::{ChangeFeedBridge, ChangeFeedHandle};
+use crate::eventstore::{EventStore, SurrealEventStore};
+use crate::queue::EventQueueConfig;
+use crate::deadletter::{CacheDeadLetterSink, DeadLetterSink, EventDeadLetterSink, SurrealDeadLetterSink};
+use crate::reload::Reloader;
+use crate::middleware::{self, Middleware};
+use crate::auth::{self, Verifier};
+use crate::ratelimit::{self, RateLimitedFunction, RateLimitedStream, RateLimiter, RateLimits};
+use crate::scheduler::{self, RunStore, ScheduledJob, Scheduler};
+use crate::workflow::{Workflow, WorkflowInstance, Workflows};
+use crate::migration::{Migration, MigrationRecord, Migrations};
+use crate::schema::{self, SchemaDrift, Table};
+use crate::tenancy::{self, Provisioning, Tenant, TenantEvents, TenantResolver, TenantRouting};
+use crate::transform::{self, RouteTransform};
+use crate::bridge::EventBridge;
+use crate::streaming::StreamCompression;
+use crate::subscriptions::{self, EventAcl};
+use crate::openapi::{self, ApiDocument, ApiOperation};
+use crate::rest;
+use crate::health::{self, HealthChecks, HealthReport};
+use crate::admin::{self, Admin, DisabledModules};
+use crate::error::{Error, Result};
+
+/// How unknown keys are handled when loading configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfigStrictness {
+    /// Ignore unknown keys, for forward compatibility
+    #[default]
+    Lenient,
+    /// Reject unknown keys with `Error::Config`
+    Strict,
+}
+
+/// What `build()` does when a module fails to initialize
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModuleInitFailurePolicy {
+    /// Fail the whole build
+    #[default]
+    Abort,
+    /// Skip the module, log the failure and record it in the `BuildReport`
+    SkipAndReport,
+}
+
+/// Where module functions are registered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FunctionNamespacing {
+    /// Under `ext::<module>::<name>`, with `ext::<name>` as an alias when
+    /// only one module has a function of that name
+    #[default]
+    Module,
+    /// Under `ext::<name>`
+    Flat,
+}
+
+/// What `build()` does when modules register the same function name or route path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Fail the build with `Error::Config` naming the colliding modules
+    #[default]
+    Reject,
+    /// Log a warning and keep the registration of the module added last
+    Override,
+}
+
+/// Who may run SurrealQL at `POST /sql`
+///
+/// Queries run with the server's own access, as the root of the embedded
+/// database, so `/sql` is not served unless opened here.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SqlAccess {
+    /// `/sql` is not served
+    #[default]
+    Disabled,
+    /// Callers whose token carries `ServerConfig::admin_role`; requires
+    /// `auth`
+    Role,
+    /// Anyone who can reach the server, such as in local development
+    Open,
+}
+
+/// Where events that listeners failed to handle are kept
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterTarget {
+    /// Log the failure and drop the event
+    #[default]
+    None,
+    /// Keep dead letters under a key of the cache provider
+    Cache,
+    /// Keep dead letters in a table of the embedded database
+    Database,
+    /// Emit dead letters as events of this pattern, such as
+    /// `dead_letter:events`, for its listeners to handle; see
+    /// [`EventDeadLetterSink`](crate::deadletter::EventDeadLetterSink)
+    Event(String),
+}
+
+/// Where the start time of each scheduled job's last run is kept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobRunStore {
+    /// Keep nothing; runs missed while the server was down are not made up
+    None,
+    /// Keep it under a key of the cache provider
+    #[default]
+    Cache,
+    /// Keep it in the `sx_job` table of the embedded database
+    Database,
+}
+
+/// What building the server does with migrations not yet applied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationPolicy {
+    /// Apply them in order; the build fails if one fails
+    #[default]
+    Apply,
+    /// Log each one without applying it
+    DryRun,
+    /// Leave them alone
+    Skip,
+}
+
+/// What building the server does with the tables modules declare
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaPolicy {
+    /// Define every declared table, field and index, and report drift
+    #[default]
+    Reconcile,
+    /// Report drift without changing the database; only missing and
+    /// undeclared definitions are found, not changed ones
+    Report,
+    /// Leave the database alone
+    Skip,
+}
+
+/// How the subscriber `serve()` installs prints logs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Pretty,
+    /// One JSON object per line, including the fields of enclosing spans
+    Json,
+}
+
+/// Cache provider `build()` opens when none is set with `with_cache`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum CacheBackend {
+    /// In-process memory
+    #[default]
+    Memory,
+    /// A Redis server (requires the `redis-cache` feature)
+    Redis { url: String },
+    /// A Memcached server (requires the `memcached-cache` feature)
+    Memcached { url: String },
+    /// An on-disk store in a directory (requires the `disk-cache` feature)
+    Disk { path: String },
+}
+
+/// How the bearer tokens of HTTP requests are verified; see the
+/// [`auth`](crate::auth) module
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// JWT algorithm tokens are signed with, such as `HS512` or `RS256`
+    #[serde(default = "AuthConfig::default_algorithm")]
+    pub algorithm: String,
+    /// Secret for the `HS*` algorithms, public key in PEM for the others;
+    /// the key of the SurrealDB access method issuing the tokens
+    pub key: String,
+    /// Only accept tokens whose `iss` claim is this
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Only accept tokens whose `aud` claim includes this
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// Reject requests without a token instead of serving them anonymously
+    #[serde(default)]
+    pub required: bool,
+}
+
+impl AuthConfig {
+    /// Verify tokens signed with `algorithm` and `key`
+    pub fn new(algorithm: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            algorithm: algorithm.into(),
+            key: key.into(),
+            issuer: None,
+            audience: None,
+            required: false,
+        }
+    }
+
+    fn default_algorithm() -> String {
+        "HS512".to_string()
+    }
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("algorithm", &self.algorithm)
+            .field("key", &"<redacted>")
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .field("required", &self.required)
+            .finish()
+    }
+}
+
+/// External SurrealDB server that SurrealX runs in front of, instead of
+/// embedding the database; see the [`upstream`](crate::upstream) module
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UpstreamConfig {
+    /// Address of the server, such as `ws://db:8000` or `https://db:8000`
+    pub url: String,
+    /// Root user SurrealX signs in as; it connects without signing in when
+    /// unset
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password of `username`
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Connections function and module contexts query the server over; see
+    /// the [`pool`](crate::pool) module
+    #[serde(default)]
+    pub pool: PoolConfig,
+}
+
+impl UpstreamConfig {
+    /// Proxy to the server at `url`
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            username: None,
+            password: None,
+            pool: PoolConfig::default(),
+        }
+    }
+
+    /// Sign in to the server as the root user `username`
+    pub fn with_credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self.password = Some(password.into());
+        self
+    }
+}
+
+impl std::fmt::Debug for UpstreamConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpstreamConfig")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+/// Size of the upstream connection pool and how its connections are
+/// maintained
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Most connections open at once
+    pub size: usize,
+    /// How long a connection may go unused before it is closed
+    #[serde(with = "seconds")]
+    pub idle_timeout: Duration,
+    /// How often open connections are checked; broken ones are reopened
+    #[serde(with = "seconds")]
+    pub health_check_interval: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 4,
+            idle_timeout: Duration::from_secs(300),
+            health_check_interval: Duration::from_secs(30),
+        }
+    }
+}
 
 /// Server configuration
-#[derive(Debug, Clone)]
+///
+/// Build one in code, load it with [`from_file`](Self::from_file) or from
+/// `SURREALX_*` variables with [`from_env`](Self::from_env).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
     pub bind_addr: String,
+    /// Directory for on-disk storage; data is kept in memory when unset
     pub data_path: Option<String>,
+    /// Run in front of an external SurrealDB server instead of embedding
+    /// one, in which case `data_path` must be unset
+    ///
+    /// Requires the `upstream` feature.
+    pub upstream: Option<UpstreamConfig>,
+    /// Cache provider, unless one is set with `SurrealX::with_cache`
+    pub cache: CacheBackend,
+    /// SurrealDB namespace used by the embedded database
+    pub namespace: String,
+    /// SurrealDB database used by the embedded database
+    pub database: String,
+    /// Tables whose writes are emitted as events
+    pub live_tables: Vec<String>,
+    /// How catch-all event listeners are treated
+    pub global_wildcard: GlobalWildcardPolicy,
+    /// How emitted events are dispatched to listeners
+    pub event_dispatch: DispatchPolicy,
+    /// Deliver events from a bounded queue rather than while they are
+    /// emitted; see the [`queue`](crate::queue) module
+    pub event_queue: Option<EventQueueConfig>,
+    /// Record emitted events in the embedded database for replay
+    pub event_log: bool,
+    /// Record function calls, admin actions and `audit_events` in the
+    /// embedded database; see the [`audit`](crate::audit) module
+    pub audit_log: bool,
+    /// Event patterns recorded in the audit log
+    pub audit_events: Vec<String>,
+    /// Where events that listeners failed to handle are kept
+    pub dead_letters: DeadLetterTarget,
+    /// Where scheduled jobs keep the start time of their last run
+    pub job_runs: JobRunStore,
+    /// What building the server does with pending module migrations; see
+    /// the [`migration`](crate::migration) module
+    pub migrations: MigrationPolicy,
+    /// What building the server does with the tables modules declare; see
+    /// the [`schema`](crate::schema) module
+    pub schema: SchemaPolicy,
+    /// How often the outbox relay looks for events emitted after commit that
+    /// it was not notified of, such as those left by a previous run; in
+    /// seconds
+    #[serde(with = "seconds")]
+    pub outbox_relay_interval: Duration,
+    /// What to do when a module fails to initialize
+    pub on_module_init_failure: ModuleInitFailurePolicy,
+    /// Serve cache statistics at `GET /sx/cache/stats`, behind
+    /// authentication and the route rate limits
+    pub cache_stats_route: bool,
+    /// Serve `GET /health`, `/ready` and `/version`; see the
+    /// [`health`](crate::health) module
+    pub health_routes: bool,
+    /// Stream events to WebSocket clients at `GET /sx/events`; see the
+    /// [`subscriptions`](crate::subscriptions) module
+    pub event_stream_route: bool,
+    /// Serve an OpenAPI document at `/sx/openapi.json` and Swagger UI at
+    /// `/sx/docs`; see the [`openapi`](crate::openapi) module
+    pub openapi_route: bool,
+    /// Serve registered functions over GraphQL at `/sx/graphql`; see the
+    /// [`graphql`](crate::graphql) module
+    #[cfg(feature = "graphql")]
+    pub graphql_route: bool,
+    /// Serve the gRPC service of `proto/surrealx.proto` on the HTTP port;
+    /// see the [`grpc`](crate::grpc) module
+    #[cfg(feature = "grpc")]
+    pub grpc_service: bool,
+    /// What to do when modules register the same function or route
+    pub on_conflict: ConflictPolicy,
+    /// Where module functions are registered
+    pub function_namespacing: FunctionNamespacing,
+    /// Directory of plugin libraries to load when the server is built
+    ///
+    /// Requires the `dylib-plugins` feature. Every library in it is trusted
+    /// to meet the requirements of `DynamicModule::load`.
+    pub plugin_dir: Option<String>,
+    /// How long `serve()` waits, once asked to stop, for open connections
+    /// and then for in-flight calls to finish; in seconds
+    #[serde(with = "seconds")]
+    pub shutdown_timeout: Duration,
+    /// How long secrets are cached, and how often those with rotation
+    /// callbacks are read again; in seconds, 0 to read them on every use.
+    /// See the [`secrets`](crate::secrets) module
+    #[serde(with = "seconds")]
+    pub secret_ttl: Duration,
+    /// Log calls to module functions taking at least this long, with their
+    /// redacted arguments; in milliseconds. See the [`stats`](crate::stats)
+    /// module
+    #[serde(with = "optional_millis")]
+    pub slow_call_threshold: Option<Duration>,
+    /// Serve HTTPS with this certificate instead of plain HTTP
+    ///
+    /// Requires the `tls` feature.
+    pub tls: Option<TlsConfig>,
+    /// Log filter for the subscriber `serve()` installs, such as `info` or
+    /// `surrealx=debug,warn`
+    ///
+    /// When unset, or when the application installed a subscriber of its
+    /// own, `serve()` leaves logging alone.
+    pub log_level: Option<String>,
+    /// Format of the logs printed for `log_level`
+    pub log_format: LogFormat,
+    /// OTLP/gRPC collector to export spans to, such as `http://localhost:4317`
+    ///
+    /// Requires the `otlp` feature. Logs are printed at `info` unless
+    /// `log_level` says otherwise.
+    pub otlp_endpoint: Option<String>,
+    /// Authenticate requests to module routes and `/sql` with bearer tokens
+    pub auth: Option<AuthConfig>,
+    /// Serve the admin API under `/sx/admin`; requires `auth`. See the
+    /// [`admin`](crate::admin) module
+    pub admin_route: bool,
+    /// Role a token must carry to use the admin API, and `/sql` with
+    /// [`SqlAccess::Role`]
+    pub admin_role: String,
+    /// Who may run SurrealQL at `POST /sql`; nobody by default
+    pub sql_access: SqlAccess,
+    /// Refuse requests the tenant resolver maps to no tenant; see the
+    /// [`tenancy`](crate::tenancy) module
+    pub require_tenant: bool,
+    /// Most each tenant may keep in the cache
+    pub tenant_cache_quota: TenantQuota,
+    /// Quotas of particular tenants, by tenant ID, replacing
+    /// `tenant_cache_quota`
+    pub tenant_cache_quotas: HashMap<String, TenantQuota>,
+}
+
+/// Certificate and private key for serving HTTPS
+///
+/// Both files are PEM encoded; the certificate file holds the full chain,
+/// leaf first. On Unix, SIGHUP reloads both files without dropping open
+/// connections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
 }
 
 impl Default for ServerConfig {
@@ -20,102 +473,1787 @@ impl Default for ServerConfig {
         Self {
             bind_addr: "127.0.0.1:8000".to_string(),
             data_path: None,
+            upstream: None,
+            cache: CacheBackend::default(),
+            namespace: "surrealx".to_string(),
+            database: "main".to_string(),
+            live_tables: Vec::new(),
+            global_wildcard: GlobalWildcardPolicy::default(),
+            event_dispatch: DispatchPolicy::default(),
+            event_queue: None,
+            event_log: false,
+            audit_log: false,
+            audit_events: Vec::new(),
+            dead_letters: DeadLetterTarget::default(),
+            job_runs: JobRunStore::default(),
+            migrations: MigrationPolicy::default(),
+            schema: SchemaPolicy::default(),
+            outbox_relay_interval: Duration::from_secs(5),
+            on_module_init_failure: ModuleInitFailurePolicy::default(),
+            cache_stats_route: false,
+            health_routes: true,
+            event_stream_route: false,
+            openapi_route: false,
+            #[cfg(feature = "graphql")]
+            graphql_route: false,
+            #[cfg(feature = "grpc")]
+            grpc_service: false,
+            on_conflict: ConflictPolicy::default(),
+            function_namespacing: FunctionNamespacing::default(),
+            plugin_dir: None,
+            shutdown_timeout: Duration::from_secs(30),
+            secret_ttl: Duration::from_secs(300),
+            slow_call_threshold: None,
+            tls: None,
+            log_level: None,
+            log_format: LogFormat::default(),
+            otlp_endpoint: None,
+            auth: None,
+            admin_route: false,
+            admin_role: "Owner".to_string(),
+            sql_access: SqlAccess::default(),
+            require_tenant: false,
+            tenant_cache_quota: TenantQuota::default(),
+            tenant_cache_quotas: HashMap::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Serve HTTPS with the given PEM certificate chain and private key
+    pub fn tls(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        self.tls = Some(TlsConfig {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        });
+        self
+    }
+
+    /// Proxy the SurrealDB server at `url`, such as `ws://db:8000`, instead
+    /// of embedding the database
+    pub fn upstream(mut self, url: impl Into<String>) -> Self {
+        self.upstream = Some(UpstreamConfig::new(url));
+        self
+    }
+
+    /// Load configuration from a `.toml` or `.json` file
+    ///
+    /// Unknown keys are ignored; parse the file with `from_value` to reject
+    /// them instead.
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let value = ConfigSource::file(path).load()?;
+        Self::from_value(value, ConfigStrictness::default())
+            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))
+    }
+
+    /// Load configuration from `SURREALX_*` environment variables
+    ///
+    /// Anything not set keeps its default; see [`with_env`](Self::with_env)
+    /// for the variables read.
+    pub fn from_env() -> Result<Self> {
+        Self::default().with_env()
+    }
+
+    /// Override settings with those given in environment variables
+    ///
+    /// | Variable | Setting |
+    /// |---|---|
+    /// | `SURREALX_BIND_ADDR` | `bind_addr` |
+    /// | `SURREALX_DATA_PATH` | `data_path`; empty for in-memory storage |
+    /// | `SURREALX_UPSTREAM` | `upstream`; empty to embed the database |
+    /// | `SURREALX_UPSTREAM_USER`, `SURREALX_UPSTREAM_PASS` | `upstream` credentials; both or neither |
+    /// | `SURREALX_CACHE` | `cache`: `memory`, `redis`, `memcached` or `disk` |
+    /// | `SURREALX_CACHE_URL` | server URL for `redis` and `memcached` |
+    /// | `SURREALX_CACHE_PATH` | directory for `disk` |
+    /// | `SURREALX_TLS_CERT`, `SURREALX_TLS_KEY` | `tls`; both or neither |
+    /// | `SURREALX_LOG_LEVEL` | `log_level` |
+    /// | `SURREALX_LOG_FORMAT` | `log_format`: `pretty` or `json` |
+    /// | `SURREALX_OTLP_ENDPOINT` | `otlp_endpoint` |
+    /// | `SURREALX_AUTH_KEY` | `auth`, with the key tokens are verified with |
+    /// | `SURREALX_AUTH_ALGORITHM` | `auth.algorithm`; `HS512` by default |
+    ///
+    /// Typically applied on top of a file, so each environment only sets
+    /// what differs: `ServerConfig::from_file("surrealx.toml")?.with_env()?`.
+    pub fn with_env(self) -> Result<Self> {
+        self.with_vars(|name| std::env::var(name).ok())
+    }
+
+    fn with_vars(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let required = |name: &str, backend: &str| {
+            var(name).ok_or_else(|| Error::Config(format!("SURREALX_CACHE={} requires {}", backend, name)))
+        };
+
+        if let Some(bind_addr) = var("SURREALX_BIND_ADDR") {
+            self.bind_addr = bind_addr;
+        }
+        if let Some(data_path) = var("SURREALX_DATA_PATH") {
+            self.data_path = Some(data_path).filter(|path| !path.is_empty());
+        }
+        if let Some(url) = var("SURREALX_UPSTREAM") {
+            self.upstream = Some(url).filter(|url| !url.is_empty()).map(UpstreamConfig::new);
+        }
+        match (var("SURREALX_UPSTREAM_USER"), var("SURREALX_UPSTREAM_PASS"), self.upstream.take()) {
+            (Some(username), Some(password), Some(upstream)) => {
+                self.upstream = Some(upstream.with_credentials(username, password));
+            }
+            (None, None, upstream) => self.upstream = upstream,
+            (Some(_), Some(_), None) => {
+                return Err(Error::Config("SURREALX_UPSTREAM_USER requires an upstream".to_string()))
+            }
+            _ => {
+                return Err(Error::Config(
+                    "SURREALX_UPSTREAM_USER and SURREALX_UPSTREAM_PASS must be set together".to_string(),
+                ))
+            }
+        }
+        if let Some(backend) = var("SURREALX_CACHE") {
+            self.cache = match backend.as_str() {
+                "memory" => CacheBackend::Memory,
+                "redis" => CacheBackend::Redis {
+                    url: required("SURREALX_CACHE_URL", &backend)?,
+                },
+                "memcached" => CacheBackend::Memcached {
+                    url: required("SURREALX_CACHE_URL", &backend)?,
+                },
+                "disk" => CacheBackend::Disk {
+                    path: required("SURREALX_CACHE_PATH", &backend)?,
+                },
+                other => return Err(Error::Config(format!("SURREALX_CACHE: unknown cache backend '{}'", other))),
+            };
+        }
+        match (var("SURREALX_TLS_CERT"), var("SURREALX_TLS_KEY")) {
+            (Some(cert_path), Some(key_path)) => self = self.tls(cert_path, key_path),
+            (None, None) => {}
+            _ => {
+                return Err(Error::Config(
+                    "SURREALX_TLS_CERT and SURREALX_TLS_KEY must be set together".to_string(),
+                ))
+            }
+        }
+        if let Some(log_level) = var("SURREALX_LOG_LEVEL") {
+            self.log_level = Some(log_level);
+        }
+        if let Some(log_format) = var("SURREALX_LOG_FORMAT") {
+            self.log_format = match log_format.as_str() {
+                "pretty" => LogFormat::Pretty,
+                "json" => LogFormat::Json,
+                other => return Err(Error::Config(format!("SURREALX_LOG_FORMAT: unknown log format '{}'", other))),
+            };
+        }
+        if let Some(otlp_endpoint) = var("SURREALX_OTLP_ENDPOINT") {
+            self.otlp_endpoint = Some(otlp_endpoint);
+        }
+        if let Some(key) = var("SURREALX_AUTH_KEY") {
+            let algorithm = var("SURREALX_AUTH_ALGORITHM").unwrap_or_else(AuthConfig::default_algorithm);
+            self.auth = Some(AuthConfig {
+                key,
+                algorithm,
+                ..self.auth.unwrap_or_else(|| AuthConfig::new("", ""))
+            });
+        }
+        Ok(self)
+    }
+
+    /// Load configuration from a JSON document
+    pub fn from_json(json: &str, strictness: ConfigStrictness) -> Result<Self> {
+        let value = serde_json::from_str(json)
+            .map_err(|err| Error::Config(format!("invalid configuration: {}", err)))?;
+        Self::from_value(value, strictness)
+    }
+
+    /// Load configuration from a parsed manifest
+    ///
+    /// Missing keys fall back to their defaults. In strict mode any key that
+    /// is not a `ServerConfig` field is rejected.
+    pub fn from_value(value: Value, strictness: ConfigStrictness) -> Result<Self> {
+        if strictness == ConfigStrictness::Strict {
+            if let (Value::Object(given), Ok(Value::Object(known))) =
+                (&value, serde_json::to_value(Self::default()))
+            {
+                if let Some(key) = given.keys().find(|key| !known.contains_key(*key)) {
+                    return Err(Error::Config(format!("unknown configuration key '{}'", key)));
+                }
+            }
         }
+
+        serde_json::from_value(value)
+            .map_err(|err| Error::Config(format!("invalid configuration: {}", err)))
     }
 }
 
 /// Main SurrealX API
 pub struct SurrealX {
+    config: ServerConfig,
     modules: Vec<Module>,
     function_registry: FunctionRegistry,
     event_registry: EventRegistry,
     cache_provider: Arc<dyn CacheProvider>,
+    /// Whether `cache_provider` was set with `with_cache` rather than
+    /// left to `ServerConfig::cache`
+    custom_cache: bool,
+    event_store: Option<Arc<dyn EventStore>>,
+    /// Set with `with_audit_log`, or by `build()` from `ServerConfig::audit_log`
+    audit_log: Option<Arc<dyn AuditLog>>,
+    secret_provider: Option<Arc<dyn SecretProvider>>,
+    /// Set by `build()` from `secret_provider`
+    secrets: Option<Secrets>,
+    config_sources: Vec<ConfigSource>,
+    hot_reload: Option<PathBuf>,
+    in_flight: InFlight,
+    middleware: Vec<Arc<Middleware>>,
+    function_middleware: Vec<Arc<dyn FunctionMiddleware>>,
+    /// Set by `build()` from `ServerConfig::auth`
+    verifier: Option<Verifier>,
+    /// Set by `build()` once the database is open
+    db: Option<ScopedDatabase>,
+    disabled_modules: DisabledModules,
+    rate_limits: RateLimits,
+    bridges: Vec<Arc<dyn EventBridge>>,
+    event_acl: EventAcl,
+    stream_compression: Option<StreamCompression>,
+    argument_redactor: Arc<dyn ArgumentRedactor>,
+    retry_counters: Arc<RetryCounters>,
+    functions_over_http: bool,
+    tenant_resolver: Option<Arc<dyn TenantResolver>>,
+    /// Set by `build()` from the tenant cache quotas of the configuration
+    tenant_caches: TenantCaches,
+    /// Set by `build()` when there is a tenant resolver
+    provisioning: Option<Arc<Provisioning>>,
+    /// Set by `build()` when the configuration names an upstream server
+    #[cfg(feature = "upstream")]
+    upstream: Option<Arc<crate::upstream::Upstream>>,
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::Metrics,
 }
 
+type ModuleSettings = Option<Arc<dyn Any + Send + Sync>>;
+
 impl SurrealX {
     /// Create a new SurrealX instance
     pub fn new() -> Self {
         Self {
+            config: ServerConfig::default(),
             modules: Vec::new(),
             function_registry: FunctionRegistry::new(),
             event_registry: EventRegistry::new(),
             cache_provider: Arc::new(MemoryCacheProvider::new()),
+            custom_cache: false,
+            event_store: None,
+            audit_log: None,
+            secret_provider: None,
+            secrets: None,
+            config_sources: Vec::new(),
+            hot_reload: None,
+            in_flight: InFlight::default(),
+            middleware: Vec::new(),
+            function_middleware: Vec::new(),
+            verifier: None,
+            db: None,
+            disabled_modules: DisabledModules::default(),
+            rate_limits: RateLimits::default(),
+            bridges: Vec::new(),
+            event_acl: EventAcl::default(),
+            stream_compression: None,
+            argument_redactor: Arc::new(KeyRedactor::default()),
+            retry_counters: Arc::default(),
+            functions_over_http: false,
+            tenant_resolver: None,
+            tenant_caches: TenantCaches::default(),
+            provisioning: None,
+            #[cfg(feature = "upstream")]
+            upstream: None,
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            #[cfg(feature = "metrics")]
+            metrics: crate::metrics::Metrics::new(),
         }
     }
 
+    /// Set the server configuration used by `build()`
+    pub fn with_config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Keep the database in memory whatever `data_path` says
+    #[cfg(feature = "testing")]
+    pub(crate) fn in_memory(mut self) -> Self {
+        self.config.data_path = None;
+        self
+    }
+
+    /// Add a source of module configuration
+    ///
+    /// Sources are read by `build()` and merged in the order they were added,
+    /// so later sources override earlier ones; see the [`config`](crate::config)
+    /// module.
+    pub fn with_config_source(mut self, source: ConfigSource) -> Self {
+        self.config_sources.push(source);
+        self
+    }
+
     /// Add a module
     pub fn with_module(mut self, module: Module) -> Self {
         self.modules.push(module);
         self
     }
 
+    /// Load a module from a plugin library and add it
+    ///
+    /// # Safety
+    ///
+    /// See [`DynamicModule::load`](crate::plugin::DynamicModule::load): the
+    /// library must be a plugin built with the same compiler and `surrealx`
+    /// version.
+    #[cfg(feature = "dylib-plugins")]
+    pub unsafe fn load_plugin(self, path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let module = crate::plugin::DynamicModule::load(path)?;
+        Ok(self.with_module(module))
+    }
+
+    /// Load a module from a WebAssembly plugin and add it
+    ///
+    /// See [`wasm`](crate::wasm) for the ABI the plugin must implement.
+    #[cfg(feature = "wasm-plugins")]
+    pub fn load_wasm_module(self, path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let module = crate::wasm::WasmModule::load(path)?;
+        Ok(self.with_module(module))
+    }
+
+    /// Load modules from a directory when the server starts, and reload them
+    /// whenever the directory changes
+    ///
+    /// The directory may hold WASM plugins (with `wasm-plugins`), plugin
+    /// libraries (with `dylib-plugins`) and `.toml` or `.json` files, which
+    /// are added to the configuration sources of those modules. On a change
+    /// the new modules are validated and started, their functions, listeners
+    /// and routes replace the old ones in one swap, and the old modules are
+    /// shut down once their in-flight calls finish. A reload that fails
+    /// leaves the old modules in place.
+    ///
+    /// Functions of reloaded modules never shadow those of modules added with
+    /// `with_module`, and conflicts between the two are not reported. A
+    /// plugin library stays loaded once loaded, so a changed library must be
+    /// given a new file name. Every library in the directory is trusted as
+    /// for `ServerConfig::plugin_dir`.
+    pub fn with_hot_reload(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.hot_reload = Some(dir.into());
+        self
+    }
+
+    /// Wrap every HTTP route in a tower layer, such as CORS or compression
+    ///
+    /// Applies to module routes, including hot-reloaded ones, and to the
+    /// built-in routes. Layers added later wrap those added earlier, so they
+    /// see requests first. Layers for one module's routes only are added with
+    /// [`Module::with_middleware`].
+    pub fn with_middleware<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.middleware.push(middleware::erase(layer));
+        self
+    }
+
+    /// Limit calls of a function, by its fully qualified name such as
+    /// `ext::billing::refund`
+    ///
+    /// Calls over the limit fail with [`Error::RateLimited`] before the
+    /// function runs, and `/sql` answers them with `429 Too Many Requests`.
+    /// With [`RateLimiter::per_identity`] each caller has its own quota.
+    pub fn with_function_rate_limit(mut self, function: impl Into<String>, limiter: RateLimiter) -> Self {
+        self.rate_limits.functions.insert(function.into(), limiter);
+        self
+    }
+
+    /// Limit requests to module routes and `/sql` whose path starts with `prefix`
+    ///
+    /// Requests over the limit get `429 Too Many Requests` with a
+    /// `Retry-After` header. A request matching several prefixes takes a token
+    /// from each of their limiters.
+    pub fn with_route_rate_limit(mut self, prefix: impl Into<String>, limiter: RateLimiter) -> Self {
+        self.rate_limits.routes.push((prefix.into(), limiter));
+        self
+    }
+
+    /// Connect the event registry to an external messaging system, such as
+    /// a [`KafkaBridge`](crate::kafka::KafkaBridge)
+    ///
+    /// The bridge starts with the server, after the modules, and stops at
+    /// shutdown; see [`EventBridge`].
+    pub fn with_bridge<B>(mut self, bridge: B) -> Self
+    where
+        B: EventBridge + 'static,
+    {
+        self.bridges.push(Arc::new(bridge));
+        self
+    }
+
+    /// Stream the events matching `pattern` only to callers holding one of
+    /// `roles`
+    ///
+    /// Applies to [`subscriptions`](crate::subscriptions): subscribing to
+    /// `pattern` itself is refused, and any event it matches is withheld,
+    /// unless the caller's [`Identity`](crate::Identity) has one of the
+    /// roles. An event matched by several guarded patterns needs a role
+    /// from each.
+    pub fn with_event_access<R>(mut self, pattern: impl Into<String>, roles: R) -> Self
+    where
+        R: IntoIterator,
+        R::Item: Into<String>,
+    {
+        self.event_acl.guard(pattern.into(), roles.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Compress large frames of event streams
+    ///
+    /// WebSocket clients connecting with `compression=deflate` get frames at
+    /// or above the threshold as binary raw DEFLATE; others, and every client
+    /// without it, get JSON text. SSE streams are gzip encoded for clients
+    /// accepting it.
+    pub fn with_stream_compression(mut self, compression: StreamCompression) -> Self {
+        self.stream_compression = Some(compression);
+        self
+    }
+
+    /// Redact the arguments of slow calls with `redactor` before logging them,
+    /// instead of with [`KeyRedactor`]; see `ServerConfig::slow_call_threshold`
+    pub fn with_argument_redactor<R: ArgumentRedactor + 'static>(mut self, redactor: R) -> Self {
+        self.argument_redactor = Arc::new(redactor);
+        self
+    }
+
+    /// Serve every registered function at `POST /sx/fn/{name}`
+    ///
+    /// The request body is the JSON array of arguments and the response the
+    /// function's result; see the [`rest`](crate::rest) module.
+    pub fn expose_functions_over_http(mut self, expose: bool) -> Self {
+        self.functions_over_http = expose;
+        self
+    }
+
+    /// Map requests and events to tenants with `resolver`, partitioning the
+    /// database, cache and events by tenant; see the
+    /// [`tenancy`](crate::tenancy) module
+    pub fn with_tenant_resolver<R>(mut self, resolver: R) -> Self
+    where
+        R: TenantResolver + 'static,
+    {
+        self.tenant_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Serve HTTPS with a rustls configuration built by the caller
+    ///
+    /// Takes precedence over `ServerConfig::tls`. Use it for client
+    /// certificate authentication, custom certificate resolvers or other
+    /// settings the certificate files cannot express; SIGHUP does not reload
+    /// anything for an injected configuration. Set `alpn_protocols` to offer
+    /// HTTP/2.
+    #[cfg(feature = "tls")]
+    pub fn with_tls_config(mut self, config: Arc<rustls::ServerConfig>) -> Self {
+        self.tls_config = Some(config);
+        self
+    }
+
     /// Set cache provider
+    ///
+    /// Takes precedence over `ServerConfig::cache`.
     pub fn with_cache<C>(mut self, provider: C) -> Self
     where
         C: CacheProvider + 'static,
     {
         self.cache_provider = Arc::new(provider);
+        self.custom_cache = true;
+        self
+    }
+
+    /// Run every emitted event through `middleware`, in the order added
+    ///
+    /// See [`EventMiddleware`]; modules add their own
+    /// with [`Module::with_event_middleware`].
+    pub fn with_event_middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: EventMiddleware + 'static,
+    {
+        self.event_registry = std::mem::take(&mut self.event_registry).with_middleware(middleware);
+        self
+    }
+
+    /// Run calls of every module's functions through `middleware`, in the
+    /// order added
+    ///
+    /// Applies to hot-reloaded modules too. See [`FunctionMiddleware`];
+    /// modules add their own with [`Module::with_function_middleware`].
+    pub fn with_function_middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: FunctionMiddleware + 'static,
+    {
+        self.function_middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Record emitted events in a custom store instead of the embedded database
+    ///
+    /// Takes effect regardless of `ServerConfig::event_log`.
+    pub fn with_event_store<S>(mut self, store: S) -> Self
+    where
+        S: EventStore + 'static,
+    {
+        self.event_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Record function calls, admin actions and `ServerConfig::audit_events`
+    /// in a custom audit log instead of the embedded database
+    ///
+    /// Takes effect regardless of `ServerConfig::audit_log`.
+    pub fn with_audit_log<L>(mut self, log: L) -> Self
+    where
+        L: AuditLog + 'static,
+    {
+        self.audit_log = Some(Arc::new(log));
         self
     }
 
+    /// Let handlers read secrets from `provider` with `ctx.secret(name)`;
+    /// see the [`secrets`](crate::secrets) module
+    pub fn with_secret_provider<P>(mut self, provider: P) -> Self
+    where
+        P: SecretProvider + 'static,
+    {
+        self.secret_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Validate the configured modules without building or serving
+    ///
+    /// Runs the same validation as `build()`, from the auth key and settings
+    /// that require each other to job schedules, pings the cache provider
+    /// and returns a report of everything that would be registered. Plugins
+    /// in `ServerConfig::plugin_dir` are not loaded, only their directory is
+    /// checked.
+    pub async fn check(&self) -> Result<BuildReport> {
+        let (report, _, _) = self.plan()?;
+        self.open_cache()?.exists("sx:check").await?;
+        Ok(report)
+    }
+
     /// Build the extension system
     pub async fn build(mut self) -> Result<BuiltSurrealX> {
+        self.load_plugin_dir()?;
+        self.cache_provider = self.open_cache()?;
+        self.custom_cache = true;
+        self.tenant_caches = TenantCaches::new(self.config.tenant_cache_quota, self.config.tenant_cache_quotas.clone());
+        self.secrets = self
+            .secret_provider
+            .take()
+            .map(|provider| Secrets::new(provider, self.config.secret_ttl));
+        self.verifier = self.verifier()?;
+        self.rate_limits = self.rate_limits.attach(&self.cache_provider);
+        #[cfg(feature = "metrics")]
+        self.metrics.observe_cache(self.cache_provider.clone());
+        let (mut report, healthy, settings) = self.plan()?;
+        let db = db::connect(&self.config).await?;
+        let pool = match &self.config.upstream {
+            Some(_) => Some(DatabasePool::new(self.config.clone())?),
+            None => None,
+        };
+        self.db = Some(
+            ScopedDatabase::new(db.clone(), &self.config.namespace, &self.config.database).with_pool(pool.clone()),
+        );
+        report.schema_drift = schema::reconcile(&db, None, self.module_tables(&healthy), self.config.schema).await?;
+        let migrations = Migrations::new(db.clone(), self.module_migrations(&healthy))?;
+        match self.config.migrations {
+            MigrationPolicy::Apply => {
+                migrations.apply().await?;
+            }
+            MigrationPolicy::DryRun => {
+                for pending in migrations.pending().await? {
+                    tracing::info!(module = %pending.module, version = pending.version, "pending migration not applied (dry run)");
+                }
+            }
+            MigrationPolicy::Skip => {}
+        }
+        if self.tenant_resolver.is_some() {
+            self.provisioning = Some(Arc::new(Provisioning::new(
+                db.clone(),
+                &self.config.database,
+                self.module_tables(&healthy),
+                self.config.schema,
+                migrations.clone(),
+                self.config.migrations,
+            )));
+        }
+
+        self.event_registry = std::mem::take(&mut self.event_registry)
+            .with_global_wildcard(self.config.global_wildcard)
+            .with_dispatch(self.config.event_dispatch);
+        if let Some(queue) = self.config.event_queue {
+            self.event_registry = std::mem::take(&mut self.event_registry).with_queue(queue);
+        }
+        let event_store = self.event_store.take().or_else(|| {
+            self.config
+                .event_log
+                .then(|| Arc::new(SurrealEventStore::new(db.clone())) as Arc<dyn EventStore>)
+        });
+        if let Some(store) = event_store {
+            self.event_registry = std::mem::take(&mut self.event_registry).with_store(store);
+        }
+        if self.audit_log.is_none() && self.config.audit_log {
+            self.audit_log = Some(Arc::new(SurrealAuditLog::new(db.clone())));
+        }
+        if self.audit_log.is_none() && !self.config.audit_events.is_empty() {
+            return Err(Error::Config("audit_events requires an audit log".to_string()));
+        }
+        let dead_letters: Option<Arc<dyn DeadLetterSink>> = match &self.config.dead_letters {
+            DeadLetterTarget::None => None,
+            DeadLetterTarget::Cache => Some(Arc::new(CacheDeadLetterSink::new(self.cache_provider.clone()))),
+            DeadLetterTarget::Database => Some(Arc::new(SurrealDeadLetterSink::new(db.clone()))),
+            DeadLetterTarget::Event(pattern) => Some(Arc::new(EventDeadLetterSink::new(
+                self.event_registry.clone(),
+                pattern.clone(),
+            )?)),
+        };
+        if let Some(sink) = dead_letters {
+            self.event_registry = std::mem::take(&mut self.event_registry).with_dead_letters(sink);
+        }
+        if let (Some(resolver), Some(provisioning)) = (&self.tenant_resolver, &self.provisioning) {
+            self.event_registry = std::mem::take(&mut self.event_registry).with_middleware(TenantEvents {
+                resolver: resolver.clone(),
+                provisioning: provisioning.clone(),
+            });
+        }
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.observe_events(self.event_registry.clone());
+            self.metrics.observe_retries(self.retry_counters.clone());
+        }
+
         // Register all functions from modules
-        for module in &self.modules {
-            for (name, handler) in module.functions() {
-                // Functions in modules are registered with ext:: prefix
-                let full_name = format!("ext::{}", name);
-                self.function_registry.register_arc(full_name, handler.clone());
+        for (name, handler) in self.module_functions(&healthy, &settings) {
+            self.function_registry.register_arc(name, handler);
+        }
+        for (name, handler) in self.module_streams(&healthy, &settings) {
+            self.function_registry.register_stream(name, handler);
+        }
+        for (alias, target) in self.aliases(&healthy) {
+            self.function_registry.register_alias(alias, target);
+        }
+        for (name, signature) in self.module_signatures(&healthy) {
+            self.function_registry.register_signature(name, signature);
+        }
+        for &index in &healthy {
+            let module = &self.modules[index];
+            for (name, note) in module.deprecations() {
+                self.function_registry
+                    .register_deprecation(self.function_name(module, name), note.clone());
+            }
+        }
+        for &index in &healthy {
+            for (pattern, schema) in self.modules[index].event_schemas() {
+                self.event_registry.register_schema(pattern.clone(), schema.clone());
+            }
+            for middleware in self.modules[index].event_middleware() {
+                self.event_registry
+                    .register_module_middleware(self.modules[index].name(), middleware.clone());
             }
         }
 
+        let function_context = FunctionContext::new(
+            self.config.namespace.clone(),
+            self.config.database.clone(),
+            self.cache_provider.clone(),
+            self.event_registry.clone(),
+        )
+        .with_database(db.clone())
+        .with_pool(pool.clone())
+        .with_tenant_caches(self.tenant_caches.clone())
+        .with_secrets(self.secrets.clone());
+
+        // Built-in functions available to queries
+        self.function_registry.register(
+            "sx::emit",
+            EmitFunction {
+                events: self.event_registry.clone(),
+            },
+        );
+
         // Register all event listeners from modules
-        for module in &self.modules {
-            for (pattern, listener) in module.listeners() {
-                self.event_registry.register_arc(pattern, listener.clone()).await;
+        let listeners = self.module_listeners(&healthy, &settings, &function_context, |name| {
+            self.function_registry.get(name)
+        })?;
+        for (module, pattern, listener) in listeners {
+            self.event_registry.register_for(&module, pattern, listener).await?;
+        }
+        if let Some(log) = &self.audit_log {
+            for pattern in &self.config.audit_events {
+                self.event_registry
+                    .register(pattern.clone(), AuditListener { log: log.clone() })
+                    .await?;
             }
         }
 
-        let router = self.build_router();
+        let outbox = Outbox::new(self.event_registry.clone(), self.cache_provider.clone()).with_database(db.clone());
+        let engine = QueryEngine::new(
+            db.clone(),
+            self.function_registry.clone(),
+            outbox.clone(),
+            function_context.clone(),
+        );
+        // Before the reloader's template is taken, so reloaded routes forward
+        // to the upstream server too
+        #[cfg(feature = "upstream")]
+        if let Some(upstream) = &self.config.upstream {
+            self.upstream = Some(Arc::new(crate::upstream::Upstream::new(upstream, engine.clone())?));
+        }
+
+        let reloader = self
+            .hot_reload
+            .take()
+            .map(|dir| Arc::new(Reloader::new(dir, self.template(), function_context.clone())));
+
+        let lifecycles = Arc::new(self.module_lifecycles(&healthy, &settings));
+        let job_runs = match self.config.job_runs {
+            JobRunStore::None => RunStore::None,
+            JobRunStore::Cache => RunStore::Cache(self.cache_provider.clone()),
+            JobRunStore::Database => RunStore::Database(db.clone()),
+        };
+        let scheduler = Arc::new(Scheduler::new(
+            self.module_jobs(&healthy, &settings),
+            job_runs,
+            self.event_registry.clone(),
+            self.in_flight.clone(),
+        )?);
+        let workflows = Workflows::new(
+            self.module_workflows(&healthy, &settings),
+            db.clone(),
+            self.event_registry.clone(),
+            self.in_flight.clone(),
+        )?;
+        for (pattern, trigger) in workflows.triggers() {
+            self.event_registry.register_arc(pattern, trigger).await?;
+        }
+        let health_checks = HealthChecks::new(
+            db.clone(),
+            self.cache_provider.clone(),
+            lifecycles.clone(),
+            reloader.clone(),
+        );
+
+        let mut router = self.build_router(&healthy);
+        match self.config.sql_access {
+            SqlAccess::Disabled => {}
+            SqlAccess::Role => {
+                let sql = auth::require_role(sql::router(engine), self.config.admin_role.clone());
+                router = router.merge(self.protected(sql));
+            }
+            SqlAccess::Open => router = router.merge(self.protected(sql::router(engine))),
+        }
+        if self.config.cache_stats_route {
+            router = router.merge(self.protected(cache::stats_router(self.cache_provider.clone())));
+        }
+        if self.config.health_routes {
+            router = router.merge(health::router(health_checks.clone()));
+        }
+        if self.config.event_stream_route {
+            router = router.merge(self.protected(subscriptions::router(
+                self.event_registry.clone(),
+                self.event_acl.clone(),
+                self.stream_compression.clone(),
+                self.functions_over_http.then(|| {
+                    rest::Functions::new(self.function_registry.clone(), outbox.clone(), function_context.clone())
+                }),
+            )));
+        }
+        #[cfg(feature = "graphql")]
+        if self.config.graphql_route {
+            router = router.merge(self.protected(crate::graphql::router(
+                self.function_registry.clone(),
+                outbox.clone(),
+                function_context.clone(),
+            )?));
+        }
+        #[cfg(feature = "grpc")]
+        if self.config.grpc_service {
+            router = router.merge(self.protected(crate::grpc::router(
+                self.function_registry.clone(),
+                outbox.clone(),
+                function_context.clone(),
+                self.event_registry.clone(),
+                self.event_acl.clone(),
+            )));
+        }
+        if self.functions_over_http {
+            router = router.merge(self.protected(rest::router(
+                self.function_registry.clone(),
+                outbox.clone(),
+                function_context.clone(),
+            )));
+        }
+        if self.config.openapi_route {
+            router = router.merge(openapi::router(self.api_document(&healthy)));
+        }
+        if self.config.admin_route {
+            let admin = Admin {
+                functions: self.function_registry.clone(),
+                events: self.event_registry.clone(),
+                cache: self.cache_provider.clone(),
+                health: health_checks.clone(),
+                scheduler: scheduler.clone(),
+                modules: Arc::new(report.modules.clone()),
+                failed_modules: Arc::new(report.failed_modules.clone()),
+                disabled: self.disabled_modules.clone(),
+                audit: self.audit_log.clone(),
+            };
+            router = router.merge(self.protected(admin::router(admin, self.config.admin_role.clone())));
+        }
+        #[cfg(feature = "metrics")]
+        {
+            router = router.merge(crate::metrics::router(self.metrics.clone()));
+        }
+        if let Some(reloader) = &reloader {
+            router = crate::reload::fallback(router, reloader.clone());
+        }
+        router = transform::transform_routes(router, self.module_transforms(&healthy), self.disabled_modules.clone());
+        router = middleware::apply(router, &self.middleware);
+        #[cfg(feature = "metrics")]
+        {
+            router = crate::metrics::track_requests(router, self.metrics.clone());
+        }
+        let router = crate::telemetry::trace_requests(router);
+
+        let change_feed = if self.config.live_tables.is_empty() {
+            None
+        } else {
+            let bridge = self
+                .config
+                .live_tables
+                .iter()
+                .fold(ChangeFeedBridge::new(db.clone(), self.event_registry.clone()), |bridge, table| {
+                    bridge.with_table(table.clone())
+                });
+            Some(bridge.start().await?)
+        };
+        let cache_maintenance = self.cache_provider.start_maintenance();
+        let outbox_relay = outbox.start_relay(self.config.outbox_relay_interval)?;
 
         Ok(BuiltSurrealX {
             function_registry: self.function_registry,
             event_registry: self.event_registry,
             cache_provider: self.cache_provider,
+            outbox,
+            outbox_relay,
+            db,
+            change_feed,
+            cache_maintenance,
             router,
+            report,
+            function_context,
+            lifecycles,
+            reloader,
+            health_checks,
+            scheduler,
+            workflows,
+            migrations,
+            disabled_modules: self.disabled_modules,
+            tenant_caches: self.tenant_caches,
+            provisioning: self.provisioning,
+            pool,
+            audit_log: self.audit_log,
+            retry_counters: self.retry_counters,
+            bridges: self.bridges,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            in_flight: self.in_flight,
+            shutdown_timeout: self.config.shutdown_timeout,
         })
     }
 
-    /// Serve the SurrealX server
-    pub async fn serve(self, _config: ServerConfig) -> Result<()> {
+    /// The signatures of the functions of `modules`, under their fully
+    /// qualified names
+    fn module_signatures(&self, modules: &[usize]) -> Vec<(String, FunctionSignature)> {
+        modules
+            .iter()
+            .flat_map(|&index| {
+                let module = &self.modules[index];
+                module
+                    .signatures()
+                    .iter()
+                    .map(move |(name, signature)| (self.function_name(module, name), signature.clone()))
+            })
+            .collect()
+    }
+
+    /// Wrap the functions of `modules` so they run in their module's context,
+    /// under their fully qualified names
+    fn module_functions(&self, modules: &[usize], settings: &[ModuleSettings]) -> Vec<(String, Arc<dyn FunctionHandler>)> {
+        modules
+            .iter()
+            .flat_map(|&index| {
+                let module = &self.modules[index];
+                module.functions().iter().map(move |(name, handler)| {
+                    // Retries run inside validation, so rejected arguments are not retried
+                    let handler: Arc<dyn FunctionHandler> = match module.function_retries().iter().find(|(function, _)| function == name) {
+                        Some((_, policy)) => Arc::new(RetryingFunction {
+                            inner: handler.clone(),
+                            policy: policy.clone(),
+                            count: self.retry_count(RetryKind::Function, module, self.function_name(module, name)),
+                        }),
+                        None => handler.clone(),
+                    };
+                    let handler: Arc<dyn FunctionHandler> = match module
+                        .signatures()
+                        .iter()
+                        .find(|(function, signature)| function == name && signature.validate)
+                    {
+                        Some((_, signature)) => Arc::new(ValidatedFunctionHandler {
+                            inner: handler,
+                            function: self.function_name(module, name),
+                            signature: signature.clone(),
+                        }),
+                        None => handler,
+                    };
+                    let middleware: Vec<_> = self
+                        .function_middleware
+                        .iter()
+                        .chain(module.function_middleware().iter().filter_map(|(function, middleware)| {
+                            match function {
+                                Some(function) if function != name => None,
+                                _ => Some(middleware),
+                            }
+                        }))
+                        .cloned()
+                        .collect();
+                    let handler: Arc<dyn FunctionHandler> = if middleware.is_empty() {
+                        handler
+                    } else {
+                        Arc::new(LayeredFunctionHandler {
+                            inner: handler,
+                            function: self.function_name(module, name),
+                            middleware,
+                        })
+                    };
+                    let handler: Arc<dyn FunctionHandler> = match module.deprecations().iter().find(|(function, _)| function == name) {
+                        Some((_, note)) => Arc::new(DeprecatedFunctionHandler {
+                            inner: handler,
+                            function: self.function_name(module, name),
+                            note: note.clone(),
+                            warned: AtomicBool::new(false),
+                        }),
+                        None => handler,
+                    };
+                    let handler: Arc<dyn FunctionHandler> = Arc::new(InstrumentedFunctionHandler {
+                        inner: handler,
+                        context: self.module_context(module, &settings[index]),
+                        function: self.function_name(module, name),
+                        trace: module.trace_span(),
+                        in_flight: self.in_flight.clone(),
+                    });
+                    let handler: Arc<dyn FunctionHandler> = match self.rate_limits.functions.get(&self.function_name(module, name)) {
+                        Some(limiter) => Arc::new(RateLimitedFunction {
+                            inner: handler,
+                            limiter: limiter.clone(),
+                            function: self.function_name(module, name),
+                        }),
+                        None => handler,
+                    };
+                    #[cfg(feature = "metrics")]
+                    let handler: Arc<dyn FunctionHandler> = Arc::new(crate::metrics::MeteredFunction {
+                        inner: handler,
+                        metrics: self.metrics.clone(),
+                        function: self.function_name(module, name),
+                        deprecated: module.deprecations().iter().any(|(function, _)| function == name),
+                    });
+                    let handler: Arc<dyn FunctionHandler> = Arc::new(TrackedFunction {
+                        inner: handler,
+                        stats: self.function_registry.call_stats().clone(),
+                        function: self.function_name(module, name),
+                        slow_calls: self.config.slow_call_threshold.map(|threshold| SlowCallLog {
+                            threshold,
+                            redactor: self.argument_redactor.clone(),
+                        }),
+                    });
+                    let handler: Arc<dyn FunctionHandler> = match &self.audit_log {
+                        Some(log) => Arc::new(AuditedFunction {
+                            inner: handler,
+                            log: log.clone(),
+                            redactor: self.argument_redactor.clone(),
+                            function: self.function_name(module, name),
+                        }),
+                        None => handler,
+                    };
+                    (self.function_name(module, name), handler)
+                })
+            })
+            .collect()
+    }
+
+    /// Wrap the streaming functions of `modules` so their streams run in
+    /// their module's context, under their fully qualified names
+    fn module_streams(
+        &self,
+        modules: &[usize],
+        settings: &[ModuleSettings],
+    ) -> Vec<(String, Arc<dyn StreamingFunctionHandler>)> {
+        modules
+            .iter()
+            .flat_map(|&index| {
+                let module = &self.modules[index];
+                module.streaming_functions().iter().map(move |(name, handler)| {
+                    let function = self.function_name(module, name);
+                    let handler: Arc<dyn StreamingFunctionHandler> = Arc::new(InstrumentedStreamingHandler {
+                        inner: handler.clone(),
+                        context: self.module_context(module, &settings[index]),
+                        function: function.clone(),
+                        trace: module.trace_span(),
+                        in_flight: self.in_flight.clone(),
+                    });
+                    let handler: Arc<dyn StreamingFunctionHandler> = match self.rate_limits.functions.get(&function) {
+                        Some(limiter) => Arc::new(RateLimitedStream {
+                            inner: handler,
+                            limiter: limiter.clone(),
+                            function: function.clone(),
+                        }),
+                        None => handler,
+                    };
+                    (function, handler)
+                })
+            })
+            .collect()
+    }
+
+    /// Wrap the listeners of `modules` so they run in their module's context,
+    /// looking up the functions of function listeners with `resolve`
+    ///
+    /// Returns each listener with its module's name and its pattern.
+    fn module_listeners(
+        &self,
+        modules: &[usize],
+        settings: &[ModuleSettings],
+        function_context: &FunctionContext,
+        resolve: impl Fn(&str) -> Option<Arc<dyn FunctionHandler>>,
+    ) -> Result<Vec<(String, String, Arc<dyn EventListener>)>> {
+        let mut wrapped = Vec::new();
+        for &index in modules {
+            let module = &self.modules[index];
+            let function_listeners = module.function_listeners().iter().map(|(pattern, name)| {
+                let function = self
+                    .candidate_names(module, name)
+                    .iter()
+                    .find_map(|name| resolve(name))
+                    .ok_or_else(|| unknown_function(module, name))?;
+                let listener = FunctionListener {
+                    function,
+                    context: function_context.clone(),
+                };
+                Ok((pattern, Arc::new(listener) as Arc<dyn EventListener>))
+            });
+            let listeners = module
+                .listeners()
+                .iter()
+                .map(|(pattern, listener)| Ok((pattern, listener.clone())))
+                .chain(function_listeners);
+            #[cfg(feature = "webhooks")]
+            let listeners = listeners.chain(module.webhooks().iter().map(|(pattern, webhook)| {
+                let webhook = webhook.clone().with_events(self.event_registry.clone());
+                Ok((pattern, Arc::new(webhook) as Arc<dyn EventListener>))
+            }));
+            let listeners = listeners.collect::<Result<Vec<_>>>()?;
+
+            for (pattern, listener) in listeners {
+                let listener: Arc<dyn EventListener> = match module.listener_retries().iter().find(|(retried, _)| retried == pattern) {
+                    Some((_, policy)) => Arc::new(
+                        RetryingListener::new(listener, policy.clone())
+                            .with_count(self.retry_count(RetryKind::Listener, module, pattern.clone())),
+                    ),
+                    None => listener,
+                };
+                let listener: Arc<dyn EventListener> = Arc::new(InstrumentedEventListener {
+                    inner: listener,
+                    context: self.module_context(module, &settings[index]),
+                    pattern: pattern.clone(),
+                    trace: module.trace_span(),
+                    in_flight: self.in_flight.clone(),
+                });
+                #[cfg(feature = "metrics")]
+                let listener: Arc<dyn EventListener> = Arc::new(crate::metrics::MeteredListener {
+                    inner: listener,
+                    metrics: self.metrics.clone(),
+                    module: module.name().to_string(),
+                    pattern: pattern.clone(),
+                });
+                wrapped.push((module.name().to_string(), pattern.clone(), listener));
+            }
+        }
+        Ok(wrapped)
+    }
+
+    /// Scheduled jobs of `modules`, with the context they run in
+    fn module_jobs(&self, modules: &[usize], settings: &[ModuleSettings]) -> Vec<(Context, ScheduledJob)> {
+        modules
+            .iter()
+            .flat_map(|&index| {
+                let module = &self.modules[index];
+                let context = self.module_context(module, &settings[index]);
+                module.jobs().iter().map(move |job| (context.clone(), job.clone()))
+            })
+            .collect()
+    }
+
+    /// Tables `modules` declare, by module in registration order
+    fn module_tables(&self, modules: &[usize]) -> Vec<(String, Vec<Table>)> {
+        modules
+            .iter()
+            .map(|&index| {
+                let module = &self.modules[index];
+                (module.name().to_string(), module.tables().to_vec())
+            })
+            .collect()
+    }
+
+    /// Route transforms of `modules`, with the module adding each
+    fn module_transforms(&self, modules: &[usize]) -> Vec<(String, RouteTransform)> {
+        modules
+            .iter()
+            .flat_map(|&index| {
+                let module = &self.modules[index];
+                module
+                    .route_transforms()
+                    .iter()
+                    .map(|transform| (module.name().to_string(), transform.clone()))
+            })
+            .collect()
+    }
+
+    /// Migrations of `modules`, by module in registration order
+    fn module_migrations(&self, modules: &[usize]) -> Vec<(String, Vec<Migration>)> {
+        modules
+            .iter()
+            .map(|&index| {
+                let module = &self.modules[index];
+                (module.name().to_string(), module.migrations().to_vec())
+            })
+            .collect()
+    }
+
+    /// Workflows of `modules`, with the context their steps run in
+    fn module_workflows(&self, modules: &[usize], settings: &[ModuleSettings]) -> Vec<(Context, Workflow)> {
+        modules
+            .iter()
+            .flat_map(|&index| {
+                let module = &self.modules[index];
+                let context = self.module_context(module, &settings[index]);
+                module
+                    .workflows()
+                    .iter()
+                    .map(move |workflow| (context.clone(), workflow.clone()))
+            })
+            .collect()
+    }
+
+    fn module_lifecycles(&self, modules: &[usize], settings: &[ModuleSettings]) -> Vec<ModuleLifecycle> {
+        modules
+            .iter()
+            .map(|&index| ModuleLifecycle {
+                context: self.module_context(&self.modules[index], &settings[index]),
+                lifecycle: self.modules[index].lifecycle().clone(),
+            })
+            .collect()
+    }
+
+    /// An instance without modules sharing this one's configuration and
+    /// registries, used to stage hot-reloaded modules
+    fn template(&self) -> SurrealX {
+        SurrealX {
+            config: self.config.clone(),
+            modules: Vec::new(),
+            function_registry: self.function_registry.clone(),
+            event_registry: self.event_registry.clone(),
+            cache_provider: self.cache_provider.clone(),
+            custom_cache: true,
+            event_store: None,
+            audit_log: self.audit_log.clone(),
+            secret_provider: None,
+            secrets: self.secrets.clone(),
+            config_sources: self.config_sources.clone(),
+            hot_reload: None,
+            in_flight: self.in_flight.clone(),
+            middleware: Vec::new(),
+            function_middleware: self.function_middleware.clone(),
+            verifier: self.verifier.clone(),
+            db: self.db.clone(),
+            disabled_modules: self.disabled_modules.clone(),
+            rate_limits: self.rate_limits.clone(),
+            bridges: Vec::new(),
+            event_acl: self.event_acl.clone(),
+            stream_compression: self.stream_compression.clone(),
+            argument_redactor: self.argument_redactor.clone(),
+            retry_counters: self.retry_counters.clone(),
+            functions_over_http: self.functions_over_http,
+            tenant_resolver: self.tenant_resolver.clone(),
+            tenant_caches: self.tenant_caches.clone(),
+            provisioning: self.provisioning.clone(),
+            #[cfg(feature = "upstream")]
+            upstream: self.upstream.clone(),
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Validate modules loaded after the build and prepare their registrations
+    ///
+    /// Called on a template, with the configuration sources found alongside
+    /// the modules.
+    pub(crate) fn stage(
+        &self,
+        modules: Vec<Module>,
+        sources: Vec<ConfigSource>,
+        function_context: &FunctionContext,
+    ) -> Result<StagedModules> {
+        let mut staging = self.template();
+        staging.modules = modules;
+        staging.config_sources.extend(sources);
+
+        let (report, healthy, settings) = staging.plan()?;
+        let mut functions: HashMap<String, Arc<dyn FunctionHandler>> =
+            staging.module_functions(&healthy, &settings).into_iter().collect();
+        for (alias, target) in staging.aliases(&healthy) {
+            if let Some(handler) = functions.get(&target).cloned() {
+                functions.entry(alias).or_insert(handler);
+            }
+        }
+
+        let listeners = staging.module_listeners(&healthy, &settings, function_context, |name| {
+            functions
+                .get(name)
+                .cloned()
+                .or_else(|| staging.function_registry.get_registered(name))
+        })?;
+
+        Ok(StagedModules {
+            report,
+            functions,
+            listeners,
+            router: staging.build_router(&healthy),
+            lifecycles: staging.module_lifecycles(&healthy, &settings),
+        })
+    }
+
+    /// The cache provider set with `with_cache`, or else the one
+    /// `ServerConfig::cache` selects
+    fn open_cache(&self) -> Result<Arc<dyn CacheProvider>> {
+        if self.custom_cache {
+            return Ok(self.cache_provider.clone());
+        }
+
+        match &self.config.cache {
+            CacheBackend::Memory => Ok(self.cache_provider.clone()),
+            #[cfg(feature = "redis-cache")]
+            CacheBackend::Redis { url } => Ok(Arc::new(cache::RedisCacheProvider::new(url)?)),
+            #[cfg(not(feature = "redis-cache"))]
+            CacheBackend::Redis { .. } => Err(Error::Config(
+                "cache backend 'redis' requires the redis-cache feature".to_string(),
+            )),
+            #[cfg(feature = "memcached-cache")]
+            CacheBackend::Memcached { url } => Ok(Arc::new(cache::MemcachedCacheProvider::new(url)?)),
+            #[cfg(not(feature = "memcached-cache"))]
+            CacheBackend::Memcached { .. } => Err(Error::Config(
+                "cache backend 'memcached' requires the memcached-cache feature".to_string(),
+            )),
+            #[cfg(feature = "disk-cache")]
+            CacheBackend::Disk { path } => Ok(Arc::new(cache::DiskCacheProvider::open(path)?)),
+            #[cfg(not(feature = "disk-cache"))]
+            CacheBackend::Disk { .. } => Err(Error::Config(
+                "cache backend 'disk' requires the disk-cache feature".to_string(),
+            )),
+        }
+    }
+
+    /// Add the modules of every plugin in `ServerConfig::plugin_dir`
+    fn load_plugin_dir(&mut self) -> Result<()> {
+        let Some(dir) = self.config.plugin_dir.take() else {
+            return Ok(());
+        };
+        check_plugin_dir(&dir)?;
+
+        #[cfg(feature = "dylib-plugins")]
+        {
+            // SAFETY: the operator vouches for the directory's contents by
+            // configuring it, as documented on `ServerConfig::plugin_dir`
+            let modules = unsafe { crate::plugin::DynamicModule::load_dir(&dir)? };
+            tracing::info!(dir = %dir, plugins = modules.len(), "loaded plugins");
+            self.modules.extend(modules);
+        }
+        Ok(())
+    }
+
+    /// Token verifier for `ServerConfig::auth`, if set
+    fn verifier(&self) -> Result<Option<Verifier>> {
+        self.config
+            .auth
+            .as_ref()
+            .map(|config| {
+                Verifier::new(
+                    config,
+                    &self.config.namespace,
+                    &self.config.database,
+                    self.tenant_resolver.is_some(),
+                )
+            })
+            .transpose()
+    }
+
+    /// Reject settings that cannot work together
+    fn check_config(&self) -> Result<()> {
+        let auth = self.verifier()?.is_some();
+        if self.config.admin_route && !auth {
+            return Err(Error::Config("admin_route requires auth to be configured".to_string()));
+        }
+        if self.config.sql_access == SqlAccess::Role && !auth {
+            return Err(Error::Config("sql_access = \"role\" requires auth to be configured".to_string()));
+        }
+        if self.config.require_tenant && self.tenant_resolver.is_none() {
+            return Err(Error::Config("require_tenant requires a tenant resolver".to_string()));
+        }
+        // Tenant namespaces are provisioned with SurrealQL only
+        if self.tenant_resolver.is_some() {
+            for module in &self.modules {
+                if let Some(migration) = module.migrations().iter().find(|migration| !migration.is_sql()) {
+                    return Err(Error::Config(format!(
+                        "migration {}@{} is written in Rust, so it cannot run in tenant namespaces",
+                        module.name(),
+                        migration.version()
+                    )));
+                }
+            }
+        }
+        // Loaded by `build`, so only `check` sees it here
+        if let Some(dir) = &self.config.plugin_dir {
+            check_plugin_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Validate the configuration and every module, and work out which
+    /// modules will be registered
+    fn plan(&self) -> Result<(BuildReport, Vec<usize>, Vec<ModuleSettings>)> {
+        self.check_config()?;
+        let document = config::load_all(&self.config_sources)?;
+        let mut healthy: Vec<usize> = (0..self.modules.len()).collect();
+        let mut settings: Vec<ModuleSettings> = vec![None; self.modules.len()];
+        let mut failed_modules = Vec::new();
+
+        // Skipping a module removes its functions, which may break listeners
+        // in other modules, so validate until no further modules fail
+        loop {
+            let functions: HashSet<String> = healthy
+                .iter()
+                .flat_map(|&index| {
+                    let module = &self.modules[index];
+                    module.functions().iter().map(|(name, _)| self.function_name(module, name))
+                })
+                .chain(self.aliases(&healthy).into_iter().map(|(alias, _)| alias))
+                .collect();
+
+            let mut newly_failed = Vec::new();
+            for &index in &healthy {
+                let module = &self.modules[index];
+                let err = match self.validate_module(module, &functions, &document) {
+                    Ok(module_settings) => {
+                        settings[index] = module_settings;
+                        continue;
+                    }
+                    Err(err) => err,
+                };
+
+                match self.config.on_module_init_failure {
+                    ModuleInitFailurePolicy::Abort => return Err(err),
+                    ModuleInitFailurePolicy::SkipAndReport => {
+                        tracing::error!(module = module.name(), error = %err, "module failed to initialize; skipping it");
+                        failed_modules.push(ModuleFailure {
+                            module: module.name().to_string(),
+                            error: err.to_string(),
+                        });
+                        newly_failed.push(index);
+                    }
+                }
+            }
+
+            if newly_failed.is_empty() {
+                break;
+            }
+            healthy.retain(|index| !newly_failed.contains(index));
+        }
+
+        let conflicts = self.conflicts(&healthy);
+        if !conflicts.is_empty() {
+            match self.config.on_conflict {
+                ConflictPolicy::Reject => return Err(Error::Config(conflicts.join("; "))),
+                ConflictPolicy::Override => {
+                    for conflict in &conflicts {
+                        tracing::warn!(conflict = %conflict, "overriding earlier registration");
+                    }
+                }
+            }
+        }
+
+        let mut report = BuildReport {
+            modules: Vec::new(),
+            functions: Vec::new(),
+            listeners: Vec::new(),
+            routes: Vec::new(),
+            cache: self.cache_provider.backend_info(),
+            failed_modules,
+            schema_drift: Vec::new(),
+        };
+
+        for &index in &healthy {
+            let module = &self.modules[index];
+            report.modules.push(module.name().to_string());
+            report
+                .functions
+                .extend(module.functions().iter().map(|(name, _)| self.function_name(module, name)));
+            report.listeners.extend(listener_patterns(module).cloned());
+            report.routes.extend(module.routes().iter().map(|(path, _)| path.to_string()));
+        }
+        dedup(&mut report.functions);
+        dedup(&mut report.routes);
+
+        Ok((report, healthy, settings))
+    }
+
+    /// Describe every function name and route path registered by more than one module
+    ///
+    /// Event patterns are not checked: several modules listening to the same
+    /// events is intended.
+    fn conflicts(&self, modules: &[usize]) -> Vec<String> {
+        let mut functions: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        let mut routes: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for &index in modules {
+            let module = &self.modules[index];
+            for (name, _) in module.functions() {
+                functions.entry(self.function_name(module, name)).or_default().push(module.name());
+            }
+            for (path, _) in module.routes() {
+                routes.entry(*path).or_default().push(module.name());
+            }
+        }
+
+        let describe = |kind: &str, name: &str, modules: &[&str]| {
+            let modules: Vec<String> = modules.iter().map(|module| format!("'{}'", module)).collect();
+            format!("{} '{}' is registered by modules {}", kind, name, modules.join(", "))
+        };
+        let functions = functions
+            .iter()
+            .filter(|(_, modules)| modules.len() > 1)
+            .map(|(name, modules)| describe("function", name, modules));
+        let routes = routes
+            .iter()
+            .filter(|(_, modules)| modules.len() > 1)
+            .map(|(path, modules)| describe("route", path, modules));
+        functions.chain(routes).collect()
+    }
+
+    /// Fully qualified name of a module's function
+    fn function_name(&self, module: &Module, name: &str) -> String {
+        match self.config.function_namespacing {
+            FunctionNamespacing::Module => format!("ext::{}::{}", module.name(), name),
+            FunctionNamespacing::Flat => flat_name(name),
+        }
+    }
+
+    /// `ext::<name>` aliases for module-namespaced functions, as (alias, target)
+    ///
+    /// A name used by several modules gets no alias, since it would be
+    /// ambiguous.
+    ///
+    /// Versioned functions are also aliased from their name without a
+    /// version to their default version.
+    fn aliases(&self, modules: &[usize]) -> Vec<(String, String)> {
+        let mut versions = Vec::new();
+        for &index in modules {
+            let module = &self.modules[index];
+            for (name, default) in module.versioned_functions() {
+                versions.push((self.function_name(module, &name), self.function_name(module, &default)));
+            }
+        }
+        if self.config.function_namespacing == FunctionNamespacing::Flat {
+            return versions;
+        }
+
+        let mut targets: HashMap<String, Vec<String>> = HashMap::new();
+        for &index in modules {
+            let module = &self.modules[index];
+            let versioned = module.versioned_functions();
+            let names = module
+                .functions()
+                .iter()
+                .map(|(name, _)| (name.clone(), name.clone()))
+                .chain(versioned);
+            for (name, target) in names {
+                let target = self.function_name(module, &target);
+                let entry = targets.entry(flat_name(&name)).or_default();
+                if !entry.contains(&target) {
+                    entry.push(target);
+                }
+            }
+        }
+
+        versions.extend(targets.into_iter().filter_map(|(alias, mut targets)| {
+            if targets.len() > 1 {
+                tracing::debug!(alias = %alias, targets = ?targets, "ambiguous function name; not aliasing it");
+                return None;
+            }
+            targets.pop().map(|target| (alias, target))
+        }));
+        versions
+    }
+
+    /// Names a function listener's function may be registered under: as
+    /// given, in the listener's own module, or under `ext::`
+    fn candidate_names(&self, module: &Module, name: &str) -> [String; 3] {
+        [name.to_string(), self.function_name(module, name), flat_name(name)]
+    }
+
+    fn validate_module(&self, module: &Module, functions: &HashSet<String>, document: &Value) -> Result<ModuleSettings> {
+        for (_, name) in module.function_listeners() {
+            let known = |name: &String| functions.contains(name) || self.function_registry.contains(name);
+            if !self.candidate_names(module, name).iter().any(known) {
+                return Err(unknown_function(module, name));
+            }
+        }
+
+        for pattern in listener_patterns(module) {
+            self.config
+                .global_wildcard
+                .validate(pattern, self.event_registry.syntax())?;
+        }
+
+        for job in module.jobs() {
+            scheduler::parse_schedule(module.name(), job)?;
+        }
+
+        // A module without a section gets an empty one, so settings with
+        // serde defaults need no configuration at all
+        module
+            .config_parser()
+            .map(|parse| {
+                let section = document.get(module.name()).cloned().unwrap_or_else(|| json!({}));
+                parse(section).map_err(|err| {
+                    Error::Config(format!("module '{}' configuration: {}", module.name(), err))
+                })
+            })
+            .transpose()
+    }
+
+    /// Build, open the embedded database and serve HTTP on `bind_addr`
+    ///
+    /// Serves HTTPS instead when `ServerConfig::tls` or `with_tls_config` is
+    /// set. Runs the modules' `on_start` hooks before listening. On SIGINT or
+    /// SIGTERM the server stops accepting connections, waits up to
+    /// `shutdown_timeout` for open ones to close, then shuts down as
+    /// described on [`BuiltSurrealX::shutdown`].
+    pub async fn serve(mut self, config: ServerConfig) -> Result<()> {
+        self.config = config;
+        let telemetry = crate::telemetry::init(&self.config)?;
+        let bind_addr = self.config.bind_addr.clone();
+        #[cfg(feature = "tls")]
+        let tls = self.tls_server()?;
+        #[cfg(not(feature = "tls"))]
+        if self.config.tls.is_some() {
+            return Err(Error::Config("tls is set but the tls feature is not enabled".to_string()));
+        }
+
         let built = self.build().await?;
+        built.start().await?;
+
+        let result = Self::listen(
+            &bind_addr,
+            &built,
+            #[cfg(feature = "tls")]
+            tls,
+        )
+        .await;
+        built.shutdown().await;
+        telemetry.shutdown();
+        result
+    }
+
+    /// Load the configured certificate, before anything is built so a bad
+    /// certificate fails fast
+    #[cfg(feature = "tls")]
+    fn tls_server(&self) -> Result<Option<crate::tls::TlsServer>> {
+        if let Some(config) = &self.tls_config {
+            return Ok(Some(crate::tls::TlsServer::from_config(config.clone())));
+        }
+        self.config
+            .tls
+            .as_ref()
+            .map(|tls| crate::tls::TlsServer::from_files(&tls.cert_path, &tls.key_path))
+            .transpose()
+    }
+
+    async fn listen(
+        bind_addr: &str,
+        built: &BuiltSurrealX,
+        #[cfg(feature = "tls")] tls: Option<crate::tls::TlsServer>,
+    ) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(bind_addr).await?;
+        tracing::info!(
+            addr = %listener.local_addr()?,
+            functions = ?built.report.functions,
+            listeners = ?built.report.listeners,
+            "SurrealX listening"
+        );
 
-        println!("🚀 SurrealX Extensions Loaded:");
-        println!("   Functions: {:?}", built.function_registry.list());
-        println!("   Events: {:?}", built.event_registry.patterns().await);
-        println!();
-        println!("✨ Framework ready for SurrealDB integration");
-        println!();
-        println!("📝 Next Steps:");
-        println!("   1. Complete SurrealDB server transformation");
-        println!("   2. Uncomment surrealdb-server dependency in Cargo.toml");
-        println!("   3. Implement ServerExtension integration");
+        // Fail readiness probes from the moment shutdown begins
+        let health_checks = built.health_checks.clone();
+        let signal = async move {
+            shutdown_signal().await;
+            health_checks.set_ready(false);
+        };
 
+        #[cfg(feature = "tls")]
+        if let Some(tls) = tls {
+            let hangup = tls.watch_hangup();
+            let result = tls
+                .serve(listener, built.router.clone(), signal, built.shutdown_timeout)
+                .await;
+            if let Some(hangup) = hangup {
+                hangup.abort();
+            }
+            return result;
+        }
+
+        let (stopping, stopped) = tokio::sync::oneshot::channel();
+        let server = axum::serve(listener, built.router.clone())
+            .with_graceful_shutdown(async move {
+                signal.await;
+                tracing::info!("shutting down; no longer accepting connections");
+                let _ = stopping.send(());
+            })
+            .into_future();
+
+        // Connections still open `shutdown_timeout` after the signal are dropped
+        let timeout = built.shutdown_timeout;
+        let deadline = async move {
+            match stopped.await {
+                Ok(()) => tokio::time::sleep(timeout).await,
+                Err(_) => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            result = server => result?,
+            _ = deadline => tracing::warn!("connections still open after shutdown_timeout; dropping them"),
+        }
         Ok(())
     }
 
-    fn build_router(&self) -> Router {
-        let mut router = Router::new();
+    fn retry_count(&self, kind: RetryKind, module: &Module, name: String) -> RetryCount {
+        RetryCount {
+            counters: self.retry_counters.clone(),
+            kind,
+            module: module.name().to_string(),
+            name,
+        }
+    }
+
+    fn module_context(&self, module: &Module, settings: &ModuleSettings) -> Context {
+        let cache = ScopedCache::new(self.cache_provider.clone(), format!("module:{}", module.name()));
+        Context::new(
+            module.name(),
+            cache,
+            settings.clone(),
+            self.db.clone(),
+            self.disabled_modules.clone(),
+            self.tenant_caches.clone(),
+            self.secrets.clone(),
+        )
+    }
 
-        // Add routes from modules
-        for module in &self.modules {
+    fn build_router(&self, modules: &[usize]) -> Router {
+        // Add routes from modules; when a path is registered twice, which
+        // `plan()` only allows under `ConflictPolicy::Override`, the last wins
+        let mut routes: Vec<(&str, &Router, &Module)> = Vec::new();
+        for &index in modules {
+            let module = &self.modules[index];
             for (path, module_router) in module.routes() {
-                router = router.nest(path, module_router.clone());
+                routes.retain(|(existing, _, _)| existing != path);
+                routes.push((*path, module_router, module));
             }
         }
 
+        let router = routes.into_iter().fold(Router::new(), |router, (path, module_router, module)| {
+            let module_router = middleware::apply(module_router.clone(), module.middleware());
+            router.nest(path, admin::guard_routes(module_router, module.name(), self.disabled_modules.clone()))
+        });
+        let router = self.protected(router);
+        // Outside authentication, since forwarded requests carry SurrealDB
+        // credentials rather than tokens for `ServerConfig::auth`
+        #[cfg(feature = "upstream")]
+        if let Some(upstream) = &self.upstream {
+            return crate::upstream::fallback(router, upstream.clone());
+        }
         router
     }
+
+    /// OpenAPI document of `POST /sql`, when served, the routes of `modules`
+    /// and, when exposed, the function endpoints
+    fn api_document(&self, modules: &[usize]) -> Value {
+        let mut document = ApiDocument::new(self.config.auth.as_ref());
+        if self.config.sql_access != SqlAccess::Disabled {
+            let sql = ApiOperation::post()
+                .summary("Run SurrealQL with registered functions available")
+                .request_body_as("text/plain", json!({ "type": "string" }))
+                .json_response(200, "Result of each statement", json!({ "type": "array", "items": {} }))
+                .response(400, "The query failed")
+                .response(403, "The caller lacks the admin role, or a function requires a role the caller lacks")
+                .response(429, "A rate limit was exceeded");
+            document.operation("/sql", &sql, "sql");
+        }
+        for &index in modules {
+            document.module(&self.modules[index]);
+        }
+        if self.functions_over_http {
+            document.functions(&self.function_registry);
+        }
+        document.into_json()
+    }
+
+    /// Apply the route rate limits to `router`'s requests, and require them to
+    /// authenticate if `ServerConfig::auth` is set
+    ///
+    /// Authentication runs first, so per-identity limits see the caller.
+    fn protected(&self, router: Router) -> Router {
+        let mut router = ratelimit::limit_routes(router, self.rate_limits.routes.clone());
+        if let (Some(resolver), Some(provisioning)) = (&self.tenant_resolver, &self.provisioning) {
+            router = tenancy::resolve_routes(
+                router,
+                TenantRouting {
+                    resolver: resolver.clone(),
+                    required: self.config.require_tenant,
+                    namespace: self.config.namespace.clone(),
+                    provisioning: provisioning.clone(),
+                },
+            );
+        }
+        match &self.verifier {
+            Some(verifier) => auth::authenticate(router, verifier.clone()),
+            None => router,
+        }
+    }
 }
 
 impl Default for SurrealX {
@@ -124,10 +2262,503 @@ impl Default for SurrealX {
     }
 }
 
+/// Wait for Ctrl-C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::warn!(error = %err, "cannot listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::warn!(error = %err, "cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// (De)serialize a `Duration` as whole seconds
+mod seconds {
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
+
+/// Optional durations as whole milliseconds
+mod optional_millis {
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&(duration.as_millis() as u64)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        Option::<u64>::deserialize(deserializer).map(|millis| millis.map(Duration::from_millis))
+    }
+}
+
+// Name of a function under the flat ext:: prefix
+fn flat_name(name: &str) -> String {
+    format!("ext::{}", name)
+}
+
+// Keep the first occurrence of each name
+fn dedup(names: &mut Vec<String>) {
+    let mut seen = HashSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+}
+
+fn listener_patterns(module: &Module) -> impl Iterator<Item = &String> {
+    module
+        .listeners()
+        .iter()
+        .map(|(pattern, _)| pattern)
+        .chain(module.function_listeners().iter().map(|(pattern, _)| pattern))
+}
+
+/// Fail on a plugin directory that cannot be loaded from
+fn check_plugin_dir(dir: &str) -> Result<()> {
+    #[cfg(feature = "dylib-plugins")]
+    {
+        std::fs::read_dir(dir)
+            .map(drop)
+            .map_err(|err| Error::Config(format!("plugin_dir '{}': {}", dir, err)))
+    }
+
+    #[cfg(not(feature = "dylib-plugins"))]
+    {
+        Err(Error::Config(format!(
+            "plugin_dir '{}' is set but the dylib-plugins feature is not enabled",
+            dir
+        )))
+    }
+}
+
+fn unknown_function(module: &Module, name: &str) -> Error {
+    Error::Config(format!(
+        "module '{}' has a listener for unknown function '{}'",
+        module.name(),
+        name
+    ))
+}
+
+/// Run every module's `on_start` hook, in order
+///
+/// If a hook fails, the modules started before it are shut down.
+pub(crate) async fn start_modules(lifecycles: &[ModuleLifecycle]) -> Result<()> {
+    for (started, module) in lifecycles.iter().enumerate() {
+        let Some(hook) = &module.lifecycle.on_start else {
+            continue;
+        };
+        if let Err(err) = context::scope(module.context.clone(), hook(module.context.clone())).await {
+            shut_down_modules(&lifecycles[..started]).await;
+            return Err(Error::Server(format!(
+                "module '{}' failed to start: {}",
+                module.context.module(),
+                err
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Run every module's `on_shutdown` hook, in reverse order, and return the failures
+pub(crate) async fn shut_down_modules(lifecycles: &[ModuleLifecycle]) -> Vec<ModuleFailure> {
+    let mut failures = Vec::new();
+    for module in lifecycles.iter().rev() {
+        let Some(hook) = &module.lifecycle.on_shutdown else {
+            continue;
+        };
+        if let Err(err) = context::scope(module.context.clone(), hook(module.context.clone())).await {
+            tracing::error!(module = module.context.module(), error = %err, "module failed to shut down");
+            failures.push(ModuleFailure {
+                module: module.context.module().to_string(),
+                error: err.to_string(),
+            });
+        }
+    }
+    failures
+}
+
+/// Run every module's health check concurrently
+pub(crate) async fn check_modules(lifecycles: &[ModuleLifecycle]) -> Vec<ModuleHealth> {
+    futures::future::join_all(lifecycles.iter().map(|module| async move {
+        let result = match &module.lifecycle.health {
+            Some(check) => context::scope(module.context.clone(), check()).await,
+            None => Ok(()),
+        };
+        ModuleHealth {
+            module: module.context.module().to_string(),
+            healthy: result.is_ok(),
+            error: result.err().map(|err| err.to_string()),
+        }
+    }))
+    .await
+}
+
+/// Report of everything a build registers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// Module names, in registration order
+    pub modules: Vec<String>,
+    /// Fully qualified function names
+    pub functions: Vec<String>,
+    /// Event listener patterns
+    pub listeners: Vec<String>,
+    /// HTTP route paths
+    pub routes: Vec<String>,
+    /// Active cache backend
+    pub cache: CacheBackendInfo,
+    /// Modules skipped because they failed to initialize
+    pub failed_modules: Vec<ModuleFailure>,
+    /// Differences between the tables modules declare and the database,
+    /// found when the server was built
+    #[serde(default)]
+    pub schema_drift: Vec<SchemaDrift>,
+}
+
+impl BuildReport {
+    /// Check whether any module was skipped
+    pub fn is_degraded(&self) -> bool {
+        !self.failed_modules.is_empty()
+    }
+}
+
+/// A module that failed to initialize and was skipped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleFailure {
+    /// Module name
+    pub module: String,
+    /// Why initialization failed
+    pub error: String,
+}
+
 /// Built SurrealX instance with all extensions registered
 pub struct BuiltSurrealX {
     pub function_registry: FunctionRegistry,
     pub event_registry: EventRegistry,
     pub cache_provider: Arc<dyn CacheProvider>,
+    pub outbox: Outbox,
+    /// Emits the events that functions emitted after commit
+    pub outbox_relay: OutboxRelay,
+    pub db: Database,
+    /// Live query subscriptions for `ServerConfig::live_tables`
+    pub change_feed: Option<ChangeFeedHandle>,
+    /// Background maintenance started by the cache provider
+    pub cache_maintenance: Option<MaintenanceHandle>,
     pub router: Router,
+    pub report: BuildReport,
+    function_context: FunctionContext,
+    lifecycles: Arc<Vec<ModuleLifecycle>>,
+    reloader: Option<Arc<Reloader>>,
+    health_checks: HealthChecks,
+    scheduler: Arc<Scheduler>,
+    disabled_modules: DisabledModules,
+    workflows: Workflows,
+    migrations: Migrations,
+    tenant_caches: TenantCaches,
+    provisioning: Option<Arc<Provisioning>>,
+    pool: Option<DatabasePool>,
+    audit_log: Option<Arc<dyn AuditLog>>,
+    retry_counters: Arc<RetryCounters>,
+    bridges: Vec<Arc<dyn EventBridge>>,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::Metrics,
+    in_flight: InFlight,
+    shutdown_timeout: Duration,
+}
+
+/// Lifecycle hooks of a registered module, with the context they run in
+pub(crate) struct ModuleLifecycle {
+    context: Context,
+    lifecycle: Lifecycle,
+}
+
+/// Registrations for modules loaded after the build
+pub(crate) struct StagedModules {
+    pub(crate) report: BuildReport,
+    /// Functions by fully qualified name and alias
+    pub(crate) functions: HashMap<String, Arc<dyn FunctionHandler>>,
+    /// Listeners with their module and pattern
+    pub(crate) listeners: Vec<(String, String, Arc<dyn EventListener>)>,
+    pub(crate) router: Router,
+    pub(crate) lifecycles: Vec<ModuleLifecycle>,
+}
+
+impl BuiltSurrealX {
+    /// Run every module's `on_start` hook, in registration order
+    ///
+    /// If a hook fails, the modules started before it are shut down and the
+    /// error is returned. With hot reload, the reloadable modules are loaded
+    /// and started last, and the directory is watched from then on. The event
+    /// bridges start next, and scheduled jobs run from then on. Workflow
+    /// instances left running by an earlier process resume last.
+    pub async fn start(&self) -> Result<()> {
+        start_modules(&self.lifecycles).await?;
+        if let Some(reloader) = &self.reloader {
+            if let Err(err) = reloader.start().await {
+                shut_down_modules(&self.lifecycles).await;
+                return Err(err);
+            }
+        }
+        for (index, bridge) in self.bridges.iter().enumerate() {
+            if let Err(err) = bridge.start(self.event_registry.clone()).await {
+                for started in &self.bridges[..index] {
+                    started.stop().await;
+                }
+                if let Some(reloader) = &self.reloader {
+                    reloader.stop().await;
+                }
+                shut_down_modules(&self.lifecycles).await;
+                return Err(Error::Server(format!("failed to start the {} bridge: {}", bridge.name(), err)));
+            }
+        }
+        self.scheduler.start();
+        match self.workflows.resume().await {
+            Ok(0) => {}
+            Ok(resumed) => tracing::info!(instances = resumed, "resumed workflow instances"),
+            Err(err) => tracing::error!(error = %err, "failed to resume workflow instances"),
+        }
+        self.health_checks.set_ready(true);
+        Ok(())
+    }
+
+    /// Shut the modules down and flush buffered state
+    ///
+    /// Stops scheduling jobs, waits up to `ServerConfig::shutdown_timeout`
+    /// for in-flight function calls, event handlers, job runs and workflow
+    /// steps to finish, stops driving workflow instances until the next
+    /// start, stops the event bridges, runs every module's `on_shutdown`
+    /// hook in reverse registration order, then flushes the cache provider
+    /// and the event store.
+    ///
+    /// Returns the modules whose hook failed; failures are also logged.
+    pub async fn shutdown(&self) -> Vec<ModuleFailure> {
+        self.health_checks.set_ready(false);
+        self.scheduler.stop();
+        if !self.in_flight.drain(self.shutdown_timeout).await {
+            tracing::warn!(
+                in_flight = self.in_flight.count(),
+                "calls still running after shutdown_timeout; shutting down anyway"
+            );
+        }
+        self.workflows.stop();
+
+        for bridge in &self.bridges {
+            bridge.stop().await;
+        }
+
+        let mut failures = match &self.reloader {
+            Some(reloader) => reloader.stop().await,
+            None => Vec::new(),
+        };
+        failures.extend(shut_down_modules(&self.lifecycles).await);
+
+        if let Err(err) = self.cache_provider.flush().await {
+            tracing::error!(error = %err, "failed to flush the cache");
+        }
+        if let Err(err) = self.event_registry.flush().await {
+            tracing::error!(error = %err, "failed to flush the event store");
+        }
+        if let Some(log) = &self.audit_log {
+            if let Err(err) = log.flush().await {
+                tracing::error!(error = %err, "failed to flush the audit log");
+            }
+        }
+        failures
+    }
+
+    /// Run every module's health check concurrently
+    pub async fn health(&self) -> Vec<ModuleHealth> {
+        self.health_checks.modules().await
+    }
+
+    /// Check the embedded database, the cache provider and every module,
+    /// as served at `GET /health`
+    pub async fn health_report(&self) -> HealthReport {
+        self.health_checks.report().await
+    }
+
+    /// Reload the hot reload directory now, if it changed
+    ///
+    /// Returns whether the modules were replaced. Does nothing without
+    /// `SurrealX::with_hot_reload`.
+    pub async fn reload(&self) -> Result<bool> {
+        match &self.reloader {
+            Some(reloader) => reloader.reload().await,
+            None => Ok(false),
+        }
+    }
+
+    /// Metrics recorded by the server, as served at `GET /metrics`
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &crate::metrics::Metrics {
+        &self.metrics
+    }
+
+    /// Get the cache provider's counters, if it tracks them
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache_provider.stats()
+    }
+
+    /// Where function calls, admin actions and audited events are recorded,
+    /// if anywhere
+    pub fn audit_log(&self) -> Option<&Arc<dyn AuditLog>> {
+        self.audit_log.as_ref()
+    }
+
+    /// Connections to the upstream server, in upstream mode
+    pub fn database_pool(&self) -> Option<&DatabasePool> {
+        self.pool.as_ref()
+    }
+
+    /// Call statistics of every module function called so far; see the
+    /// [`stats`](crate::stats) module
+    pub fn function_stats(&self) -> Vec<FunctionStats> {
+        self.function_registry.stats()
+    }
+
+    /// Retries of every module function and listener that retried so far;
+    /// see the [`retry`](crate::retry) module
+    pub fn retry_stats(&self) -> Vec<RetryStats> {
+        self.retry_counters.snapshot()
+    }
+
+    /// What `tenant` keeps in the cache, as far as this process knows
+    pub fn tenant_cache_usage(&self, tenant: &Tenant) -> TenantCacheUsage {
+        self.tenant_caches.scope(self.cache_provider.clone(), tenant).usage()
+    }
+
+    /// Stop serving a module's functions, routes, listeners and jobs until
+    /// it is enabled again
+    ///
+    /// Calls to its functions fail with [`Error::ModuleDisabled`]. Fails if
+    /// no module of that name was loaded when the server was built.
+    pub fn disable_module(&self, name: &str) -> Result<()> {
+        self.toggle_module(name, true)
+    }
+
+    /// Resume serving a module disabled with `disable_module` or the admin API
+    pub fn enable_module(&self, name: &str) -> Result<()> {
+        self.toggle_module(name, false)
+    }
+
+    fn toggle_module(&self, name: &str, disabled: bool) -> Result<()> {
+        if !self.report.modules.iter().any(|module| module == name) {
+            return Err(Error::NotFound(format!("module '{}'", name)));
+        }
+        self.disabled_modules.set(name, disabled);
+        Ok(())
+    }
+
+    /// Every migration of the loaded modules in the order they are applied,
+    /// with when each was
+    pub async fn migrations(&self) -> Result<Vec<MigrationRecord>> {
+        self.migrations.status().await
+    }
+
+    /// The migrations `apply_migrations` would apply, in order, without
+    /// applying them
+    pub async fn pending_migrations(&self) -> Result<Vec<MigrationRecord>> {
+        self.migrations.pending().await
+    }
+
+    /// Apply every pending migration in order, such as after building with
+    /// `MigrationPolicy::DryRun`; returns those applied
+    pub async fn apply_migrations(&self) -> Result<Vec<MigrationRecord>> {
+        self.migrations.apply().await
+    }
+
+    /// Roll back the applied migrations of `module` newer than `to_version`,
+    /// newest first; returns those rolled back
+    ///
+    /// Fails without rolling anything back if one of them has no down
+    /// migration or an applied migration of another module depends on one.
+    /// Roll back to `0` to undo every migration of the module.
+    pub async fn rollback_migrations(&self, module: &str, to_version: u64) -> Result<Vec<MigrationRecord>> {
+        self.migrations.rollback(module, to_version).await
+    }
+
+    /// Reconcile the declared tables and apply the migrations in the
+    /// namespace of `tenant` now, rather than before its first request
+    ///
+    /// Does nothing if this process has already provisioned it. Fails
+    /// without a tenant resolver.
+    pub async fn provision_tenant(&self, tenant: &Tenant) -> Result<()> {
+        match &self.provisioning {
+            Some(provisioning) => provisioning.provision(tenant).await,
+            None => Err(Error::Config("provisioning a tenant requires a tenant resolver".to_string())),
+        }
+    }
+
+    /// Call a registered function by its fully qualified name
+    ///
+    /// Events the function records with `outbox::record` are emitted only if
+    /// it returns successfully, like those it emits with
+    /// [`FunctionContext::emit_after_commit`].
+    pub async fn call_function(&self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let handler = self
+            .function_registry
+            .get(name)
+            .ok_or_else(|| Error::NotFound(format!("function '{}'", name)))?;
+        self.outbox
+            .call(handler.as_ref(), self.function_context.for_request(None), args)
+            .await
+    }
+
+    /// Run SurrealQL against the embedded database
+    ///
+    /// Calls to registered functions, such as `ext::calculate_tax(100.0)`,
+    /// are evaluated before the query runs; see the [`sql`](crate::sql)
+    /// module for what their arguments may contain. Returns the result of
+    /// each statement as JSON.
+    pub async fn query(&self, sql: &str) -> Result<Vec<Value>> {
+        QueryEngine::new(
+            self.db.clone(),
+            self.function_registry.clone(),
+            self.outbox.clone(),
+            self.function_context.clone(),
+        )
+        .query(sql, None, None, None)
+        .await
+    }
+
+    /// Start an instance of the workflow `<module>::<name>` with `input`,
+    /// returning its ID
+    ///
+    /// Returns once the instance is saved; its steps run in the background.
+    pub async fn start_workflow(&self, name: &str, input: Value) -> Result<String> {
+        self.workflows.start(name, input).await
+    }
+
+    /// Saved state of the workflow instance `id`
+    pub async fn workflow_instance(&self, id: &str) -> Result<Option<WorkflowInstance>> {
+        self.workflows.instance(id).await
+    }
 }
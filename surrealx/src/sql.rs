@@ -0,0 +1,539 @@
+//! Calling registered functions from SurrealQL
+//!
+//! SurrealDB cannot call back into Rust, so queries are rewritten before they
+//! run: every call to a registered function, such as
+//! `ext::calculate_tax(100.0, 0.15)`, has its arguments evaluated by the
+//! database and is replaced by the function's result as a literal.
+//!
+//! Arguments are evaluated once, before the query runs, so they may use
+//! literals, expressions and subqueries but not fields of the records being
+//! selected or parameters defined earlier in the same query.
+//!
+//! When a function emits events after commit, the query runs as a single
+//! transaction that also writes those events to the outbox table; such a
+//! query cannot begin, commit or cancel transactions of its own.
+//!
+//! A tenant's query, and the arguments of the functions it calls, run in the
+//! tenant's namespace and cannot contain statements leaving it, such as
+//! `USE NS other`; see [`check_bound`].
+//!
+//! `POST /sql` runs SurrealQL as the embedded database's root, not as the
+//! caller, so it is only served with `ServerConfig::sql_access` set: to
+//! callers holding `ServerConfig::admin_role`, or to anyone in development.
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+use crate::auth::Identity;
+use crate::context::FunctionContext;
+use crate::db::{self, Database};
+#[cfg(feature = "upstream")]
+use crate::events::Event;
+use crate::functions::FunctionRegistry;
+use crate::outbox::{self, Outbox};
+use crate::ratelimit;
+use crate::tenancy::Tenant;
+use crate::error::{Error, Result};
+
+/// Runs SurrealQL with registered functions available
+#[derive(Clone)]
+pub(crate) struct QueryEngine {
+    db: Database,
+    functions: FunctionRegistry,
+    outbox: Outbox,
+    context: FunctionContext,
+}
+
+impl QueryEngine {
+    pub(crate) fn new(db: Database, functions: FunctionRegistry, outbox: Outbox, context: FunctionContext) -> Self {
+        Self { db, functions, outbox, context }
+    }
+
+    /// Run SurrealQL and return the result of each statement as JSON
+    ///
+    /// Every function call in the query shares one request ID, taken from
+    /// `request_id` or generated, and sees the caller's `identity`. With a
+    /// `tenant`, the query runs in the tenant's namespace.
+    pub(crate) async fn query(
+        &self,
+        sql: &str,
+        request_id: Option<String>,
+        identity: Option<Identity>,
+        tenant: Option<Tenant>,
+    ) -> Result<Vec<Value>> {
+        let mut context = self.context.for_request(request_id);
+        if let Some(identity) = identity {
+            context = context.with_identity(identity);
+        }
+        if let Some(tenant) = tenant {
+            check_bound(sql)?;
+            context = context.with_tenant(tenant);
+        }
+        let switch = context
+            .tenant()
+            .map(|tenant| db::use_statement(tenant.namespace(), context.database()));
+        let switched = usize::from(switch.is_some());
+        let (sql, committed) = outbox::transaction(self.rewrite(sql, &context)).await;
+        let sql = sql?;
+        if committed.is_empty() {
+            let sql = format!("{}{}", switch.unwrap_or_default(), sql);
+            return Ok(db::query(&self.db, sql).await?.into_iter().skip(switched).collect());
+        }
+
+        if manages_transaction(&sql) {
+            return Err(Error::Validation(
+                "functions emitting events after commit cannot be called from a query with its own transaction"
+                    .to_string(),
+            ));
+        }
+        // The outbox is written in the server's namespace, where the relay
+        // reads it, before switching to the tenant's
+        let sql = format!(
+            "BEGIN TRANSACTION;\n{};\n{}{}\n;\nCOMMIT TRANSACTION;",
+            outbox::insert_statement(&committed)?,
+            switch.unwrap_or_default(),
+            sql.trim_end().trim_end_matches(';')
+        );
+        let results = db::query(&self.db, sql).await?;
+        self.outbox.committed();
+        // Without the results of the outbox insert and the switch
+        Ok(results.into_iter().skip(1 + switched).collect())
+    }
+
+    /// Replace calls to registered functions in `sql` with their results,
+    /// returning the query and the events the calls emit after commit, for
+    /// queries run by someone else
+    #[cfg(feature = "upstream")]
+    pub(crate) async fn prepare(&self, sql: &str) -> Result<(String, Vec<Event>)> {
+        let context = self.context.for_request(None);
+        let (sql, committed) = outbox::transaction(self.rewrite(sql, &context)).await;
+        Ok((sql?, committed))
+    }
+
+    /// Write events emitted after commit by a query run by someone else,
+    /// once it has succeeded
+    #[cfg(feature = "upstream")]
+    pub(crate) async fn committed(&self, events: &[Event]) -> Result<()> {
+        self.db.query(outbox::insert_statement(events)?).await?.check()?;
+        self.outbox.committed();
+        Ok(())
+    }
+
+    /// Replace calls to registered functions with their results
+    fn rewrite<'a>(&'a self, sql: &'a str, context: &'a FunctionContext) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let bytes = sql.as_bytes();
+            let mut rewritten = String::with_capacity(sql.len());
+            let mut copied = 0;
+            let mut i = 0;
+
+            while i < bytes.len() {
+                if let Some(end) = skip_quoted(sql, i) {
+                    i = end;
+                    continue;
+                }
+
+                let Some((name, open)) = function_call(sql, i) else {
+                    i = next_char(sql, i);
+                    continue;
+                };
+                if !self.functions.contains(name) {
+                    i = open;
+                    continue;
+                }
+
+                let close = closing_paren(sql, open).ok_or_else(|| {
+                    Error::Function(format!("unterminated call to '{}'", name))
+                })?;
+                let args = self.rewrite(&sql[open + 1..close], context).await?;
+                let result = self.call(name, &args, context).await?;
+
+                rewritten.push_str(&sql[copied..i]);
+                rewritten.push_str(&serde_json::to_string(&result)?);
+                i = close + 1;
+                copied = i;
+            }
+
+            rewritten.push_str(&sql[copied..]);
+            Ok(rewritten)
+        })
+    }
+
+    async fn call(&self, name: &str, args: &str, context: &FunctionContext) -> Result<Value> {
+        let args = if args.trim().is_empty() {
+            Vec::new()
+        } else {
+            // Subqueries in the arguments of a tenant's call see its namespace
+            let switch = context
+                .tenant()
+                .map(|tenant| db::use_statement(tenant.namespace(), context.database()));
+            let switched = usize::from(switch.is_some());
+            let sql = format!("{}RETURN [{}]", switch.unwrap_or_default(), args);
+            let mut response = self.db.query(sql).await?;
+            match response.take::<surrealdb::Value>(switched)?.into_inner().into_json() {
+                Value::Array(args) => args,
+                other => vec![other],
+            }
+        };
+
+        let handler = self
+            .functions
+            .get(name)
+            .ok_or_else(|| Error::NotFound(format!("function '{}'", name)))?;
+        self.outbox.call(handler.as_ref(), context.clone(), args).await
+    }
+}
+
+/// Router exposing `POST /sql` against the embedded database
+///
+/// Queries run with the server's own access, so the server only serves it
+/// as `ServerConfig::sql_access` allows.
+pub(crate) fn router(engine: QueryEngine) -> Router {
+    Router::new().route("/sql", post(sql_handler)).with_state(engine)
+}
+
+async fn sql_handler(
+    State(engine): State<QueryEngine>,
+    identity: Option<Identity>,
+    tenant: Option<Tenant>,
+    headers: HeaderMap,
+    sql: String,
+) -> Response {
+    let request_id = headers
+        .get(crate::telemetry::REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let err = match engine.query(&sql, request_id, identity, tenant).await {
+        Ok(results) => return (StatusCode::OK, Json(Value::Array(results))).into_response(),
+        Err(Error::RateLimited { retry_after, .. }) => return ratelimit::too_many_requests(retry_after),
+        Err(err) => err,
+    };
+    let status = match err {
+        Error::Forbidden { .. } => StatusCode::FORBIDDEN,
+        Error::ModuleDisabled(_) | Error::CircuitOpen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, Json(json!({ "status": "ERR", "result": err.to_string() }))).into_response()
+}
+
+fn next_char(sql: &str, i: usize) -> usize {
+    i + sql[i..].chars().next().map_or(1, char::len_utf8)
+}
+
+fn is_ident(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// If a string, quoted identifier or comment starts at `i`, return its end
+fn skip_quoted(sql: &str, i: usize) -> Option<usize> {
+    let rest = &sql[i..];
+    let until = |terminator: &str, from: usize| {
+        rest[from..]
+            .find(terminator)
+            .map_or(sql.len(), |offset| i + from + offset + terminator.len())
+    };
+
+    match rest.as_bytes()[0] {
+        quote @ (b'\'' | b'"' | b'`') => {
+            let mut escaped = false;
+            for (offset, byte) in rest.bytes().enumerate().skip(1) {
+                match byte {
+                    b'\\' if !escaped => escaped = true,
+                    byte if byte == quote && !escaped => return Some(i + offset + 1),
+                    _ => escaped = false,
+                }
+            }
+            Some(sql.len())
+        }
+        b'#' => Some(until("\n", 1)),
+        _ if rest.starts_with("--") || rest.starts_with("//") => Some(until("\n", 2)),
+        _ if rest.starts_with("/*") => Some(until("*/", 2)),
+        _ if rest.starts_with('⟨') => Some(until("⟩", '⟨'.len_utf8())),
+        _ => None,
+    }
+}
+
+/// Whether a comment, rather than a string or quoted identifier, starts at `i`
+fn is_comment(sql: &str, i: usize) -> bool {
+    let rest = &sql[i..];
+    ["#", "--", "//", "/*"].iter().any(|start| rest.starts_with(start))
+}
+
+/// Fail if `sql` has a statement that would leave the namespace and
+/// database it runs in, or reach the root: `USE`, defining, altering or
+/// removing a namespace or database, and anything `ON ROOT` or `FOR ROOT`
+///
+/// The embedded engine has a single session, on the server's namespace, so
+/// a query bound to a tenant starts with a `USE` statement switching to the
+/// tenant's namespace; a statement of the caller's after it could reach
+/// every other tenant's data.
+pub(crate) fn check_bound(sql: &str) -> Result<()> {
+    const SCOPES: [&str; 4] = ["NS", "NAMESPACE", "DB", "DATABASE"];
+    let is = |word: &str, keywords: &[&str]| keywords.iter().any(|keyword| word.eq_ignore_ascii_case(keyword));
+    let bytes = sql.as_bytes();
+    let mut previous: Option<&str> = None;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if let Some(end) = skip_quoted(sql, i) {
+            // Comments separate keywords as whitespace does
+            if !is_comment(sql, i) {
+                previous = None;
+            }
+            i = end;
+            continue;
+        }
+        match bytes[i] {
+            byte if byte.is_ascii_whitespace() => {}
+            byte if is_ident(byte) => {
+                let start = i;
+                while i < bytes.len() && is_ident(bytes[i]) {
+                    i += 1;
+                }
+                let word = &sql[start..i];
+                if let Some(previous) = previous {
+                    let leaves = (is(previous, &["USE", "DEFINE", "ALTER", "REMOVE"]) && is(word, &SCOPES))
+                        || (is(previous, &["ON", "FOR"]) && is(word, &["ROOT"]));
+                    if leaves {
+                        return Err(Error::Validation(format!(
+                            "'{} {}' cannot be used in a query bound to a tenant",
+                            previous.to_ascii_uppercase(),
+                            word.to_ascii_uppercase()
+                        )));
+                    }
+                }
+                previous = Some(word);
+                continue;
+            }
+            _ => previous = None,
+        }
+        i = next_char(sql, i);
+    }
+
+    Ok(())
+}
+
+/// Whether `sql` has a `BEGIN`, `COMMIT` or `CANCEL` statement
+fn manages_transaction(sql: &str) -> bool {
+    let bytes = sql.as_bytes();
+    let mut statement_start = true;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if let Some(end) = skip_quoted(sql, i) {
+            i = end;
+            continue;
+        }
+        match bytes[i] {
+            b';' => statement_start = true,
+            byte if byte.is_ascii_whitespace() => {}
+            byte if is_ident(byte) => {
+                let start = i;
+                while i < bytes.len() && is_ident(bytes[i]) {
+                    i += 1;
+                }
+                let keyword = &sql[start..i];
+                if statement_start
+                    && ["BEGIN", "COMMIT", "CANCEL"]
+                        .iter()
+                        .any(|statement| keyword.eq_ignore_ascii_case(statement))
+                {
+                    return true;
+                }
+                statement_start = false;
+                continue;
+            }
+            _ => statement_start = false,
+        }
+        i = next_char(sql, i);
+    }
+
+    false
+}
+
+/// If a path call such as `ext::name(` starts at `i`, return the path and the
+/// position of its opening parenthesis
+fn function_call(sql: &str, i: usize) -> Option<(&str, usize)> {
+    let bytes = sql.as_bytes();
+    if !(bytes[i].is_ascii_alphabetic() || bytes[i] == b'_') {
+        return None;
+    }
+    if i > 0 && (is_ident(bytes[i - 1]) || matches!(bytes[i - 1], b':' | b'$' | b'.')) {
+        return None;
+    }
+
+    let mut end = i;
+    let mut segments = 0;
+    loop {
+        let start = end;
+        while end < bytes.len() && is_ident(bytes[end]) {
+            end += 1;
+        }
+        if end == start {
+            return None;
+        }
+        segments += 1;
+        if !sql[end..].starts_with("::") {
+            break;
+        }
+        end += 2;
+    }
+    // A version, as in `ext::tax@v2(`
+    if bytes.get(end) == Some(&b'@') && bytes.get(end + 1).copied().is_some_and(is_ident) {
+        end += 1;
+        while end < bytes.len() && (is_ident(bytes[end]) || bytes[end] == b'.') {
+            end += 1;
+        }
+    }
+
+    let open = end + sql[end..].len() - sql[end..].trim_start().len();
+    (segments > 1 && bytes.get(open) == Some(&b'(')).then(|| (&sql[i..end], open))
+}
+
+/// Find the parenthesis closing the one at `open`
+fn closing_paren(sql: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+
+    while i < sql.len() {
+        if let Some(end) = skip_quoted(sql, i) {
+            i = end;
+            continue;
+        }
+        match sql.as_bytes()[i] {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i = next_char(sql, i);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Module, SurrealX};
+
+    #[test]
+    fn skips_strings_and_quoted_identifiers() {
+        assert_eq!(skip_quoted("'a' + 1", 0), Some(3));
+        assert_eq!(skip_quoted(r#""a\"b" x"#, 0), Some(6));
+        assert_eq!(skip_quoted(r"'it\'s' x", 0), Some(7));
+        assert_eq!(skip_quoted("`odd name` x", 0), Some(10));
+        assert_eq!(skip_quoted("⟨odd name⟩ x", 0), Some("⟨odd name⟩".len()));
+        assert_eq!(skip_quoted("'unterminated", 0), Some(13));
+        assert_eq!(skip_quoted("a 'b'", 0), None);
+    }
+
+    #[test]
+    fn skips_comments() {
+        assert_eq!(skip_quoted("-- note\nRETURN 1", 0), Some(8));
+        assert_eq!(skip_quoted("// note\nRETURN 1", 0), Some(8));
+        assert_eq!(skip_quoted("# note\nRETURN 1", 0), Some(7));
+        assert_eq!(skip_quoted("/* a\nb */ RETURN 1", 0), Some(9));
+        assert_eq!(skip_quoted("/* open", 0), Some(7));
+        assert!(is_comment("-- note", 0));
+        assert!(!is_comment("'-- note'", 0));
+    }
+
+    #[test]
+    fn finds_function_calls() {
+        assert_eq!(function_call("ext::tax(1)", 0), Some(("ext::tax", 8)));
+        assert_eq!(function_call("ext::billing::tax (1)", 0), Some(("ext::billing::tax", 18)));
+        assert_eq!(function_call("ext::tax@v2(1)", 0), Some(("ext::tax@v2", 11)));
+        assert_eq!(function_call("ext::tax@v2.1(1)", 0), Some(("ext::tax@v2.1", 13)));
+        assert_eq!(function_call("ext::tax@(1)", 0), None);
+        assert_eq!(function_call("tax(1)", 0), None);
+        assert_eq!(function_call("ext::tax", 0), None);
+    }
+
+    #[test]
+    fn finds_calls_only_where_a_path_starts() {
+        let sql = "$ext::tax(1)";
+        assert_eq!(function_call(sql, 1), None);
+        let sql = "a.ext::tax(1)";
+        assert_eq!(function_call(sql, 2), None);
+        let sql = "myext::tax(1)";
+        assert_eq!(function_call(sql, 2), None);
+        let sql = "RETURN ext::tax(1)";
+        assert_eq!(function_call(sql, 7), Some(("ext::tax", 15)));
+    }
+
+    #[test]
+    fn closes_nested_parentheses() {
+        let sql = "ext::tax((1 + 2) * 3, ')', ext::rate(4))";
+        assert_eq!(closing_paren(sql, 8), Some(sql.len() - 1));
+        assert_eq!(closing_paren("ext::tax((1)", 8), None);
+        assert_eq!(closing_paren("ext::tax(1 /* ) */", 8), None);
+    }
+
+    #[test]
+    fn detects_transaction_statements() {
+        assert!(manages_transaction("BEGIN TRANSACTION; CREATE a; COMMIT TRANSACTION;"));
+        assert!(manages_transaction("CREATE a;\n  begin;"));
+        assert!(manages_transaction("CREATE a; CANCEL"));
+        assert!(!manages_transaction("CREATE a SET note = 'BEGIN'"));
+        assert!(!manages_transaction("CREATE a; -- COMMIT\nCREATE b"));
+        assert!(!manages_transaction("CREATE commit SET begin = 1"));
+        assert!(!manages_transaction("SELECT * FROM `COMMIT`"));
+    }
+
+    #[test]
+    fn bound_queries_cannot_leave_their_namespace() {
+        assert!(check_bound("SELECT * FROM person WHERE ns = 'USE NS other'").is_ok());
+        assert!(check_bound("DEFINE TABLE database; SELECT * FROM use").is_ok());
+        assert!(check_bound("USE NS other").is_err());
+        assert!(check_bound("use\n\tdb other").is_err());
+        assert!(check_bound("USE /* hidden */ NS other").is_err());
+        assert!(check_bound("REMOVE NAMESPACE other").is_err());
+        assert!(check_bound("DEFINE USER admin ON ROOT PASSWORD 'x'").is_err());
+        assert!(check_bound("DEFINE ACCESS token FOR ROOT").is_err());
+        assert!(check_bound("SELECT 'USE' NS").is_ok());
+    }
+
+    #[tokio::test]
+    async fn rewrites_calls_outside_strings_and_comments() -> Result<()> {
+        let module = Module::new("t")
+            .with_function("double", |args: Vec<Value>| async move {
+                Ok(json!(args[0].as_i64().unwrap_or_default() * 2))
+            })
+            .with_function("tax@v2", |args: Vec<Value>| async move {
+                Ok(json!(args[0].as_i64().unwrap_or_default() + 1))
+            });
+        let server = SurrealX::new().with_module(module).build().await?;
+
+        let results = server
+            .query(
+                "RETURN ext::t::double(ext::t::double(1 + 1));
+                RETURN 'ext::t::double(1)';
+                RETURN ext::t::tax@v2((RETURN 2 + 2));
+                -- an unterminated ext::t::double( in a comment
+                RETURN string::len('a');",
+            )
+            .await?;
+        assert_eq!(results, vec![json!(8), json!("ext::t::double(1)"), json!(5), json!(1)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_unterminated_call() -> Result<()> {
+        let module = Module::new("t").with_function("double", |args: Vec<Value>| async move { Ok(args[0].clone()) });
+        let server = SurrealX::new().with_module(module).build().await?;
+
+        let err = server.query("RETURN ext::t::double(1").await.unwrap_err();
+        assert!(matches!(err, Error::Function(message) if message.contains("unterminated")));
+        Ok(())
+    }
+}
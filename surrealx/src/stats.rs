@@ -0,0 +1,266 @@
+//! Call statistics of functions and the slow-call log
+//!
+//! Every call to a module's function is counted in the
+//! [`FunctionRegistry`](crate::FunctionRegistry), with whether it failed and
+//! how long it took; [`FunctionRegistry::stats`](crate::FunctionRegistry::stats),
+//! `BuiltSurrealX::function_stats` and `GET /sx/admin/functions/stats` report
+//! the counts with latency percentiles over each function's most recent
+//! calls. Statistics cover the calls this process served since it started.
+//!
+//! With `ServerConfig::slow_call_threshold` set, calls taking longer are
+//! logged at `warn` with their arguments, after an [`ArgumentRedactor`] has
+//! removed what should not reach the logs. [`KeyRedactor`] is used unless
+//! another is set with `SurrealX::with_argument_redactor`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::context::FunctionContext;
+use crate::functions::FunctionHandler;
+use crate::error::Result;
+
+/// Latencies kept per function for the percentiles
+const SAMPLES: usize = 1024;
+
+/// Calls of a function, as reported by [`FunctionRegistry::stats`](crate::FunctionRegistry::stats)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionStats {
+    /// Fully qualified function name
+    pub function: String,
+    pub calls: u64,
+    /// Calls that returned an error
+    pub errors: u64,
+    /// Share of calls that returned an error, from 0 to 1
+    pub error_rate: f64,
+    /// Median latency in milliseconds, over the most recent calls
+    pub p50_ms: f64,
+    /// 90th percentile latency in milliseconds, over the most recent calls
+    pub p90_ms: f64,
+    /// 99th percentile latency in milliseconds, over the most recent calls
+    pub p99_ms: f64,
+    /// Longest latency in milliseconds, over the most recent calls
+    pub max_ms: f64,
+}
+
+/// Counters of every function, shared by clones of a registry
+#[derive(Default)]
+pub(crate) struct CallStats {
+    functions: Mutex<HashMap<String, Counters>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    calls: u64,
+    errors: u64,
+    /// Latencies of the most recent calls, oldest first
+    recent: VecDeque<Duration>,
+}
+
+impl CallStats {
+    pub(crate) fn record(&self, function: &str, elapsed: Duration, failed: bool) {
+        let mut functions = self.functions.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let counters = match functions.get_mut(function) {
+            Some(counters) => counters,
+            None => functions.entry(function.to_string()).or_default(),
+        };
+        counters.calls += 1;
+        counters.errors += u64::from(failed);
+        if counters.recent.len() == SAMPLES {
+            counters.recent.pop_front();
+        }
+        counters.recent.push_back(elapsed);
+    }
+
+    /// Statistics of every function called so far, by name
+    pub(crate) fn snapshot(&self) -> Vec<FunctionStats> {
+        let functions = self.functions.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut stats: Vec<FunctionStats> = functions
+            .iter()
+            .map(|(function, counters)| counters.stats(function))
+            .collect();
+        stats.sort_by(|a, b| a.function.cmp(&b.function));
+        stats
+    }
+
+    pub(crate) fn get(&self, function: &str) -> Option<FunctionStats> {
+        let functions = self.functions.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        functions.get(function).map(|counters| counters.stats(function))
+    }
+
+    /// Forget the counters of `function`, such as when it is unregistered
+    pub(crate) fn remove(&self, function: &str) {
+        self.functions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(function);
+    }
+}
+
+impl Counters {
+    fn stats(&self, function: &str) -> FunctionStats {
+        let mut latencies: Vec<Duration> = self.recent.iter().copied().collect();
+        latencies.sort_unstable();
+        let percentile = |p: f64| match latencies.len() {
+            0 => 0.0,
+            len => millis(latencies[((len - 1) as f64 * p).round() as usize]),
+        };
+        FunctionStats {
+            function: function.to_string(),
+            calls: self.calls,
+            errors: self.errors,
+            error_rate: if self.calls == 0 {
+                0.0
+            } else {
+                self.errors as f64 / self.calls as f64
+            },
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: latencies.last().copied().map_or(0.0, millis),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Removes sensitive values from the arguments of slow calls before they
+/// are logged
+pub trait ArgumentRedactor: Send + Sync {
+    /// Arguments of a call to `function` as they may be logged
+    fn redact(&self, function: &str, args: &[Value]) -> Vec<Value>;
+}
+
+/// Replaces the values of object fields with sensitive names, at any depth,
+/// with `"[redacted]"`
+///
+/// Names are compared without regard to case. By default the fields are
+/// `password`, `secret`, `token`, `api_key`, `authorization` and
+/// `card_number`.
+#[derive(Debug, Clone)]
+pub struct KeyRedactor {
+    keys: Vec<String>,
+}
+
+impl KeyRedactor {
+    /// Redact the fields named `keys` only
+    pub fn new<I, K>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = K>,
+        K: Into<String>,
+    {
+        Self {
+            keys: keys.into_iter().map(|key| key.into().to_ascii_lowercase()).collect(),
+        }
+    }
+
+    /// Redact fields named `key` too
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.keys.push(key.into().to_ascii_lowercase());
+        self
+    }
+
+    fn redact_value(&self, value: &Value) -> Value {
+        match value {
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = if self.keys.contains(&name.to_ascii_lowercase()) {
+                            Value::String("[redacted]".to_string())
+                        } else {
+                            self.redact_value(value)
+                        };
+                        (name.clone(), value)
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(items.iter().map(|item| self.redact_value(item)).collect()),
+            other => other.clone(),
+        }
+    }
+}
+
+impl Default for KeyRedactor {
+    fn default() -> Self {
+        Self::new(["password", "secret", "token", "api_key", "authorization", "card_number"])
+    }
+}
+
+impl ArgumentRedactor for KeyRedactor {
+    fn redact(&self, _function: &str, args: &[Value]) -> Vec<Value> {
+        args.iter().map(|arg| self.redact_value(arg)).collect()
+    }
+}
+
+/// Logs no argument values at all, only how many there were
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RedactAll;
+
+impl ArgumentRedactor for RedactAll {
+    fn redact(&self, _function: &str, args: &[Value]) -> Vec<Value> {
+        vec![Value::String("[redacted]".to_string()); args.len()]
+    }
+}
+
+/// When calls are slow enough to log, and how their arguments are redacted
+#[derive(Clone)]
+pub(crate) struct SlowCallLog {
+    pub(crate) threshold: Duration,
+    pub(crate) redactor: Arc<dyn ArgumentRedactor>,
+}
+
+/// Function handler that counts its calls, and logs slow ones
+pub(crate) struct TrackedFunction {
+    pub(crate) inner: Arc<dyn FunctionHandler>,
+    pub(crate) stats: Arc<CallStats>,
+    pub(crate) function: String,
+    pub(crate) slow_calls: Option<SlowCallLog>,
+}
+
+impl TrackedFunction {
+    fn record(&self, started: Instant, result: &Result<Value>, args: Option<&[Value]>, request_id: Option<&str>) {
+        let elapsed = started.elapsed();
+        self.stats.record(&self.function, elapsed, result.is_err());
+        let (Some(slow_calls), Some(args)) = (&self.slow_calls, args) else {
+            return;
+        };
+        if elapsed < slow_calls.threshold {
+            return;
+        }
+        let args = slow_calls.redactor.redact(&self.function, args);
+        tracing::warn!(
+            function = %self.function,
+            elapsed_ms = millis(elapsed),
+            args = %Value::Array(args),
+            request_id = request_id,
+            failed = result.is_err(),
+            "slow function call"
+        );
+    }
+}
+
+#[async_trait]
+impl FunctionHandler for TrackedFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        // Arguments are only kept for logging when slow calls are
+        let logged = self.slow_calls.as_ref().map(|_| args.clone());
+        let started = Instant::now();
+        let result = self.inner.call(args).await;
+        self.record(started, &result, logged.as_deref(), None);
+        result
+    }
+
+    async fn call_with_context(&self, context: FunctionContext, args: Vec<Value>) -> Result<Value> {
+        let logged = self.slow_calls.as_ref().map(|_| args.clone());
+        let request_id = context.request_id().to_string();
+        let started = Instant::now();
+        let result = self.inner.call_with_context(context, args).await;
+        self.record(started, &result, logged.as_deref(), Some(&request_id));
+        result
+    }
+}
@@ -0,0 +1,121 @@
+//! Event stream encoding for subscription endpoints
+
+use std::io::Write;
+use axum::http::{header, HeaderMap};
+use flate2::Compression;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use crate::events::Event;
+use crate::error::Result;
+
+/// Compression settings for streamed event frames
+#[derive(Debug, Clone)]
+pub struct StreamCompression {
+    /// Frames smaller than this many bytes are sent uncompressed
+    pub threshold: usize,
+    /// Compression level (0-9)
+    pub level: u32,
+}
+
+impl StreamCompression {
+    /// Create compression settings with the given threshold
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            ..Self::default()
+        }
+    }
+
+    /// Set the compression level (0-9)
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+        self
+    }
+
+    /// Encode an event as a WebSocket frame
+    ///
+    /// Frames at or above the threshold are compressed with raw DEFLATE and
+    /// should be sent as binary messages; smaller frames stay as JSON text.
+    /// This is not `permessage-deflate`, so only clients that asked for it
+    /// can read them.
+    pub fn encode_frame(&self, event: &Event) -> Result<EventFrame> {
+        let json = serde_json::to_string(event)?;
+        if json.len() < self.threshold {
+            return Ok(EventFrame::Text(json));
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(self.level));
+        encoder.write_all(json.as_bytes())?;
+        Ok(EventFrame::Deflate(encoder.finish()?))
+    }
+
+    /// Create an incremental gzip encoder for an SSE response body
+    pub fn gzip_stream(&self) -> GzipStreamEncoder {
+        GzipStreamEncoder::new(self.level)
+    }
+}
+
+impl Default for StreamCompression {
+    fn default() -> Self {
+        Self {
+            threshold: 512,
+            level: 6,
+        }
+    }
+}
+
+/// A serialized event frame ready to be written to a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventFrame {
+    /// Uncompressed JSON
+    Text(String),
+    /// JSON compressed with raw DEFLATE
+    Deflate(Vec<u8>),
+}
+
+/// Incremental gzip encoder for `Content-Encoding: gzip` event streams
+///
+/// Each chunk is sync-flushed so the client can decode every event as soon
+/// as it arrives instead of waiting for the stream to end.
+pub struct GzipStreamEncoder {
+    encoder: GzEncoder<Vec<u8>>,
+}
+
+impl GzipStreamEncoder {
+    pub fn new(level: u32) -> Self {
+        Self {
+            encoder: GzEncoder::new(Vec::new(), Compression::new(level.min(9))),
+        }
+    }
+
+    /// Compress a chunk and return the bytes to write to the response body
+    pub fn encode(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.encoder.write_all(chunk)?;
+        self.encoder.flush()?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    /// Finish the gzip stream and return the trailing bytes
+    pub fn finish(self) -> Result<Vec<u8>> {
+        Ok(self.encoder.finish()?)
+    }
+}
+
+/// Check whether a request's `Accept-Encoding` header allows gzip
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+        })
+}
@@ -0,0 +1,461 @@
+//! Streaming events to clients over WebSocket and Server-Sent Events
+//!
+//! With `ServerConfig::event_stream_route` set, clients open a WebSocket at
+//! `GET /sx/events` and pick the events they receive by sending JSON text
+//! messages: `{"subscribe": "orders:*"}` and `{"unsubscribe": "orders:*"}`.
+//! Each is answered with `{"subscribed": ...}`, `{"unsubscribed": ...}` or
+//! `{"error": ...}`, and every event matching one of the connection's
+//! patterns is sent as a JSON text frame of its own. A first pattern may also
+//! be given as the `pattern` query parameter. With
+//! [`SurrealX::with_stream_compression`](crate::SurrealX::with_stream_compression)
+//! set, clients that open the socket with `compression=deflate` get large
+//! frames as binary raw DEFLATE instead; this is not the `permessage-deflate`
+//! extension, so others keep getting text.
+//!
+//! With [`SurrealX::expose_functions_over_http`](crate::SurrealX::expose_functions_over_http)
+//! enabled, the WebSocket also takes
+//! `{"call": {"id": 1, "function": "ext::reports::export", "args": [...]}}`.
+//! The values of a streaming function are sent as they are produced, as
+//! `{"id": 1, "result": ...}`, and other functions' result as one such
+//! message; then `{"id": 1, "done": true}`, or `{"id": 1, "error": ...}` if
+//! the call fails. Calls run concurrently with each other and with the
+//! connection's events, up to 16 at a time; further calls are answered with
+//! an error until one finishes.
+//!
+//! Clients that cannot use WebSockets request the same path without upgrading
+//! to receive Server-Sent Events instead, for the pattern given as the
+//! required `pattern` query parameter. Every event is sent as JSON data with
+//! its timestamp as the id, and a `heartbeat` comment is sent every 15
+//! seconds to keep proxies from closing idle streams. When the browser
+//! reconnects with a `Last-Event-ID` header and the registry has an event
+//! store, the stored events from that second on are sent first, so events of
+//! that second may arrive twice. With stream compression set, streams are
+//! gzip encoded for clients that accept it.
+//!
+//! The route is authenticated and rate limited like module routes. Patterns
+//! guarded with [`SurrealX::with_event_access`](crate::SurrealX::with_event_access)
+//! can only be subscribed to, and their events only received, by callers
+//! holding one of the given roles. Each connection may fall up to 1024
+//! events behind; beyond that it misses the oldest and is sent
+//! `{"lagged": <missed>}`, or a `lagged` event over SSE.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::body::Body;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::stream::{BoxStream, SelectAll};
+use futures::{stream, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::auth::Identity;
+use crate::events::{Event, EventRegistry};
+use crate::rest::Functions;
+use crate::streaming::{self, EventFrame, StreamCompression};
+use crate::tenancy::Tenant;
+use crate::error::Result;
+
+/// How often an idle event stream is sent a comment
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Calls a WebSocket connection may have running at once
+const MAX_CALLS_IN_FLIGHT: usize = 16;
+
+/// Roles required to receive the events of guarded patterns
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventAcl {
+    rules: Vec<(String, Vec<String>)>,
+}
+
+impl EventAcl {
+    /// Require one of `roles` for events matching `pattern`
+    pub(crate) fn guard(&mut self, pattern: String, roles: Vec<String>) {
+        self.rules.push((pattern, roles));
+    }
+
+    /// Whether `identity` may subscribe to `pattern` itself
+    fn permits_subscription(&self, pattern: &str, identity: Option<&Identity>) -> bool {
+        self.rules
+            .iter()
+            .filter(|(guarded, _)| guarded == pattern)
+            .all(|(_, roles)| holds(roles, identity))
+    }
+
+    /// Whether `identity` may receive `event`, matched under `events`' syntax
+    fn permits(&self, events: &EventRegistry, event: &Event, identity: Option<&Identity>) -> bool {
+        self.rules
+            .iter()
+            .filter(|(guarded, _)| events.syntax().matches(guarded, event))
+            .all(|(_, roles)| holds(roles, identity))
+    }
+}
+
+fn holds(roles: &[String], identity: Option<&Identity>) -> bool {
+    identity.is_some_and(|identity| roles.iter().any(|role| identity.has_role(role)))
+}
+
+#[derive(Clone)]
+struct StreamState {
+    events: EventRegistry,
+    acl: Arc<EventAcl>,
+    compression: Option<StreamCompression>,
+    /// Functions WebSocket clients may call, if any
+    functions: Option<Functions>,
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    pattern: Option<String>,
+    /// How the client wants WebSocket frames compressed
+    compression: Option<FrameCompression>,
+}
+
+/// Frame compression a WebSocket client can decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FrameCompression {
+    Deflate,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe(String),
+    Unsubscribe(String),
+    Call(CallRequest),
+}
+
+/// A function call sent over the WebSocket
+#[derive(Deserialize)]
+struct CallRequest {
+    /// Echoed in every message answering the call
+    #[serde(default)]
+    id: Value,
+    function: String,
+    #[serde(default)]
+    args: Vec<Value>,
+}
+
+/// Serve `GET /sx/events`
+pub(crate) fn router(
+    events: EventRegistry,
+    acl: EventAcl,
+    compression: Option<StreamCompression>,
+    functions: Option<Functions>,
+) -> Router {
+    Router::new().route("/sx/events", get(events_handler)).with_state(StreamState {
+        events,
+        acl: Arc::new(acl),
+        compression,
+        functions,
+    })
+}
+
+async fn events_handler(
+    State(state): State<StreamState>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+    identity: Option<Identity>,
+    tenant: Option<Tenant>,
+    upgrade: Option<WebSocketUpgrade>,
+) -> Response {
+    let session = Session::new(identity, tenant);
+    match upgrade {
+        Some(upgrade) => {
+            let compression = match query.compression {
+                Some(FrameCompression::Deflate) => state.compression.clone(),
+                None => None,
+            };
+            upgrade.on_upgrade(move |socket| stream_events(socket, state, session, query.pattern, compression))
+        }
+        None => match query.pattern {
+            Some(pattern) => send_events(state, session, pattern, &headers).await,
+            None => error(StatusCode::BAD_REQUEST, "the pattern query parameter is required".to_string()),
+        },
+    }
+}
+
+/// Patterns a connection subscribed to
+pub(crate) struct Session {
+    identity: Option<Identity>,
+    tenant: Option<Tenant>,
+    patterns: Vec<String>,
+}
+
+impl Session {
+    pub(crate) fn new(identity: Option<Identity>, tenant: Option<Tenant>) -> Self {
+        Self {
+            identity,
+            tenant,
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Add `pattern`, or say why it was refused
+    pub(crate) fn subscribe(&mut self, acl: &EventAcl, pattern: &str) -> std::result::Result<(), (StatusCode, String)> {
+        if pattern.is_empty() || pattern.chars().any(char::is_whitespace) {
+            return Err((StatusCode::BAD_REQUEST, format!("invalid pattern '{}'", pattern)));
+        }
+        if !acl.permits_subscription(pattern, self.identity.as_ref()) {
+            return Err((StatusCode::FORBIDDEN, format!("not allowed to subscribe to '{}'", pattern)));
+        }
+        if !self.patterns.iter().any(|subscribed| subscribed == pattern) {
+            self.patterns.push(pattern.to_string());
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, acl: &EventAcl, message: ClientMessage) -> Value {
+        match message {
+            ClientMessage::Subscribe(pattern) => match self.subscribe(acl, &pattern) {
+                Ok(()) => json!({ "subscribed": pattern }),
+                Err((_, message)) => json!({ "error": message }),
+            },
+            ClientMessage::Unsubscribe(pattern) => {
+                self.patterns.retain(|subscribed| *subscribed != pattern);
+                json!({ "unsubscribed": pattern })
+            }
+            ClientMessage::Call(request) => json!({ "id": request.id, "error": "function calls are not enabled" }),
+        }
+    }
+
+    fn wants(&self, state: &StreamState, event: &Event) -> bool {
+        self.receives(&state.events, &state.acl, event)
+    }
+
+    /// Whether `event` matches one of the patterns and may be sent
+    pub(crate) fn receives(&self, events: &EventRegistry, acl: &EventAcl, event: &Event) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| events.syntax().matches(pattern, event))
+            && self.permits(events, acl, event)
+    }
+
+    /// Whether `event` belongs to the session's tenant and its caller may
+    /// receive it
+    fn permits(&self, events: &EventRegistry, acl: &EventAcl, event: &Event) -> bool {
+        event.tenant.as_deref() == self.tenant.as_ref().map(Tenant::id)
+            && acl.permits(events, event, self.identity.as_ref())
+    }
+}
+
+async fn stream_events(
+    mut socket: WebSocket,
+    state: StreamState,
+    mut session: Session,
+    pattern: Option<String>,
+    compression: Option<StreamCompression>,
+) {
+    let mut tap = state.events.tap();
+    let mut calls: SelectAll<BoxStream<'static, Value>> = SelectAll::new();
+    if let Some(pattern) = pattern {
+        let reply = session.handle(&state.acl, ClientMessage::Subscribe(pattern));
+        if socket.send(Message::Text(reply.to_string())).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let outgoing = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Call(request)) if state.functions.is_some() => {
+                        if calls.len() >= MAX_CALLS_IN_FLIGHT {
+                            let message = format!("too many calls in flight, at most {} at a time", MAX_CALLS_IN_FLIGHT);
+                            Message::Text(json!({ "id": request.id, "error": message }).to_string())
+                        } else {
+                            let functions = state.functions.clone().expect("checked above");
+                            calls.push(call_messages(functions, session.identity.clone(), session.tenant.clone(), request));
+                            continue;
+                        }
+                    }
+                    Ok(message) => Message::Text(session.handle(&state.acl, message).to_string()),
+                    Err(err) => Message::Text(json!({ "error": format!("invalid message: {}", err) }).to_string()),
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+            event = tap.recv() => match event {
+                Ok(event) if session.wants(&state, &event) => match encode(compression.as_ref(), &event) {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        tracing::warn!(pattern = %event.pattern(), error = %err, "failed to encode streamed event");
+                        continue;
+                    }
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => Message::Text(json!({ "lagged": missed }).to_string()),
+                Err(RecvError::Closed) => return,
+            },
+            Some(reply) = calls.next(), if !calls.is_empty() => Message::Text(reply.to_string()),
+        };
+        if socket.send(outgoing).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Messages answering a call: one per value, then `done` or an error
+fn call_messages(
+    functions: Functions,
+    identity: Option<Identity>,
+    tenant: Option<Tenant>,
+    request: CallRequest,
+) -> BoxStream<'static, Value> {
+    let CallRequest { id, function, args } = request;
+    let values = stream::once(async move {
+        let mut context = functions.context.for_request(None);
+        if let Some(identity) = identity {
+            context = context.with_identity(identity);
+        }
+        if let Some(tenant) = tenant {
+            context = context.with_tenant(tenant);
+        }
+        functions.stream(&function, context, args).await
+    })
+    .flat_map(|values| match values {
+        Ok(values) => values,
+        Err(err) => stream::once(async move { Err(err) }).boxed(),
+    });
+
+    stream::unfold(Some(values.boxed()), move |values| {
+        let id = id.clone();
+        async move {
+            let mut values = values?;
+            match values.next().await {
+                Some(Ok(value)) => Some((json!({ "id": id, "result": value }), Some(values))),
+                Some(Err(err)) => Some((json!({ "id": id, "error": err.to_string() }), None)),
+                None => Some((json!({ "id": id, "done": true }), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Encode `event`, compressed only for clients that asked for it
+fn encode(compression: Option<&StreamCompression>, event: &Event) -> Result<Message> {
+    match compression {
+        Some(compression) => Ok(match compression.encode_frame(event)? {
+            EventFrame::Text(text) => Message::Text(text),
+            EventFrame::Deflate(bytes) => Message::Binary(bytes),
+        }),
+        None => Ok(Message::Text(serde_json::to_string(event)?)),
+    }
+}
+
+/// State of a Server-Sent Events stream
+struct SseStream {
+    state: StreamState,
+    session: Session,
+    tap: broadcast::Receiver<Event>,
+    /// Stored events still to be sent
+    backlog: VecDeque<Event>,
+    /// Stored events of the last second sent from the backlog, which may also
+    /// arrive live
+    replayed: Vec<String>,
+    replayed_until: i64,
+}
+
+impl SseStream {
+    async fn next(&mut self) -> Option<sse::Event> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                return Some(sse_event(&event));
+            }
+            match self.tap.recv().await {
+                Ok(event) if self.session.wants(&self.state, &event) && !self.was_replayed(&event) => {
+                    return Some(sse_event(&event));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => return Some(sse::Event::default().event("lagged").data(missed.to_string())),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    fn was_replayed(&self, event: &Event) -> bool {
+        event.timestamp <= self.replayed_until
+            && serde_json::to_string(event).is_ok_and(|json| self.replayed.contains(&json))
+    }
+}
+
+async fn send_events(state: StreamState, mut session: Session, pattern: String, headers: &HeaderMap) -> Response {
+    if let Err((status, message)) = session.subscribe(&state.acl, &pattern) {
+        return error(status, message);
+    }
+
+    // Listen before reading the store, so nothing emitted meanwhile is missed
+    let tap = state.events.tap();
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok());
+    let backlog: VecDeque<Event> = match last_event_id {
+        Some(from) => match state.events.history(from, &pattern).await {
+            Ok(events) => events
+                .into_iter()
+                .filter(|event| session.permits(&state.events, &state.acl, event))
+                .collect(),
+            Err(err) => {
+                tracing::debug!(pattern = %pattern, error = %err, "not resuming event stream");
+                VecDeque::new()
+            }
+        },
+        None => VecDeque::new(),
+    };
+    let replayed_until = backlog.back().map(|event| event.timestamp).unwrap_or(i64::MIN);
+    let replayed = backlog
+        .iter()
+        .filter(|event| event.timestamp == replayed_until)
+        .filter_map(|event| serde_json::to_string(event).ok())
+        .collect();
+
+    let compression = state.compression.clone();
+    let events = stream::unfold(
+        SseStream {
+            state,
+            session,
+            tap,
+            backlog,
+            replayed,
+            replayed_until,
+        },
+        |mut stream| async move { stream.next().await.map(|event| (Ok::<_, axum::Error>(event), stream)) },
+    );
+    let response = Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(HEARTBEAT_INTERVAL).text("heartbeat"))
+        .into_response();
+
+    match compression {
+        Some(compression) if streaming::accepts_gzip(headers) => gzip(response, &compression),
+        _ => response,
+    }
+}
+
+fn sse_event(event: &Event) -> sse::Event {
+    let data = serde_json::to_string(event).unwrap_or_else(|err| json!({ "error": err.to_string() }).to_string());
+    sse::Event::default().id(event.timestamp.to_string()).data(data)
+}
+
+/// Compress `response`'s body as it is streamed
+fn gzip(response: Response, compression: &StreamCompression) -> Response {
+    let (mut parts, body) = response.into_parts();
+    let mut encoder = compression.gzip_stream();
+    let body = body
+        .into_data_stream()
+        .map(move |chunk| chunk.and_then(|chunk| encoder.encode(&chunk).map_err(axum::Error::new)));
+    parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    parts.headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,242 @@
+//! Logging and tracing
+//!
+//! Every HTTP request runs in an `sx.http` span carrying its correlation ID,
+//! taken from the `x-request-id` header or generated, and returned in the
+//! response's `x-request-id` header. Function calls (`sx.function`), event
+//! dispatch (`sx.emit`) and listeners (`sx.listener`) run in spans nested
+//! under it, naming the module, function or pattern involved. The spans of
+//! a module with [trace fields](crate::Module::with_trace_field) are nested
+//! in an `sx.module` span carrying each of them as a field of its own.
+//!
+//! `SurrealX::serve` installs a subscriber printing these in the
+//! `ServerConfig::log_format` when `log_level` is set, and with the `otlp`
+//! feature exports the spans to `ServerConfig::otlp_endpoint`.
+
+use std::sync::{Arc, OnceLock};
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::Router;
+use tracing::field::{Field, Value};
+use tracing::{Instrument, Level, Metadata, Span};
+use tracing_core::callsite::{self, Callsite, Identifier};
+use tracing_core::field::FieldSet;
+use tracing_core::{Interest, Kind};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+use crate::context;
+use crate::server::{LogFormat, ServerConfig};
+use crate::error::{Error, Result};
+
+/// Header carrying the correlation ID of a request
+pub(crate) const REQUEST_ID: &str = "x-request-id";
+
+/// Subscriber state to tear down when the server stops
+#[derive(Default)]
+pub(crate) struct Telemetry {
+    #[cfg(feature = "otlp")]
+    tracer_provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Export the spans still buffered
+    pub(crate) fn shutdown(self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.tracer_provider {
+            if let Err(err) = provider.shutdown() {
+                tracing::warn!(error = %err, "failed to export the remaining spans");
+            }
+        }
+    }
+}
+
+/// The `sx.module` span of a module's trace fields
+///
+/// tracing learns the names of a span's fields from its callsite, so each
+/// module with trace fields registers a callsite of its own, once per build,
+/// naming them.
+#[derive(Clone)]
+pub(crate) struct ModuleSpan {
+    /// `None` for modules without trace fields
+    callsite: Option<&'static ModuleCallsite>,
+    module: Arc<str>,
+    fields: Arc<[(String, String)]>,
+}
+
+struct ModuleCallsite {
+    metadata: OnceLock<Metadata<'static>>,
+}
+
+impl Callsite for ModuleCallsite {
+    // Subscribers are asked again for every span, in `ModuleSpan::in_scope`
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata.get().expect("set before the callsite is registered")
+    }
+}
+
+impl ModuleSpan {
+    pub(crate) fn new(module: &str, fields: &[(String, String)]) -> Self {
+        let mut span = Self {
+            callsite: None,
+            module: module.into(),
+            fields: fields.into(),
+        };
+        if fields.is_empty() {
+            return span;
+        }
+
+        let mut names: Vec<&'static str> = vec!["module"];
+        for (key, _) in fields {
+            if !names.contains(&key.as_str()) {
+                names.push(Box::leak(key.clone().into_boxed_str()));
+            }
+        }
+        let site: &'static ModuleCallsite = Box::leak(Box::new(ModuleCallsite {
+            metadata: OnceLock::new(),
+        }));
+        let metadata = Metadata::new(
+            "sx.module",
+            module_path!(),
+            Level::INFO,
+            None,
+            None,
+            Some(module_path!()),
+            FieldSet::new(Box::leak(names.into_boxed_slice()), Identifier(site)),
+            Kind::SPAN,
+        );
+        site.metadata.get_or_init(|| metadata);
+        callsite::register(site);
+        span.callsite = Some(site);
+        span
+    }
+
+    /// Run `f` inside a new `sx.module` span, so spans it creates are nested
+    /// in it
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        let Some(metadata) = self.callsite.and_then(|site| site.metadata.get()) else {
+            return f();
+        };
+        let enabled = tracing::dispatcher::get_default(|dispatch| dispatch.enabled(metadata));
+        if !enabled {
+            return f();
+        }
+
+        let values: [(&Field, Option<&dyn Value>); 0] = [];
+        let span = Span::new(metadata, &metadata.fields().value_set(&values));
+        span.record("module", &*self.module);
+        for (key, value) in self.fields.iter() {
+            span.record(key.as_str(), value.as_str());
+        }
+        span.in_scope(f)
+    }
+}
+
+/// Install a subscriber as `config` describes
+///
+/// Installs nothing unless `log_level` or `otlp_endpoint` is set, and leaves
+/// a subscriber the application installed itself in place.
+pub(crate) fn init(config: &ServerConfig) -> Result<Telemetry> {
+    #[cfg(not(feature = "otlp"))]
+    if config.otlp_endpoint.is_some() {
+        return Err(Error::Config(
+            "otlp_endpoint is set but the otlp feature is not enabled".to_string(),
+        ));
+    }
+    if config.log_level.is_none() && config.otlp_endpoint.is_none() {
+        return Ok(Telemetry::default());
+    }
+
+    let filter = config.log_level.as_deref().unwrap_or("info");
+    let env_filter = tracing_subscriber::EnvFilter::try_new(filter)
+        .map_err(|err| Error::Config(format!("invalid log_level '{}': {}", filter, err)))?;
+    let output = match config.log_format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+    let subscriber = tracing_subscriber::registry().with(env_filter).with(output);
+
+    #[cfg(feature = "otlp")]
+    {
+        use opentelemetry::trace::TracerProvider as _;
+
+        let tracer_provider = config.otlp_endpoint.as_deref().map(otlp_tracer_provider).transpose()?;
+        let exporter = tracer_provider
+            .as_ref()
+            .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("surrealx")));
+        if subscriber.with(exporter).try_init().is_err() {
+            tracing::debug!("a tracing subscriber is already installed; ignoring log_level and otlp_endpoint");
+            return Ok(Telemetry::default());
+        }
+        Ok(Telemetry { tracer_provider })
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        if subscriber.try_init().is_err() {
+            tracing::debug!("a tracing subscriber is already installed; ignoring log_level");
+        }
+        Ok(Telemetry::default())
+    }
+}
+
+/// Export spans over OTLP/gRPC, batched
+///
+/// The service is named `surrealx` unless `OTEL_SERVICE_NAME` says otherwise.
+#[cfg(feature = "otlp")]
+fn otlp_tracer_provider(endpoint: &str) -> Result<opentelemetry_sdk::trace::SdkTracerProvider> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|err| Error::Config(format!("invalid otlp_endpoint '{}': {}", endpoint, err)))?;
+
+    let mut resource = opentelemetry_sdk::Resource::builder();
+    if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name("surrealx");
+    }
+    Ok(opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build())
+}
+
+/// Run every request `router` serves in an `sx.http` span
+pub(crate) fn trace_requests(router: Router) -> Router {
+    router.layer(middleware::from_fn(trace_request))
+}
+
+async fn trace_request(mut request: Request, next: Next) -> Response {
+    let given = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    // Handlers further in, such as `/sql`, read the ID from the request
+    let request_id = given.unwrap_or_else(|| {
+        let request_id = context::next_request_id();
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            request.headers_mut().insert(REQUEST_ID, value);
+        }
+        request_id
+    });
+
+    let span = tracing::info_span!(
+        "sx.http",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id = %request_id,
+        status = tracing::field::Empty,
+    );
+    let mut response = next.run(request).instrument(span.clone()).await;
+    span.record("status", response.status().as_u16());
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+    response
+}
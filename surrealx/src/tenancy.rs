@@ -0,0 +1,427 @@
+//! Serving many tenants from one process
+//!
+//! With a [`TenantResolver`] set through `SurrealX::with_tenant_resolver`,
+//! every HTTP request is mapped to a tenant after authentication, and
+//! everything the request leads to is partitioned by it:
+//!
+//! - [`FunctionContext::tenant`](crate::FunctionContext::tenant) and
+//!   [`Context::tenant`](crate::Context::tenant) name the tenant, and route
+//!   handlers can extract it as `Option<Tenant>`.
+//! - The database handles of both contexts and the `/sql` endpoint run
+//!   queries in the tenant's namespace, named after the tenant, in the
+//!   server's database. A token issued for another namespace is refused, as
+//!   are queries with a statement that would leave the tenant's namespace,
+//!   such as `USE NS other`.
+//! - The caches of both contexts only see keys under `tenant:<id>:`.
+//! - Events emitted while a tenant's handler runs carry the tenant, as do
+//!   events the resolver maps with [`TenantResolver::resolve_event`].
+//!   Listeners handle them with the tenant's context, and event streams only
+//!   send a client the events of its own tenant.
+//!
+//! A token naming a namespace (`NS`) is accepted only for requests resolved
+//! to the tenant of that namespace, or, without a tenant, naming the
+//! server's namespace.
+//!
+//! Requests without a tenant are served as before, against the server's
+//! namespace, unless `ServerConfig::require_tenant` is set, in which case
+//! they are refused with `400 Bad Request`, as are requests naming an
+//! invalid tenant.
+//!
+//! The first request or event of each tenant provisions its namespace: the
+//! tables modules declare are reconciled and their migrations applied there,
+//! as `ServerConfig::schema` and `ServerConfig::migrations` say, before it is
+//! handled. `BuiltSurrealX::provision_tenant` does the same ahead of time.
+//! Migrations written in Rust cannot run in a tenant's namespace, so building
+//! a server with a tenant resolver fails if a module has one.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::auth::Identity;
+use crate::context::Context;
+use crate::db::{self, Database};
+use crate::events::{EmitNext, EmitReport, Event, EventMiddleware};
+use crate::error::{Error, Result};
+use crate::migration::Migrations;
+use crate::schema::{self, Table};
+use crate::server::{MigrationPolicy, SchemaPolicy};
+
+/// A tenant served by the process
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Tenant(String);
+
+impl Tenant {
+    /// Tenant `id`, which is also the name of its namespace
+    ///
+    /// Fails unless `id` is 1 to 64 ASCII letters, digits, `_` or `-`.
+    pub fn new(id: impl Into<String>) -> Result<Self> {
+        let id = id.into();
+        let valid = !id.is_empty()
+            && id.len() <= 64
+            && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            return Err(Error::Validation(format!("invalid tenant '{}'", id)));
+        }
+        Ok(Self(id))
+    }
+
+    /// Tenant identifier
+    pub fn id(&self) -> &str {
+        &self.0
+    }
+
+    /// SurrealDB namespace the tenant's data lives in
+    pub fn namespace(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Tenant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl TryFrom<String> for Tenant {
+    type Error = Error;
+
+    fn try_from(id: String) -> Result<Self> {
+        Tenant::new(id)
+    }
+}
+
+impl From<Tenant> for String {
+    fn from(tenant: Tenant) -> Self {
+        tenant.0
+    }
+}
+
+/// Extracts the tenant of a request, refusing requests without one
+///
+/// Use `Option<Tenant>` on routes that also serve requests without a tenant.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Tenant {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> std::result::Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Tenant>()
+            .cloned()
+            .ok_or_else(|| bad_request("a tenant is required"))
+    }
+}
+
+/// Maps requests and events to the tenant they belong to
+pub trait TenantResolver: Send + Sync {
+    /// Tenant of an HTTP request, given its headers and authenticated caller
+    fn resolve_request(&self, headers: &HeaderMap, identity: Option<&Identity>) -> Option<String>;
+
+    /// Tenant of an event emitted outside any tenant's handler, such as a
+    /// change feed event; none by default
+    fn resolve_event(&self, event: &Event) -> Option<String> {
+        let _ = event;
+        None
+    }
+}
+
+/// Takes the tenant from a request header, such as `X-Tenant-Id`
+///
+/// Any caller can set a header, so pair this with authorization that checks
+/// the caller belongs to the tenant, or prefer [`ClaimTenantResolver`].
+pub struct HeaderTenantResolver {
+    header: String,
+    event_field: Option<String>,
+}
+
+impl HeaderTenantResolver {
+    pub fn new(header: impl Into<String>) -> Self {
+        Self {
+            header: header.into(),
+            event_field: None,
+        }
+    }
+
+    /// Take the tenant of events from the string field `field` of their data
+    pub fn with_event_field(mut self, field: impl Into<String>) -> Self {
+        self.event_field = Some(field.into());
+        self
+    }
+}
+
+impl TenantResolver for HeaderTenantResolver {
+    fn resolve_request(&self, headers: &HeaderMap, _identity: Option<&Identity>) -> Option<String> {
+        headers
+            .get(self.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+    }
+
+    fn resolve_event(&self, event: &Event) -> Option<String> {
+        event_field(event, self.event_field.as_deref()?)
+    }
+}
+
+/// Takes the tenant from a claim of the caller's token, such as `tenant`
+pub struct ClaimTenantResolver {
+    claim: String,
+    event_field: Option<String>,
+}
+
+impl ClaimTenantResolver {
+    pub fn new(claim: impl Into<String>) -> Self {
+        Self {
+            claim: claim.into(),
+            event_field: None,
+        }
+    }
+
+    /// Take the tenant of events from the string field `field` of their data
+    pub fn with_event_field(mut self, field: impl Into<String>) -> Self {
+        self.event_field = Some(field.into());
+        self
+    }
+}
+
+impl TenantResolver for ClaimTenantResolver {
+    fn resolve_request(&self, _headers: &HeaderMap, identity: Option<&Identity>) -> Option<String> {
+        identity?.claims.get(&self.claim).and_then(Value::as_str).map(str::to_string)
+    }
+
+    fn resolve_event(&self, event: &Event) -> Option<String> {
+        event_field(event, self.event_field.as_deref()?)
+    }
+}
+
+fn event_field(event: &Event, field: &str) -> Option<String> {
+    event.data.get(field).and_then(Value::as_str).map(str::to_string)
+}
+
+/// Brings the namespace of each tenant to the declared schema and
+/// migrations, once per process
+pub(crate) struct Provisioning {
+    db: Database,
+    /// Server's database, which tenant namespaces use too
+    database: String,
+    tables: Vec<(String, Vec<Table>)>,
+    schema: SchemaPolicy,
+    migrations: Migrations,
+    policy: MigrationPolicy,
+    provisioned: Mutex<HashSet<Tenant>>,
+    /// Held while a tenant is provisioned, so each one is provisioned once
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Provisioning {
+    pub(crate) fn new(
+        db: Database,
+        database: impl Into<String>,
+        tables: Vec<(String, Vec<Table>)>,
+        schema: SchemaPolicy,
+        migrations: Migrations,
+        policy: MigrationPolicy,
+    ) -> Self {
+        Self {
+            db,
+            database: database.into(),
+            tables,
+            schema,
+            migrations,
+            policy,
+            provisioned: Mutex::default(),
+            running: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// Provision `tenant` unless this process already has
+    pub(crate) async fn provision(&self, tenant: &Tenant) -> Result<()> {
+        let done = || self.provisioned.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).contains(tenant);
+        if done() {
+            return Ok(());
+        }
+        let _running = self.running.lock().await;
+        if done() {
+            return Ok(());
+        }
+
+        let switch = db::use_statement(tenant.namespace(), &self.database);
+        schema::reconcile(&self.db, Some(&switch), self.tables.clone(), self.schema).await?;
+        let migrations = self.migrations.in_namespace(tenant.namespace(), &self.database);
+        match self.policy {
+            MigrationPolicy::Apply => {
+                migrations.apply().await?;
+            }
+            MigrationPolicy::DryRun => {
+                for pending in migrations.pending().await? {
+                    tracing::info!(
+                        tenant = %tenant,
+                        module = %pending.module,
+                        version = pending.version,
+                        "pending migration not applied (dry run)"
+                    );
+                }
+            }
+            MigrationPolicy::Skip => {}
+        }
+        tracing::info!(tenant = %tenant, "provisioned tenant");
+        self.provisioned
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(tenant.clone());
+        Ok(())
+    }
+}
+
+/// How requests are mapped to tenants
+#[derive(Clone)]
+pub(crate) struct TenantRouting {
+    pub(crate) resolver: Arc<dyn TenantResolver>,
+    pub(crate) required: bool,
+    /// Server's namespace, which tokens of requests without a tenant may name
+    pub(crate) namespace: String,
+    pub(crate) provisioning: Arc<Provisioning>,
+}
+
+/// Resolve the tenant of every request to `router`, after authentication
+pub(crate) fn resolve_routes(router: Router, routing: TenantRouting) -> Router {
+    router.layer(middleware::from_fn_with_state(routing, resolve_request))
+}
+
+async fn resolve_request(State(routing): State<TenantRouting>, mut request: Request, next: Next) -> Response {
+    let identity = request.extensions().get::<Identity>();
+    let id = routing.resolver.resolve_request(request.headers(), identity);
+    let tenant = match id.map(Tenant::new) {
+        Some(Ok(tenant)) => Some(tenant),
+        Some(Err(err)) => return bad_request(&err.to_string()),
+        None if routing.required => return bad_request("a tenant is required"),
+        None => None,
+    };
+    let namespace = tenant.as_ref().map_or(routing.namespace.as_str(), Tenant::namespace);
+    if let Some(issued) = identity.and_then(|identity| identity.namespace.as_deref()) {
+        if issued != namespace {
+            return crate::auth::unauthorized("token was issued for another namespace");
+        }
+    }
+    if let Some(tenant) = tenant {
+        if let Err(err) = routing.provisioning.provision(&tenant).await {
+            tracing::error!(tenant = %tenant, error = %err, "failed to provision tenant");
+            let body = Json(json!({ "error": format!("failed to provision tenant '{}'", tenant) }));
+            return (StatusCode::SERVICE_UNAVAILABLE, body).into_response();
+        }
+        request.extensions_mut().insert(tenant);
+    }
+    next.run(request).await
+}
+
+fn bad_request(message: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": message }))).into_response()
+}
+
+/// Tags emitted events with the tenant of the handler emitting them, or
+/// else the one the resolver maps them to, provisioning that tenant
+pub(crate) struct TenantEvents {
+    pub(crate) resolver: Arc<dyn TenantResolver>,
+    pub(crate) provisioning: Arc<Provisioning>,
+}
+
+#[async_trait]
+impl EventMiddleware for TenantEvents {
+    async fn on_emit(&self, mut event: Event, next: EmitNext<'_>) -> Result<EmitReport> {
+        if event.tenant.is_none() {
+            event.tenant = Context::current()
+                .and_then(|context| context.tenant().map(Tenant::id).map(str::to_string))
+                .or_else(|| self.resolver.resolve_event(&event));
+        }
+        if let Some(id) = &event.tenant {
+            self.provisioning.provision(&Tenant::new(id.as_str())?).await?;
+        }
+        next.run(event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use crate::cache::{CacheProvider, MemoryCacheProvider};
+    use crate::context::FunctionContext;
+    use crate::events::EventRegistry;
+    use crate::subscriptions::{EventAcl, Session};
+
+    fn tenant(id: &str) -> Tenant {
+        Tenant::new(id).unwrap()
+    }
+
+    #[test]
+    fn accepts_only_namespace_safe_tenant_ids() {
+        assert_eq!(tenant("acme-eu_1").namespace(), "acme-eu_1");
+        for invalid in ["", "acme corp", "acme;USE NS other", "a".repeat(65).as_str()] {
+            assert!(matches!(Tenant::new(invalid), Err(Error::Validation(_))));
+        }
+    }
+
+    #[test]
+    fn resolves_tenants_from_headers_claims_and_event_data() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant", HeaderValue::from_static(" acme "));
+        let by_header = HeaderTenantResolver::new("x-tenant").with_event_field("tenant");
+        assert_eq!(by_header.resolve_request(&headers, None).as_deref(), Some("acme"));
+        let event = Event::custom("orders:created", json!({ "tenant": "globex" })).unwrap();
+        assert_eq!(by_header.resolve_event(&event).as_deref(), Some("globex"));
+
+        let identity = Identity {
+            namespace: None,
+            database: None,
+            access: None,
+            record: None,
+            roles: Vec::new(),
+            claims: json!({ "tenant": "initech" }),
+        };
+        let by_claim = ClaimTenantResolver::new("tenant");
+        assert_eq!(by_claim.resolve_request(&headers, Some(&identity)).as_deref(), Some("initech"));
+        assert_eq!(by_claim.resolve_request(&headers, None), None);
+    }
+
+    #[tokio::test]
+    async fn keeps_each_tenants_cache_keys_apart() -> Result<()> {
+        let provider: Arc<dyn CacheProvider> = Arc::new(MemoryCacheProvider::new());
+        let context = FunctionContext::new("shop", "main", provider.clone(), EventRegistry::new());
+        let acme = context.clone().with_tenant(tenant("acme"));
+        let globex = context.clone().with_tenant(tenant("globex"));
+
+        acme.cache().set("orders", json!(1), None).await?;
+        globex.cache().set("orders", json!(2), None).await?;
+        assert_eq!(acme.cache().get("orders").await?, Some(json!(1)));
+        assert_eq!(globex.cache().get("orders").await?, Some(json!(2)));
+        assert_eq!(context.cache().get("orders").await?, None);
+        assert_eq!(provider.get("tenant:acme:orders").await?, Some(json!(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn streams_only_the_events_of_the_sessions_tenant() {
+        let events = EventRegistry::new();
+        let acl = EventAcl::default();
+        let mut session = Session::new(None, Some(tenant("acme")));
+        session.subscribe(&acl, "orders:*").unwrap();
+
+        let event = Event::custom("orders:created", json!({})).unwrap();
+        assert!(session.receives(&events, &acl, &event.clone().with_tenant(&tenant("acme"))));
+        assert!(!session.receives(&events, &acl, &event.clone().with_tenant(&tenant("globex"))));
+        assert!(!session.receives(&events, &acl, &event));
+
+        let mut untenanted = Session::new(None, None);
+        untenanted.subscribe(&acl, "orders:*").unwrap();
+        assert!(untenanted.receives(&events, &acl, &event));
+        assert!(!untenanted.receives(&events, &acl, &event.with_tenant(&tenant("acme"))));
+    }
+}
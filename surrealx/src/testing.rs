@@ -0,0 +1,702 @@
+//! Helpers for testing modules against a running server
+//!
+//! With the `testing` feature, [`TestHarness`] builds and starts a server
+//! with an in-memory database, so module authors can write integration
+//! tests without binding a port:
+//!
+//! ```rust,ignore
+//! #[tokio::test]
+//! async fn places_orders() -> surrealx::Result<()> {
+//!     let harness = TestHarness::new(vec![orders::module()]).await?;
+//!
+//!     let order = harness.call_function("ext::orders::place", vec![json!("book")]).await?;
+//!     harness.assert_emitted("orders:created");
+//!
+//!     let response = harness.http().get(&format!("/orders/{}", order["id"])).await;
+//!     assert_eq!(response.status, StatusCode::OK);
+//!     harness.shutdown().await;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Every event emitted while the harness runs is captured, including those
+//! emitted by functions, listeners and jobs, in the order they were emitted.
+//!
+//! [`EventCapture`] records the events of chosen patterns for tests that
+//! work with an [`EventRegistry`] directly, and [`MockCacheProvider`] stands
+//! in for a cache to test how modules behave when it is slow, fails or holds
+//! particular values.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use async_trait::async_trait;
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::Router;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::watch;
+use tower::ServiceExt;
+use crate::cache::{CacheBackendInfo, CacheProvider, CacheStats, MaintenanceHandle, MemoryCacheProvider};
+use crate::events::{EmitReport, Event, EventListener, EventRegistry, ListenerId, PatternSyntax};
+use crate::module::Module;
+use crate::server::{BuiltSurrealX, ModuleFailure, SurrealX};
+use crate::error::{Error, Result};
+
+/// How often `wait_for_event` looks for the event
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A started server with an in-memory database and every emitted event
+/// captured
+pub struct TestHarness {
+    server: BuiltSurrealX,
+    captured: Mutex<Captured>,
+}
+
+/// Events received from the registry's tap so far
+struct Captured {
+    tap: Receiver<Event>,
+    events: Vec<Event>,
+}
+
+impl TestHarness {
+    /// Build and start a server with `modules` and the default configuration
+    pub async fn new(modules: Vec<Module>) -> Result<Self> {
+        Self::with_server(modules.into_iter().fold(SurrealX::new(), SurrealX::with_module)).await
+    }
+
+    /// Build and start a server set up in code, such as one with middleware
+    /// or custom configuration
+    ///
+    /// `ServerConfig::data_path` is ignored, so the database is always in
+    /// memory; other settings, such as the cache backend, are used as given.
+    pub async fn with_server(surrealx: SurrealX) -> Result<Self> {
+        let server = surrealx.in_memory().build().await?;
+        let tap = server.event_registry.tap();
+        server.start().await?;
+        Ok(Self {
+            server,
+            captured: Mutex::new(Captured {
+                tap,
+                events: Vec::new(),
+            }),
+        })
+    }
+
+    /// The built server, for anything the harness does not wrap
+    pub fn server(&self) -> &BuiltSurrealX {
+        &self.server
+    }
+
+    /// Call a registered function by its fully qualified name
+    pub async fn call_function(&self, name: &str, args: Vec<Value>) -> Result<Value> {
+        self.server.call_function(name, args).await
+    }
+
+    /// Run SurrealQL against the embedded database, with registered
+    /// functions available
+    pub async fn query(&self, sql: &str) -> Result<Vec<Value>> {
+        self.server.query(sql).await
+    }
+
+    /// Emit an event to the registered listeners
+    pub async fn emit(&self, event: Event) -> Result<EmitReport> {
+        self.server.event_registry.emit(event).await
+    }
+
+    /// A client sending requests to the server's router
+    pub fn http(&self) -> TestClient {
+        TestClient {
+            router: self.server.router.clone(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Every event emitted so far, oldest first
+    pub fn events(&self) -> Vec<Event> {
+        self.captured().events.clone()
+    }
+
+    /// The events emitted so far that `pattern` matches, under the
+    /// registry's pattern syntax
+    pub fn events_matching(&self, pattern: &str) -> Vec<Event> {
+        let syntax = self.server.event_registry.syntax();
+        self.captured()
+            .events
+            .iter()
+            .filter(|event| syntax.matches(pattern, event))
+            .cloned()
+            .collect()
+    }
+
+    /// Forget the events captured so far
+    pub fn clear_events(&self) {
+        self.captured().events.clear();
+    }
+
+    /// Assert that an event matching `pattern` was emitted, and return the
+    /// latest one
+    ///
+    /// # Panics
+    ///
+    /// If no such event was emitted, listing those that were.
+    pub fn assert_emitted(&self, pattern: &str) -> Event {
+        match self.events_matching(pattern).pop() {
+            Some(event) => event,
+            None => panic!(
+                "expected an event matching '{}'; emitted: [{}]",
+                pattern,
+                self.emitted_patterns()
+            ),
+        }
+    }
+
+    /// Assert that no event matching `pattern` was emitted
+    ///
+    /// # Panics
+    ///
+    /// If one was.
+    pub fn assert_not_emitted(&self, pattern: &str) {
+        let matching = self.events_matching(pattern);
+        assert!(
+            matching.is_empty(),
+            "expected no event matching '{}'; emitted {} of them",
+            pattern,
+            matching.len()
+        );
+    }
+
+    /// Wait until an event matching `pattern` has been emitted, such as by a
+    /// listener running on another task, and return the latest one
+    ///
+    /// Fails with `Error::NotFound` if none is emitted within `timeout`.
+    pub async fn wait_for_event(&self, pattern: &str, timeout: Duration) -> Result<Event> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.events_matching(pattern).pop() {
+                return Ok(event);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(Error::NotFound(format!(
+                    "no event matching '{}' within {:?}",
+                    pattern, timeout
+                )));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Shut the server down as [`BuiltSurrealX::shutdown`] does
+    pub async fn shutdown(self) -> Vec<ModuleFailure> {
+        self.server.shutdown().await
+    }
+
+    /// The captured events, including those received since the last look
+    fn captured(&self) -> MutexGuard<'_, Captured> {
+        let mut captured = self.captured.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            match captured.tap.try_recv() {
+                Ok(event) => captured.events.push(event),
+                Err(TryRecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "test harness fell behind; some events were not captured");
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+        captured
+    }
+
+    fn emitted_patterns(&self) -> String {
+        self.captured()
+            .events
+            .iter()
+            .map(Event::pattern)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Sends requests to a server's router without a network connection
+#[derive(Clone)]
+pub struct TestClient {
+    router: Router,
+    headers: HeaderMap,
+}
+
+impl TestClient {
+    /// Send `Authorization: Bearer <token>` with every request
+    pub fn with_token(self, token: &str) -> Self {
+        self.with_header(header::AUTHORIZATION.as_str(), &format!("Bearer {}", token))
+    }
+
+    /// Send a header with every request
+    ///
+    /// # Panics
+    ///
+    /// If `name` or `value` is not a valid header.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        let name = header::HeaderName::try_from(name).expect("invalid header name");
+        let value = HeaderValue::try_from(value).expect("invalid header value");
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// `GET path`
+    pub async fn get(&self, path: &str) -> TestResponse {
+        self.send(Method::GET, path, Body::empty(), None).await
+    }
+
+    /// `POST path` with `body` as JSON
+    pub async fn post(&self, path: &str, body: Value) -> TestResponse {
+        self.send(Method::POST, path, Body::from(body.to_string()), Some("application/json"))
+            .await
+    }
+
+    /// `POST path` with `body` as plain text, such as SurrealQL for `/sql`
+    /// when `ServerConfig::sql_access` serves it
+    pub async fn post_text(&self, path: &str, body: impl Into<String>) -> TestResponse {
+        self.send(Method::POST, path, Body::from(body.into()), Some("text/plain"))
+            .await
+    }
+
+    /// `DELETE path`
+    pub async fn delete(&self, path: &str) -> TestResponse {
+        self.send(Method::DELETE, path, Body::empty(), None).await
+    }
+
+    /// Send any request, with the client's headers added
+    pub async fn request(&self, mut request: Request<Body>) -> TestResponse {
+        for (name, value) in &self.headers {
+            request.headers_mut().entry(name).or_insert_with(|| value.clone());
+        }
+        let response = match self.router.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        };
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        TestResponse { status, headers, body }
+    }
+
+    async fn send(&self, method: Method, path: &str, body: Body, content_type: Option<&str>) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        self.request(request.body(body).expect("invalid request path")).await
+    }
+}
+
+/// A response received by a [`TestClient`]
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    /// Parse the body as JSON
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// The body as text, with invalid UTF-8 replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Listener recording the events it is registered for, for tests to
+/// inspect and wait on
+///
+/// Clones share their events, so register one and keep another:
+///
+/// ```rust,ignore
+/// let capture = EventCapture::attach(&registry, "orders:*").await?;
+/// registry.emit(Event::created("orders", "orders:1", json!({ "total": 10 }))).await?;
+///
+/// let event = capture.wait_for("orders:created", Duration::from_secs(1)).await?;
+/// capture.assert_emitted_matching(|event| event.data["total"] == 10);
+/// ```
+#[derive(Clone)]
+pub struct EventCapture {
+    events: Arc<Mutex<Vec<Event>>>,
+    /// Number of events recorded, for waiters to be woken by
+    recorded: Arc<watch::Sender<usize>>,
+    syntax: PatternSyntax,
+}
+
+impl EventCapture {
+    /// Create a capture matching patterns under `PatternSyntax::Legacy`
+    pub fn new() -> Self {
+        Self {
+            events: Arc::new(Mutex::new(Vec::new())),
+            recorded: Arc::new(watch::channel(0).0),
+            syntax: PatternSyntax::default(),
+        }
+    }
+
+    /// Match the patterns given to `matching` and `wait_for` under `syntax`
+    pub fn with_syntax(mut self, syntax: PatternSyntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Create a capture, register it on `registry` for `pattern` and match
+    /// patterns under the registry's syntax
+    pub async fn attach(registry: &EventRegistry, pattern: impl Into<String>) -> Result<Self> {
+        let capture = Self::new().with_syntax(registry.syntax());
+        registry.register(pattern, capture.clone()).await?;
+        Ok(capture)
+    }
+
+    /// Register the capture on `registry` for another pattern
+    pub async fn listen(&self, registry: &EventRegistry, pattern: impl Into<String>) -> Result<ListenerId> {
+        registry.register(pattern, self.clone()).await
+    }
+
+    /// Every event recorded so far, oldest first
+    pub fn events(&self) -> Vec<Event> {
+        self.lock().clone()
+    }
+
+    /// Number of events recorded so far
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether no event was recorded yet
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Forget the events recorded so far
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// The recorded events that `pattern` matches
+    pub fn matching(&self, pattern: &str) -> Vec<Event> {
+        self.lock()
+            .iter()
+            .filter(|event| self.syntax.matches(pattern, event))
+            .cloned()
+            .collect()
+    }
+
+    /// Wait until an event matching `pattern` is recorded and return the
+    /// first one
+    ///
+    /// Returns at once if one already was. Fails with `Error::NotFound` if
+    /// none is recorded within `timeout`.
+    pub async fn wait_for(&self, pattern: &str, timeout: Duration) -> Result<Event> {
+        self.wait_until(timeout, |events| {
+            events.iter().find(|event| self.syntax.matches(pattern, event)).cloned()
+        })
+        .await
+        .ok_or_else(|| Error::NotFound(format!("no event matching '{}' within {:?}", pattern, timeout)))
+    }
+
+    /// Wait until at least `count` events are recorded and return them all
+    ///
+    /// Fails with `Error::NotFound` if fewer are recorded within `timeout`.
+    pub async fn wait_for_count(&self, count: usize, timeout: Duration) -> Result<Vec<Event>> {
+        self.wait_until(timeout, |events| (events.len() >= count).then(|| events.to_vec()))
+            .await
+            .ok_or_else(|| {
+                Error::NotFound(format!("fewer than {} events within {:?}; got {}", count, timeout, self.len()))
+            })
+    }
+
+    /// Assert that a recorded event satisfies `predicate`, and return the
+    /// first one that does
+    ///
+    /// # Panics
+    ///
+    /// If none does, listing the patterns of those recorded.
+    pub fn assert_emitted_matching(&self, predicate: impl Fn(&Event) -> bool) -> Event {
+        let events = self.lock();
+        match events.iter().find(|event| predicate(event)) {
+            Some(event) => event.clone(),
+            None => panic!(
+                "expected an event satisfying the predicate; recorded: [{}]",
+                events.iter().map(Event::pattern).collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+
+    /// Assert that an event matching `pattern` was recorded, and return the
+    /// first one
+    ///
+    /// # Panics
+    ///
+    /// If none was.
+    pub fn assert_emitted(&self, pattern: &str) -> Event {
+        self.assert_emitted_matching(|event| self.syntax.matches(pattern, event))
+    }
+
+    /// Check the recorded events every time one is added until `check`
+    /// finds what it looks for or `timeout` passes
+    async fn wait_until<T>(&self, timeout: Duration, check: impl Fn(&[Event]) -> Option<T>) -> Option<T> {
+        let mut recorded = self.recorded.subscribe();
+        let wait = async {
+            loop {
+                recorded.borrow_and_update();
+                if let Some(found) = check(&self.lock()) {
+                    return Some(found);
+                }
+                if recorded.changed().await.is_err() {
+                    return None;
+                }
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.ok().flatten()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Event>> {
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for EventCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl EventListener for EventCapture {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.lock().push(event);
+        self.recorded.send_modify(|recorded| *recorded += 1);
+        Ok(())
+    }
+}
+
+/// A cache operation, as recorded by [`MockCacheProvider`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheOperation {
+    Get,
+    Set,
+    Delete,
+    Exists,
+    Clear,
+    ClearPrefix,
+}
+
+/// A call made to a [`MockCacheProvider`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheCall {
+    pub operation: CacheOperation,
+    /// The key, or the prefix of `ClearPrefix`; `None` for `Clear`
+    pub key: Option<String>,
+}
+
+/// Cache provider for tests, with programmable responses, injected latency
+/// and failures, and a record of every call
+///
+/// Values are stored in a [`MemoryCacheProvider`] unless a response is
+/// programmed for the key. Clones share their state, so a test can keep one
+/// to program and inspect while the server uses another:
+///
+/// ```rust,ignore
+/// let cache = MockCacheProvider::new();
+/// let harness = TestHarness::with_server(SurrealX::new().with_module(orders).with_cache(cache.clone())).await?;
+///
+/// cache.fail_all(true);
+/// assert!(harness.call_function("ext::orders::summary", vec![]).await.is_ok());
+/// assert_eq!(cache.gets("module:orders:summary"), 1);
+/// ```
+///
+/// Multi-key operations and the default `incr`, `set_if_absent` and
+/// `get_or_set` go through `get` and `set`, and are recorded as those.
+#[derive(Clone)]
+pub struct MockCacheProvider {
+    inner: Arc<MemoryCacheProvider>,
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    responses: HashMap<String, Option<Value>>,
+    latency: Duration,
+    fail_all: bool,
+    fail_next: usize,
+    failing_keys: Vec<String>,
+    calls: Vec<CacheCall>,
+}
+
+impl MockCacheProvider {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(MemoryCacheProvider::new()),
+            state: Arc::new(Mutex::new(MockState::default())),
+        }
+    }
+
+    /// Answer `get` for `key` with `response`, whatever is stored; `None`
+    /// makes the key a miss
+    pub fn respond(&self, key: impl Into<String>, response: Option<Value>) {
+        self.state().responses.insert(key.into(), response);
+    }
+
+    /// Delay every operation by `latency`
+    pub fn set_latency(&self, latency: Duration) {
+        self.state().latency = latency;
+    }
+
+    /// Make every operation fail, or stop doing so
+    pub fn fail_all(&self, failing: bool) {
+        self.state().fail_all = failing;
+    }
+
+    /// Make the next `count` operations fail
+    pub fn fail_next(&self, count: usize) {
+        self.state().fail_next = count;
+    }
+
+    /// Make every operation on `key` fail
+    pub fn fail_key(&self, key: impl Into<String>) {
+        self.state().failing_keys.push(key.into());
+    }
+
+    /// Remove programmed responses, latency and failures; stored values and
+    /// recorded calls are kept
+    pub fn reset(&self) {
+        let mut state = self.state();
+        let calls = std::mem::take(&mut state.calls);
+        *state = MockState {
+            calls,
+            ..MockState::default()
+        };
+    }
+
+    /// Every call made so far, oldest first
+    pub fn calls(&self) -> Vec<CacheCall> {
+        self.state().calls.clone()
+    }
+
+    /// Forget the calls recorded so far
+    pub fn clear_calls(&self) {
+        self.state().calls.clear();
+    }
+
+    /// How many times `operation` was called with `key`
+    pub fn count(&self, operation: CacheOperation, key: &str) -> usize {
+        self.state()
+            .calls
+            .iter()
+            .filter(|call| call.operation == operation && call.key.as_deref() == Some(key))
+            .count()
+    }
+
+    /// How many times `key` was read
+    pub fn gets(&self, key: &str) -> usize {
+        self.count(CacheOperation::Get, key)
+    }
+
+    /// How many times `key` was written
+    pub fn sets(&self, key: &str) -> usize {
+        self.count(CacheOperation::Set, key)
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Record a call, wait out the latency and fail if a failure is due
+    async fn enter(&self, operation: CacheOperation, key: Option<&str>) -> Result<()> {
+        let (latency, failing) = {
+            let mut state = self.state();
+            state.calls.push(CacheCall {
+                operation,
+                key: key.map(str::to_string),
+            });
+            let failing = state.fail_all
+                || key.is_some_and(|key| state.failing_keys.iter().any(|failing| failing == key))
+                || state.fail_next > 0;
+            state.fail_next = state.fail_next.saturating_sub(1);
+            (state.latency, failing)
+        };
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        if failing {
+            return Err(Error::Cache(format!(
+                "injected failure of {:?} on '{}'",
+                operation,
+                key.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for MockCacheProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl CacheProvider for MockCacheProvider {
+    async fn get(&self, key: &str) -> Result<Option<Value>> {
+        self.enter(CacheOperation::Get, Some(key)).await?;
+        let response = self.state().responses.get(key).cloned();
+        match response {
+            Some(response) => Ok(response),
+            None => self.inner.get(key).await,
+        }
+    }
+
+    async fn set(&self, key: &str, value: Value, ttl: Option<u64>) -> Result<()> {
+        self.enter(CacheOperation::Set, Some(key)).await?;
+        self.inner.set(key, value, ttl).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.enter(CacheOperation::Delete, Some(key)).await?;
+        self.inner.delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.enter(CacheOperation::Exists, Some(key)).await?;
+        let response = self.state().responses.get(key).map(Option::is_some);
+        match response {
+            Some(exists) => Ok(exists),
+            None => self.inner.exists(key).await,
+        }
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.enter(CacheOperation::Clear, None).await?;
+        self.inner.clear().await
+    }
+
+    async fn clear_prefix(&self, prefix: &str) -> Result<()> {
+        self.enter(CacheOperation::ClearPrefix, Some(prefix)).await?;
+        self.inner.clear_prefix(prefix).await
+    }
+
+    fn backend_info(&self) -> CacheBackendInfo {
+        CacheBackendInfo {
+            kind: "mock".to_string(),
+            details: Value::Null,
+        }
+    }
+
+    fn start_maintenance(&self) -> Option<MaintenanceHandle> {
+        self.inner.start_maintenance()
+    }
+
+    fn stats(&self) -> Option<CacheStats> {
+        self.inner.stats()
+    }
+}
@@ -0,0 +1,205 @@
+//! Serving HTTPS (requires tls feature)
+//!
+//! Connections are accepted on the server's listener, completed as TLS
+//! handshakes and then served over HTTP/1.1 or HTTP/2, whichever the client
+//! negotiates through ALPN.
+
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use arc_swap::ArcSwap;
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+use crate::error::{Error, Result};
+
+/// rustls configuration the server listens with
+pub(crate) struct TlsServer {
+    config: Arc<rustls::ServerConfig>,
+    /// Certificate files reloaded on SIGHUP; unset for an injected configuration
+    certificates: Option<Arc<CertificateFiles>>,
+}
+
+impl TlsServer {
+    /// Serve the certificate and key in PEM files
+    pub(crate) fn from_files(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        let certificates = Arc::new(CertificateFiles::load(cert_path, key_path)?);
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|err| Error::Tls(err.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(certificates.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(Self {
+            config: Arc::new(config),
+            certificates: Some(certificates),
+        })
+    }
+
+    /// Serve with a configuration built by the caller
+    pub(crate) fn from_config(config: Arc<rustls::ServerConfig>) -> Self {
+        Self {
+            config,
+            certificates: None,
+        }
+    }
+
+    /// Reload the certificate files on every SIGHUP
+    ///
+    /// New handshakes use the reloaded certificate; established connections
+    /// keep theirs. A reload that fails keeps the current certificate.
+    pub(crate) fn watch_hangup(&self) -> Option<JoinHandle<()>> {
+        #[cfg(unix)]
+        {
+            let certificates = self.certificates.clone()?;
+            let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(err) => {
+                    tracing::warn!(error = %err, "cannot listen for SIGHUP; certificates will not be reloaded");
+                    return None;
+                }
+            };
+            Some(tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    match certificates.reload() {
+                        Ok(()) => tracing::info!(cert = %certificates.cert_path.display(), "reloaded TLS certificate"),
+                        Err(err) => tracing::error!(error = %err, "failed to reload TLS certificate; keeping the current one"),
+                    }
+                }
+            }))
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    /// Serve `router` over TLS until `shutdown` resolves, then wait up to
+    /// `timeout` for open connections to close
+    pub(crate) async fn serve(
+        &self,
+        listener: TcpListener,
+        router: Router,
+        shutdown: impl Future<Output = ()>,
+        timeout: Duration,
+    ) -> Result<()> {
+        let acceptor = TlsAcceptor::from(self.config.clone());
+        let builder = auto::Builder::new(TokioExecutor::new());
+        let graceful = GracefulShutdown::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        // Usually out of file descriptors; back off instead of spinning
+                        tracing::warn!(error = %err, "failed to accept connection");
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            let acceptor = acceptor.clone();
+            let builder = builder.clone();
+            let service = TowerToHyperService::new(router.clone());
+            let watcher = graceful.watcher();
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        tracing::debug!(%peer, error = %err, "TLS handshake failed");
+                        return;
+                    }
+                };
+                let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+                if let Err(err) = watcher.watch(connection.into_owned()).await {
+                    tracing::debug!(%peer, error = %err, "connection closed with an error");
+                }
+            });
+        }
+
+        tracing::info!("shutting down; no longer accepting connections");
+        drop(listener);
+        tokio::select! {
+            _ = graceful.shutdown() => {}
+            _ = tokio::time::sleep(timeout) => {
+                tracing::warn!("connections still open after shutdown_timeout; dropping them")
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Certificate chain and private key read from PEM files
+struct CertificateFiles {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: ArcSwap<CertifiedKey>,
+}
+
+impl CertificateFiles {
+    fn load(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: ArcSwap::new(read_certified_key(cert_path, key_path)?),
+        })
+    }
+
+    fn reload(&self) -> Result<()> {
+        self.current.store(read_certified_key(&self.cert_path, &self.key_path)?);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CertificateFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertificateFiles")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for CertificateFiles {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.load_full())
+    }
+}
+
+fn read_certified_key(cert_path: &Path, key_path: &Path) -> Result<Arc<CertifiedKey>> {
+    let file_error = |path: &Path, err: std::io::Error| Error::Tls(format!("{}: {}", path.display(), err));
+
+    let mut certs = BufReader::new(File::open(cert_path).map_err(|err| file_error(cert_path, err))?);
+    let certs = rustls_pemfile::certs(&mut certs)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|err| file_error(cert_path, err))?;
+    if certs.is_empty() {
+        return Err(Error::Tls(format!("{}: no certificates found", cert_path.display())));
+    }
+
+    let mut key = BufReader::new(File::open(key_path).map_err(|err| file_error(key_path, err))?);
+    let key = rustls_pemfile::private_key(&mut key)
+        .map_err(|err| file_error(key_path, err))?
+        .ok_or_else(|| Error::Tls(format!("{}: no private key found", key_path.display())))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|err| Error::Tls(format!("{}: {}", key_path.display(), err)))?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
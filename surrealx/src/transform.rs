@@ -0,0 +1,232 @@
+//! Rewriting the bodies of HTTP requests and responses
+//!
+//! A [`RouteTransform`] added with `Module::with_route_transform` sees the
+//! body of every request to its path, and of every JSON response to one,
+//! whichever module or built-in endpoint serves the path, so a module can
+//! redact fields of `/sql` results or inject defaults into another module's
+//! payloads without replacing its handlers.
+//!
+//! Request bodies are handed to the transform as JSON when they parse as
+//! JSON, as a JSON string when they are other text, such as the statements
+//! sent to `/sql`, and as `null` when empty. A string returned for a text
+//! body is sent on as text, anything else as JSON. Binary request bodies,
+//! and responses that are not `application/json`, such as event streams,
+//! are passed on untouched.
+//!
+//! Transforms run outside authentication and rate limiting, so they also see
+//! the requests those refuse, and their responses. They are taken from the
+//! modules served when the server is built, and stay in place across hot
+//! reloads.
+
+use std::sync::Arc;
+use axum::body::{self, Body, Bytes};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use crate::admin::DisabledModules;
+use crate::error::{Error, Result};
+
+/// Largest request body a transform is handed, as for axum's extractors
+const MAX_REQUEST_BODY: usize = 2 * 1024 * 1024;
+
+/// Rewrites a request or response body
+pub type TransformFn = dyn Fn(Value) -> Result<Value> + Send + Sync;
+
+/// Hooks rewriting the bodies of requests to a path and of its responses
+#[derive(Clone)]
+pub struct RouteTransform {
+    path: String,
+    request: Option<Arc<TransformFn>>,
+    response: Option<Arc<TransformFn>>,
+}
+
+impl RouteTransform {
+    /// Transform of the requests to `path` and every path below it, such as
+    /// `/orders/1` for `/orders`, which rewrites nothing until given hooks
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            request: None,
+            response: None,
+        }
+    }
+
+    /// Rewrite request bodies with `transform`
+    ///
+    /// An error refuses the request with `400 Bad Request`, or the status
+    /// matching the error, such as `403 Forbidden` for `Error::Forbidden`.
+    pub fn on_request<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.request = Some(Arc::new(transform));
+        self
+    }
+
+    /// Rewrite JSON response bodies with `transform`, whatever their status
+    ///
+    /// An error replaces the response with `500 Internal Server Error`.
+    pub fn on_response<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.response = Some(Arc::new(transform));
+        self
+    }
+
+    /// Path the transform applies to
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let prefix = self.path.trim_end_matches('/');
+        match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.is_empty(),
+            None => false,
+        }
+    }
+}
+
+/// Transforms of the served modules, by module
+struct Transforms {
+    transforms: Vec<(String, RouteTransform)>,
+    disabled: DisabledModules,
+}
+
+/// Rewrite the bodies of `router`'s requests and responses with the
+/// transforms of modules that are not disabled
+///
+/// Request hooks run in registration order, response hooks in reverse.
+pub(crate) fn transform_routes(
+    router: Router,
+    transforms: Vec<(String, RouteTransform)>,
+    disabled: DisabledModules,
+) -> Router {
+    if transforms.is_empty() {
+        return router;
+    }
+    let state = Arc::new(Transforms { transforms, disabled });
+    router.layer(middleware::from_fn_with_state(state, transform_request))
+}
+
+async fn transform_request(State(state): State<Arc<Transforms>>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let transforms: Vec<RouteTransform> = state
+        .transforms
+        .iter()
+        .filter(|(module, transform)| transform.matches(&path) && !state.disabled.contains(module))
+        .map(|(_, transform)| transform.clone())
+        .collect();
+    if transforms.is_empty() {
+        return next.run(request).await;
+    }
+
+    let request = match rewrite_request(request, &transforms).await {
+        Ok(request) => request,
+        Err(err) => return failure(err, StatusCode::BAD_REQUEST),
+    };
+    let response = next.run(request).await;
+    match rewrite_response(response, &transforms).await {
+        Ok(response) => response,
+        Err(err) => {
+            tracing::warn!(error = %err, path = %path, "response transform failed");
+            failure(err, StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn rewrite_request(request: Request, transforms: &[RouteTransform]) -> Result<Request> {
+    let hooks: Vec<&Arc<TransformFn>> = transforms.iter().filter_map(|transform| transform.request.as_ref()).collect();
+    if hooks.is_empty() {
+        return Ok(request);
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let bytes = body::to_bytes(body, MAX_REQUEST_BODY)
+        .await
+        .map_err(|err| Error::Validation(format!("unreadable request body: {}", err)))?;
+    let Some((mut value, text)) = decode(&bytes) else {
+        return Ok(Request::from_parts(parts, Body::from(bytes)));
+    };
+    for hook in hooks {
+        value = hook(value)?;
+    }
+    let bytes = encode(value, text, &mut parts.headers)?;
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+async fn rewrite_response(response: Response, transforms: &[RouteTransform]) -> Result<Response> {
+    let hooks: Vec<&Arc<TransformFn>> = transforms
+        .iter()
+        .rev()
+        .filter_map(|transform| transform.response.as_ref())
+        .collect();
+    if hooks.is_empty() || !is_json(response.headers()) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|err| Error::Server(format!("unreadable response body: {}", err)))?;
+    let mut value = if bytes.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&bytes)?
+    };
+    for hook in hooks {
+        value = hook(value)?;
+    }
+    let bytes = encode(value, false, &mut parts.headers)?;
+    Ok(Response::from_parts(parts, Body::from(bytes)))
+}
+
+/// A body as the value handed to transforms, and whether it was text other
+/// than JSON; `None` for binary bodies
+fn decode(bytes: &Bytes) -> Option<(Value, bool)> {
+    if bytes.is_empty() {
+        return Some((Value::Null, false));
+    }
+    if let Ok(value) = serde_json::from_slice(bytes) {
+        return Some((value, false));
+    }
+    let text = std::str::from_utf8(bytes).ok()?;
+    Some((Value::String(text.to_string()), true))
+}
+
+/// The body for a transformed value, with `headers` updated to match
+fn encode(value: Value, text: bool, headers: &mut HeaderMap) -> Result<Bytes> {
+    let bytes = match value {
+        Value::Null if !text => Bytes::new(),
+        Value::String(text_body) if text => Bytes::from(text_body),
+        value => {
+            if text {
+                headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+            Bytes::from(serde_json::to_vec(&value)?)
+        }
+    };
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    Ok(bytes)
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+fn failure(err: Error, fallback: StatusCode) -> Response {
+    let status = match err {
+        Error::NotFound(_) => StatusCode::NOT_FOUND,
+        Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+        Error::Forbidden { .. } => StatusCode::FORBIDDEN,
+        _ => fallback,
+    };
+    (status, Json(json!({ "error": err.to_string() }))).into_response()
+}
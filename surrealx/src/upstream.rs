@@ -0,0 +1,334 @@
+//! Running in front of an external SurrealDB server
+//!
+//! With `ServerConfig::upstream` set, SurrealX connects to that server
+//! instead of embedding the database, and can be deployed as its sidecar:
+//!
+//! - Function contexts, migrations, declared schemas, the outbox and the
+//!   `/sql` endpoint use the upstream server, in the configured namespace
+//!   and database, signed in with the configured credentials.
+//! - `ServerConfig::live_tables` subscribes to the upstream server's live
+//!   queries, so writes made by any of its clients are emitted as events.
+//! - Module routes and the built-in endpoints are served as usual, and any
+//!   other request is forwarded to the upstream server, so clients can keep
+//!   using SurrealDB's HTTP endpoints, such as `/signin` or `/key/:table`,
+//!   through SurrealX.
+//! - WebSocket connections to `/rpc` are relayed to the upstream server.
+//!   Calls to registered functions in the SurrealQL of `query` calls are
+//!   replaced by their results first, as on `/sql`, and events those
+//!   functions emit after commit are written to the outbox once the upstream
+//!   server reports the query succeeded. Only the `json` protocol can be
+//!   rewritten; messages of other protocols, such as `cbor`, are relayed as
+//!   they are.
+//!
+//! Forwarded requests and relayed connections carry the client's own
+//! SurrealDB credentials and are not checked by `ServerConfig::auth`, and
+//! functions called from `/rpc` run without a caller or tenant.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use axum::body::Body;
+use axum::extract::ws::{self, CloseFrame, WebSocket, WebSocketUpgrade};
+use axum::extract::{FromRequestParts, Request};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::{self, protocol};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use crate::events::Event;
+use crate::server::UpstreamConfig;
+use crate::sql::QueryEngine;
+use crate::error::{Error, Result};
+
+/// SurrealDB RPC protocols clients may speak over `/rpc`
+const PROTOCOLS: [&str; 2] = ["json", "cbor"];
+
+/// Headers that describe a single connection rather than the request
+const HOP_BY_HOP: [header::HeaderName; 8] = [
+    header::CONNECTION,
+    header::HOST,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Forwards requests and relays connections to the upstream server
+pub(crate) struct Upstream {
+    /// Base URL of the server's HTTP endpoints
+    http: String,
+    /// Base URL of the server's WebSocket endpoint
+    ws: String,
+    client: reqwest::Client,
+    engine: QueryEngine,
+}
+
+impl Upstream {
+    pub(crate) fn new(config: &UpstreamConfig, engine: QueryEngine) -> Result<Self> {
+        let (scheme, host) = config
+            .url
+            .split_once("://")
+            .ok_or_else(|| Error::Config(format!("upstream '{}' has no scheme", config.url)))?;
+        let secure = match scheme {
+            "ws" | "http" => false,
+            "wss" | "https" => true,
+            other => {
+                return Err(Error::Config(format!(
+                    "upstream '{}': unsupported scheme '{}'",
+                    config.url, other
+                )))
+            }
+        };
+        let host = host.trim_end_matches('/');
+        Ok(Self {
+            http: format!("{}://{}", if secure { "https" } else { "http" }, host),
+            ws: format!("{}://{}", if secure { "wss" } else { "ws" }, host),
+            client: reqwest::Client::new(),
+            engine,
+        })
+    }
+
+    /// Send a request to the upstream server and stream back its response
+    async fn forward(&self, request: Request) -> Result<Response> {
+        let (parts, body) = request.into_parts();
+        let path = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+        let mut headers = parts.headers;
+        strip_hop_by_hop(&mut headers);
+
+        let response = self
+            .client
+            .request(parts.method, format!("{}{}", self.http, path))
+            .headers(headers)
+            .body(reqwest::Body::wrap_stream(body.into_data_stream()))
+            .send()
+            .await
+            .map_err(|err| Error::Server(format!("upstream request failed: {}", err)))?;
+
+        let status = response.status();
+        let mut headers = response.headers().clone();
+        strip_hop_by_hop(&mut headers);
+        let mut forwarded = Response::new(Body::from_stream(response.bytes_stream()));
+        *forwarded.status_mut() = status;
+        *forwarded.headers_mut() = headers;
+        Ok(forwarded)
+    }
+
+    /// Open an RPC connection to the upstream server speaking `protocol`
+    async fn connect(&self, protocol: Option<&'static str>) -> Result<UpstreamSocket> {
+        let mut request = format!("{}/rpc", self.ws)
+            .into_client_request()
+            .map_err(|err| Error::Config(format!("invalid upstream: {}", err)))?;
+        if let Some(protocol) = protocol {
+            request
+                .headers_mut()
+                .insert(header::SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(protocol));
+        }
+        let (socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .map_err(|err| Error::Server(format!("upstream connection failed: {}", err)))?;
+        Ok(socket)
+    }
+
+    /// Relay messages between a client and the upstream server until either
+    /// closes the connection
+    async fn relay(&self, client: WebSocket, upstream: UpstreamSocket) -> Result<()> {
+        let (mut client_tx, mut client_rx) = client.split();
+        let (mut upstream_tx, mut upstream_rx) = upstream.split();
+        // Events emitted after commit by calls in queries awaiting their
+        // response, by message ID
+        let mut pending: HashMap<String, Vec<Event>> = HashMap::new();
+        let closed = |err: tungstenite::Error| Error::Server(format!("upstream connection failed: {}", err));
+
+        loop {
+            tokio::select! {
+                message = client_rx.next() => {
+                    let Some(Ok(message)) = message else { break };
+                    let message = match message {
+                        ws::Message::Text(text) => match self.rewrite_call(text, &mut pending).await {
+                            Ok(text) => tungstenite::Message::Text(text),
+                            Err(reply) => {
+                                if client_tx.send(ws::Message::Text(reply.to_string())).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                        },
+                        message => to_upstream(message),
+                    };
+                    upstream_tx.send(message).await.map_err(closed)?;
+                }
+                message = upstream_rx.next() => {
+                    let message = match message {
+                        Some(Ok(message)) => message,
+                        Some(Err(err)) => return Err(closed(err)),
+                        None => break,
+                    };
+                    if let tungstenite::Message::Text(text) = &message {
+                        self.settle_call(text, &mut pending).await;
+                    }
+                    let Some(message) = from_upstream(message) else { continue };
+                    if client_tx.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = upstream_tx.close().await;
+        Ok(())
+    }
+
+    /// Replace calls to registered functions in the SurrealQL of a `query`
+    /// call; the error is the reply refusing the call
+    async fn rewrite_call(
+        &self,
+        text: String,
+        pending: &mut HashMap<String, Vec<Event>>,
+    ) -> std::result::Result<String, Value> {
+        let Ok(mut call) = serde_json::from_str::<Value>(&text) else {
+            return Ok(text);
+        };
+        if call["method"] != "query" {
+            return Ok(text);
+        }
+        let Some(sql) = call["params"].get(0).and_then(Value::as_str) else {
+            return Ok(text);
+        };
+
+        match self.engine.prepare(sql).await {
+            Ok((sql, committed)) => {
+                call["params"][0] = Value::String(sql);
+                if !committed.is_empty() {
+                    pending.insert(call["id"].to_string(), committed);
+                }
+                Ok(call.to_string())
+            }
+            Err(err) => Err(json!({
+                "id": call["id"],
+                "error": { "code": -32000, "message": err.to_string() },
+            })),
+        }
+    }
+
+    /// Write the events pending on a response's call to the outbox if every
+    /// statement of the query succeeded
+    async fn settle_call(&self, text: &str, pending: &mut HashMap<String, Vec<Event>>) {
+        if pending.is_empty() {
+            return;
+        }
+        let Ok(response) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        let Some(events) = pending.remove(&response["id"].to_string()) else {
+            return;
+        };
+        let failed = response.get("error").is_some()
+            || response["result"]
+                .as_array()
+                .is_some_and(|results| results.iter().any(|result| result["status"] == "ERR"));
+        if failed {
+            return;
+        }
+        if let Err(err) = self.engine.committed(&events).await {
+            tracing::error!(error = %err, events = events.len(), "failed to write events of an upstream query to the outbox");
+        }
+    }
+}
+
+/// Forward every request `router` has no route for to the upstream server
+pub(crate) fn fallback(router: Router, upstream: Arc<Upstream>) -> Router {
+    router.fallback(move |request: Request| {
+        let upstream = upstream.clone();
+        async move { proxy(upstream, request).await }
+    })
+}
+
+async fn proxy(upstream: Arc<Upstream>, request: Request) -> Response {
+    let upgrade = request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if request.uri().path() != "/rpc" || !upgrade {
+        return match upstream.forward(request).await {
+            Ok(response) => response,
+            Err(err) => bad_gateway(err),
+        };
+    }
+
+    let protocol = requested_protocol(request.headers());
+    let (mut parts, _) = request.into_parts();
+    let upgrade = match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return rejection.into_response(),
+    };
+    // Connect before upgrading, so the client learns if the server is down
+    let socket = match upstream.connect(protocol).await {
+        Ok(socket) => socket,
+        Err(err) => return bad_gateway(err),
+    };
+    let upgrade = match protocol {
+        Some(protocol) => upgrade.protocols([protocol]),
+        None => upgrade,
+    };
+    upgrade.on_upgrade(move |client| async move {
+        if let Err(err) = upstream.relay(client, socket).await {
+            tracing::warn!(error = %err, "upstream RPC connection closed");
+        }
+    })
+}
+
+/// First protocol the client offers that SurrealDB speaks
+fn requested_protocol(headers: &HeaderMap) -> Option<&'static str> {
+    headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|offered| PROTOCOLS.into_iter().find(|protocol| *protocol == offered.trim()))
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+}
+
+fn bad_gateway(err: Error) -> Response {
+    tracing::warn!(error = %err, "upstream request failed");
+    (StatusCode::BAD_GATEWAY, Json(json!({ "error": err.to_string() }))).into_response()
+}
+
+fn to_upstream(message: ws::Message) -> tungstenite::Message {
+    match message {
+        ws::Message::Text(text) => tungstenite::Message::Text(text),
+        ws::Message::Binary(data) => tungstenite::Message::Binary(data),
+        ws::Message::Ping(data) => tungstenite::Message::Ping(data),
+        ws::Message::Pong(data) => tungstenite::Message::Pong(data),
+        ws::Message::Close(frame) => tungstenite::Message::Close(frame.map(|frame| protocol::CloseFrame {
+            code: CloseCode::from(frame.code),
+            reason: frame.reason,
+        })),
+    }
+}
+
+fn from_upstream(message: tungstenite::Message) -> Option<ws::Message> {
+    Some(match message {
+        tungstenite::Message::Text(text) => ws::Message::Text(text),
+        tungstenite::Message::Binary(data) => ws::Message::Binary(data),
+        tungstenite::Message::Ping(data) => ws::Message::Ping(data),
+        tungstenite::Message::Pong(data) => ws::Message::Pong(data),
+        tungstenite::Message::Close(frame) => ws::Message::Close(frame.map(|frame| CloseFrame {
+            code: frame.code.into(),
+            reason: frame.reason,
+        })),
+        tungstenite::Message::Frame(_) => return None,
+    })
+}
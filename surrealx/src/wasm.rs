@@ -0,0 +1,226 @@
+//! Loading modules from WebAssembly (requires wasm-plugins feature)
+//!
+//! A WASM plugin is a core WebAssembly module that talks to the host by
+//! passing JSON through its linear memory. It must export:
+//!
+//! - `memory`
+//! - `sx_abi_version() -> i32`, returning [`ABI_VERSION`]
+//! - `sx_alloc(len: i32) -> i32`, returning a buffer of `len` bytes the host
+//!   writes arguments into
+//! - `sx_manifest() -> i64`, returning a manifest such as
+//!   `{"name": "billing", "functions": ["charge"], "listeners": ["orders:*"]}`
+//! - `sx_call(name_ptr, name_len, args_ptr, args_len) -> i64`, called with a
+//!   function name from the manifest and a JSON array of arguments
+//! - `sx_on_event(pattern_ptr, pattern_len, event_ptr, event_len) -> i64`,
+//!   called with a listener pattern from the manifest and the JSON event;
+//!   only required if the manifest has listeners
+//!
+//! Every `i64` result packs a pointer to a JSON buffer in its upper 32 bits
+//! and the buffer's length in the lower 32 bits. `sx_manifest` returns the
+//! manifest itself; `sx_call` and `sx_on_event` return `{"ok": <value>}` or
+//! `{"err": "<message>"}`.
+//!
+//! The plugin has no imports, so it can only compute on what it is given.
+//! Calls into one plugin are serialized, since a WASM instance is
+//! single-threaded.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use wasmtime::{Engine, Instance, Memory, Store};
+use crate::events::{Event, EventListener};
+use crate::functions::FunctionHandler;
+use crate::module::Module;
+use crate::error::{Error, Result};
+
+/// Version of the host ABI described in the module docs
+pub const ABI_VERSION: i32 = 1;
+
+/// Loader for modules compiled to WebAssembly
+pub struct WasmModule;
+
+impl WasmModule {
+    /// Load a module from a `.wasm` or `.wat` file
+    pub fn load(path: impl AsRef<Path>) -> Result<Module> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|err| Error::Wasm(format!("{}: {}", path.display(), err)))?;
+        Self::from_bytes(&bytes).map_err(|err| match err {
+            Error::Wasm(message) => Error::Wasm(format!("{}: {}", path.display(), message)),
+            err => err,
+        })
+    }
+
+    /// Load a module from compiled WASM bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Module> {
+        let engine = Engine::default();
+        let module = wasmtime::Module::new(&engine, bytes).map_err(wasm_error)?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[]).map_err(wasm_error)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| Error::Wasm("plugin does not export its memory".to_string()))?;
+
+        let mut plugin = Plugin { store, instance, memory };
+
+        let abi_version = plugin
+            .instance
+            .get_typed_func::<(), i32>(&mut plugin.store, "sx_abi_version")
+            .and_then(|abi_version| abi_version.call(&mut plugin.store, ()))
+            .map_err(wasm_error)?;
+        if abi_version != ABI_VERSION {
+            return Err(Error::Wasm(format!(
+                "plugin ABI version {} does not match host ABI version {}",
+                abi_version, ABI_VERSION
+            )));
+        }
+
+        let manifest = plugin.manifest()?;
+        let plugin = Arc::new(Mutex::new(plugin));
+
+        let module = manifest
+            .functions
+            .into_iter()
+            .fold(Module::new(manifest.name), |module, name| {
+                module.with_raw_function(
+                    name.clone(),
+                    WasmFunction {
+                        plugin: plugin.clone(),
+                        name,
+                    },
+                )
+            });
+        let module = manifest.listeners.into_iter().fold(module, |module, pattern| {
+            module.with_raw_listener(
+                pattern.clone(),
+                WasmListener {
+                    plugin: plugin.clone(),
+                    pattern,
+                },
+            )
+        });
+
+        Ok(module)
+    }
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    name: String,
+    #[serde(default)]
+    functions: Vec<String>,
+    #[serde(default)]
+    listeners: Vec<String>,
+}
+
+/// Outcome of a call into the plugin
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Ok(Value),
+    Err(String),
+}
+
+struct Plugin {
+    store: Store<()>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl Plugin {
+    fn manifest(&mut self) -> Result<Manifest> {
+        let manifest = self
+            .instance
+            .get_typed_func::<(), i64>(&mut self.store, "sx_manifest")
+            .and_then(|manifest| manifest.call(&mut self.store, ()))
+            .map_err(wasm_error)?;
+        let manifest = self.read(manifest)?;
+        serde_json::from_slice(&manifest).map_err(|err| Error::Wasm(format!("invalid manifest: {}", err)))
+    }
+
+    /// Call an export taking two buffers and returning an outcome
+    fn invoke(&mut self, export: &str, first: &[u8], second: &[u8]) -> Result<Value> {
+        let (first_ptr, first_len) = self.write(first)?;
+        let (second_ptr, second_len) = self.write(second)?;
+        let result = self
+            .instance
+            .get_typed_func::<(i32, i32, i32, i32), i64>(&mut self.store, export)
+            .and_then(|export| export.call(&mut self.store, (first_ptr, first_len, second_ptr, second_len)))
+            .map_err(wasm_error)?;
+
+        let outcome = self.read(result)?;
+        match serde_json::from_slice(&outcome) {
+            Ok(Outcome::Ok(value)) => Ok(value),
+            Ok(Outcome::Err(message)) => Err(Error::Function(message)),
+            Err(err) => Err(Error::Wasm(format!("invalid result from {}: {}", export, err))),
+        }
+    }
+
+    /// Copy bytes into a buffer allocated by the plugin
+    fn write(&mut self, bytes: &[u8]) -> Result<(i32, i32)> {
+        let len = i32::try_from(bytes.len()).map_err(|_| Error::Wasm("argument too large".to_string()))?;
+        let ptr = self
+            .instance
+            .get_typed_func::<i32, i32>(&mut self.store, "sx_alloc")
+            .and_then(|alloc| alloc.call(&mut self.store, len))
+            .map_err(wasm_error)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, bytes)
+            .map_err(|err| Error::Wasm(err.to_string()))?;
+        Ok((ptr, len))
+    }
+
+    /// Copy out the buffer a packed pointer and length refer to
+    fn read(&mut self, packed: i64) -> Result<Vec<u8>> {
+        let ptr = (packed as u64 >> 32) as usize;
+        let len = (packed as u64 & 0xffff_ffff) as usize;
+        let mut bytes = vec![0; len];
+        self.memory
+            .read(&self.store, ptr, &mut bytes)
+            .map_err(|err| Error::Wasm(err.to_string()))?;
+        Ok(bytes)
+    }
+}
+
+/// Run a call into the plugin on the blocking pool
+async fn run(plugin: &Arc<Mutex<Plugin>>, export: &'static str, first: String, second: Vec<u8>) -> Result<Value> {
+    let plugin = plugin.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut plugin = plugin
+            .lock()
+            .map_err(|_| Error::Wasm("plugin panicked during an earlier call".to_string()))?;
+        plugin.invoke(export, first.as_bytes(), &second)
+    })
+    .await
+    .map_err(|err| Error::Wasm(err.to_string()))?
+}
+
+struct WasmFunction {
+    plugin: Arc<Mutex<Plugin>>,
+    name: String,
+}
+
+#[async_trait]
+impl FunctionHandler for WasmFunction {
+    async fn call(&self, args: Vec<Value>) -> Result<Value> {
+        run(&self.plugin, "sx_call", self.name.clone(), serde_json::to_vec(&args)?).await
+    }
+}
+
+struct WasmListener {
+    plugin: Arc<Mutex<Plugin>>,
+    pattern: String,
+}
+
+#[async_trait]
+impl EventListener for WasmListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        run(&self.plugin, "sx_on_event", self.pattern.clone(), serde_json::to_vec(&event)?).await?;
+        Ok(())
+    }
+}
+
+fn wasm_error(err: wasmtime::Error) -> Error {
+    Error::Wasm(err.to_string())
+}
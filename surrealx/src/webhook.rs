@@ -0,0 +1,189 @@
+//! Forwarding events to HTTP endpoints (requires webhooks feature)
+//!
+//! A [`WebhookListener`] POSTs every event it receives to a URL as JSON. With
+//! a secret, each request is signed: the `x-surrealx-signature` header holds
+//! `sha256=` and the hex HMAC-SHA256 of the `x-surrealx-timestamp` header, a
+//! `.` and the body, so receivers can check both origin and freshness.
+//!
+//! Failed deliveries are retried according to the listener's
+//! [`RetryPolicy`]. Server errors, `429` and network failures are retried;
+//! other responses are not. Once a delivery succeeds or is given up on, a
+//! `webhook:delivered` or `webhook:failed` event is emitted, except for
+//! `webhook:*` events themselves, so forwarding every event cannot loop.
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use crate::retry::RetryPolicy;
+use crate::events::{Event, EventListener, EventRegistry};
+use crate::error::{Error, Result};
+
+/// Header carrying the signature of a request
+pub const SIGNATURE_HEADER: &str = "x-surrealx-signature";
+/// Header carrying the Unix time, in seconds, a request was signed at
+pub const TIMESTAMP_HEADER: &str = "x-surrealx-timestamp";
+/// Header carrying the pattern of the forwarded event
+pub const EVENT_HEADER: &str = "x-surrealx-event";
+
+/// Event listener that POSTs events to an HTTP endpoint
+#[derive(Clone)]
+pub struct WebhookListener {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<Arc<[u8]>>,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    retry: RetryPolicy,
+    events: Option<EventRegistry>,
+}
+
+/// Outcome of a single delivery attempt
+enum Attempt {
+    Delivered(u16),
+    /// Worth trying again, with the status if the endpoint answered
+    Retry(Option<u16>, String),
+    Rejected(u16, String),
+}
+
+impl WebhookListener {
+    /// Forward events to `url`, trying each up to 5 times over about 15
+    /// seconds and waiting up to 10 seconds for every response
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            secret: None,
+            headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+            retry: RetryPolicy::exponential(5, Duration::from_secs(1)),
+            events: None,
+        }
+    }
+
+    /// Sign requests with an HMAC-SHA256 secret shared with the receiver
+    pub fn with_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.secret = Some(Arc::from(secret.as_ref()));
+        self
+    }
+
+    /// Send an extra header with every request, such as an API key
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set how long to wait for each response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how failed deliveries are retried
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Emit delivery-status events to `events`
+    ///
+    /// Set by the server for webhooks added with
+    /// [`Module::with_webhook`](crate::Module::with_webhook).
+    pub fn with_events(mut self, events: EventRegistry) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// URL events are forwarded to
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn attempt(&self, pattern: &str, body: &[u8]) -> Attempt {
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let mut request = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, pattern)
+            .header(TIMESTAMP_HEADER, &timestamp);
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &timestamp, body));
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        match request.body(body.to_vec()).send().await {
+            Ok(response) => {
+                let status = response.status();
+                if status.is_success() {
+                    Attempt::Delivered(status.as_u16())
+                } else if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    Attempt::Retry(Some(status.as_u16()), format!("endpoint answered {}", status))
+                } else {
+                    Attempt::Rejected(status.as_u16(), format!("endpoint answered {}", status))
+                }
+            }
+            Err(err) => Attempt::Retry(None, err.to_string()),
+        }
+    }
+
+    async fn report(&self, event: &Event, outcome: &str, data: Value) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        if event.table == "webhook" {
+            return;
+        }
+        let result = match Event::custom(&format!("webhook:{}", outcome), data) {
+            Ok(status) => events.emit(status).await.map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            tracing::warn!(url = %self.url, error = %err, "failed to emit webhook delivery status");
+        }
+    }
+}
+
+#[async_trait]
+impl EventListener for WebhookListener {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        let pattern = event.pattern();
+        let body = serde_json::to_vec(&event)?;
+
+        let mut attempt = 1;
+        let (status, error) = loop {
+            match self.attempt(&pattern, &body).await {
+                Attempt::Delivered(status) => {
+                    let delivered = json!({ "url": self.url, "event": pattern, "status": status, "attempts": attempt });
+                    self.report(&event, "delivered", delivered).await;
+                    return Ok(());
+                }
+                Attempt::Rejected(status, error) => break (Some(status), error),
+                Attempt::Retry(status, error) if attempt >= self.retry.max_attempts => break (status, error),
+                Attempt::Retry(_, error) => {
+                    let delay = self.retry.jittered_delay(attempt);
+                    tracing::debug!(url = %self.url, attempt, error = %error, ?delay, "retrying webhook");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        };
+
+        let failed = json!({ "url": self.url, "event": pattern, "status": status, "attempts": attempt, "error": error });
+        self.report(&event, "failed", failed).await;
+        Err(Error::Webhook(format!("delivery to {} failed after {} attempts: {}", self.url, attempt, error)))
+    }
+}
+
+fn sign(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
@@ -0,0 +1,535 @@
+//! Sagas: multi-step processes with compensation
+//!
+//! A [`Workflow`] is a sequence of steps, such as reserving stock, charging
+//! the payment and shipping, added to a module with
+//! [`Module::with_workflow`](crate::Module::with_workflow). Each run of it,
+//! an instance, goes through the steps in order, keeping their outputs. When
+//! a step fails or times out, the compensations of the steps already done
+//! run in reverse order, so the stock reserved is released if the payment is
+//! declined.
+//!
+//! Instances start with [`BuiltSurrealX::start_workflow`] or, for a workflow
+//! [triggered](Workflow::triggered_by) by a pattern, with every matching
+//! event. Their state is kept in the `sx_workflow` table of the embedded
+//! database and saved after every step, so instances interrupted by a crash
+//! or a shutdown resume from their last saved step when the server starts
+//! again. That step then runs a second time, so steps and compensations
+//! should be idempotent.
+//!
+//! Every transition emits an event with the workflow and instance:
+//! `workflow:started`, `workflow:step_completed`, `workflow:step_failed`,
+//! then `workflow:completed`, `workflow:compensated`, or `workflow:failed`
+//! when a compensation fails too and the instance needs attention.
+//!
+//! [`BuiltSurrealX::start_workflow`]: crate::server::BuiltSurrealX::start_workflow
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+use crate::context::{self, Context};
+use crate::db::Database;
+use crate::events::{Event, EventListener, EventRegistry};
+use crate::functions::InFlight;
+use crate::error::{Error, Result};
+
+/// Table holding the state of workflow instances
+pub const WORKFLOW_TABLE: &str = "sx_workflow";
+
+pub(crate) type StepHandler = dyn Fn(WorkflowContext) -> BoxFuture<'static, Result<Value>> + Send + Sync;
+
+/// One step of a [`Workflow`]
+#[derive(Clone)]
+pub struct WorkflowStep {
+    name: String,
+    action: Arc<StepHandler>,
+    compensation: Option<Arc<StepHandler>>,
+    timeout: Option<Duration>,
+}
+
+impl WorkflowStep {
+    /// Run `action`, whose output later steps read under `name`
+    pub fn new<F, Fut>(name: impl Into<String>, action: F) -> Self
+    where
+        F: Fn(WorkflowContext) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            action: Arc::new(move |context| Box::pin(action(context))),
+            compensation: None,
+            timeout: None,
+        }
+    }
+
+    /// Undo the step with `compensation` when a later step fails
+    ///
+    /// It sees the step's own output among the outputs of the context.
+    pub fn with_compensation<F, Fut>(mut self, compensation: F) -> Self
+    where
+        F: Fn(WorkflowContext) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+    {
+        self.compensation = Some(Arc::new(move |context| Box::pin(compensation(context))));
+        self
+    }
+
+    /// Fail the step, and cancel it, if it runs longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Name of the step within its workflow
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A multi-step process with compensation; see the module documentation
+#[derive(Clone)]
+pub struct Workflow {
+    name: String,
+    steps: Vec<WorkflowStep>,
+    trigger: Option<String>,
+    timeout: Option<Duration>,
+}
+
+impl Workflow {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            steps: Vec::new(),
+            trigger: None,
+            timeout: None,
+        }
+    }
+
+    /// Add a step without compensation
+    pub fn step<F, Fut>(self, name: impl Into<String>, action: F) -> Self
+    where
+        F: Fn(WorkflowContext) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<Value>> + Send + 'static,
+    {
+        self.with_step(WorkflowStep::new(name, action))
+    }
+
+    /// Add a step
+    pub fn with_step(mut self, step: WorkflowStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Start an instance for every event matching `pattern`, with the
+    /// event's data as input
+    pub fn triggered_by(mut self, pattern: impl Into<String>) -> Self {
+        self.trigger = Some(pattern.into());
+        self
+    }
+
+    /// Fail an instance, and compensate it, if it has not completed within
+    /// `timeout` of starting
+    ///
+    /// Checked between steps; a running step is bounded by its own timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Name of the workflow within its module
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Steps in the order they run
+    pub fn steps(&self) -> &[WorkflowStep] {
+        &self.steps
+    }
+}
+
+/// What a step or compensation sees of its instance
+#[derive(Clone)]
+pub struct WorkflowContext {
+    instance_id: String,
+    workflow: String,
+    input: Value,
+    outputs: Map<String, Value>,
+    module: Context,
+}
+
+impl WorkflowContext {
+    /// Identifier of the instance, for correlating its effects
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Fully qualified name of the workflow, `module::name`
+    pub fn workflow(&self) -> &str {
+        &self.workflow
+    }
+
+    /// Input the instance was started with
+    pub fn input(&self) -> &Value {
+        &self.input
+    }
+
+    /// Output of the completed step `step`
+    pub fn output(&self, step: &str) -> Option<&Value> {
+        self.outputs.get(step)
+    }
+
+    /// Context of the module the workflow belongs to
+    pub fn module(&self) -> &Context {
+        &self.module
+    }
+}
+
+/// Where a workflow instance stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowStatus {
+    /// Running its steps
+    Running,
+    /// Undoing its completed steps after a failure
+    Compensating,
+    /// Every step completed
+    Completed,
+    /// A step failed and every completed step was compensated
+    Compensated,
+    /// A compensation failed; the instance is left as it stands
+    Failed,
+}
+
+/// Saved state of a workflow instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowInstance {
+    pub id: String,
+    /// Fully qualified name of the workflow, `module::name`
+    pub workflow: String,
+    pub status: WorkflowStatus,
+    /// Steps completed while running, or left to compensate while
+    /// compensating
+    pub step: usize,
+    pub input: Value,
+    /// Outputs of the completed steps, by step name
+    pub outputs: Map<String, Value>,
+    /// Why the instance is compensating or failed
+    pub error: Option<String>,
+    /// When the instance started, in milliseconds since the Unix epoch
+    pub started_at: i64,
+    /// When the instance times out, in milliseconds since the Unix epoch
+    pub deadline: Option<i64>,
+}
+
+/// A workflow with the context of its module
+struct Registered {
+    context: Context,
+    workflow: Workflow,
+}
+
+/// Runs the workflow instances of a built server
+pub(crate) struct Workflows {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// By fully qualified name
+    workflows: HashMap<String, Registered>,
+    db: Database,
+    events: EventRegistry,
+    in_flight: InFlight,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Workflows {
+    /// Check `workflows`, given with the context of their module
+    pub(crate) fn new(
+        workflows: Vec<(Context, Workflow)>,
+        db: Database,
+        events: EventRegistry,
+        in_flight: InFlight,
+    ) -> Result<Self> {
+        let mut registered = HashMap::new();
+        for (context, workflow) in workflows {
+            let id = format!("{}::{}", context.module(), workflow.name);
+            if workflow.steps.is_empty() {
+                return Err(Error::Config(format!("workflow '{}' has no steps", id)));
+            }
+            let mut names = HashSet::new();
+            if let Some(step) = workflow.steps.iter().find(|step| !names.insert(step.name.as_str())) {
+                return Err(Error::Config(format!("workflow '{}' has two steps named '{}'", id, step.name)));
+            }
+            if registered.insert(id.clone(), Registered { context, workflow }).is_some() {
+                return Err(Error::Config(format!("workflow '{}' is defined twice", id)));
+            }
+        }
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                workflows: registered,
+                db,
+                events,
+                in_flight,
+                tasks: Mutex::new(Vec::new()),
+            }),
+        })
+    }
+
+    /// Listeners starting the workflows that have a trigger, by pattern
+    pub(crate) fn triggers(&self) -> Vec<(String, Arc<dyn EventListener>)> {
+        self.inner
+            .workflows
+            .iter()
+            .filter_map(|(id, registered)| {
+                let pattern = registered.workflow.trigger.clone()?;
+                let trigger = WorkflowTrigger {
+                    inner: self.inner.clone(),
+                    workflow: id.clone(),
+                };
+                Some((pattern, Arc::new(trigger) as Arc<dyn EventListener>))
+            })
+            .collect()
+    }
+
+    /// Start an instance of `workflow`, returning its ID
+    pub(crate) async fn start(&self, workflow: &str, input: Value) -> Result<String> {
+        self.inner.start(workflow, input).await
+    }
+
+    /// Resume the instances left running or compensating
+    pub(crate) async fn resume(&self) -> Result<usize> {
+        let mut response = self
+            .inner
+            .db
+            .query("SELECT VALUE instance FROM type::table($table) WHERE instance.status IN ['running', 'compensating']")
+            .bind(("table", WORKFLOW_TABLE))
+            .await?;
+        let instances = response.take::<surrealdb::Value>(0)?.into_inner().into_json();
+        let mut instances: Vec<WorkflowInstance> = serde_json::from_value(instances)?;
+        instances.sort_by_key(|instance| instance.started_at);
+
+        let mut resumed = 0;
+        for instance in instances {
+            if self.inner.workflows.contains_key(&instance.workflow) {
+                tracing::info!(workflow = %instance.workflow, instance = %instance.id, "resuming workflow instance");
+                self.inner.spawn(instance);
+                resumed += 1;
+            } else {
+                tracing::warn!(workflow = %instance.workflow, instance = %instance.id, "instance of an unknown workflow left as it is");
+            }
+        }
+        Ok(resumed)
+    }
+
+    /// Saved state of the instance `id`
+    pub(crate) async fn instance(&self, id: &str) -> Result<Option<WorkflowInstance>> {
+        let mut response = self
+            .inner
+            .db
+            .query("SELECT VALUE instance FROM type::thing($table, $id)")
+            .bind(("table", WORKFLOW_TABLE))
+            .bind(("id", id.to_string()))
+            .await?;
+        let instances = response.take::<surrealdb::Value>(0)?.into_inner().into_json();
+        let instances: Vec<WorkflowInstance> = serde_json::from_value(instances)?;
+        Ok(instances.into_iter().next())
+    }
+
+    /// Stop driving instances; they resume from their saved state at the
+    /// next start
+    pub(crate) fn stop(&self) {
+        let mut tasks = self.inner.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for task in tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
+
+impl Inner {
+    async fn start(self: &Arc<Self>, workflow: &str, input: Value) -> Result<String> {
+        let registered = self
+            .workflows
+            .get(workflow)
+            .ok_or_else(|| Error::NotFound(format!("workflow '{}'", workflow)))?;
+        let started_at = chrono::Utc::now().timestamp_millis();
+        let instance = WorkflowInstance {
+            id: format!("{}-{:08x}", started_at, fastrand::u32(..)),
+            workflow: workflow.to_string(),
+            status: WorkflowStatus::Running,
+            step: 0,
+            input,
+            outputs: Map::new(),
+            error: None,
+            started_at,
+            deadline: registered
+                .workflow
+                .timeout
+                .map(|timeout| started_at + i64::try_from(timeout.as_millis()).unwrap_or(i64::MAX)),
+        };
+        self.save(&instance).await?;
+        self.emit("workflow:started", &instance, json!({})).await;
+
+        let id = instance.id.clone();
+        self.spawn(instance);
+        Ok(id)
+    }
+
+    fn spawn(self: &Arc<Self>, instance: WorkflowInstance) {
+        let inner = self.clone();
+        let span = tracing::info_span!("sx.workflow", workflow = %instance.workflow, instance = %instance.id);
+        let task = tokio::spawn(
+            async move {
+                let id = instance.id.clone();
+                if let Err(err) = inner.drive(instance).await {
+                    tracing::error!(instance = %id, error = %err, "workflow instance stopped; it resumes at the next start");
+                }
+            }
+            .instrument(span),
+        );
+
+        let mut tasks = self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+    }
+
+    /// Run `instance` to completion, saving it after every transition
+    ///
+    /// Fails only if its state cannot be saved.
+    async fn drive(&self, mut instance: WorkflowInstance) -> Result<()> {
+        let Some(registered) = self.workflows.get(&instance.workflow) else {
+            return Ok(());
+        };
+        let steps = &registered.workflow.steps;
+
+        loop {
+            match instance.status {
+                WorkflowStatus::Running if instance.step >= steps.len() => {
+                    instance.status = WorkflowStatus::Completed;
+                    self.save(&instance).await?;
+                    self.emit("workflow:completed", &instance, json!({ "outputs": instance.outputs })).await;
+                    return Ok(());
+                }
+                WorkflowStatus::Running => {
+                    let step = &steps[instance.step];
+                    let timed_out = instance
+                        .deadline
+                        .is_some_and(|deadline| chrono::Utc::now().timestamp_millis() >= deadline);
+                    let result = if timed_out {
+                        Err(Error::Function("workflow timed out".to_string()))
+                    } else {
+                        self.run(registered, &instance, &step.action, step.timeout).await
+                    };
+
+                    match result {
+                        Ok(output) => {
+                            instance.outputs.insert(step.name.clone(), output);
+                            instance.step += 1;
+                            self.save(&instance).await?;
+                            self.emit("workflow:step_completed", &instance, json!({ "step": step.name })).await;
+                        }
+                        Err(err) => {
+                            tracing::warn!(step = %step.name, error = %err, "workflow step failed; compensating");
+                            instance.status = WorkflowStatus::Compensating;
+                            instance.error = Some(format!("step '{}' failed: {}", step.name, err));
+                            self.save(&instance).await?;
+                            self.emit("workflow:step_failed", &instance, json!({ "step": step.name })).await;
+                        }
+                    }
+                }
+                WorkflowStatus::Compensating if instance.step == 0 => {
+                    instance.status = WorkflowStatus::Compensated;
+                    self.save(&instance).await?;
+                    self.emit("workflow:compensated", &instance, json!({})).await;
+                    return Ok(());
+                }
+                WorkflowStatus::Compensating => {
+                    let step = &steps[instance.step - 1];
+                    if let Some(compensation) = &step.compensation {
+                        if let Err(err) = self.run(registered, &instance, compensation, step.timeout).await {
+                            tracing::error!(step = %step.name, error = %err, "workflow compensation failed");
+                            let cause = instance.error.take().unwrap_or_default();
+                            instance.status = WorkflowStatus::Failed;
+                            instance.error =
+                                Some(format!("{}; compensating step '{}' failed: {}", cause, step.name, err));
+                            self.save(&instance).await?;
+                            self.emit("workflow:failed", &instance, json!({ "step": step.name })).await;
+                            return Ok(());
+                        }
+                    }
+                    instance.step -= 1;
+                    self.save(&instance).await?;
+                }
+                WorkflowStatus::Completed | WorkflowStatus::Compensated | WorkflowStatus::Failed => return Ok(()),
+            }
+        }
+    }
+
+    /// Run a step's action or compensation in the module's context
+    async fn run(
+        &self,
+        registered: &Registered,
+        instance: &WorkflowInstance,
+        handler: &Arc<StepHandler>,
+        timeout: Option<Duration>,
+    ) -> Result<Value> {
+        let _in_flight = self.in_flight.enter();
+        let context = WorkflowContext {
+            instance_id: instance.id.clone(),
+            workflow: instance.workflow.clone(),
+            input: instance.input.clone(),
+            outputs: instance.outputs.clone(),
+            module: registered.context.clone(),
+        };
+        let run = context::scope(registered.context.clone(), handler(context));
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, run)
+                .await
+                .unwrap_or_else(|_| Err(Error::Function(format!("timed out after {:?}", timeout)))),
+            None => run.await,
+        }
+    }
+
+    async fn save(&self, instance: &WorkflowInstance) -> Result<()> {
+        self.db
+            .query("UPSERT type::thing($table, $id) CONTENT $record RETURN NONE")
+            .bind(("table", WORKFLOW_TABLE))
+            .bind(("id", instance.id.clone()))
+            .bind(("record", json!({ "instance": serde_json::to_value(instance)? })))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    async fn emit(&self, pattern: &str, instance: &WorkflowInstance, mut data: Value) {
+        data["workflow"] = json!(instance.workflow);
+        data["instance"] = json!(instance.id);
+        if let Some(error) = &instance.error {
+            data["error"] = json!(error);
+        }
+        let result = match Event::custom(pattern, data) {
+            Ok(event) => self.events.emit(event).await.map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            tracing::warn!(event = pattern, error = %err, "failed to emit a workflow event");
+        }
+    }
+}
+
+/// Listener starting an instance of a workflow for every event
+struct WorkflowTrigger {
+    inner: Arc<Inner>,
+    workflow: String,
+}
+
+#[async_trait]
+impl EventListener for WorkflowTrigger {
+    async fn on_event(&self, event: Event) -> Result<()> {
+        self.inner.start(&self.workflow, event.data).await.map(|_| ())
+    }
+}